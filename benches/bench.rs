@@ -102,7 +102,8 @@ criterion_group!(
     benches,
     cases::parsing::group,
     cases::rewriting::group,
-    cases::selector_matching::group
+    cases::selector_matching::group,
+    cases::instantiation::group
 );
 
 criterion_main!(benches);
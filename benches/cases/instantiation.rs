@@ -0,0 +1,62 @@
+use criterion::*;
+use lol_html::*;
+
+const SELECTORS: [&str; 8] = [
+    "a[href]",
+    "img[src]",
+    "script[src]",
+    "link[rel=stylesheet]",
+    "div.note > p:first-child",
+    "ul > li:nth-child(2n+1)",
+    "table > tbody td dfn",
+    "form input[type=password]",
+];
+
+pub fn group(c: &mut Criterion) {
+    let selectors = SELECTORS
+        .iter()
+        .map(|s| s.parse::<Selector>().unwrap())
+        .collect::<Vec<_>>();
+
+    let template = RewriterTemplate::try_new(TemplateSettings {
+        element_content_handlers: selectors
+            .iter()
+            .map(|s| -> (_, ElementContentHandlersFactory) {
+                (s, Box::new(|| ElementContentHandlers::default().element(noop_handler!())))
+            })
+            .collect(),
+        ..TemplateSettings::default()
+    })
+    .unwrap();
+
+    let mut g = c.benchmark_group("Instantiation");
+
+    g.bench_function("Compilation of the settings", |b| {
+        b.iter(|| {
+            HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: selectors
+                        .iter()
+                        .map(|s| (s, ElementContentHandlers::default().element(noop_handler!())))
+                        .collect(),
+                    ..Settings::default()
+                },
+                |c: &[u8]| {
+                    black_box(c);
+                },
+            )
+            .unwrap()
+        })
+    });
+
+    g.bench_function("Instantiation from a template", |b| {
+        b.iter(|| {
+            HtmlRewriter::from_template(&template, |c: &[u8]| {
+                black_box(c);
+            })
+            .unwrap()
+        })
+    });
+
+    g.finish();
+}
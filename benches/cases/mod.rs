@@ -1,3 +1,4 @@
+pub mod instantiation;
 pub mod parsing;
 pub mod rewriting;
 pub mod selector_matching;
@@ -26,6 +26,7 @@ typedef struct lol_html_DocumentEnd lol_html_doc_end_t;
 typedef struct lol_html_Comment lol_html_comment_t;
 typedef struct lol_html_TextChunk lol_html_text_chunk_t;
 typedef struct lol_html_Element lol_html_element_t;
+typedef struct lol_html_EndTag lol_html_end_tag_t;
 typedef struct lol_html_AttributesIterator lol_html_attributes_iterator_t;
 typedef struct lol_html_Attribute lol_html_attribute_t;
 typedef struct lol_html_Selector lol_html_selector_t;
@@ -108,6 +109,11 @@ typedef lol_html_rewriter_directive_t (*lol_html_doc_end_handler_t)(
     void *user_data
 );
 
+typedef lol_html_rewriter_directive_t (*lol_html_end_tag_handler_t)(
+    lol_html_end_tag_t *end_tag,
+    void *user_data
+);
+
 // Selector
 //---------------------------------------------------------------------
 
@@ -195,6 +201,27 @@ int lol_html_rewriter_builder_add_element_content_handlers(
     void *text_handler_user_data
 );
 
+// Specifies whether the rewriters built from the builder buffer the input to
+// match the selectors that depend on the content following the start tag of
+// an element (e.g. `:last-child`, `:empty` or `:has()`).
+//
+// With LOL_HTML_LOOKAHEAD_DISABLED, which is the default, `lol_html_rewriter_build`
+// returns NULL if some of the selectors require lookahead. Otherwise, the input
+// is withheld until the decision can be made, and exceeding the memory limit
+// either makes `write()` and `end()` methods of the rewriter return an error code
+// (LOL_HTML_LOOKAHEAD_FAIL_ON_MEMORY_LIMIT) or passes the withheld input through
+// without matching the pending elements (LOL_HTML_LOOKAHEAD_PASS_THROUGH_ON_MEMORY_LIMIT).
+typedef enum {
+    LOL_HTML_LOOKAHEAD_DISABLED,
+    LOL_HTML_LOOKAHEAD_FAIL_ON_MEMORY_LIMIT,
+    LOL_HTML_LOOKAHEAD_PASS_THROUGH_ON_MEMORY_LIMIT
+} lol_html_lookahead_mode_t;
+
+void lol_html_rewriter_builder_set_lookahead_mode(
+    lol_html_rewriter_builder_t *builder,
+    lol_html_lookahead_mode_t lookahead_mode
+);
+
 // Frees the memory held by the builder.
 //
 // Note that builder can be freed before any rewriters constructed from
@@ -286,6 +313,89 @@ lol_html_str_t *lol_html_doctype_public_id_get(const lol_html_doctype_t *doctype
 // Returns NULL if the doctype doesn't have a SYSTEM identifier.
 lol_html_str_t *lol_html_doctype_system_id_get(const lol_html_doctype_t *doctype);
 
+// Sets the name of the doctype.
+//
+// Name should be a valid UTF8-string.
+//
+// Returns 0 in case of success and -1 otherwise. The actual error message
+// can be obtained using `lol_html_take_last_error` function.
+int lol_html_doctype_name_set(
+    lol_html_doctype_t *doctype,
+    const char *name,
+    size_t name_len
+);
+
+// Sets the PUBLIC identifier of the doctype.
+//
+// The identifier is removed if `id` is NULL. Otherwise the identifier
+// should be a valid UTF8-string.
+//
+// Returns 0 in case of success and -1 otherwise. The actual error message
+// can be obtained using `lol_html_take_last_error` function.
+int lol_html_doctype_public_id_set(
+    lol_html_doctype_t *doctype,
+    const char *id,
+    size_t id_len
+);
+
+// Sets the SYSTEM identifier of the doctype.
+//
+// The identifier is removed if `id` is NULL. Otherwise the identifier
+// should be a valid UTF8-string.
+//
+// Returns 0 in case of success and -1 otherwise. The actual error message
+// can be obtained using `lol_html_take_last_error` function.
+int lol_html_doctype_system_id_set(
+    lol_html_doctype_t *doctype,
+    const char *id,
+    size_t id_len
+);
+
+// Inserts the content string before the doctype either as raw text or as HTML.
+//
+// Content should be a valid UTF8-string.
+//
+// Returns 0 in case of success and -1 otherwise. The actual error message
+// can be obtained using `lol_html_take_last_error` function.
+int lol_html_doctype_before(
+    lol_html_doctype_t *doctype,
+    const char *content,
+    size_t content_len,
+    bool is_html
+);
+
+// Inserts the content string after the doctype either as raw text or as HTML.
+//
+// Content should be a valid UTF8-string.
+//
+// Returns 0 in case of success and -1 otherwise. The actual error message
+// can be obtained using `lol_html_take_last_error` function.
+int lol_html_doctype_after(
+    lol_html_doctype_t *doctype,
+    const char *content,
+    size_t content_len,
+    bool is_html
+);
+
+// Replaces the doctype with the provided text or HTML content.
+//
+// Content should be a valid UTF8-string.
+//
+// Returns 0 in case of success and -1 otherwise. The actual error message
+// can be obtained using `lol_html_take_last_error` function.
+int lol_html_doctype_replace(
+    lol_html_doctype_t *doctype,
+    const char *content,
+    size_t content_len,
+    bool is_html
+);
+
+// Removes the doctype.
+void lol_html_doctype_remove(lol_html_doctype_t *doctype);
+
+// Returns `true` if the doctype has been removed.
+bool lol_html_doctype_is_removed(const lol_html_doctype_t *doctype);
+
 // Attaches custom user data to the doctype.
 //
 // The same doctype can be passed to multiple handlers if it has been
@@ -504,9 +614,13 @@ const lol_html_attribute_t *lol_html_attributes_iterator_next(
 // Returns the attribute name.
 lol_html_str_t lol_html_attribute_name_get(const lol_html_attribute_t *attribute);
 
-// Returns the attribute value.
+// Returns the attribute value with the character references decoded.
 lol_html_str_t lol_html_attribute_value_get(const lol_html_attribute_t *attribute);
 
+// Returns the attribute value as it appears in the markup, i.e. without
+// the character references decoded.
+lol_html_str_t lol_html_attribute_raw_value_get(const lol_html_attribute_t *attribute);
+
 // Returns the attribute value or NULL if attribute with the given name
 // doesn't exist on the element.
 //
@@ -659,6 +773,85 @@ void lol_html_element_user_data_set(
 // Returns user data attached to the text chunk.
 void *lol_html_element_user_data_get(const lol_html_element_t *element);
 
+// Sets the handler that is invoked with the end tag of the element.
+//
+// The handler is invoked after the inner content of the element has been
+// processed. Consequent calls to the function add handlers that are invoked
+// in the order they were added. The handler is never invoked if the element
+// can't have an end tag (e.g. a void element like `<br>`) or if the element
+// is not closed by the end of the input.
+//
+// WARNING: the user data pointer should remain valid until the handler is
+// invoked or the rewriter is freed.
+void lol_html_element_on_end_tag(
+    lol_html_element_t *element,
+    lol_html_end_tag_handler_t handler,
+    void *user_data
+);
+
+// End tag
+//---------------------------------------------------------------------
+
+// Returns the name of the end tag.
+lol_html_str_t lol_html_end_tag_name_get(const lol_html_end_tag_t *end_tag);
+
+// Sets the name of the end tag.
+//
+// Name should be a valid UTF8-string.
+//
+// Returns 0 in case of success and -1 otherwise. The actual error message
+// can be obtained using `lol_html_take_last_error` function.
+int lol_html_end_tag_name_set(
+    lol_html_end_tag_t *end_tag,
+    const char *name,
+    size_t name_len
+);
+
+// Inserts the content string before the end tag either as raw text or as HTML.
+//
+// Content should be a valid UTF8-string.
+//
+// Returns 0 in case of success and -1 otherwise. The actual error message
+// can be obtained using `lol_html_take_last_error` function.
+int lol_html_end_tag_before(
+    lol_html_end_tag_t *end_tag,
+    const char *content,
+    size_t content_len,
+    bool is_html
+);
+
+// Inserts the content string after the end tag either as raw text or as HTML.
+//
+// Content should be a valid UTF8-string.
+//
+// Returns 0 in case of success and -1 otherwise. The actual error message
+// can be obtained using `lol_html_take_last_error` function.
+int lol_html_end_tag_after(
+    lol_html_end_tag_t *end_tag,
+    const char *content,
+    size_t content_len,
+    bool is_html
+);
+
+// Replaces the end tag with the provided text or HTML content.
+//
+// Content should be a valid UTF8-string.
+//
+// Returns 0 in case of success and -1 otherwise. The actual error message
+// can be obtained using `lol_html_take_last_error` function.
+int lol_html_end_tag_replace(
+    lol_html_end_tag_t *end_tag,
+    const char *content,
+    size_t content_len,
+    bool is_html
+);
+
+// Removes the end tag.
+void lol_html_end_tag_remove(lol_html_end_tag_t *end_tag);
+
+// Returns `true` if the end tag has been removed.
+bool lol_html_end_tag_is_removed(const lol_html_end_tag_t *end_tag);
+
 // Inserts the content at the end of the document, either as raw text or as HTML.
 //
 // The content should be a valid UTF-8 string.
@@ -15,6 +15,98 @@ pub extern "C" fn lol_html_doctype_system_id_get(doctype: *const Doctype) -> *co
     Str::opt_ptr(to_ref!(doctype).system_id())
 }
 
+#[no_mangle]
+pub extern "C" fn lol_html_doctype_name_set(
+    doctype: *mut Doctype,
+    name: *const c_char,
+    name_len: size_t,
+) -> c_int {
+    let doctype = to_ref_mut!(doctype);
+    let name = unwrap_or_ret_err_code! { to_str!(name, name_len) };
+
+    unwrap_or_ret_err_code! { doctype.set_name(name) };
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_doctype_public_id_set(
+    doctype: *mut Doctype,
+    id: *const c_char,
+    id_len: size_t,
+) -> c_int {
+    let doctype = to_ref_mut!(doctype);
+
+    let id = if id.is_null() {
+        None
+    } else {
+        Some(unwrap_or_ret_err_code! { to_str!(id, id_len) })
+    };
+
+    unwrap_or_ret_err_code! { doctype.set_public_id(id) };
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_doctype_system_id_set(
+    doctype: *mut Doctype,
+    id: *const c_char,
+    id_len: size_t,
+) -> c_int {
+    let doctype = to_ref_mut!(doctype);
+
+    let id = if id.is_null() {
+        None
+    } else {
+        Some(unwrap_or_ret_err_code! { to_str!(id, id_len) })
+    };
+
+    unwrap_or_ret_err_code! { doctype.set_system_id(id) };
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_doctype_before(
+    doctype: *mut Doctype,
+    content: *const c_char,
+    content_len: size_t,
+    is_html: bool,
+) -> c_int {
+    content_insertion_fn_body! { doctype.before(content, content_len, is_html) }
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_doctype_after(
+    doctype: *mut Doctype,
+    content: *const c_char,
+    content_len: size_t,
+    is_html: bool,
+) -> c_int {
+    content_insertion_fn_body! { doctype.after(content, content_len, is_html) }
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_doctype_replace(
+    doctype: *mut Doctype,
+    content: *const c_char,
+    content_len: size_t,
+    is_html: bool,
+) -> c_int {
+    content_insertion_fn_body! { doctype.replace(content, content_len, is_html) }
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_doctype_remove(doctype: *mut Doctype) {
+    to_ref_mut!(doctype).remove();
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_doctype_is_removed(doctype: *const Doctype) -> bool {
+    to_ref!(doctype).removed()
+}
+
 #[no_mangle]
 pub extern "C" fn lol_html_doctype_user_data_set(doctype: *mut Doctype, user_data: *mut c_void) {
     to_ref_mut!(doctype).set_user_data(user_data);
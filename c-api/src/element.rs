@@ -1,3 +1,4 @@
+use super::rewriter_builder::EndTagHandler;
 use super::*;
 use std::slice::Iter;
 
@@ -74,6 +75,13 @@ pub extern "C" fn lol_html_attribute_value_get(attribute: *const Attribute) -> S
     Str::new(attribute.value())
 }
 
+#[no_mangle]
+pub extern "C" fn lol_html_attribute_raw_value_get(attribute: *const Attribute) -> Str {
+    let attribute = to_ref!(attribute);
+
+    Str::new(attribute.raw_value())
+}
+
 #[no_mangle]
 pub extern "C" fn lol_html_element_get_attribute(
     element: *const Element,
@@ -208,6 +216,23 @@ pub extern "C" fn lol_html_element_is_removed(element: *mut Element) -> bool {
     to_ref_mut!(element).removed()
 }
 
+#[no_mangle]
+pub extern "C" fn lol_html_element_on_end_tag(
+    element: *mut Element,
+    handler: Option<EndTagHandler>,
+    user_data: *mut c_void,
+) {
+    let element = to_ref_mut!(element);
+    let handler = handler.expect("handler is NULL");
+
+    element.on_end_tag(
+        move |end_tag| match unsafe { handler(end_tag, user_data) } {
+            RewriterDirective::Continue => Ok(()),
+            RewriterDirective::Stop => Err("The rewriter has been stopped.".into()),
+        },
+    );
+}
+
 #[no_mangle]
 pub extern "C" fn lol_html_element_user_data_set(element: *mut Element, user_data: *mut c_void) {
     to_ref_mut!(element).set_user_data(user_data);
@@ -0,0 +1,62 @@
+use super::*;
+
+#[no_mangle]
+pub extern "C" fn lol_html_end_tag_name_get(end_tag: *const EndTag) -> Str {
+    let end_tag = to_ref!(end_tag);
+
+    Str::new(end_tag.name())
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_end_tag_name_set(
+    end_tag: *mut EndTag,
+    name: *const c_char,
+    name_len: size_t,
+) -> c_int {
+    let end_tag = to_ref_mut!(end_tag);
+    let name = unwrap_or_ret_err_code! { to_str!(name, name_len) };
+
+    unwrap_or_ret_err_code! { end_tag.set_name(name) };
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_end_tag_before(
+    end_tag: *mut EndTag,
+    content: *const c_char,
+    content_len: size_t,
+    is_html: bool,
+) -> c_int {
+    content_insertion_fn_body! { end_tag.before(content, content_len, is_html) }
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_end_tag_after(
+    end_tag: *mut EndTag,
+    content: *const c_char,
+    content_len: size_t,
+    is_html: bool,
+) -> c_int {
+    content_insertion_fn_body! { end_tag.after(content, content_len, is_html) }
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_end_tag_replace(
+    end_tag: *mut EndTag,
+    content: *const c_char,
+    content_len: size_t,
+    is_html: bool,
+) -> c_int {
+    content_insertion_fn_body! { end_tag.replace(content, content_len, is_html) }
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_end_tag_remove(end_tag: *mut EndTag) {
+    to_ref_mut!(end_tag).remove();
+}
+
+#[no_mangle]
+pub extern "C" fn lol_html_end_tag_is_removed(end_tag: *const EndTag) -> bool {
+    to_ref!(end_tag).removed()
+}
@@ -119,6 +119,7 @@ mod comment;
 mod doctype;
 mod document_end;
 mod element;
+mod end_tag;
 mod errors;
 mod rewriter;
 mod rewriter_builder;
@@ -49,6 +49,8 @@ pub extern "C" fn lol_html_rewriter_build(
         encoding: unwrap_or_ret_null! { to_str!(encoding, encoding_len) },
         memory_settings,
         strict,
+        lookahead_mode: builder.lookahead_mode,
+        ..Settings::default()
     };
 
     let output_sink = ExternOutputSink::new(output_sink, output_sink_user_data);
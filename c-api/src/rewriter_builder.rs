@@ -7,11 +7,31 @@ pub enum RewriterDirective {
     Stop,
 }
 
+#[repr(C)]
+pub enum ExternLookaheadMode {
+    Disabled,
+    FailOnMemoryLimit,
+    PassThroughOnMemoryLimit,
+}
+
+impl From<ExternLookaheadMode> for LookaheadMode {
+    fn from(mode: ExternLookaheadMode) -> Self {
+        match mode {
+            ExternLookaheadMode::Disabled => LookaheadMode::Disabled,
+            ExternLookaheadMode::FailOnMemoryLimit => LookaheadMode::FailOnMemoryLimit,
+            ExternLookaheadMode::PassThroughOnMemoryLimit => {
+                LookaheadMode::PassThroughOnMemoryLimit
+            }
+        }
+    }
+}
+
 type ElementHandler = unsafe extern "C" fn(*mut Element, *mut c_void) -> RewriterDirective;
 type DoctypeHandler = unsafe extern "C" fn(*mut Doctype, *mut c_void) -> RewriterDirective;
 type CommentsHandler = unsafe extern "C" fn(*mut Comment, *mut c_void) -> RewriterDirective;
 type TextHandler = unsafe extern "C" fn(*mut TextChunk, *mut c_void) -> RewriterDirective;
 type DocumentEndHandler = unsafe extern "C" fn(*mut DocumentEnd, *mut c_void) -> RewriterDirective;
+pub(crate) type EndTagHandler = unsafe extern "C" fn(*mut EndTag, *mut c_void) -> RewriterDirective;
 
 struct ExternHandler<F> {
     func: Option<F>,
@@ -96,6 +116,7 @@ pub struct SafeContentHandlers<'b> {
 pub struct HtmlRewriterBuilder {
     document_content_handlers: Vec<ExternDocumentContentHandlers>,
     element_content_handlers: Vec<(&'static Selector, ExternElementContentHandlers)>,
+    pub lookahead_mode: LookaheadMode,
 }
 
 impl HtmlRewriterBuilder {
@@ -169,6 +190,16 @@ pub extern "C" fn lol_html_rewriter_builder_add_element_content_handlers(
     0
 }
 
+#[no_mangle]
+pub extern "C" fn lol_html_rewriter_builder_set_lookahead_mode(
+    builder: *mut HtmlRewriterBuilder,
+    lookahead_mode: ExternLookaheadMode,
+) {
+    let builder = to_ref_mut!(builder);
+
+    builder.lookahead_mode = lookahead_mode.into();
+}
+
 #[no_mangle]
 pub extern "C" fn lol_html_rewriter_builder_free(builder: *mut HtmlRewriterBuilder) {
     drop(to_box!(builder));
@@ -109,6 +109,110 @@ static void test_get_user_data(void *user_data) {
     );
 }
 
+//-------------------------------------------------------------------------
+EXPECT_OUTPUT(
+    modify_doctype_output_sink,
+    "<!-- legacy --><!DOCTYPE html><meta charset=\"utf-8\">",
+    &EXPECTED_USER_DATA,
+    sizeof(EXPECTED_USER_DATA)
+)
+
+static lol_html_rewriter_directive_t modify_doctype(
+    lol_html_doctype_t *doctype,
+    void *user_data
+) {
+    UNUSED(user_data);
+
+    const char *name = "html";
+    const char *before = "<!-- legacy -->";
+    const char *after = "<meta charset=\"utf-8\">";
+
+    note("Set invalid doctype name");
+    ok(lol_html_doctype_name_set(doctype, "", 0) == -1);
+
+    lol_html_str_t *msg = lol_html_take_last_error();
+
+    str_eq(msg, "Doctype name can't be empty.");
+
+    lol_html_str_free(*msg);
+
+    note("Set doctype name and identifiers");
+    ok(!lol_html_doctype_name_set(doctype, name, strlen(name)));
+    ok(!lol_html_doctype_public_id_set(doctype, NULL, 0));
+    ok(!lol_html_doctype_system_id_set(doctype, NULL, 0));
+
+    note("Insert before/after doctype");
+    ok(!lol_html_doctype_before(doctype, before, strlen(before), true));
+    ok(!lol_html_doctype_after(doctype, after, strlen(after), true));
+
+    note("Removed flag");
+    ok(!lol_html_doctype_is_removed(doctype));
+
+    return LOL_HTML_CONTINUE;
+}
+
+static void test_modify_doctype(void *user_data) {
+    lol_html_rewriter_builder_t *builder = lol_html_rewriter_builder_new();
+
+    lol_html_rewriter_builder_add_document_content_handlers(
+        builder,
+        &modify_doctype,
+        NULL,
+        NULL,
+        NULL,
+        NULL,
+        NULL,
+        NULL,
+        NULL
+    );
+
+    run_rewriter(
+        builder,
+        "<!DOCTYPE math SYSTEM \"http://www.w3.org/Math/DTD/mathml1/mathml.dtd\">",
+        modify_doctype_output_sink,
+        user_data
+    );
+}
+
+//-------------------------------------------------------------------------
+EXPECT_OUTPUT(
+    remove_doctype_output_sink,
+    "<html>",
+    &EXPECTED_USER_DATA,
+    sizeof(EXPECTED_USER_DATA)
+)
+
+static lol_html_rewriter_directive_t remove_doctype(
+    lol_html_doctype_t *doctype,
+    void *user_data
+) {
+    UNUSED(user_data);
+
+    note("Remove");
+    lol_html_doctype_remove(doctype);
+    ok(lol_html_doctype_is_removed(doctype));
+
+    return LOL_HTML_CONTINUE;
+}
+
+static void test_remove_doctype(void *user_data) {
+    lol_html_rewriter_builder_t *builder = lol_html_rewriter_builder_new();
+
+    lol_html_rewriter_builder_add_document_content_handlers(
+        builder,
+        &remove_doctype,
+        NULL,
+        NULL,
+        NULL,
+        NULL,
+        NULL,
+        NULL,
+        NULL
+    );
+
+    run_rewriter(builder, "<!doctype html><html>", remove_doctype_output_sink, user_data);
+}
+
 //-------------------------------------------------------------------------
 static lol_html_rewriter_directive_t stop_rewriting(
     lol_html_doctype_t *doctype,
@@ -145,5 +249,7 @@ void test_doctype_api() {
 
     test_get_doctype_fields(&user_data);
     test_get_user_data(&user_data);
+    test_modify_doctype(&user_data);
+    test_remove_doctype(&user_data);
     test_stop(&user_data);
 }
@@ -488,8 +488,13 @@ static lol_html_rewriter_directive_t iterate_element_attributes(
     str_eq(&name, "bar");
     str_eq(&value, "1337");
 
+    lol_html_str_t raw_value = lol_html_attribute_raw_value_get(attr);
+
+    str_eq(&raw_value, "13&#51;7");
+
     lol_html_str_free(name);
     lol_html_str_free(value);
+    lol_html_str_free(raw_value);
 
     attr = lol_html_attributes_iterator_next(iter);
 
@@ -516,7 +521,7 @@ static void test_iterate_attributes(lol_html_selector_t *selector, void *user_da
 
     ok(!err);
 
-    run_rewriter(builder, "<div foo=42 bar='1337'>", output_sink_stub, user_data);
+    run_rewriter(builder, "<div foo=42 bar='13&#51;7'>", output_sink_stub, user_data);
 }
 
 //-------------------------------------------------------------------------
@@ -617,6 +622,82 @@ static void test_stop(lol_html_selector_t *selector, void *user_data) {
     ok(!err);
 }
 
+//-------------------------------------------------------------------------
+EXPECT_OUTPUT(
+    modify_end_tag_output_sink,
+    "<div>Hi!</span><hr>",
+    &EXPECTED_USER_DATA,
+    sizeof(EXPECTED_USER_DATA)
+);
+
+static lol_html_rewriter_directive_t modify_end_tag(
+    lol_html_end_tag_t *end_tag,
+    void *user_data
+) {
+    const char *new_name = "span";
+    const char *before = "!";
+    const char *after = "<hr>";
+
+    note("End tag handler user data");
+    ok(*(int*)user_data == EXPECTED_USER_DATA);
+
+    note("Get end tag name");
+    lol_html_str_t name = lol_html_end_tag_name_get(end_tag);
+
+    str_eq(&name, "div");
+
+    lol_html_str_free(name);
+
+    note("Set invalid end tag name");
+    ok(lol_html_end_tag_name_set(end_tag, "", 0) == -1);
+
+    lol_html_str_t *msg = lol_html_take_last_error();
+
+    str_eq(msg, "Tag name can't be empty.");
+
+    lol_html_str_free(*msg);
+
+    note("Set end tag name");
+    ok(!lol_html_end_tag_name_set(end_tag, new_name, strlen(new_name)));
+
+    note("Insert before/after end tag");
+    ok(!lol_html_end_tag_before(end_tag, before, strlen(before), false));
+    ok(!lol_html_end_tag_after(end_tag, after, strlen(after), true));
+
+    ok(!lol_html_end_tag_is_removed(end_tag));
+
+    return LOL_HTML_CONTINUE;
+}
+
+static lol_html_rewriter_directive_t add_end_tag_handler(
+    lol_html_element_t *element,
+    void *user_data
+) {
+    note("Set end tag handler");
+    lol_html_element_on_end_tag(element, &modify_end_tag, user_data);
+
+    return LOL_HTML_CONTINUE;
+}
+
+static void test_modify_end_tag(lol_html_selector_t *selector, void *user_data) {
+    lol_html_rewriter_builder_t *builder = lol_html_rewriter_builder_new();
+
+    int err = lol_html_rewriter_builder_add_element_content_handlers(
+        builder,
+        selector,
+        &add_end_tag_handler,
+        user_data,
+        NULL,
+        NULL,
+        NULL,
+        NULL
+    );
+
+    ok(!err);
+
+    run_rewriter(builder, "<div>Hi</div>", modify_end_tag_output_sink, user_data);
+}
+
 void element_api_test() {
     int user_data = 43;
 
@@ -659,6 +740,7 @@ void element_api_test() {
         );
 
         test_replace_element(selector, &user_data);
+        test_modify_end_tag(selector, &user_data);
 
         lol_html_selector_free(selector);
     }
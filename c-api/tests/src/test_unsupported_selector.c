@@ -5,15 +5,52 @@
 #include "tests.h"
 #include "test_util.h"
 
+static lol_html_rewriter_t *build_rewriter(
+    const lol_html_selector_t *selector,
+    lol_html_lookahead_mode_t lookahead_mode
+) {
+    lol_html_rewriter_builder_t *builder = lol_html_rewriter_builder_new();
+
+    lol_html_rewriter_builder_add_element_content_handlers(
+        builder,
+        selector,
+        &get_and_free_empty_element_attribute,
+        NULL,
+        NULL,
+        NULL,
+        NULL,
+        NULL
+    );
+
+    lol_html_rewriter_builder_set_lookahead_mode(builder, lookahead_mode);
+
+    return create_rewriter(builder, output_sink_stub, NULL, MAX_MEMORY);
+}
+
 void test_unsupported_selector() {
     const char *selector_str = "p:last-child";
     lol_html_selector_t *selector = lol_html_selector_parse(selector_str, strlen(selector_str));
 
-    ok(selector == NULL);
+    ok(selector != NULL);
+
+    lol_html_rewriter_t *rewriter = build_rewriter(selector, LOL_HTML_LOOKAHEAD_DISABLED);
+
+    ok(rewriter == NULL);
 
     lol_html_str_t *msg = lol_html_take_last_error();
 
-    str_eq(msg, "Unsupported pseudo-class or pseudo-element in selector.");
+    str_eq(
+        msg,
+        "Selectors with pseudo-classes that depend on the following content require "
+        "the lookahead to be enabled with the `lookahead_mode` setting."
+    );
 
     lol_html_str_free(*msg);
+
+    rewriter = build_rewriter(selector, LOL_HTML_LOOKAHEAD_FAIL_ON_MEMORY_LIMIT);
+
+    ok(rewriter != NULL);
+
+    lol_html_rewriter_free(rewriter);
+    lol_html_selector_free(selector);
 }
@@ -27,18 +27,7 @@ fn main() {
     )
     .unwrap();
 
-    // Feed chunks from the stdin to the rewriter
-    loop {
-        let mut stdin = stdin.lock();
-        let buffer = stdin.fill_buf().unwrap();
-        let len = buffer.len();
-
-        if len > 0 {
-            rewriter.write(buffer).unwrap();
-            stdin.consume(len);
-        } else {
-            rewriter.end().unwrap();
-            break;
-        }
-    }
+    // Feed the stdin to the rewriter
+    io::copy(&mut stdin.lock(), &mut rewriter).unwrap();
+    rewriter.end().unwrap();
 }
@@ -15,7 +15,8 @@ use std::ffi::{CStr, CString};
 use encoding_rs::*;
 use lol_html::html_content::ContentType;
 use lol_html::{
-    comments, doc_comments, doc_text, element, text, HtmlRewriter, MemorySettings, Settings,
+    comments, doc_comments, doc_text, element, text, HtmlRewriter, LookaheadMode, MemorySettings,
+    Settings,
 };
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
@@ -59,7 +60,7 @@ static ASCII_COMPATIBLE_ENCODINGS: [&Encoding; 36] = [
     X_USER_DEFINED,
 ];
 
-static SUPPORTED_SELECTORS: [&str; 16] = [
+static SUPPORTED_SELECTORS: [&str; 20] = [
     "*",
     "p",
     "p:not(.firstline)",
@@ -76,6 +77,10 @@ static SUPPORTED_SELECTORS: [&str; 16] = [
     "p[foo|=\"bar\"]",
     "p a",
     "p > a",
+    "p:last-child",
+    "p:empty",
+    "p:has(> a)",
+    ":is(p, div) > a",
 ];
 
 extern "C" fn empty_handler(_foo: *const c_char, _size: size_t, _boo: *mut c_void) -> () {}
@@ -179,6 +184,10 @@ fn run_rewriter_iter(data: &[u8], selector: &str, encoding: &str) -> () {
             encoding,
             memory_settings: MemorySettings::default(),
             strict: false,
+            lookahead_mode: LookaheadMode::FailOnMemoryLimit,
+            namespace_prefixes: vec![],
+            track_ancestors: false,
+            retained_ancestor_attributes: vec![],
         },
         |_: &[u8]| {},
     )
@@ -5,6 +5,7 @@ use lol_html::html_content::Doctype as NativeDoctype;
 pub struct Doctype(NativeRefWrap<NativeDoctype<'static>>);
 
 impl_from_native!(NativeDoctype --> Doctype);
+impl_mutations!(Doctype);
 
 #[wasm_bindgen]
 impl Doctype {
@@ -22,4 +23,25 @@ impl Doctype {
     pub fn system_id(&self) -> JsResult<Option<String>> {
         self.0.get().map(|d| d.system_id())
     }
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_name(&mut self, name: &str) -> JsResult<()> {
+        self.0.get_mut()?.set_name(name).into_js_result()
+    }
+
+    #[wasm_bindgen(method, setter=publicId)]
+    pub fn set_public_id(&mut self, id: Option<String>) -> JsResult<()> {
+        self.0
+            .get_mut()?
+            .set_public_id(id.as_deref())
+            .into_js_result()
+    }
+
+    #[wasm_bindgen(method, setter=systemId)]
+    pub fn set_system_id(&mut self, id: Option<String>) -> JsResult<()> {
+        self.0
+            .get_mut()?
+            .set_system_id(id.as_deref())
+            .into_js_result()
+    }
 }
@@ -1,5 +1,9 @@
+use super::end_tag::EndTag;
 use super::*;
-use lol_html::html_content::{Attribute as NativeAttribute, Element as NativeElement};
+use js_sys::Function as JsFunction;
+use lol_html::html_content::{
+    Attribute as NativeAttribute, Element as NativeElement, EndTag as NativeEndTag,
+};
 use serde::Serialize;
 use serde_wasm_bindgen::to_value as to_js_value;
 
@@ -112,4 +116,19 @@ impl Element {
     pub fn remove_and_keep_content(&mut self) -> Result<(), JsValue> {
         self.0.get_mut().map(|e| e.remove_and_keep_content())
     }
+
+    #[wasm_bindgen(method, js_name=onEndTag)]
+    pub fn on_end_tag(&mut self, handler: JsFunction) -> Result<(), JsValue> {
+        self.0.get_mut().map(|e| {
+            e.on_end_tag(move |end_tag: &mut NativeEndTag| {
+                let this = JsValue::NULL;
+                let (end_tag, _anchor) = EndTag::from_native(end_tag);
+
+                handler
+                    .call1(&this, &JsValue::from(end_tag))
+                    .map(|_| ())
+                    .map_err(|_| "The end tag handler has thrown an exception.".into())
+            })
+        })
+    }
 }
@@ -0,0 +1,21 @@
+use super::*;
+use lol_html::html_content::EndTag as NativeEndTag;
+
+#[wasm_bindgen]
+pub struct EndTag(NativeRefWrap<NativeEndTag<'static>>);
+
+impl_from_native!(NativeEndTag --> EndTag);
+impl_mutations!(EndTag);
+
+#[wasm_bindgen]
+impl EndTag {
+    #[wasm_bindgen(method, getter)]
+    pub fn name(&self) -> JsResult<String> {
+        self.0.get().map(|t| t.name())
+    }
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_name(&mut self, name: &str) -> JsResult<()> {
+        self.0.get_mut()?.set_name(name).into_js_result()
+    }
+}
@@ -2,7 +2,7 @@ use super::*;
 use js_sys::{Function as JsFunction, Uint8Array};
 use lol_html::{
     DocumentContentHandlers, ElementContentHandlers, HtmlRewriter as NativeHTMLRewriter,
-    OutputSink, Selector,
+    LookaheadMode, OutputSink, Selector,
 };
 
 struct JsOutputSink(JsFunction);
@@ -24,6 +24,27 @@ impl OutputSink for JsOutputSink {
     }
 }
 
+#[wasm_bindgen]
+extern "C" {
+    pub type RewriterSettings;
+
+    #[wasm_bindgen(method, getter, js_name = lookaheadMode)]
+    fn lookahead_mode(this: &RewriterSettings) -> Option<String>;
+}
+
+impl IntoNative<JsResult<LookaheadMode>> for Option<RewriterSettings> {
+    fn into_native(self) -> JsResult<LookaheadMode> {
+        let mode = self.and_then(|settings| settings.lookahead_mode());
+
+        match mode.as_deref() {
+            None | Some("disabled") => Ok(LookaheadMode::Disabled),
+            Some("failOnMemoryLimit") => Ok(LookaheadMode::FailOnMemoryLimit),
+            Some("passThroughOnMemoryLimit") => Ok(LookaheadMode::PassThroughOnMemoryLimit),
+            Some(mode) => Err(JsValue::from(format!("Unknown lookahead mode: {}.", mode))),
+        }
+    }
+}
+
 #[wasm_bindgen]
 pub struct HTMLRewriterBuilder {
     element_content_handlers: (Selector, ElementContentHandlers<'static>),
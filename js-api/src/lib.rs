@@ -171,5 +171,6 @@ mod comment;
 mod doctype;
 mod document_end;
 mod element;
+mod end_tag;
 mod html_rewriter;
 mod text_chunk;
@@ -3,8 +3,8 @@
 set -e
 
 echo "===  Running library tests... ==="
-cargo clippy --features=integration_test --all-targets
-cargo test --features=integration_test "$@"
+cargo clippy --features=integration_test,async --all-targets
+cargo test --features=integration_test,async "$@"
 
 echo "=== Running C API tests... ==="
 prove -e 'cargo' run ::  --manifest-path=./c-api/tests/Cargo.toml
@@ -1,6 +1,6 @@
 use super::Range;
 use encoding_rs::{Encoding, WINDOWS_1252};
-use memchr::{memchr, memchr3};
+use memchr::memchr3;
 use std::borrow::Cow;
 use std::fmt::{self, Debug};
 use std::ops::Deref;
@@ -96,21 +96,6 @@ macro_rules! impl_replace_byte {
 }
 
 impl<'b> Bytes<'b> {
-    #[inline]
-    pub fn replace_byte(&self, (needle, repl): (u8, &[u8]), output_handler: &mut dyn FnMut(&[u8])) {
-        macro_rules! impls {
-            (@find $tail:ident) => {
-                memchr(needle, $tail)
-            };
-
-            (@get_replacement $tail:ident, $pos:ident) => {
-                repl
-            };
-        }
-
-        impl_replace_byte!(self, output_handler, impls);
-    }
-
     #[inline]
     pub fn replace_byte3(
         &self,
@@ -0,0 +1,200 @@
+mod named;
+
+use self::named::NAMED_CHAR_REFS;
+use std::borrow::Cow;
+
+// NOTE: code points 0x80 - 0x9F are replaced with the characters they represent in
+// windows-1252 when used in numeric character references.
+// See: https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state
+static C1_REPLACEMENTS: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+/// Resolves a numeric character reference with the given code point.
+fn numeric_char_ref_value(code_point: u32) -> char {
+    match code_point {
+        0 => '\u{FFFD}',
+        0x80..=0x9F => C1_REPLACEMENTS[(code_point - 0x80) as usize],
+        // NOTE: surrogates and code points outside of the Unicode range are
+        // rejected by `char::from_u32`.
+        _ => std::char::from_u32(code_point).unwrap_or('\u{FFFD}'),
+    }
+}
+
+/// Decodes a numeric character reference at the start of `input` (right after `&#`).
+///
+/// Returns the referenced character and the length of the consumed input.
+fn decode_numeric(input: &str) -> Option<(char, usize)> {
+    let bytes = input.as_bytes();
+
+    let (radix, digits_start) = match bytes.first() {
+        Some(b'x') | Some(b'X') => (16, 1),
+        _ => (10, 0),
+    };
+
+    let digits_len = bytes[digits_start..]
+        .iter()
+        .take_while(|&&b| (b as char).is_digit(radix))
+        .count();
+
+    if digits_len == 0 {
+        return None;
+    }
+
+    let digits_end = digits_start + digits_len;
+
+    // NOTE: the value saturates, so overflows are replaced with U+FFFD as well.
+    let code_point = input[digits_start..digits_end]
+        .chars()
+        .fold(0u32, |value, ch| {
+            value
+                .saturating_mul(radix)
+                .saturating_add(ch.to_digit(radix).unwrap_or(0))
+        });
+
+    let len = if bytes.get(digits_end) == Some(&b';') {
+        digits_end + 1
+    } else {
+        digits_end
+    };
+
+    Some((numeric_char_ref_value(code_point), len))
+}
+
+/// Decodes a named character reference at the start of `input` (right after `&`) using
+/// the longest matching name.
+///
+/// Returns the referenced characters and the length of the consumed input.
+fn decode_named(input: &str, in_attribute: bool) -> Option<(&'static str, usize)> {
+    let bytes = input.as_bytes();
+    let alnum_len = bytes
+        .iter()
+        .take_while(|b| b.is_ascii_alphanumeric())
+        .count();
+
+    let lookup = |name: &str| {
+        NAMED_CHAR_REFS
+            .binary_search_by(|(n, _)| n.cmp(&name))
+            .ok()
+            .map(|idx| NAMED_CHAR_REFS[idx].1)
+    };
+
+    if bytes.get(alnum_len) == Some(&b';') {
+        if let Some(chars) = lookup(&input[..=alnum_len]) {
+            return Some((chars, alnum_len + 1));
+        }
+    }
+
+    // NOTE: only the legacy references can be used without the trailing semicolon,
+    // and they can be a prefix of a longer alphanumeric sequence (e.g. `&notit;`).
+    (1..=alnum_len).rev().find_map(|len| {
+        lookup(&input[..len]).and_then(|chars| {
+            // NOTE: for historical reasons, references without a semicolon in attribute
+            // values are not decoded if they are followed by `=` or an alphanumeric
+            // character, e.g. `?foo=1&copy=2` in URLs.
+            let next = bytes.get(len);
+
+            if in_attribute && matches!(next, Some(b) if *b == b'=' || b.is_ascii_alphanumeric()) {
+                None
+            } else {
+                Some((chars, len))
+            }
+        })
+    })
+}
+
+/// Decodes [character references] in `text`.
+///
+/// If `in_attribute` is `true`, then the text is considered to be an attribute value,
+/// which affects handling of the named references without the trailing semicolon.
+///
+/// [character references]: https://html.spec.whatwg.org/multipage/syntax.html#character-references
+pub fn decode_char_refs(text: &str, in_attribute: bool) -> Cow<'_, str> {
+    let mut amp_positions = text.match_indices('&').map(|(pos, _)| pos).peekable();
+
+    if amp_positions.peek().is_none() {
+        return Cow::Borrowed(text);
+    }
+
+    let mut decoded = String::with_capacity(text.len());
+    let mut consumed = 0;
+
+    for pos in amp_positions {
+        if pos < consumed {
+            continue;
+        }
+
+        let rest = &text[pos + 1..];
+
+        let reference = if let Some(numeric) = rest.strip_prefix('#') {
+            decode_numeric(numeric).map(|(ch, len)| (Cow::Owned(ch.to_string()), len + 1))
+        } else {
+            decode_named(rest, in_attribute).map(|(chars, len)| (Cow::Borrowed(chars), len))
+        };
+
+        if let Some((chars, len)) = reference {
+            decoded.push_str(&text[consumed..pos]);
+            decoded.push_str(&chars);
+            consumed = pos + 1 + len;
+        }
+    }
+
+    decoded.push_str(&text[consumed..]);
+
+    Cow::Owned(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_char_refs_table_is_sorted() {
+        assert!(NAMED_CHAR_REFS.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn decode() {
+        let tests = [
+            ("foo bar", "foo bar"),
+            ("&amp;&lt;&gt;&quot;&#x27;", "&<>\"'"),
+            ("&AMP &copy2 &notit; &notin;", "& ©2 ¬it; ∉"),
+            ("&#65;&#x42;&#X43;&#68", "ABCD"),
+            (
+                "&#0; &#xD800; &#x110000; &#99999999999;",
+                "\u{FFFD} \u{FFFD} \u{FFFD} \u{FFFD}",
+            ),
+            ("&#x80;&#x9F;", "€Ÿ"),
+            ("&; &# &#x; &foo; & amp;", "&; &# &#x; &foo; & amp;"),
+            ("&CounterClockwiseContourIntegral;", "∳"),
+            ("&NotEqualTilde;", "≂\u{338}"),
+        ];
+
+        for (input, expected) in tests.iter() {
+            assert_eq!(
+                decode_char_refs(input, false),
+                *expected,
+                "Input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn decode_in_attribute() {
+        let tests = [
+            ("?a=1&copy=2", "?a=1&copy=2"),
+            ("?a=1&copyx", "?a=1&copyx"),
+            ("?a=1&copy;=2", "?a=1©=2"),
+            ("&copy-2", "©-2"),
+            ("&amp;amp", "&amp"),
+        ];
+
+        for (input, expected) in tests.iter() {
+            assert_eq!(decode_char_refs(input, true), *expected, "Input: {}", input);
+        }
+    }
+}
@@ -70,6 +70,30 @@ impl LocalNameHash {
             };
         }
     }
+
+    /// Decodes the lowercased tag name from the hash.
+    pub fn to_lowercase_string(self) -> Option<String> {
+        let mut h = self.0?;
+        let mut chars = Vec::new();
+
+        // NOTE: tag names start with an ASCII alpha, so the zero high bits of
+        // the hash never encode leading `1` digits.
+        while h != 0 {
+            let code = (h & 0x1F) as u8;
+
+            chars.push(if code < 6 {
+                b'1' + code
+            } else {
+                b'a' + code - 6
+            });
+
+            h >>= 5;
+        }
+
+        chars.reverse();
+
+        String::from_utf8(chars).ok()
+    }
 }
 
 impl From<&str> for LocalNameHash {
@@ -137,6 +161,16 @@ impl<'i> LocalName<'i> {
     }
 }
 
+impl LocalName<'_> {
+    #[inline]
+    pub fn as_lowercase_string(&self, encoding: &'static Encoding) -> String {
+        match self {
+            LocalName::Hash(h) => h.to_lowercase_string().unwrap_or_default(),
+            LocalName::Bytes(b) => b.as_lowercase_string(encoding),
+        }
+    }
+}
+
 impl PartialEq<Tag> for LocalName<'_> {
     #[inline]
     fn eq(&self, tag: &Tag) -> bool {
@@ -169,6 +203,20 @@ mod tests {
         assert_eq!(LocalNameHash::from("div"), LocalNameHash(Some(9691u64)));
     }
 
+    #[test]
+    fn to_lowercase_string() {
+        ["div", "h1", "h6", "blockquote", "a", "b1a6"]
+            .iter()
+            .for_each(|&name| {
+                assert_eq!(
+                    LocalNameHash::from(&*name.to_ascii_uppercase()).to_lowercase_string(),
+                    Some(name.to_owned())
+                );
+            });
+
+        assert_eq!(LocalNameHash::from("div@&").to_lowercase_string(), None);
+    }
+
     #[test]
     fn hash_invalidation_for_non_ascii_chars() {
         assert!(LocalNameHash::from("div@&").is_empty());
@@ -1,10 +1,12 @@
 #[macro_use]
 mod tag;
 
+mod char_refs;
 mod local_name;
 mod namespace;
 mod text_type;
 
+pub use self::char_refs::decode_char_refs;
 pub use self::local_name::{LocalName, LocalNameHash};
 pub use self::namespace::Namespace;
 pub use self::tag::*;
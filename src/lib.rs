@@ -34,27 +34,38 @@ mod transform_stream;
 use cfg_if::cfg_if;
 
 pub use self::rewriter::{
-    rewrite_str, DocumentContentHandlers, ElementContentHandlers, HtmlRewriter, MemorySettings,
-    RewriteStrSettings, Settings,
+    rewrite_str, ContentHandlerTypes, DocumentContentHandlers, DocumentContentHandlersFactory,
+    ElementContentHandlers, ElementContentHandlersFactory, HandlerTypes, HtmlRewriter, IntoHandler,
+    LocalHandlerTypes, LookaheadMode, MemorySettings, RewriteStrSettings, RewriterTemplate,
+    RewritingReader, SendHandlerTypes, Settings, TemplateSettings, TextReplacer,
 };
-pub use self::selectors_vm::Selector;
+#[cfg(feature = "async")]
+pub use self::rewriter::{AsyncRewritingReader, RewritingStream};
+pub use self::selectors_vm::{CustomPseudoClasses, PseudoClassElement, Selector};
 pub use self::transform_stream::OutputSink;
 
 /// The errors that can be produced by the crate's API.
 pub mod errors {
     pub use super::memory::MemoryLimitExceededError;
     pub use super::parser::ParsingAmbiguityError;
-    pub use super::rewritable_units::{AttributeNameError, CommentTextError, TagNameError};
-    pub use super::rewriter::{EncodingError, RewritingError};
+    pub use super::rewritable_units::{
+        AttributeNameError, CommentTextError, DoctypeError, TagNameError,
+    };
+    pub use super::rewriter::{
+        EncodingError, HandlersKindMismatchError, RewritingError, SettingsError,
+    };
     pub use super::selectors_vm::SelectorError;
 }
 
 /// HTML content descriptors that can be produced and modified by a rewriter.
 pub mod html_content {
     pub use super::rewritable_units::{
-        Attribute, Comment, ContentType, Doctype, DocumentEnd, Element, TextChunk, UserData,
+        Attribute, Comment, ContentType, Doctype, DocumentEnd, Element, EndTag, SourceLocation,
+        TextChunk, UserData,
     };
 
+    pub use super::selectors_vm::{Ancestor, Ancestors};
+
     pub use super::html::TextType;
 }
 
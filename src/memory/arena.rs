@@ -11,7 +11,7 @@ pub struct Arena {
 
 impl Arena {
     pub fn new(limiter: SharedMemoryLimiter, preallocated_size: usize) -> Self {
-        limiter.borrow_mut().preallocate(preallocated_size);
+        limiter.preallocate(preallocated_size);
 
         Arena {
             limiter,
@@ -28,7 +28,7 @@ impl Arena {
 
             // NOTE: approximate usage, as `Vec::reserve_exact` doesn't
             // give guarantees about exact capacity value :).
-            self.limiter.borrow_mut().increase_usage(additional)?;
+            self.limiter.increase_usage(additional)?;
 
             // NOTE: with wicely choosen preallocated size this branch should be
             // executed quite rarely. We can't afford to use double capacity
@@ -50,10 +50,22 @@ impl Arena {
     pub fn shift(&mut self, byte_count: usize) {
         let remainder_len = self.data.len() - byte_count;
 
-        copy_over(&mut self.data, byte_count, 0, remainder_len);
+        if remainder_len > 0 {
+            copy_over(&mut self.data, byte_count, 0, remainder_len);
+        }
+
         self.data.truncate(remainder_len);
     }
 
+    /// Gives the memory that is not occupied by the data back to the limiter.
+    pub fn shrink_to_fit(&mut self) {
+        let capacity = self.data.capacity();
+
+        self.data.shrink_to_fit();
+
+        self.limiter.decrease_usage(capacity - self.data.capacity());
+    }
+
     pub fn bytes(&self) -> &[u8] {
         &self.data
     }
@@ -63,24 +75,24 @@ impl Arena {
 mod tests {
     use super::super::limiter::MemoryLimiter;
     use super::*;
-    use std::rc::Rc;
+    use std::sync::Arc;
 
     #[test]
     fn append() {
         let limiter = MemoryLimiter::new_shared(10);
-        let mut arena = Arena::new(Rc::clone(&limiter), 2);
+        let mut arena = Arena::new(Arc::clone(&limiter), 2);
 
         arena.append(&[1, 2]).unwrap();
         assert_eq!(arena.bytes(), &[1, 2]);
-        assert_eq!(limiter.borrow().current_usage(), 2);
+        assert_eq!(limiter.current_usage(), 2);
 
         arena.append(&[3, 4]).unwrap();
         assert_eq!(arena.bytes(), &[1, 2, 3, 4]);
-        assert_eq!(limiter.borrow().current_usage(), 4);
+        assert_eq!(limiter.current_usage(), 4);
 
         arena.append(&[5, 6, 7, 8, 9, 10]).unwrap();
         assert_eq!(arena.bytes(), &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
-        assert_eq!(limiter.borrow().current_usage(), 10);
+        assert_eq!(limiter.current_usage(), 10);
 
         let err = arena.append(&[11]).unwrap_err();
 
@@ -90,23 +102,23 @@ mod tests {
     #[test]
     fn init_with() {
         let limiter = MemoryLimiter::new_shared(5);
-        let mut arena = Arena::new(Rc::clone(&limiter), 0);
+        let mut arena = Arena::new(Arc::clone(&limiter), 0);
 
         arena.init_with(&[1]).unwrap();
         assert_eq!(arena.bytes(), &[1]);
-        assert_eq!(limiter.borrow().current_usage(), 1);
+        assert_eq!(limiter.current_usage(), 1);
 
         arena.append(&[1, 2]).unwrap();
         assert_eq!(arena.bytes(), &[1, 1, 2]);
-        assert_eq!(limiter.borrow().current_usage(), 3);
+        assert_eq!(limiter.current_usage(), 3);
 
         arena.init_with(&[1, 2, 3]).unwrap();
         assert_eq!(arena.bytes(), &[1, 2, 3]);
-        assert_eq!(limiter.borrow().current_usage(), 3);
+        assert_eq!(limiter.current_usage(), 3);
 
         arena.init_with(&[]).unwrap();
         assert_eq!(arena.bytes(), &[]);
-        assert_eq!(limiter.borrow().current_usage(), 3);
+        assert_eq!(limiter.current_usage(), 3);
 
         let err = arena.init_with(&[1, 2, 3, 4, 5, 6, 7]).unwrap_err();
 
@@ -116,24 +128,43 @@ mod tests {
     #[test]
     fn shift() {
         let limiter = MemoryLimiter::new_shared(10);
-        let mut arena = Arena::new(Rc::clone(&limiter), 0);
+        let mut arena = Arena::new(Arc::clone(&limiter), 0);
 
         arena.append(&[0, 1, 2, 3]).unwrap();
         arena.shift(2);
         assert_eq!(arena.bytes(), &[2, 3]);
-        assert_eq!(limiter.borrow().current_usage(), 4);
+        assert_eq!(limiter.current_usage(), 4);
 
         arena.append(&[0, 1]).unwrap();
         assert_eq!(arena.bytes(), &[2, 3, 0, 1]);
-        assert_eq!(limiter.borrow().current_usage(), 4);
+        assert_eq!(limiter.current_usage(), 4);
 
         arena.shift(3);
         assert_eq!(arena.bytes(), &[1]);
-        assert_eq!(limiter.borrow().current_usage(), 4);
+        assert_eq!(limiter.current_usage(), 4);
 
         arena.append(&[2, 3, 4, 5]).unwrap();
         arena.shift(1);
         assert_eq!(arena.bytes(), &[2, 3, 4, 5]);
-        assert_eq!(limiter.borrow().current_usage(), 5);
+        assert_eq!(limiter.current_usage(), 5);
+
+        arena.shift(4);
+        assert_eq!(arena.bytes(), &[]);
+        assert_eq!(limiter.current_usage(), 5);
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let limiter = MemoryLimiter::new_shared(10);
+        let mut arena = Arena::new(Arc::clone(&limiter), 0);
+
+        arena.append(&[0, 1, 2, 3, 4, 5]).unwrap();
+        arena.shift(4);
+        arena.shrink_to_fit();
+        assert_eq!(arena.bytes(), &[4, 5]);
+        assert_eq!(limiter.current_usage(), 2);
+
+        arena.append(&[6, 7, 8, 9, 10, 11, 12, 13]).unwrap();
+        assert_eq!(limiter.current_usage(), 10);
     }
 }
@@ -2,6 +2,7 @@
 
 use std::mem::size_of;
 use std::ops::{Deref, Index, RangeBounds};
+use std::slice::IterMut;
 use std::vec::Drain;
 
 use super::{MemoryLimitExceededError, SharedMemoryLimiter};
@@ -21,7 +22,7 @@ impl<T> LimitedVec<T> {
     }
 
     pub fn push(&mut self, element: T) -> Result<(), MemoryLimitExceededError> {
-        self.limiter.borrow_mut().increase_usage(size_of::<T>())?;
+        self.limiter.increase_usage(size_of::<T>())?;
         self.vec.push(element);
         Ok(())
     }
@@ -44,6 +45,27 @@ impl<T> LimitedVec<T> {
         self.vec.last_mut()
     }
 
+    /// Returns an iterator that allows modifying each element.
+    #[inline]
+    pub fn iter_mut(&mut self) -> IterMut<T> {
+        self.vec.iter_mut()
+    }
+
+    /// Retains only the elements specified by the predicate.
+    pub fn retain(&mut self, f: impl FnMut(&T) -> bool) {
+        let len = self.vec.len();
+
+        self.vec.retain(f);
+        self.limiter
+            .decrease_usage(size_of::<T>() * (len - self.vec.len()));
+    }
+
+    /// Clears the vector, keeping its allocated capacity.
+    pub fn clear(&mut self) {
+        self.limiter.decrease_usage(size_of::<T>() * self.vec.len());
+        self.vec.clear();
+    }
+
     /// Creates a draining iterator that removes the specified range in the
     /// vector and yields the removed items.
     pub fn drain<R>(&mut self, range: R) -> Drain<T>
@@ -64,9 +86,7 @@ impl<T> LimitedVec<T> {
             Unbounded => self.len(),
         };
 
-        self.limiter
-            .borrow_mut()
-            .decrease_usage(size_of::<T>() * (end - start));
+        self.limiter.decrease_usage(size_of::<T>() * (end - start));
 
         self.vec.drain(range)
     }
@@ -91,9 +111,7 @@ impl<T> Index<usize> for LimitedVec<T> {
 
 impl<T> Drop for LimitedVec<T> {
     fn drop(&mut self) {
-        self.limiter
-            .borrow_mut()
-            .decrease_usage(size_of::<T>() * self.vec.len());
+        self.limiter.decrease_usage(size_of::<T>() * self.vec.len());
     }
 }
 
@@ -101,33 +119,33 @@ impl<T> Drop for LimitedVec<T> {
 mod tests {
     use super::super::MemoryLimiter;
     use super::*;
-    use std::rc::Rc;
+    use std::sync::Arc;
 
     #[test]
     fn current_usage() {
         {
             let limiter = MemoryLimiter::new_shared(10);
-            let mut vec_u8: LimitedVec<u8> = LimitedVec::new(Rc::clone(&limiter));
+            let mut vec_u8: LimitedVec<u8> = LimitedVec::new(Arc::clone(&limiter));
 
             vec_u8.push(1).unwrap();
             vec_u8.push(2).unwrap();
-            assert_eq!(limiter.borrow().current_usage(), 2);
+            assert_eq!(limiter.current_usage(), 2);
         }
 
         {
             let limiter = MemoryLimiter::new_shared(10);
-            let mut vec_u32: LimitedVec<u32> = LimitedVec::new(Rc::clone(&limiter));
+            let mut vec_u32: LimitedVec<u32> = LimitedVec::new(Arc::clone(&limiter));
 
             vec_u32.push(1).unwrap();
             vec_u32.push(2).unwrap();
-            assert_eq!(limiter.borrow().current_usage(), 8);
+            assert_eq!(limiter.current_usage(), 8);
         }
     }
 
     #[test]
     fn max_limit() {
         let limiter = MemoryLimiter::new_shared(2);
-        let mut vector: LimitedVec<u8> = LimitedVec::new(Rc::clone(&limiter));
+        let mut vector: LimitedVec<u8> = LimitedVec::new(Arc::clone(&limiter));
 
         vector.push(1).unwrap();
         vector.push(2).unwrap();
@@ -142,35 +160,50 @@ mod tests {
         let limiter = MemoryLimiter::new_shared(1);
 
         {
-            let mut vector: LimitedVec<u8> = LimitedVec::new(Rc::clone(&limiter));
+            let mut vector: LimitedVec<u8> = LimitedVec::new(Arc::clone(&limiter));
 
             vector.push(1).unwrap();
-            assert_eq!(limiter.borrow().current_usage(), 1);
+            assert_eq!(limiter.current_usage(), 1);
         }
 
-        assert_eq!(limiter.borrow().current_usage(), 0);
+        assert_eq!(limiter.current_usage(), 0);
     }
 
     #[test]
     fn drain() {
         let limiter = MemoryLimiter::new_shared(10);
-        let mut vector: LimitedVec<u8> = LimitedVec::new(Rc::clone(&limiter));
+        let mut vector: LimitedVec<u8> = LimitedVec::new(Arc::clone(&limiter));
 
         vector.push(1).unwrap();
         vector.push(2).unwrap();
         vector.push(3).unwrap();
-        assert_eq!(limiter.borrow().current_usage(), 3);
+        assert_eq!(limiter.current_usage(), 3);
 
         vector.drain(0..3);
-        assert_eq!(limiter.borrow().current_usage(), 0);
+        assert_eq!(limiter.current_usage(), 0);
 
         vector.push(1).unwrap();
         vector.push(2).unwrap();
         vector.push(3).unwrap();
         vector.push(4).unwrap();
-        assert_eq!(limiter.borrow().current_usage(), 4);
+        assert_eq!(limiter.current_usage(), 4);
 
         vector.drain(1..=2);
-        assert_eq!(limiter.borrow().current_usage(), 2);
+        assert_eq!(limiter.current_usage(), 2);
+    }
+
+    #[test]
+    fn retain() {
+        let limiter = MemoryLimiter::new_shared(10);
+        let mut vector: LimitedVec<u8> = LimitedVec::new(Arc::clone(&limiter));
+
+        vector.push(1).unwrap();
+        vector.push(2).unwrap();
+        vector.push(3).unwrap();
+        assert_eq!(limiter.current_usage(), 3);
+
+        vector.retain(|&n| n != 2);
+        assert_eq!(&*vector, [1, 3]);
+        assert_eq!(limiter.current_usage(), 2);
     }
 }
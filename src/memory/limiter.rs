@@ -1,8 +1,8 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use thiserror::Error;
 
-pub type SharedMemoryLimiter = Rc<RefCell<MemoryLimiter>>;
+pub type SharedMemoryLimiter = Arc<MemoryLimiter>;
 
 /// An error that occures when rewriter exceedes the memory limit specified in the
 /// [`MemorySettings`].
@@ -14,44 +14,52 @@ pub struct MemoryLimitExceededError;
 
 #[derive(Debug)]
 pub struct MemoryLimiter {
-    current_usage: usize,
+    current_usage: AtomicUsize,
     max: usize,
 }
 
 impl MemoryLimiter {
     pub fn new_shared(max: usize) -> SharedMemoryLimiter {
-        Rc::new(RefCell::new(MemoryLimiter {
+        Arc::new(MemoryLimiter {
             max,
-            current_usage: 0,
-        }))
+            current_usage: AtomicUsize::new(0),
+        })
     }
 
     #[cfg(test)]
     pub fn current_usage(&self) -> usize {
-        self.current_usage
+        self.current_usage.load(Ordering::Relaxed)
     }
 
+    // NOTE: the usage is updated with read-modify-write operations, so the limit holds even
+    // if the components that share the limiter update it from different threads. The usage
+    // doesn't synchronize any other memory, so the relaxed ordering is sufficient.
     #[inline]
-    pub fn increase_usage(&mut self, byte_count: usize) -> Result<(), MemoryLimitExceededError> {
-        self.current_usage += byte_count;
-
-        if self.current_usage > self.max {
-            Err(MemoryLimitExceededError)
-        } else {
-            Ok(())
-        }
+    pub fn increase_usage(&self, byte_count: usize) -> Result<(), MemoryLimitExceededError> {
+        // NOTE: don't account the memory that hasn't been allocated,
+        // so the caller can recover from the error if it's able to.
+        self.current_usage
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |usage| {
+                usage
+                    .checked_add(byte_count)
+                    .filter(|&new_usage| new_usage <= self.max)
+            })
+            .map(|_| ())
+            .map_err(|_| MemoryLimitExceededError)
     }
 
     #[inline]
-    pub fn preallocate(&mut self, byte_count: usize) {
+    pub fn preallocate(&self, byte_count: usize) {
         self.increase_usage(byte_count).expect(
             "Total preallocated memory size should be less than `MemorySettings::max_allowed_memory_usage`.",
         );
     }
 
     #[inline]
-    pub fn decrease_usage(&mut self, byte_count: usize) {
-        self.current_usage -= byte_count;
+    pub fn decrease_usage(&self, byte_count: usize) {
+        let usage = self.current_usage.fetch_sub(byte_count, Ordering::Relaxed);
+
+        debug_assert!(usage >= byte_count, "Memory usage underflow.");
     }
 }
 
@@ -62,7 +70,6 @@ mod tests {
     #[test]
     fn current_usage() {
         let limiter = MemoryLimiter::new_shared(10);
-        let mut limiter = limiter.borrow_mut();
 
         assert_eq!(limiter.current_usage(), 0);
 
@@ -78,6 +85,7 @@ mod tests {
         let err = limiter.increase_usage(15).unwrap_err();
 
         assert_eq!(err, MemoryLimitExceededError);
+        assert_eq!(limiter.current_usage(), 4);
     }
 
     #[test]
@@ -86,7 +94,6 @@ mod tests {
     )]
     fn preallocate() {
         let limiter = MemoryLimiter::new_shared(10);
-        let mut limiter = limiter.borrow_mut();
 
         limiter.preallocate(8);
         assert_eq!(limiter.current_usage(), 8);
@@ -1,6 +1,5 @@
 use crate::base::{Align, Range};
 use crate::html::{LocalNameHash, Namespace, TextType};
-use crate::parser::SharedAttributeBuffer;
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct AttributeOutline {
@@ -24,7 +23,7 @@ pub enum TagTokenOutline {
         name: Range,
         name_hash: LocalNameHash,
         ns: Namespace,
-        attributes: SharedAttributeBuffer,
+        attributes: Vec<AttributeOutline>,
         self_closing: bool,
     },
 
@@ -57,7 +56,7 @@ impl Align for TagTokenOutline {
                 name, attributes, ..
             } => {
                 name.align(offset);
-                attributes.borrow_mut().align(offset);
+                attributes.align(offset);
             }
             TagTokenOutline::EndTag { name, .. } => name.align(offset),
         }
@@ -9,15 +9,12 @@ use self::lexer::Lexer;
 use self::state_machine::{ActionError, ParsingTermination, StateMachine};
 use self::tag_scanner::TagScanner;
 use self::tree_builder_simulator::{TreeBuilderFeedback, TreeBuilderSimulator};
-use crate::html::{LocalName, Namespace};
 use crate::rewriter::RewritingError;
 use cfg_if::cfg_if;
-use std::cell::RefCell;
-use std::rc::Rc;
 
 pub use self::lexer::{
     AttributeOutline, Lexeme, LexemeSink, NonTagContentLexeme, NonTagContentTokenOutline,
-    SharedAttributeBuffer, TagLexeme, TagTokenOutline,
+    TagLexeme, TagTokenOutline,
 };
 pub use self::tag_scanner::TagHintSink;
 pub use self::tree_builder_simulator::ParsingAmbiguityError;
@@ -32,42 +29,18 @@ pub enum ParserDirective {
     Lex,
 }
 
-impl<S: LexemeSink> LexemeSink for Rc<RefCell<S>> {
-    #[inline]
-    fn handle_tag(&mut self, lexeme: &TagLexeme) -> Result<ParserDirective, RewritingError> {
-        self.borrow_mut().handle_tag(lexeme)
-    }
-
-    #[inline]
-    fn handle_non_tag_content(
-        &mut self,
-        lexeme: &NonTagContentLexeme,
-    ) -> Result<(), RewritingError> {
-        self.borrow_mut().handle_non_tag_content(lexeme)
-    }
-}
-
-impl<S: TagHintSink> TagHintSink for Rc<RefCell<S>> {
-    #[inline]
-    fn handle_start_tag_hint(
-        &mut self,
-        name: LocalName,
-        ns: Namespace,
-    ) -> Result<ParserDirective, RewritingError> {
-        self.borrow_mut().handle_start_tag_hint(name, ns)
-    }
+pub trait ParserOutputSink: LexemeSink + TagHintSink {}
 
-    #[inline]
-    fn handle_end_tag_hint(&mut self, name: LocalName) -> Result<ParserDirective, RewritingError> {
-        self.borrow_mut().handle_end_tag_hint(name)
-    }
+/// The state that is shared by the lexer and the tag scanner.
+pub struct ParserContext<S> {
+    pub output_sink: S,
+    pub tree_builder_simulator: TreeBuilderSimulator,
 }
 
-pub trait ParserOutputSink: LexemeSink + TagHintSink {}
-
 pub struct Parser<S: ParserOutputSink> {
-    lexer: Lexer<Rc<RefCell<S>>>,
-    tag_scanner: TagScanner<Rc<RefCell<S>>>,
+    lexer: Lexer<S>,
+    tag_scanner: TagScanner<S>,
+    context: ParserContext<S>,
     current_directive: ParserDirective,
 }
 
@@ -84,27 +57,42 @@ macro_rules! with_current_sm {
 }
 
 impl<S: ParserOutputSink> Parser<S> {
-    pub fn new(
-        output_sink: &Rc<RefCell<S>>,
-        initial_directive: ParserDirective,
-        strict: bool,
-    ) -> Self {
-        let tree_builder_simulator = Rc::new(RefCell::new(TreeBuilderSimulator::new(strict)));
-
+    pub fn new(output_sink: S, initial_directive: ParserDirective, strict: bool) -> Self {
         Parser {
-            lexer: Lexer::new(Rc::clone(output_sink), Rc::clone(&tree_builder_simulator)),
-            tag_scanner: TagScanner::new(
-                Rc::clone(output_sink),
-                Rc::clone(&tree_builder_simulator),
-            ),
+            lexer: Lexer::new(),
+            tag_scanner: TagScanner::new(),
+            context: ParserContext {
+                output_sink,
+                tree_builder_simulator: TreeBuilderSimulator::new(strict),
+            },
             current_directive: initial_directive,
         }
     }
 
+    /// Returns the parser to the state of the beginning of a document. The output sink
+    /// is expected to be reset by the caller.
+    pub fn reset(&mut self, initial_directive: ParserDirective) {
+        self.lexer.reset();
+        self.tag_scanner.reset();
+        self.context.tree_builder_simulator.reset();
+        self.current_directive = initial_directive;
+    }
+
+    #[inline]
+    pub fn output_sink(&self) -> &S {
+        &self.context.output_sink
+    }
+
+    #[inline]
+    pub fn output_sink_mut(&mut self) -> &mut S {
+        &mut self.context.output_sink
+    }
+
     pub fn parse(&mut self, input: &[u8], last: bool) -> Result<usize, RewritingError> {
         use ActionError::*;
 
-        let mut parse_result = with_current_sm!(self, sm.run_parsing_loop(input, last));
+        let mut parse_result =
+            with_current_sm!(self, sm.run_parsing_loop(&mut self.context, input, last));
 
         loop {
             match parse_result {
@@ -121,8 +109,10 @@ impl<S: ParserOutputSink> Parser<S> {
 
                     trace!(@continue_from_bookmark sm_bookmark, self.current_directive, input);
 
-                    parse_result =
-                        with_current_sm!(self, sm.continue_from_bookmark(input, last, sm_bookmark));
+                    parse_result = with_current_sm!(
+                        self,
+                        sm.continue_from_bookmark(&mut self.context, input, last, sm_bookmark)
+                    );
                 }
                 Err(ParsingTermination::ActionError(RewritingError(err))) => return Err(err),
                 Ok(unreachable) => match unreachable {},
@@ -72,46 +72,62 @@ pub type StateResult = Result<(), ParsingTermination>;
 pub type ParseResult = Result<Never, ParsingTermination>;
 
 pub trait StateMachineActions {
-    fn emit_eof(&mut self, input: &[u8]) -> ActionResult;
-    fn emit_text(&mut self, input: &[u8]) -> ActionResult;
-    fn emit_current_token(&mut self, input: &[u8]) -> ActionResult;
-    fn emit_tag(&mut self, input: &[u8]) -> ActionResult;
-    fn emit_current_token_and_eof(&mut self, input: &[u8]) -> ActionResult;
-    fn emit_raw_without_token(&mut self, input: &[u8]) -> ActionResult;
-    fn emit_raw_without_token_and_eof(&mut self, input: &[u8]) -> ActionResult;
-
-    fn create_start_tag(&mut self, input: &[u8]);
-    fn create_end_tag(&mut self, input: &[u8]);
-    fn create_doctype(&mut self, input: &[u8]);
-    fn create_comment(&mut self, input: &[u8]);
-
-    fn start_token_part(&mut self, input: &[u8]);
-
-    fn mark_comment_text_end(&mut self, input: &[u8]);
-    fn shift_comment_text_end_by(&mut self, input: &[u8], offset: usize);
-
-    fn set_force_quirks(&mut self, input: &[u8]);
-    fn finish_doctype_name(&mut self, input: &[u8]);
-    fn finish_doctype_public_id(&mut self, input: &[u8]);
-    fn finish_doctype_system_id(&mut self, input: &[u8]);
-
-    fn finish_tag_name(&mut self, input: &[u8]) -> ActionResult;
-    fn update_tag_name_hash(&mut self, input: &[u8]);
-    fn mark_as_self_closing(&mut self, input: &[u8]);
-
-    fn start_attr(&mut self, input: &[u8]);
-    fn finish_attr_name(&mut self, input: &[u8]);
-    fn finish_attr_value(&mut self, input: &[u8]);
-    fn finish_attr(&mut self, input: &[u8]);
-
-    fn set_closing_quote_to_double(&mut self, input: &[u8]);
-    fn set_closing_quote_to_single(&mut self, input: &[u8]);
-
-    fn mark_tag_start(&mut self, input: &[u8]);
-    fn unmark_tag_start(&mut self, input: &[u8]);
-
-    fn enter_cdata(&mut self, input: &[u8]);
-    fn leave_cdata(&mut self, input: &[u8]);
+    type Context;
+
+    fn emit_eof(&mut self, context: &mut Self::Context, input: &[u8]) -> ActionResult;
+    fn emit_text(&mut self, context: &mut Self::Context, input: &[u8]) -> ActionResult;
+    fn emit_current_token(&mut self, context: &mut Self::Context, input: &[u8]) -> ActionResult;
+    fn emit_tag(&mut self, context: &mut Self::Context, input: &[u8]) -> ActionResult;
+    fn emit_current_token_and_eof(
+        &mut self,
+        context: &mut Self::Context,
+        input: &[u8],
+    ) -> ActionResult;
+    fn emit_raw_without_token(&mut self, context: &mut Self::Context, input: &[u8])
+        -> ActionResult;
+    fn emit_raw_without_token_and_eof(
+        &mut self,
+        context: &mut Self::Context,
+        input: &[u8],
+    ) -> ActionResult;
+
+    fn create_start_tag(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn create_end_tag(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn create_doctype(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn create_comment(&mut self, context: &mut Self::Context, input: &[u8]);
+
+    fn start_token_part(&mut self, context: &mut Self::Context, input: &[u8]);
+
+    fn mark_comment_text_end(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn shift_comment_text_end_by(
+        &mut self,
+        context: &mut Self::Context,
+        input: &[u8],
+        offset: usize,
+    );
+
+    fn set_force_quirks(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn finish_doctype_name(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn finish_doctype_public_id(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn finish_doctype_system_id(&mut self, context: &mut Self::Context, input: &[u8]);
+
+    fn finish_tag_name(&mut self, context: &mut Self::Context, input: &[u8]) -> ActionResult;
+    fn update_tag_name_hash(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn mark_as_self_closing(&mut self, context: &mut Self::Context, input: &[u8]);
+
+    fn start_attr(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn finish_attr_name(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn finish_attr_value(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn finish_attr(&mut self, context: &mut Self::Context, input: &[u8]);
+
+    fn set_closing_quote_to_double(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn set_closing_quote_to_single(&mut self, context: &mut Self::Context, input: &[u8]);
+
+    fn mark_tag_start(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn unmark_tag_start(&mut self, context: &mut Self::Context, input: &[u8]);
+
+    fn enter_cdata(&mut self, context: &mut Self::Context, input: &[u8]);
+    fn leave_cdata(&mut self, context: &mut Self::Context, input: &[u8]);
 }
 
 pub trait StateMachineConditions {
@@ -122,8 +138,8 @@ pub trait StateMachineConditions {
 pub trait StateMachine: StateMachineActions + StateMachineConditions {
     define_states!();
 
-    fn state(&self) -> fn(&mut Self, &[u8]) -> StateResult;
-    fn set_state(&mut self, state: fn(&mut Self, &[u8]) -> StateResult);
+    fn state(&self) -> fn(&mut Self, &mut Self::Context, &[u8]) -> StateResult;
+    fn set_state(&mut self, state: fn(&mut Self, &mut Self::Context, &[u8]) -> StateResult);
 
     fn is_state_enter(&self) -> bool;
     fn set_is_state_enter(&mut self, val: bool);
@@ -153,16 +169,22 @@ pub trait StateMachine: StateMachineActions + StateMachineConditions {
     fn is_last_input(&self) -> bool;
     fn set_is_last_input(&mut self, last: bool);
 
-    fn run_parsing_loop(&mut self, input: &[u8], last: bool) -> ParseResult {
+    fn run_parsing_loop(
+        &mut self,
+        context: &mut Self::Context,
+        input: &[u8],
+        last: bool,
+    ) -> ParseResult {
         self.set_is_last_input(last);
 
         loop {
-            self.state()(self, input)?;
+            self.state()(self, context, input)?;
         }
     }
 
     fn continue_from_bookmark(
         &mut self,
+        context: &mut Self::Context,
         input: &[u8],
         last: bool,
         bookmark: StateMachineBookmark,
@@ -173,7 +195,7 @@ pub trait StateMachine: StateMachineActions + StateMachineConditions {
         self.adjust_to_bookmark(bookmark.pos, bookmark.feedback_directive);
         self.set_pos(bookmark.pos);
 
-        self.run_parsing_loop(input, last)
+        self.run_parsing_loop(context, input, last)
     }
 
     #[inline]
@@ -220,7 +242,7 @@ pub trait StateMachine: StateMachineActions + StateMachineConditions {
     }
 
     #[inline]
-    fn switch_state(&mut self, state: fn(&mut Self, &[u8]) -> StateResult) {
+    fn switch_state(&mut self, state: fn(&mut Self, &mut Self::Context, &[u8]) -> StateResult) {
         self.set_state(state);
         self.set_is_state_enter(true);
     }
@@ -232,7 +254,7 @@ pub trait StateMachine: StateMachineActions + StateMachineConditions {
     }
 
     #[inline]
-    fn next_text_parsing_state(&self) -> fn(&mut Self, &[u8]) -> StateResult {
+    fn next_text_parsing_state(&self) -> fn(&mut Self, &mut Self::Context, &[u8]) -> StateResult {
         match self.last_text_type() {
             TextType::Data => Self::data_state,
             TextType::PlainText => Self::plaintext_state,
@@ -291,22 +313,22 @@ macro_rules! impl_common_sm_accessors {
 macro_rules! impl_common_sm_actions {
     () => {
         #[inline]
-        fn set_closing_quote_to_double(&mut self, _input: &[u8]) {
+        fn set_closing_quote_to_double(&mut self, _context: &mut Self::Context, _input: &[u8]) {
             self.closing_quote = b'"';
         }
 
         #[inline]
-        fn set_closing_quote_to_single(&mut self, _input: &[u8]) {
+        fn set_closing_quote_to_single(&mut self, _context: &mut Self::Context, _input: &[u8]) {
             self.closing_quote = b'\'';
         }
 
         #[inline]
-        fn enter_cdata(&mut self, _input: &[u8]) {
+        fn enter_cdata(&mut self, _context: &mut Self::Context, _input: &[u8]) {
             self.set_last_text_type(TextType::CDataSection);
         }
 
         #[inline]
-        fn leave_cdata(&mut self, _input: &[u8]) {
+        fn leave_cdata(&mut self, _context: &mut Self::Context, _input: &[u8]) {
             self.set_last_text_type(TextType::Data);
         }
     };
@@ -376,7 +398,7 @@ macro_rules! noop_action {
     ($($fn_name:ident),*) => {
         $(
             #[inline]
-            fn $fn_name(&mut self, _input: &[u8]) {
+            fn $fn_name(&mut self, _context: &mut Self::Context, _input: &[u8]) {
                 trace!(@noop);
             }
         )*
@@ -387,7 +409,7 @@ macro_rules! noop_action_with_result {
     ($($fn_name:ident),*) => {
         $(
             #[inline]
-            fn $fn_name(&mut self, _input: &[u8]) -> ActionResult {
+            fn $fn_name(&mut self, _context: &mut Self::Context, _input: &[u8]) -> ActionResult {
                 trace!(@noop);
 
                 Ok(())
@@ -6,12 +6,11 @@ use crate::base::{Align, Bytes, Range};
 use crate::html::{LocalName, LocalNameHash, Namespace, TextType};
 use crate::parser::state_machine::{FeedbackDirective, StateMachine, StateResult};
 use crate::parser::{
-    ParserDirective, ParsingAmbiguityError, TreeBuilderFeedback, TreeBuilderSimulator,
+    ParserContext, ParserDirective, ParsingAmbiguityError, TreeBuilderFeedback,
+    TreeBuilderSimulator,
 };
 use crate::rewriter::RewritingError;
-use std::cell::RefCell;
 use std::cmp::min;
-use std::rc::Rc;
 
 pub trait TagHintSink {
     fn handle_start_tag_hint(
@@ -22,7 +21,7 @@ pub trait TagHintSink {
     fn handle_end_tag_hint(&mut self, name: LocalName) -> Result<ParserDirective, RewritingError>;
 }
 
-pub type State<S> = fn(&mut TagScanner<S>, &[u8]) -> StateResult;
+pub type State<S> = fn(&mut TagScanner<S>, &mut ParserContext<S>, &[u8]) -> StateResult;
 
 /// Tag scanner skips the majority of lexer operations and, thus,
 /// is faster. It also has much less requirements for buffering which makes it more
@@ -46,19 +45,14 @@ pub struct TagScanner<S: TagHintSink> {
     last_start_tag_name_hash: LocalNameHash,
     is_state_enter: bool,
     cdata_allowed: bool,
-    tag_hint_sink: S,
     state: State<S>,
     closing_quote: u8,
-    tree_builder_simulator: Rc<RefCell<TreeBuilderSimulator>>,
     pending_text_type_change: Option<TextType>,
     last_text_type: TextType,
 }
 
 impl<S: TagHintSink> TagScanner<S> {
-    pub fn new(
-        tag_hint_sink: S,
-        tree_builder_simulator: Rc<RefCell<TreeBuilderSimulator>>,
-    ) -> Self {
+    pub fn new() -> Self {
         TagScanner {
             next_pos: 0,
             is_last_input: false,
@@ -70,16 +64,24 @@ impl<S: TagHintSink> TagScanner<S> {
             last_start_tag_name_hash: LocalNameHash::default(),
             is_state_enter: true,
             cdata_allowed: false,
-            tag_hint_sink,
             state: TagScanner::data_state,
             closing_quote: b'"',
-            tree_builder_simulator,
             pending_text_type_change: None,
             last_text_type: TextType::Data,
         }
     }
 
-    fn emit_tag_hint(&mut self, input: &[u8]) -> Result<ParserDirective, RewritingError> {
+    /// Returns the scanner to the state of the beginning of a document.
+    #[inline]
+    pub fn reset(&mut self) {
+        *self = TagScanner::new();
+    }
+
+    fn emit_tag_hint(
+        &mut self,
+        context: &mut ParserContext<S>,
+        input: &[u8],
+    ) -> Result<ParserDirective, RewritingError> {
         let name_range = Range {
             start: self.tag_name_start,
             end: self.pos(),
@@ -92,22 +94,21 @@ impl<S: TagHintSink> TagScanner<S> {
 
         if self.is_in_end_tag {
             self.is_in_end_tag = false;
-            self.tag_hint_sink.handle_end_tag_hint(name)
+            context.output_sink.handle_end_tag_hint(name)
         } else {
             self.last_start_tag_name_hash = self.tag_name_hash;
 
-            let ns = self.tree_builder_simulator.borrow_mut().current_ns();
+            let ns = context.tree_builder_simulator.current_ns();
 
-            self.tag_hint_sink.handle_start_tag_hint(name, ns)
+            context.output_sink.handle_start_tag_hint(name, ns)
         }
     }
 
     #[inline]
     fn try_apply_tree_builder_feedback(
         &mut self,
+        tree_builder_simulator: &mut TreeBuilderSimulator,
     ) -> Result<Option<TreeBuilderFeedback>, ParsingAmbiguityError> {
-        let mut tree_builder_simulator = self.tree_builder_simulator.borrow_mut();
-
         let feedback = if self.is_in_end_tag {
             tree_builder_simulator.get_feedback_for_end_tag(self.tag_name_hash)
         } else {
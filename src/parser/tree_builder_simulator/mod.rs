@@ -23,11 +23,14 @@ pub use self::ambiguity_guard::ParsingAmbiguityError;
 
 const DEFAULT_NS_STACK_CAPACITY: usize = 256;
 
+pub type LexemeRequestCallback =
+    dyn FnMut(&mut TreeBuilderSimulator, &TagLexeme) -> TreeBuilderFeedback + Send;
+
 #[must_use]
 pub enum TreeBuilderFeedback {
     SwitchTextType(TextType),
     SetAllowCdata(bool),
-    RequestLexeme(Box<dyn FnMut(&mut TreeBuilderSimulator, &TagLexeme) -> TreeBuilderFeedback>),
+    RequestLexeme(Box<LexemeRequestCallback>),
     None,
 }
 
@@ -40,7 +43,7 @@ impl From<TextType> for TreeBuilderFeedback {
 
 #[inline]
 fn request_lexeme(
-    callback: impl FnMut(&mut TreeBuilderSimulator, &TagLexeme) -> TreeBuilderFeedback + 'static,
+    callback: impl FnMut(&mut TreeBuilderSimulator, &TagLexeme) -> TreeBuilderFeedback + Send + 'static,
 ) -> TreeBuilderFeedback {
     TreeBuilderFeedback::RequestLexeme(Box::new(callback))
 }
@@ -130,6 +133,14 @@ impl TreeBuilderSimulator {
         simulator
     }
 
+    /// Returns the simulator to the state of the beginning of a document.
+    pub fn reset(&mut self) {
+        self.ns_stack.clear();
+        self.ns_stack.push(Namespace::Html);
+        self.current_ns = Namespace::Html;
+        self.ambiguity_guard = AmbiguityGuard::default();
+    }
+
     pub fn get_feedback_for_start_tag(
         &mut self,
         tag_name: LocalNameHash,
@@ -249,7 +260,7 @@ impl TreeBuilderSimulator {
             // to decide on foreign context exit
             return request_lexeme(|this, lexeme| {
                 expect_tag!(lexeme, StartTag { ref attributes, .. } => {
-                    for attr in attributes.borrow().iter() {
+                    for attr in attributes.iter() {
                         let name = lexeme.part(attr.name);
 
                         if eq_case_insensitive(&name, b"color")
@@ -278,7 +289,7 @@ impl TreeBuilderSimulator {
                     let name = lexeme.part(name);
 
                     if !self_closing && eq_case_insensitive(&name, b"annotation-xml") {
-                        for attr in attributes.borrow().iter() {
+                        for attr in attributes.iter() {
                             let name = lexeme.part(attr.name);
                             let value = lexeme.part(attr.value);
 
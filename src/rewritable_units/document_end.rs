@@ -23,6 +23,11 @@ impl<'a> DocumentEnd<'a> {
         }
     }
 
+    #[inline]
+    pub(crate) fn append_raw(&mut self, content: &[u8]) {
+        self.output_sink.handle_chunk(content);
+    }
+
     /// Appends `content` at the end of the document.
     ///
     /// Subsequent calls to this method append `content` to the previously inserted content.
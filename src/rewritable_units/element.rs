@@ -1,9 +1,14 @@
-use super::{Attribute, AttributeNameError, ContentType, EndTag, Mutations, StartTag};
+use super::{
+    Attribute, AttributeNameError, ContentType, EndTag, Mutations, SourceLocation, StartTag,
+    UserData,
+};
 use crate::base::Bytes;
-use crate::rewriter::EndTagHandler;
+use crate::rewriter::{HandlerResult, HandlerTypes, IntoHandler, LocalHandlerTypes};
+use crate::selectors_vm::{Ancestors, TreePosition};
 use encoding_rs::Encoding;
 use std::any::Any;
 use std::fmt::{self, Debug};
+use std::mem;
 use thiserror::Error;
 
 /// An error that occurs when invalid value is provided for the tag name.
@@ -30,58 +35,74 @@ pub enum TagNameError {
     UnencodableCharacter,
 }
 
+pub(crate) fn tag_name_bytes_from_str(
+    name: &str,
+    encoding: &'static Encoding,
+) -> Result<Bytes<'static>, TagNameError> {
+    match name.chars().next() {
+        Some(ch) if !ch.is_ascii_alphabetic() => Err(TagNameError::InvalidFirstCharacter),
+        Some(_) => {
+            if let Some(ch) = name
+                .chars()
+                .find(|&ch| matches!(ch, ' ' | '\n' | '\r' | '\t' | '\x0C' | '/' | '>'))
+            {
+                Err(TagNameError::ForbiddenCharacter(ch))
+            } else {
+                // NOTE: if character can't be represented in the given
+                // encoding then encoding_rs replaces it with a numeric
+                // character reference. Character references are not
+                // supported in tag names, so we need to bail.
+                match Bytes::from_str_without_replacements(name, encoding) {
+                    Ok(name) => Ok(name.into_owned()),
+                    Err(_) => Err(TagNameError::UnencodableCharacter),
+                }
+            }
+        }
+        None => Err(TagNameError::Empty),
+    }
+}
+
 /// An HTML element rewritable unit.
 ///
 /// Exposes API for examination and modification of a parsed HTML element.
-pub struct Element<'r, 't> {
+pub struct Element<'r, 't, H: HandlerTypes = LocalHandlerTypes> {
     start_tag: &'r mut StartTag<'t>,
     end_tag_mutations: Option<Mutations>,
     modified_end_tag_name: Option<Bytes<'static>>,
+    end_tag_handlers: Vec<H::EndTagHandler>,
+    inner_content_transforms: Vec<H::InnerContentTransform>,
     can_have_content: bool,
     should_remove_content: bool,
+    matched_rule_ids: Vec<usize>,
+    position: TreePosition,
     encoding: &'static Encoding,
     user_data: Box<dyn Any>,
 }
 
-impl<'r, 't> Element<'r, 't> {
-    pub(crate) fn new(start_tag: &'r mut StartTag<'t>, can_have_content: bool) -> Self {
+impl<'r, 't, H: HandlerTypes> Element<'r, 't, H> {
+    pub(crate) fn new(
+        start_tag: &'r mut StartTag<'t>,
+        can_have_content: bool,
+        matched_rule_ids: Vec<usize>,
+        position: TreePosition,
+    ) -> Self {
         let encoding = start_tag.encoding();
 
         Element {
             start_tag,
             end_tag_mutations: None,
             modified_end_tag_name: None,
+            end_tag_handlers: Vec::new(),
+            inner_content_transforms: Vec::new(),
             can_have_content,
             should_remove_content: false,
+            matched_rule_ids,
+            position,
             encoding,
             user_data: Box::new(()),
         }
     }
 
-    fn tag_name_bytes_from_str(&self, name: &str) -> Result<Bytes<'static>, TagNameError> {
-        match name.chars().next() {
-            Some(ch) if !ch.is_ascii_alphabetic() => Err(TagNameError::InvalidFirstCharacter),
-            Some(_) => {
-                if let Some(ch) = name
-                    .chars()
-                    .find(|&ch| matches!(ch, ' ' | '\n' | '\r' | '\t' | '\x0C' | '/' | '>'))
-                {
-                    Err(TagNameError::ForbiddenCharacter(ch))
-                } else {
-                    // NOTE: if character can't be represented in the given
-                    // encoding then encoding_rs replaces it with a numeric
-                    // character reference. Character references are not
-                    // supported in tag names, so we need to bail.
-                    match Bytes::from_str_without_replacements(name, self.encoding) {
-                        Ok(name) => Ok(name.into_owned()),
-                        Err(_) => Err(TagNameError::UnencodableCharacter),
-                    }
-                }
-            }
-            None => Err(TagNameError::Empty),
-        }
-    }
-
     #[inline]
     fn remove_content(&mut self) {
         self.start_tag.mutations.content_after.clear();
@@ -106,7 +127,7 @@ impl<'r, 't> Element<'r, 't> {
     /// Sets the tag name of the element.
     #[inline]
     pub fn set_tag_name(&mut self, name: &str) -> Result<(), TagNameError> {
-        let name = self.tag_name_bytes_from_str(name)?;
+        let name = tag_name_bytes_from_str(name, self.encoding)?;
 
         if self.can_have_content {
             self.modified_end_tag_name = Some(name.clone());
@@ -125,6 +146,122 @@ impl<'r, 't> Element<'r, 't> {
         self.start_tag.namespace_uri()
     }
 
+    /// Returns an iterator over the ancestors of the element, starting from the parent.
+    ///
+    /// The ancestors are tracked only if the [`track_ancestors`] setting is enabled.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<nav class="menu"><ul><li><a href="/"></a></li></ul></nav>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("a", |el| {
+    ///                 let nav = el.ancestors().find(|a| a.tag_name() == "nav").unwrap();
+    ///                 let class = nav.get_attribute("class").unwrap().to_owned();
+    ///
+    ///                 el.set_attribute("class", &format!("{}-link", class))?;
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         retained_ancestor_attributes: vec!["class"],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(
+    ///     html,
+    ///     r#"<nav class="menu"><ul><li><a href="/" class="menu-link"></a></li></ul></nav>"#
+    /// );
+    /// ```
+    ///
+    /// [`track_ancestors`]: ../struct.Settings.html#structfield.track_ancestors
+    #[inline]
+    pub fn ancestors(&self) -> Ancestors<'_> {
+        self.position.ancestors()
+    }
+
+    /// Returns the number of the element's ancestors.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.position.depth()
+    }
+
+    /// Returns the 1-based index of the element among the elements that share its parent.
+    #[inline]
+    pub fn child_index(&self) -> usize {
+        self.position.child_index()
+    }
+
+    /// Returns the location of the element's start tag in the input.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{element, rewrite_str, RewriteStrSettings};
+    ///
+    /// rewrite_str(
+    ///     "<div>\n  <img src=foo.png>\n</div>",
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("img", |el| {
+    ///                 let location = el.source_location();
+    ///
+    ///                 assert_eq!(location.bytes(), 8..25);
+    ///                 assert_eq!((location.line(), location.column()), (2, 3));
+    ///                 assert_eq!((location.end_line(), location.end_column()), (2, 20));
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    /// ```
+    #[inline]
+    pub fn source_location(&self) -> SourceLocation {
+        self.start_tag.source_location()
+    }
+
+    /// Returns the bytes of the element's start tag as they appear in the input, in the
+    /// document's [`encoding`].
+    ///
+    /// The bytes are not affected by the modifications of the element.
+    ///
+    /// [`encoding`]: ../struct.Settings.html#structfield.encoding
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{element, rewrite_str, RewriteStrSettings};
+    ///
+    /// rewrite_str(
+    ///     "<DIV Class='foo'>",
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("div", |el| {
+    ///                 el.set_attribute("class", "bar")?;
+    ///
+    ///                 assert_eq!(el.tag_name(), "div");
+    ///                 assert_eq!(el.raw_bytes(), b"<DIV Class='foo'>");
+    ///                 assert_eq!(el.attributes()[0].raw_bytes(), Some(&b"Class='foo'"[..]));
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    /// ```
+    #[inline]
+    pub fn raw_bytes(&self) -> &[u8] {
+        self.start_tag.raw_bytes()
+    }
+
     /// Returns an immutable collection of element's attributes.
     #[inline]
     pub fn attributes(&self) -> &[Attribute<'t>] {
@@ -452,36 +589,235 @@ impl<'r, 't> Element<'r, 't> {
         self.start_tag.mutations.removed()
     }
 
+    /// Sets a `handler` that is invoked with the end tag of the element.
+    ///
+    /// The handler is invoked after the inner content of the element has been processed, so it
+    /// can rely on the state gathered by the content handlers of the inner content. Consequent
+    /// calls to the method add handlers that are invoked in the order they were added.
+    ///
+    /// The handler is never invoked if the element can't have an end tag (e.g. a void element
+    /// like `<br>` or a self-closing element) or if the element is not closed by the end of the
+    /// input. If the element is implicitly closed by the end tag of one of its ancestors, then
+    /// the handler receives the end tag of that ancestor.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, element, text, RewriteStrSettings};
+    /// use lol_html::html_content::ContentType;
+    /// use std::cell::Cell;
+    /// use std::rc::Rc;
+    ///
+    /// let word_count = Rc::new(Cell::new(0));
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<article><p>Lorem ipsum</p><p>dolor sit amet</p></article>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("article", |el| {
+    ///                 let word_count = Rc::clone(&word_count);
+    ///
+    ///                 el.on_end_tag(move |end| {
+    ///                     let footer = format!("<footer>{} words</footer>", word_count.get());
+    ///
+    ///                     end.before(&footer, ContentType::Html);
+    ///
+    ///                     Ok(())
+    ///                 });
+    ///
+    ///                 Ok(())
+    ///             }),
+    ///             text!("article", |t| {
+    ///                 word_count.set(word_count.get() + t.as_str().split_whitespace().count());
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(
+    ///     html,
+    ///     r#"<article><p>Lorem ipsum</p><p>dolor sit amet</p><footer>5 words</footer></article>"#
+    /// );
+    /// ```
+    #[inline]
+    pub fn on_end_tag(
+        &mut self,
+        handler: impl FnOnce(&mut EndTag) -> HandlerResult<H::Error> + IntoHandler<H::EndTagHandler>,
+    ) {
+        if self.can_have_content {
+            self.end_tag_handlers.push(handler.into_handler());
+        }
+    }
+
+    /// Sets a `transform` that receives the inner content of the element as a string and returns
+    /// the content that replaces it.
+    ///
+    /// The rewriter buffers the inner content until the end tag of the element, so it can be used
+    /// to process the content of small elements as a whole, e.g. to minify an inline script.
+    /// The buffered content is counted against the [`max_allowed_memory_usage`] limit.
+    ///
+    /// The `transform` receives the content as it would be emitted by the rewriter, i.e.
+    /// with the modifications made by the other content handlers and with the content inserted
+    /// with [`prepend`], [`append`] and [`set_inner_content`]. The returned content is inserted as
+    /// HTML. Consequent calls to the method add transforms that are applied in the order they
+    /// were added.
+    ///
+    /// The `transform` is not invoked if the element can't have inner content (e.g. a void element
+    /// like `<br>` or a self-closing element) or if the element is removed along with its
+    /// content. If the element is not closed by the end of the input, the `transform` receives
+    /// the content up to the end of the input.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<script>let   answer =   42;</script>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("script", |el| {
+    ///                 el.transform_inner_content(|script| {
+    ///                     script.split_whitespace().collect::<Vec<_>>().join(" ")
+    ///                 });
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<script>let answer = 42;</script>"#);
+    /// ```
+    ///
+    /// [`max_allowed_memory_usage`]: ../struct.MemorySettings.html#structfield.max_allowed_memory_usage
+    /// [`prepend`]: #method.prepend
+    /// [`append`]: #method.append
+    /// [`set_inner_content`]: #method.set_inner_content
+    #[inline]
+    pub fn transform_inner_content(
+        &mut self,
+        transform: impl FnOnce(String) -> String + IntoHandler<H::InnerContentTransform>,
+    ) {
+        if self.can_have_content {
+            self.inner_content_transforms.push(transform.into_handler());
+        }
+    }
+
+    /// Returns the ids of the rules whose selectors matched the element, in ascending order.
+    ///
+    /// The id of a rule is the index of its selector and handlers pair in the
+    /// [`element_content_handlers`] setting. This allows a single handler to serve multiple rules.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, element, RewriteStrSettings};
+    /// use lol_html::html_content::Element;
+    ///
+    /// let classes = ["external", "secure"];
+    /// let handler = |el: &mut Element| {
+    ///     let class = el
+    ///         .matched_rule_ids()
+    ///         .iter()
+    ///         .map(|&id| classes[id])
+    ///         .collect::<Vec<_>>()
+    ///         .join(" ");
+    ///
+    ///     el.set_attribute("class", &class)?;
+    ///
+    ///     Ok(())
+    /// };
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<a href="/"></a><a href="https://example.com"></a>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!(r#"a[href^="http"]"#, handler),
+    ///             element!(r#"a[href^="https:"]"#, handler),
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(
+    ///     html,
+    ///     r#"<a href="/"></a><a href="https://example.com" class="external secure"></a>"#
+    /// );
+    /// ```
+    ///
+    /// [`element_content_handlers`]: ../struct.Settings.html#structfield.element_content_handlers
+    #[inline]
+    pub fn matched_rule_ids(&self) -> &[usize] {
+        &self.matched_rule_ids
+    }
+
     #[inline]
     pub(crate) fn should_remove_content(&self) -> bool {
         self.should_remove_content
     }
 
-    pub(crate) fn into_end_tag_handler(self) -> Option<EndTagHandler<'static>> {
+    pub(crate) fn take_inner_content_transforms(&mut self) -> Vec<H::InnerContentTransform> {
+        if self.should_remove_content && self.removed() {
+            Vec::new()
+        } else {
+            mem::take(&mut self.inner_content_transforms)
+        }
+    }
+
+    pub(crate) fn into_end_tag_handler(self) -> Option<H::EndTagHandler> {
         let end_tag_mutations = self.end_tag_mutations;
         let modified_end_tag_name = self.modified_end_tag_name;
+        let mut end_tag_handlers = self.end_tag_handlers;
 
         if end_tag_mutations.is_some() || modified_end_tag_name.is_some() {
-            Some(Box::new(move |end_tag: &mut EndTag| {
-                if let Some(name) = modified_end_tag_name {
-                    end_tag.set_name(name);
-                }
+            end_tag_handlers.insert(
+                0,
+                H::new_end_tag_handler(move |end_tag: &mut EndTag| {
+                    if let Some(name) = modified_end_tag_name {
+                        end_tag.set_name_raw(name);
+                    }
 
-                if let Some(mutations) = end_tag_mutations {
-                    end_tag.mutations = mutations;
-                }
+                    if let Some(mutations) = end_tag_mutations {
+                        end_tag.mutations = mutations;
+                    }
 
-                Ok(())
-            }))
+                    Ok(())
+                }),
+            );
+        }
+
+        if end_tag_handlers.len() > 1 {
+            Some(H::combine_end_tag_handlers(end_tag_handlers))
         } else {
-            None
+            end_tag_handlers.pop()
         }
     }
 }
 
-impl_user_data!(Element<'_, '_>);
+// NOTE: the macro doesn't support the type parameters.
+impl<H: HandlerTypes> UserData for Element<'_, '_, H> {
+    #[inline]
+    fn user_data(&self) -> &dyn Any {
+        &*self.user_data
+    }
+
+    #[inline]
+    fn user_data_mut(&mut self) -> &mut dyn Any {
+        &mut *self.user_data
+    }
+
+    #[inline]
+    fn set_user_data(&mut self, data: impl Any) {
+        self.user_data = Box::new(data);
+    }
+}
 
-impl Debug for Element<'_, '_> {
+impl<H: HandlerTypes> Debug for Element<'_, '_, H> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Element")
             .field("tag_name", &self.tag_name())
@@ -709,6 +1045,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn get_decoded_attrs() {
+        for (html, enc) in encoded(r#"<a href="/?a=1&amp;b=2&copy=3" title='&quot;Hé&quot;&#x21'>"#)
+        {
+            rewrite_element(&html, enc, "a", |el| {
+                assert_eq!(
+                    el.get_attribute("href").unwrap(),
+                    "/?a=1&b=2&copy=3",
+                    "Encoding: {}",
+                    enc.name()
+                );
+
+                assert_eq!(
+                    el.get_attribute("title").unwrap(),
+                    r#""Hé"!"#,
+                    "Encoding: {}",
+                    enc.name()
+                );
+
+                let attrs = el.attributes();
+
+                assert_eq!(attrs[0].raw_value(), "/?a=1&amp;b=2&copy=3");
+                assert_eq!(attrs[1].raw_value(), "&quot;Hé&quot;&#x21");
+            });
+        }
+    }
+
     #[test]
     fn has_attr() {
         for (html, enc) in encoded("<Foo FooѦ1=Bar1 FooѤ2=Bar2>") {
@@ -1057,6 +1420,18 @@ mod tests {
             );
         }
 
+        #[test]
+        fn add_attr_with_char_refs() {
+            test!(
+                |el| {
+                    el.set_attribute("a5", "foo & bar &amp;é").unwrap();
+
+                    assert_eq!(el.get_attribute("a5").unwrap(), "foo & bar &amp;é");
+                },
+                r#"<a a1='foo " baré " baz' a2="foo ' bar ' baz" a3=foo/bar a4 a5="foo &amp; bar &amp;amp;é"></a>"#
+            );
+        }
+
         #[test]
         fn self_closing_flag() {
             // NOTE: we should add space between valueless attr and self-closing slash
@@ -3,6 +3,8 @@ use std::any::Any;
 pub use self::document_end::*;
 pub use self::element::*;
 pub use self::mutations::{ContentType, Mutations};
+pub use self::source_location::SourceLocation;
+pub(crate) use self::source_location::{SourceLocator, SourcePosition};
 pub use self::tokens::*;
 
 /// Data that can be attached to a rewritable unit by a user and shared between content handler
@@ -80,6 +82,7 @@ macro_rules! impl_user_data {
 
 #[macro_use]
 mod mutations;
+mod source_location;
 
 mod document_end;
 mod element;
@@ -113,11 +116,11 @@ mod test_utils {
             .collect()
     }
 
-    pub fn rewrite_html(
+    pub fn rewrite_html<'h>(
         html: &[u8],
         encoding: &'static Encoding,
-        element_content_handlers: Vec<(&Selector, ElementContentHandlers)>,
-        document_content_handlers: Vec<DocumentContentHandlers>,
+        element_content_handlers: Vec<(&Selector, ElementContentHandlers<'h>)>,
+        document_content_handlers: Vec<DocumentContentHandlers<'h>>,
     ) -> String {
         let mut output = Output::new(encoding);
 
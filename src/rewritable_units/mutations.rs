@@ -10,6 +10,14 @@ pub enum ContentType {
     ///     - `>` will be replaced with `&gt;`
     ///     - `&` will be replaced with `&amp;`
     Text,
+    /// Decoded text content type, i.e. text in the form returned by [`TextChunk::decoded_text`].
+    /// The rewriter will HTML-escape the content in the same way as for the [`Text`] content type,
+    /// except for the text chunks of the types that don't have character references (e.g. text
+    /// inside a `<script>` element), where the content is inserted as is.
+    ///
+    /// [`TextChunk::decoded_text`]: struct.TextChunk.html#method.decoded_text
+    /// [`Text`]: #variant.Text
+    DecodedText,
 }
 
 #[inline]
@@ -23,7 +31,7 @@ pub(super) fn content_to_bytes(
 
     match content_type {
         ContentType::Html => output_handler(&bytes),
-        ContentType::Text => bytes.replace_byte3(
+        ContentType::Text | ContentType::DecodedText => bytes.replace_byte3(
             (b'<', b"&lt;"),
             (b'>', b"&gt;"),
             (b'&', b"&amp;"),
@@ -0,0 +1,177 @@
+use memchr::{memchr_iter, memrchr};
+use std::ops::Range;
+
+/// A position in the input.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SourcePosition {
+    offset: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl Default for SourcePosition {
+    #[inline]
+    fn default() -> Self {
+        SourcePosition {
+            offset: 0,
+            line: 1,
+            line_start: 0,
+        }
+    }
+}
+
+impl SourcePosition {
+    /// Returns the position that precedes this position by `byte_count` bytes, given that
+    /// there are no newlines in between.
+    #[inline]
+    pub fn preceding(self, byte_count: usize) -> Self {
+        debug_assert!(self.offset - byte_count >= self.line_start);
+
+        SourcePosition {
+            offset: self.offset - byte_count,
+            ..self
+        }
+    }
+
+    /// Returns the position that follows the `bytes` that start at this position.
+    #[inline]
+    pub fn advanced(self, bytes: &[u8]) -> Self {
+        let offset = self.offset + bytes.len();
+
+        match memrchr(b'\n', bytes) {
+            Some(last_newline) => SourcePosition {
+                offset,
+                line: self.line + memchr_iter(b'\n', bytes).count(),
+                line_start: self.offset + last_newline + 1,
+            },
+            None => SourcePosition {
+                offset,
+                line: self.line,
+                line_start: self.line_start,
+            },
+        }
+    }
+}
+
+/// The location of a rewritable unit in the input.
+///
+/// Offsets are counted in bytes from the start of the input given to the rewriter, before
+/// the input is decoded with the document's [`encoding`]. Lines and columns are 1-based, and
+/// columns are counted in bytes as well.
+///
+/// [`encoding`]: ../struct.Settings.html#structfield.encoding
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    start: SourcePosition,
+    end: SourcePosition,
+}
+
+impl SourceLocation {
+    #[inline]
+    pub(crate) fn new(start: SourcePosition, end: SourcePosition) -> Self {
+        SourceLocation { start, end }
+    }
+
+    #[inline]
+    pub(crate) fn start_position(&self) -> SourcePosition {
+        self.start
+    }
+
+    #[cfg(test)]
+    pub(crate) fn end_position(&self) -> SourcePosition {
+        self.end
+    }
+
+    /// Returns the byte offset of the start of the unit.
+    #[inline]
+    pub fn start(&self) -> usize {
+        self.start.offset
+    }
+
+    /// Returns the byte offset that follows the end of the unit.
+    #[inline]
+    pub fn end(&self) -> usize {
+        self.end.offset
+    }
+
+    /// Returns the range of the bytes of the unit.
+    #[inline]
+    pub fn bytes(&self) -> Range<usize> {
+        self.start()..self.end()
+    }
+
+    /// Returns the line on which the unit starts.
+    #[inline]
+    pub fn line(&self) -> usize {
+        self.start.line
+    }
+
+    /// Returns the column in which the unit starts.
+    #[inline]
+    pub fn column(&self) -> usize {
+        self.start.offset - self.start.line_start + 1
+    }
+
+    /// Returns the line of the position that follows the end of the unit.
+    #[inline]
+    pub fn end_line(&self) -> usize {
+        self.end.line
+    }
+
+    /// Returns the column of the position that follows the end of the unit.
+    #[inline]
+    pub fn end_column(&self) -> usize {
+        self.end.offset - self.end.line_start + 1
+    }
+}
+
+/// Converts indices in the chunks of the input to positions.
+///
+/// The scan for newlines continues from the last converted index, so the indices are
+/// expected to increase.
+pub(crate) struct SourceLocator {
+    chunk_start: SourcePosition,
+    last_position: SourcePosition,
+}
+
+impl SourceLocator {
+    #[inline]
+    pub fn new() -> Self {
+        SourceLocator {
+            chunk_start: SourcePosition::default(),
+            last_position: SourcePosition::default(),
+        }
+    }
+
+    /// Returns the position of the byte at the given index in the current chunk.
+    pub fn position(&mut self, chunk: &[u8], index: usize) -> SourcePosition {
+        let offset = self.chunk_start.offset + index;
+        let last = self.last_position;
+
+        let position = if offset >= last.offset {
+            last.advanced(&chunk[last.offset - self.chunk_start.offset..index])
+        } else {
+            self.chunk_start.advanced(&chunk[..index])
+        };
+
+        self.last_position = position;
+
+        position
+    }
+
+    /// Returns the location of the bytes in the given range of the current chunk.
+    #[inline]
+    pub fn location(&mut self, chunk: &[u8], range: crate::base::Range) -> SourceLocation {
+        let start = self.position(chunk, range.start);
+        let end = self.position(chunk, range.end);
+
+        SourceLocation::new(start, end)
+    }
+
+    /// Moves to the next chunk, given that the current chunk was consumed up to the
+    /// `consumed_byte_count`.
+    #[inline]
+    pub fn consume_chunk(&mut self, chunk: &[u8], consumed_byte_count: usize) {
+        self.chunk_start = self.position(chunk, consumed_byte_count);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::base::Bytes;
-use crate::parser::SharedAttributeBuffer;
-use crate::rewritable_units::Serialize;
+use crate::html::decode_char_refs;
+use crate::parser::AttributeOutline;
+use crate::rewritable_units::{Serialize, SourceLocation, SourcePosition};
 use encoding_rs::Encoding;
 use lazycell::LazyCell;
 use std::fmt::{self, Debug};
@@ -37,16 +38,26 @@ pub struct Attribute<'i> {
     name: Bytes<'i>,
     value: Bytes<'i>,
     raw: Option<Bytes<'i>>,
+    source: Option<Bytes<'i>>,
     encoding: &'static Encoding,
+    location: Option<SourceLocation>,
 }
 
 impl<'i> Attribute<'i> {
-    fn new(name: Bytes<'i>, value: Bytes<'i>, raw: Bytes<'i>, encoding: &'static Encoding) -> Self {
+    fn new(
+        name: Bytes<'i>,
+        value: Bytes<'i>,
+        raw: Bytes<'i>,
+        encoding: &'static Encoding,
+        location: SourceLocation,
+    ) -> Self {
         Attribute {
             name,
             value,
-            raw: Some(raw),
+            raw: Some(raw.clone()),
+            source: Some(raw),
             encoding,
+            location: Some(location),
         }
     }
 
@@ -82,27 +93,74 @@ impl<'i> Attribute<'i> {
     ) -> Result<Self, AttributeNameError> {
         Ok(Attribute {
             name: Attribute::name_from_str(name, encoding)?,
-            value: Bytes::from_str(value, encoding).into_owned(),
+            value: Attribute::value_from_str(value, encoding),
             raw: None,
+            source: None,
             encoding,
+            location: None,
         })
     }
 
+    /// Converts the `value` to the form in which it can be serialized in a double-quoted
+    /// attribute value.
+    #[inline]
+    fn value_from_str(value: &str, encoding: &'static Encoding) -> Bytes<'static> {
+        // NOTE: ampersands and quotes need to be escaped before the encoding, since
+        // encoding_rs replaces unencodable characters with numeric character references
+        // that should be preserved.
+        let value = value.replace('&', "&amp;").replace('"', "&quot;");
+
+        Bytes::from_str(&value, encoding).into_owned()
+    }
+
     /// Returns the name of the attribute.
     #[inline]
     pub fn name(&self) -> String {
         self.name.as_lowercase_string(self.encoding)
     }
 
-    /// Returns the value of the attribute.
+    /// Returns the value of the attribute with the [character references] decoded.
+    ///
+    /// [character references]: https://html.spec.whatwg.org/multipage/syntax.html#character-references
     #[inline]
     pub fn value(&self) -> String {
+        decode_char_refs(&self.raw_value(), true).into_owned()
+    }
+
+    /// Returns the value of the attribute as it appears in the markup, i.e. without
+    /// the character references decoded.
+    #[inline]
+    pub fn raw_value(&self) -> String {
         self.value.as_string(self.encoding)
     }
 
+    /// Returns the location of the attribute in the input.
+    ///
+    /// Returns `None` if the attribute was added with [`Element::set_attribute`]. The location
+    /// of an attribute which value was changed is the location of the original attribute.
+    ///
+    /// [`Element::set_attribute`]: struct.Element.html#method.set_attribute
+    #[inline]
+    pub fn source_location(&self) -> Option<SourceLocation> {
+        self.location
+    }
+
+    /// Returns the bytes of the attribute as they appear in the input, in the document's
+    /// [`encoding`], including the name, the value and the quotes around the value.
+    ///
+    /// Returns `None` if the attribute was added with [`Element::set_attribute`]. The bytes
+    /// are not affected by the modifications of the attribute's value.
+    ///
+    /// [`encoding`]: ../struct.Settings.html#structfield.encoding
+    /// [`Element::set_attribute`]: struct.Element.html#method.set_attribute
+    #[inline]
+    pub fn raw_bytes(&self) -> Option<&[u8]> {
+        self.source.as_deref()
+    }
+
     #[inline]
     fn set_value(&mut self, value: &str) {
-        self.value = Bytes::from_str(value, self.encoding).into_owned();
+        self.value = Attribute::value_from_str(value, self.encoding);
         self.raw = None;
     }
 }
@@ -115,7 +173,7 @@ impl Serialize for Attribute<'_> {
             None => {
                 output_handler(&self.name);
                 output_handler(b"=\"");
-                self.value.replace_byte((b'"', b"&quot;"), output_handler);
+                output_handler(&self.value);
                 output_handler(b"\"");
             }
         }
@@ -133,22 +191,26 @@ impl Debug for Attribute<'_> {
 
 pub struct Attributes<'i> {
     input: &'i Bytes<'i>,
-    attribute_buffer: SharedAttributeBuffer,
+    attribute_buffer: &'i [AttributeOutline],
     items: LazyCell<Vec<Attribute<'i>>>,
     encoding: &'static Encoding,
+    /// The index of the start of the tag in the input and its position.
+    tag_start: (usize, SourcePosition),
 }
 
 impl<'i> Attributes<'i> {
     pub(super) fn new(
         input: &'i Bytes<'i>,
-        attribute_buffer: SharedAttributeBuffer,
+        attribute_buffer: &'i [AttributeOutline],
         encoding: &'static Encoding,
+        tag_start: (usize, SourcePosition),
     ) -> Self {
         Attributes {
             input,
             attribute_buffer,
             items: LazyCell::default(),
             encoding,
+            tag_start,
         }
     }
 
@@ -189,15 +251,28 @@ impl<'i> Attributes<'i> {
     }
 
     fn init_items(&self) -> Vec<Attribute<'i>> {
+        let (mut index, mut position) = self.tag_start;
+
         self.attribute_buffer
-            .borrow()
             .iter()
             .map(|a| {
+                // NOTE: attributes follow each other in the input, so the scan for newlines
+                // continues from the previous attribute.
+                position = position.advanced(&self.input[index..a.raw_range.start]);
+                index = a.raw_range.start;
+
+                let end = position.advanced(&self.input[index..a.raw_range.end]);
+                let location = SourceLocation::new(position, end);
+
+                position = end;
+                index = a.raw_range.end;
+
                 Attribute::new(
                     self.input.slice(a.name),
                     self.input.slice(a.value),
                     self.input.slice(a.raw_range),
                     self.encoding,
+                    location,
                 )
             })
             .collect()
@@ -221,8 +296,8 @@ impl<'i> Attributes<'i> {
     }
 
     #[cfg(test)]
-    pub fn raw_attributes(&self) -> (&'i Bytes<'i>, SharedAttributeBuffer) {
-        (self.input, std::rc::Rc::clone(&self.attribute_buffer))
+    pub fn raw_attributes(&self) -> (&'i Bytes<'i>, &'i [AttributeOutline]) {
+        (self.input, self.attribute_buffer)
     }
 }
 
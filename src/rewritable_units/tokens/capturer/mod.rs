@@ -3,7 +3,9 @@ mod to_token;
 
 use self::text_decoder::TextDecoder;
 use super::*;
+use crate::memory::SharedMemoryLimiter;
 use crate::parser::Lexeme;
+use crate::rewritable_units::SourceLocator;
 use crate::rewriter::RewritingError;
 use bitflags::bitflags;
 use encoding_rs::Encoding;
@@ -32,14 +34,20 @@ pub struct TokenCapturer {
     encoding: &'static Encoding,
     text_decoder: TextDecoder,
     capture_flags: TokenCaptureFlags,
+    source_locator: SourceLocator,
 }
 
 impl TokenCapturer {
-    pub fn new(capture_flags: TokenCaptureFlags, encoding: &'static Encoding) -> Self {
+    pub fn new(
+        capture_flags: TokenCaptureFlags,
+        encoding: &'static Encoding,
+        memory_limiter: SharedMemoryLimiter,
+    ) -> Self {
         TokenCapturer {
             encoding,
-            text_decoder: TextDecoder::new(encoding),
+            text_decoder: TextDecoder::new(encoding, memory_limiter),
             capture_flags,
+            source_locator: SourceLocator::new(),
         }
     }
 
@@ -53,6 +61,22 @@ impl TokenCapturer {
         self.capture_flags = flags;
     }
 
+    /// Drops the pending text and the source locations, so the capturer can be used
+    /// for another document.
+    pub fn reset(&mut self, capture_flags: TokenCaptureFlags) {
+        self.text_decoder.reset();
+        self.capture_flags = capture_flags;
+        self.source_locator = SourceLocator::new();
+    }
+
+    /// Moves the source locations to the next chunk of the input, given that the current
+    /// chunk was consumed up to the `consumed_byte_count`.
+    #[inline]
+    pub fn consume_chunk(&mut self, chunk: &[u8], consumed_byte_count: usize) {
+        self.source_locator
+            .consume_chunk(chunk, consumed_byte_count);
+    }
+
     #[inline]
     pub fn flush_pending_text(
         &mut self,
@@ -61,15 +85,22 @@ impl TokenCapturer {
         self.text_decoder.flush_pending(event_handler)
     }
 
+    /// Produces a token from the lexeme, if the lexeme is captured. If `text_nodes` is `true`,
+    /// the captured text is produced as whole text nodes, rather than as chunks.
     pub fn feed<'i, T>(
         &mut self,
         lexeme: &Lexeme<'i, T>,
+        text_nodes: bool,
         mut event_handler: impl FnMut(TokenCapturerEvent) -> Result<(), RewritingError>,
     ) -> Result<(), RewritingError>
     where
         Lexeme<'i, T>: ToToken,
     {
-        match lexeme.to_token(&mut self.capture_flags, self.encoding) {
+        match lexeme.to_token(
+            &mut self.capture_flags,
+            self.encoding,
+            &mut self.source_locator,
+        ) {
             ToTokenResult::Token(token) => {
                 self.flush_pending_text(&mut event_handler)?;
                 event_handler(TokenCapturerEvent::LexemeConsumed)?;
@@ -79,8 +110,17 @@ impl TokenCapturer {
                 if self.capture_flags.contains(TokenCaptureFlags::TEXT) {
                     event_handler(TokenCapturerEvent::LexemeConsumed)?;
 
-                    self.text_decoder
-                        .feed_text(&lexeme.raw(), text_type, &mut event_handler)?;
+                    let position = self
+                        .source_locator
+                        .position(lexeme.input(), lexeme.raw_range().start);
+
+                    self.text_decoder.feed_text(
+                        &lexeme.raw(),
+                        text_type,
+                        position,
+                        text_nodes,
+                        &mut event_handler,
+                    )?;
                 }
 
                 Ok(())
@@ -1,13 +1,29 @@
 use super::*;
 use crate::html::TextType;
+use crate::memory::SharedMemoryLimiter;
+use crate::rewritable_units::{SourceLocation, SourcePosition};
 use crate::rewriter::RewritingError;
 use encoding_rs::{CoderResult, Decoder, Encoding};
+use std::borrow::Cow;
+use std::mem;
+
+// NOTE: the longest name of a named character reference, excluding the semicolon.
+const MAX_CHAR_REF_NAME_LEN: usize = 31;
 
 // NOTE: this can't be refactored into method, because we hold a mutable reference for `self`
 // during the decoding loop in `feed_text`.
 macro_rules! emit {
-    ($self:tt, $text:expr, $last:ident, $event_handler:ident) => {{
-        let token = TextChunk::new_token($text, $self.last_text_type, $last, $self.encoding);
+    ($self:tt, $text:expr, $source:expr, $end:expr, $last:ident, $event_handler:ident) => {{
+        let token = TextChunk::new_token(
+            $text.into(),
+            $source.into(),
+            $self.last_text_type,
+            $last,
+            $self.encoding,
+            SourceLocation::new($self.chunk_start, $end),
+        );
+
+        $self.chunk_start = $end;
 
         $event_handler(TokenCapturerEvent::TokenProduced(Box::new(token)))
     }};
@@ -18,19 +34,182 @@ pub struct TextDecoder {
     pending_text_streaming_decoder: Option<Decoder>,
     text_buffer: String,
     last_text_type: TextType,
+    pending_char_ref: String,
+    /// The text that is withheld until the end of the text node, if the text is captured
+    /// as whole text nodes.
+    pending_text: String,
+    /// The bytes of the text that were consumed from the previous input, but are not yet
+    /// emitted in a text chunk.
+    pending_raw: Vec<u8>,
+    /// Whether the text is withheld until the end of the text node. The decoder produces a
+    /// single stream of text chunks, so this applies to all the text handlers at once.
+    text_nodes: bool,
+    /// The size of the withheld text and character reference counted against the memory limit.
+    memory_usage: usize,
+    memory_limiter: SharedMemoryLimiter,
+    /// The position of the next text chunk in the input.
+    chunk_start: SourcePosition,
+    /// The position of the next byte of the text in the input.
+    input_position: SourcePosition,
+}
+
+/// Returns the position of a trailing character reference in `text` that might continue in the
+/// next chunk, or the length of `text` if there is no such reference.
+fn pending_char_ref_start(text: &str) -> usize {
+    let amp_pos = match text.rfind('&') {
+        Some(pos) => pos,
+        None => return text.len(),
+    };
+
+    let rest = &text[amp_pos + 1..];
+
+    let is_pending = if let Some(numeric) = rest.strip_prefix('#') {
+        let (digits, radix) = match numeric.strip_prefix(|c| c == 'x' || c == 'X') {
+            Some(hex) => (hex, 16),
+            None => (numeric, 10),
+        };
+
+        digits.chars().all(|c| c.is_digit(radix))
+    } else {
+        // NOTE: if the alphanumeric sequence is longer than any reference name, then the
+        // reference is resolved regardless of the characters that follow it.
+        rest.len() <= MAX_CHAR_REF_NAME_LEN && rest.bytes().all(|b| b.is_ascii_alphanumeric())
+    };
+
+    if is_pending {
+        amp_pos
+    } else {
+        text.len()
+    }
+}
+
+/// Returns `true` if the pending numeric character reference already has digits and the whole
+/// `text` consists of digits that continue it.
+fn continues_numeric_char_ref(pending_char_ref: &str, text: &str) -> bool {
+    let numeric = match pending_char_ref.strip_prefix("&#") {
+        Some(numeric) => numeric,
+        None => return false,
+    };
+
+    let (digits, radix) = match numeric.strip_prefix(|c| c == 'x' || c == 'X') {
+        Some(hex) => (hex, 16),
+        None => (numeric, 10),
+    };
+
+    !digits.is_empty() && text.chars().all(|c| c.is_digit(radix))
+}
+
+/// Splits off a trailing character reference that might continue in the next chunk from `text`
+/// and stores it in `pending_char_ref`, prepending the previously stored reference to `text`.
+///
+/// This guarantees that character references are never split between text chunks, so they can
+/// be decoded chunk by chunk.
+fn take_complete_char_refs<'t>(
+    text: &'t str,
+    pending_char_ref: &mut String,
+    text_type: TextType,
+    last: bool,
+) -> Cow<'t, str> {
+    if !text_type.allows_html_entities() {
+        return Cow::Borrowed(text);
+    }
+
+    if pending_char_ref.is_empty() {
+        let split_pos = if last {
+            text.len()
+        } else {
+            pending_char_ref_start(text)
+        };
+
+        pending_char_ref.push_str(&text[split_pos..]);
+
+        Cow::Borrowed(&text[..split_pos])
+    } else if !last && continues_numeric_char_ref(pending_char_ref, text) {
+        // NOTE: numeric references can have any number of leading zeros, so they are withheld
+        // until they end, however long they are. The reference is extended in place to not copy
+        // it with every chunk.
+        pending_char_ref.push_str(text);
+
+        Cow::Borrowed("")
+    } else {
+        let mut text_with_ref = mem::take(pending_char_ref);
+
+        text_with_ref.push_str(text);
+
+        let split_pos = if last {
+            text_with_ref.len()
+        } else {
+            pending_char_ref_start(&text_with_ref)
+        };
+
+        pending_char_ref.push_str(&text_with_ref[split_pos..]);
+        text_with_ref.truncate(split_pos);
+
+        Cow::Owned(text_with_ref)
+    }
+}
+
+/// Returns the text of a text chunk, prepending the text that was withheld from
+/// the previous input.
+fn take_chunk_text<'t>(pending_text: &mut String, text: Cow<'t, str>) -> Cow<'t, str> {
+    if pending_text.is_empty() {
+        text
+    } else {
+        let mut chunk_text = mem::take(pending_text);
+
+        chunk_text.push_str(&text);
+
+        Cow::Owned(chunk_text)
+    }
+}
+
+/// Returns the bytes of a text chunk, prepending the bytes that were held back from
+/// the previous input.
+fn take_chunk_raw<'r>(pending_raw: &mut Vec<u8>, raw: &'r [u8]) -> Cow<'r, [u8]> {
+    if pending_raw.is_empty() {
+        Cow::Borrowed(raw)
+    } else {
+        let mut chunk_raw = mem::take(pending_raw);
+
+        chunk_raw.extend_from_slice(raw);
+
+        Cow::Owned(chunk_raw)
+    }
 }
 
 impl TextDecoder {
-    pub fn new(encoding: &'static Encoding) -> Self {
+    pub fn new(encoding: &'static Encoding, memory_limiter: SharedMemoryLimiter) -> Self {
         TextDecoder {
             encoding,
             pending_text_streaming_decoder: None,
             // TODO make adjustable
             text_buffer: String::from_utf8(vec![0u8; 1024]).unwrap(),
             last_text_type: TextType::Data,
+            pending_char_ref: String::new(),
+            pending_text: String::new(),
+            pending_raw: Vec::new(),
+            text_nodes: false,
+            memory_usage: 0,
+            memory_limiter,
+            chunk_start: SourcePosition::default(),
+            input_position: SourcePosition::default(),
         }
     }
 
+    /// Drops the pending text, so the decoder can be used for another document.
+    pub fn reset(&mut self) {
+        self.pending_text_streaming_decoder = None;
+        self.last_text_type = TextType::Data;
+        self.pending_char_ref.clear();
+        self.pending_text.clear();
+        self.pending_raw.clear();
+        self.text_nodes = false;
+        self.memory_limiter.decrease_usage(self.memory_usage);
+        self.memory_usage = 0;
+        self.chunk_start = SourcePosition::default();
+        self.input_position = SourcePosition::default();
+    }
+
     #[inline]
     pub fn flush_pending(
         &mut self,
@@ -57,12 +236,45 @@ impl TextDecoder {
             .get_or_insert_with(|| encoding.new_decoder_without_bom_handling());
 
         let mut consumed = 0;
+        let mut chunk_raw_start = 0;
 
         loop {
             let (status, read, written, ..) = decoder.decode_to_str(&raw[consumed..], buffer, last);
 
+            self.input_position = self
+                .input_position
+                .advanced(&raw[consumed..consumed + read]);
+
             if written > 0 || last {
-                emit!(self, &buffer[..written], last, event_handler)?;
+                let text = take_complete_char_refs(
+                    &buffer[..written],
+                    &mut self.pending_char_ref,
+                    self.last_text_type,
+                    last,
+                );
+
+                if self.text_nodes && !last {
+                    // NOTE: the bytes of the withheld text are kept in the pending bytes.
+                    self.pending_text.push_str(&text);
+                } else if !text.is_empty() || last {
+                    // NOTE: the text can become empty if it's a part of a character reference
+                    // that continues in the next chunk.
+                    // NOTE: character references consist of ASCII characters, so their
+                    // length in bytes doesn't depend on the encoding.
+                    let end = self.input_position.preceding(self.pending_char_ref.len());
+                    let chunk_raw_end = (consumed + read)
+                        .saturating_sub(self.pending_char_ref.len())
+                        .max(chunk_raw_start);
+
+                    let chunk_raw =
+                        take_chunk_raw(&mut self.pending_raw, &raw[chunk_raw_start..chunk_raw_end]);
+
+                    let text = take_chunk_text(&mut self.pending_text, text);
+
+                    chunk_raw_start = chunk_raw_end;
+
+                    emit!(self, text, chunk_raw, end, last, event_handler)?;
+                }
             }
 
             if let CoderResult::InputEmpty = status {
@@ -72,6 +284,28 @@ impl TextDecoder {
             consumed += read;
         }
 
+        if !last {
+            self.pending_raw.extend_from_slice(&raw[chunk_raw_start..]);
+        }
+
+        self.update_memory_usage()
+    }
+
+    fn update_memory_usage(&mut self) -> Result<(), RewritingError> {
+        let memory_usage =
+            self.pending_text.len() + self.pending_raw.len() + self.pending_char_ref.len();
+        let limiter = &self.memory_limiter;
+
+        if memory_usage > self.memory_usage {
+            limiter
+                .increase_usage(memory_usage - self.memory_usage)
+                .map_err(RewritingError::MemoryLimitExceeded)?;
+        } else {
+            limiter.decrease_usage(self.memory_usage - memory_usage);
+        }
+
+        self.memory_usage = memory_usage;
+
         Ok(())
     }
 
@@ -80,9 +314,17 @@ impl TextDecoder {
         &mut self,
         raw: &[u8],
         text_type: TextType,
+        position: SourcePosition,
+        text_nodes: bool,
         event_handler: CapturerEventHandler,
     ) -> Result<(), RewritingError> {
+        if self.pending_text_streaming_decoder.is_none() {
+            self.chunk_start = position;
+        }
+
+        self.text_nodes = text_nodes;
         self.last_text_type = text_type;
+        self.input_position = position;
         self.decode_with_streaming_decoder(raw, false, event_handler)
     }
 }
@@ -1,8 +1,8 @@
 use super::*;
 use crate::html::TextType;
 use crate::parser::{NonTagContentLexeme, NonTagContentTokenOutline, TagLexeme, TagTokenOutline};
+use crate::rewritable_units::SourceLocator;
 use encoding_rs::Encoding;
-use std::rc::Rc;
 
 pub enum ToTokenResult<'i> {
     Token(Box<Token<'i>>),
@@ -22,6 +22,7 @@ pub trait ToToken {
         &self,
         capture_flags: &mut TokenCaptureFlags,
         encoding: &'static Encoding,
+        source_locator: &mut SourceLocator,
     ) -> ToTokenResult;
 }
 
@@ -30,6 +31,7 @@ impl ToToken for TagLexeme<'_> {
         &self,
         capture_flags: &mut TokenCaptureFlags,
         encoding: &'static Encoding,
+        source_locator: &mut SourceLocator,
     ) -> ToTokenResult {
         match *self.token_outline() {
             TagTokenOutline::StartTag {
@@ -42,13 +44,21 @@ impl ToToken for TagLexeme<'_> {
                 // NOTE: clear the flag once we've seen required start tag.
                 capture_flags.remove(TokenCaptureFlags::NEXT_START_TAG);
 
+                let location = source_locator.location(self.input(), self.raw_range());
+
                 StartTag::new_token(
                     self.part(name),
-                    Attributes::new(self.input(), Rc::clone(attributes), encoding),
+                    Attributes::new(
+                        self.input(),
+                        attributes,
+                        encoding,
+                        (self.raw_range().start, location.start_position()),
+                    ),
                     ns,
                     self_closing,
                     self.raw(),
                     encoding,
+                    location,
                 )
                 .into()
             }
@@ -59,7 +69,9 @@ impl ToToken for TagLexeme<'_> {
                 // NOTE: clear the flag once we've seen required end tag.
                 capture_flags.remove(TokenCaptureFlags::NEXT_END_TAG);
 
-                EndTag::new_token(self.part(name), self.raw(), encoding).into()
+                let location = source_locator.location(self.input(), self.raw_range());
+
+                EndTag::new_token(self.part(name), self.raw(), encoding, location).into()
             }
             _ => ToTokenResult::None,
         }
@@ -71,13 +83,16 @@ impl ToToken for NonTagContentLexeme<'_> {
         &self,
         capture_flags: &mut TokenCaptureFlags,
         encoding: &'static Encoding,
+        source_locator: &mut SourceLocator,
     ) -> ToTokenResult {
         match *self.token_outline() {
             Some(NonTagContentTokenOutline::Text(text_type)) => ToTokenResult::Text(text_type),
             Some(NonTagContentTokenOutline::Comment(text))
                 if capture_flags.contains(TokenCaptureFlags::COMMENTS) =>
             {
-                Comment::new_token(self.part(text), self.raw(), encoding).into()
+                let location = source_locator.location(self.input(), self.raw_range());
+
+                Comment::new_token(self.part(text), self.raw(), encoding, location).into()
             }
 
             Some(NonTagContentTokenOutline::Doctype {
@@ -92,6 +107,7 @@ impl ToToken for NonTagContentLexeme<'_> {
                 force_quirks,
                 self.raw(),
                 encoding,
+                source_locator.location(self.input(), self.raw_range()),
             )
             .into(),
             _ => ToTokenResult::None,
@@ -1,5 +1,7 @@
 use super::{Mutations, Token};
 use crate::base::Bytes;
+use crate::rewritable_units::SourceLocation;
+use crate::selectors_vm::{Ancestors, TreePosition};
 use encoding_rs::Encoding;
 use std::any::Any;
 use std::fmt::{self, Debug};
@@ -25,8 +27,12 @@ pub enum CommentTextError {
 pub struct Comment<'i> {
     text: Bytes<'i>,
     raw: Option<Bytes<'i>>,
+    source: Bytes<'i>,
     encoding: &'static Encoding,
+    location: SourceLocation,
     mutations: Mutations,
+    matched_rule_ids: Vec<usize>,
+    position: TreePosition,
     user_data: Box<dyn Any>,
 }
 
@@ -35,12 +41,17 @@ impl<'i> Comment<'i> {
         text: Bytes<'i>,
         raw: Bytes<'i>,
         encoding: &'static Encoding,
+        location: SourceLocation,
     ) -> Token<'i> {
         Token::Comment(Comment {
             text,
-            raw: Some(raw),
+            raw: Some(raw.clone()),
+            source: raw,
             encoding,
+            location,
             mutations: Mutations::new(encoding),
+            matched_rule_ids: Vec::new(),
+            position: TreePosition::default(),
             user_data: Box::new(()),
         })
     }
@@ -181,6 +192,64 @@ impl<'i> Comment<'i> {
         self.mutations.removed()
     }
 
+    /// Returns the location of the comment in the input.
+    #[inline]
+    pub fn source_location(&self) -> SourceLocation {
+        self.location
+    }
+
+    /// Returns the bytes of the comment as they appear in the input, in the document's
+    /// [`encoding`].
+    ///
+    /// The bytes are not affected by the modifications of the comment.
+    ///
+    /// [`encoding`]: ../struct.Settings.html#structfield.encoding
+    #[inline]
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.source
+    }
+
+    /// Returns the ids of the rules whose selectors matched one of the elements that contain
+    /// the comment, in ascending order.
+    ///
+    /// The id of a rule is the index of its selector and handlers pair in the
+    /// [`element_content_handlers`] setting.
+    ///
+    /// [`element_content_handlers`]: ../struct.Settings.html#structfield.element_content_handlers
+    #[inline]
+    pub fn matched_rule_ids(&self) -> &[usize] {
+        &self.matched_rule_ids
+    }
+
+    /// Returns an iterator over the elements that contain the comment, starting from
+    /// the innermost one.
+    ///
+    /// The ancestors are tracked only if the rewriter has [`element_content_handlers`] and
+    /// the [`track_ancestors`] setting is enabled.
+    ///
+    /// [`element_content_handlers`]: ../struct.Settings.html#structfield.element_content_handlers
+    /// [`track_ancestors`]: ../struct.Settings.html#structfield.track_ancestors
+    #[inline]
+    pub fn ancestors(&self) -> Ancestors<'_> {
+        self.position.ancestors()
+    }
+
+    /// Returns the number of the elements that contain the comment.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.position.depth()
+    }
+
+    #[inline]
+    pub(crate) fn set_matched_rule_ids(&mut self, matched_rule_ids: Vec<usize>) {
+        self.matched_rule_ids = matched_rule_ids;
+    }
+
+    #[inline]
+    pub(crate) fn set_position(&mut self, position: TreePosition) {
+        self.position = position;
+    }
+
     #[inline]
     fn raw(&self) -> Option<&Bytes> {
         self.raw.as_ref()
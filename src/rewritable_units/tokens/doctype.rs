@@ -1,20 +1,47 @@
+use super::{Mutations, Token};
 use crate::base::Bytes;
-use crate::rewritable_units::{Serialize, Token};
+use crate::rewritable_units::{ContentType, SourceLocation};
 use encoding_rs::Encoding;
 use std::any::Any;
 use std::fmt::{self, Debug};
+use thiserror::Error;
+
+/// An error that occurs when invalid value is provided for the doctype name or identifiers.
+#[derive(Error, Debug, PartialEq, Copy, Clone)]
+pub enum DoctypeError {
+    /// The provided name is empty.
+    #[error("Doctype name can't be empty.")]
+    EmptyName,
+
+    /// The provided name contains a character that is forbidden by the HTML grammar in doctype
+    /// names (e.g. `'>'` or whitespace).
+    #[error("`{0}` character is forbidden in the doctype name.")]
+    ForbiddenCharacterInName(char),
+
+    /// The provided identifier contains the `'>'` character that preemptively closes the doctype.
+    #[error("`>` character is forbidden in the doctype identifiers.")]
+    ClosingCharacterInIdentifier,
+
+    /// The provided identifier contains both single and double quotes, so it can't be quoted.
+    #[error("Doctype identifier can't contain both single and double quotes.")]
+    MixedQuotesInIdentifier,
+
+    /// The provided value contains a character that can't be represented in the document's
+    /// [`encoding`].
+    ///
+    /// [`encoding`]: ../struct.Settings.html#structfield.encoding
+    #[error("The doctype value contains a character that can't be represented in the document's character encoding.")]
+    UnencodableCharacter,
+}
 
 /// A [document type declaration] preamble.
 ///
-/// Note that unlike other HTML content, `Doctype` can't be modified and should be used only for
-/// the examination purposes.
-///
 /// # Example
 /// ```
 /// use lol_html::{rewrite_str, doctype, RewriteStrSettings};
 ///
-/// rewrite_str(
-///     r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Transitional//EN" "DTD/xhtml1-transitional.dtd""#,
+/// let html = rewrite_str(
+///     r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Transitional//EN" "DTD/xhtml1-transitional.dtd">"#,
 ///     RewriteStrSettings {
 ///         document_content_handlers: vec![
 ///             doctype!(|d| {
@@ -22,12 +49,17 @@ use std::fmt::{self, Debug};
 ///                 assert_eq!(d.public_id(), Some("-//W3C//DTD XHTML 1.0 Transitional//EN".into()));
 ///                 assert_eq!(d.system_id(), Some("DTD/xhtml1-transitional.dtd".into()));
 ///
+///                 d.set_public_id(None)?;
+///                 d.set_system_id(None)?;
+///
 ///                 Ok(())
 ///             })
 ///         ],
 ///         ..RewriteStrSettings::default()
 ///     }
 /// ).unwrap();
+///
+/// assert_eq!(html, "<!DOCTYPE html>");
 /// ```
 ///
 /// [document type declaration]: https://developer.mozilla.org/en-US/docs/Glossary/Doctype
@@ -36,8 +68,11 @@ pub struct Doctype<'i> {
     public_id: Option<Bytes<'i>>,
     system_id: Option<Bytes<'i>>,
     force_quirks: bool,
-    raw: Bytes<'i>,
+    raw: Option<Bytes<'i>>,
+    source: Bytes<'i>,
     encoding: &'static Encoding,
+    location: SourceLocation,
+    mutations: Mutations,
     user_data: Box<dyn Any>,
 }
 
@@ -49,18 +84,42 @@ impl<'i> Doctype<'i> {
         force_quirks: bool,
         raw: Bytes<'i>,
         encoding: &'static Encoding,
+        location: SourceLocation,
     ) -> Token<'i> {
         Token::Doctype(Doctype {
             name,
             public_id,
             system_id,
             force_quirks,
-            raw,
+            raw: Some(raw.clone()),
+            source: raw,
             encoding,
+            location,
+            mutations: Mutations::new(encoding),
             user_data: Box::new(()),
         })
     }
 
+    fn id_bytes_from_str(&self, id: Option<&str>) -> Result<Option<Bytes<'static>>, DoctypeError> {
+        let id = match id {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        if id.contains('>') {
+            Err(DoctypeError::ClosingCharacterInIdentifier)
+        } else if id.contains('"') && id.contains('\'') {
+            Err(DoctypeError::MixedQuotesInIdentifier)
+        } else {
+            // NOTE: character references are not supported in doctypes,
+            // so we need to bail if encoding_rs would produce one.
+            match Bytes::from_str_without_replacements(id, self.encoding) {
+                Ok(id) => Ok(Some(id.into_owned())),
+                Err(_) => Err(DoctypeError::UnencodableCharacter),
+            }
+        }
+    }
+
     /// The name of the doctype.
     #[inline]
     pub fn name(&self) -> Option<String> {
@@ -69,34 +128,234 @@ impl<'i> Doctype<'i> {
             .map(|n| n.as_lowercase_string(self.encoding))
     }
 
+    /// Sets the name of the doctype.
+    #[inline]
+    pub fn set_name(&mut self, name: &str) -> Result<(), DoctypeError> {
+        if name.is_empty() {
+            return Err(DoctypeError::EmptyName);
+        }
+
+        if let Some(ch) = name
+            .chars()
+            .find(|&ch| matches!(ch, ' ' | '\n' | '\r' | '\t' | '\x0C' | '>'))
+        {
+            return Err(DoctypeError::ForbiddenCharacterInName(ch));
+        }
+
+        match Bytes::from_str_without_replacements(name, self.encoding) {
+            Ok(name) => {
+                self.name = Some(name.into_owned());
+                self.raw = None;
+
+                Ok(())
+            }
+            Err(_) => Err(DoctypeError::UnencodableCharacter),
+        }
+    }
+
     /// The public identifier of the doctype.
     #[inline]
     pub fn public_id(&self) -> Option<String> {
         self.public_id.as_ref().map(|i| i.as_string(self.encoding))
     }
 
+    /// Sets the public identifier of the doctype. The identifier is removed if `id` is `None`.
+    #[inline]
+    pub fn set_public_id(&mut self, id: Option<&str>) -> Result<(), DoctypeError> {
+        self.public_id = self.id_bytes_from_str(id)?;
+        self.raw = None;
+
+        Ok(())
+    }
+
     /// The system identifier of the doctype.
     #[inline]
     pub fn system_id(&self) -> Option<String> {
         self.system_id.as_ref().map(|i| i.as_string(self.encoding))
     }
 
+    /// Sets the system identifier of the doctype. The identifier is removed if `id` is `None`.
+    #[inline]
+    pub fn set_system_id(&mut self, id: Option<&str>) -> Result<(), DoctypeError> {
+        self.system_id = self.id_bytes_from_str(id)?;
+        self.raw = None;
+
+        Ok(())
+    }
+
+    /// Inserts `content` before the doctype.
+    ///
+    /// Consequent calls to the method append `content` to the previously inserted content.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, doctype, RewriteStrSettings};
+    /// use lol_html::html_content::ContentType;
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<!DOCTYPE html>"#,
+    ///     RewriteStrSettings {
+    ///         document_content_handlers: vec![
+    ///             doctype!(|d| {
+    ///                 d.before("<!-- 42 -->", ContentType::Html);
+    ///                 d.before("bar", ContentType::Text);
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<!-- 42 -->bar<!DOCTYPE html>"#);
+    /// ```
+    #[inline]
+    pub fn before(&mut self, content: &str, content_type: ContentType) {
+        self.mutations.before(content, content_type);
+    }
+
+    /// Inserts `content` after the doctype.
+    ///
+    /// Consequent calls to the method prepend `content` to the previously inserted content.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, doctype, RewriteStrSettings};
+    /// use lol_html::html_content::ContentType;
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<!DOCTYPE html><html>"#,
+    ///     RewriteStrSettings {
+    ///         document_content_handlers: vec![
+    ///             doctype!(|d| {
+    ///                 d.after("<meta charset=utf-8>", ContentType::Html);
+    ///                 d.after("<!-- 42 -->", ContentType::Html);
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<!DOCTYPE html><!-- 42 --><meta charset=utf-8><html>"#);
+    /// ```
+    #[inline]
+    pub fn after(&mut self, content: &str, content_type: ContentType) {
+        self.mutations.after(content, content_type);
+    }
+
+    /// Replaces the doctype with the `content`.
+    ///
+    /// Consequent calls to the method overwrite previous replacement content.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, doctype, RewriteStrSettings};
+    /// use lol_html::html_content::ContentType;
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 4.01//EN"><html>"#,
+    ///     RewriteStrSettings {
+    ///         document_content_handlers: vec![
+    ///             doctype!(|d| {
+    ///                 d.replace("<!DOCTYPE html>", ContentType::Html);
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<!DOCTYPE html><html>"#);
+    /// ```
+    #[inline]
+    pub fn replace(&mut self, content: &str, content_type: ContentType) {
+        self.mutations.replace(content, content_type);
+    }
+
+    /// Removes the doctype.
+    #[inline]
+    pub fn remove(&mut self) {
+        self.mutations.remove();
+    }
+
+    /// Returns `true` if the doctype has been replaced or removed.
+    #[inline]
+    pub fn removed(&self) -> bool {
+        self.mutations.removed()
+    }
+
+    /// Returns the location of the doctype in the input.
+    #[inline]
+    pub fn source_location(&self) -> SourceLocation {
+        self.location
+    }
+
+    /// Returns the bytes of the doctype as they appear in the input, in the document's
+    /// [`encoding`].
+    ///
+    /// The bytes are not affected by the modifications of the doctype.
+    ///
+    /// [`encoding`]: ../struct.Settings.html#structfield.encoding
+    #[inline]
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.source
+    }
+
     #[inline]
     #[cfg(feature = "integration_test")]
     pub fn force_quirks(&self) -> bool {
         self.force_quirks
     }
-}
 
-impl_user_data!(Doctype<'_>);
+    #[inline]
+    fn raw(&self) -> Option<&Bytes<'_>> {
+        self.raw.as_ref()
+    }
 
-impl Serialize for Doctype<'_> {
     #[inline]
-    fn to_bytes(&self, output_handler: &mut dyn FnMut(&[u8])) {
-        output_handler(&self.raw);
+    fn serialize_from_parts(&self, output_handler: &mut dyn FnMut(&[u8])) {
+        let serialize_id = |id: &Bytes, output_handler: &mut dyn FnMut(&[u8])| {
+            let quote: &[u8] = if id.contains(&b'"') { b"'" } else { b"\"" };
+
+            output_handler(b" ");
+            output_handler(quote);
+            output_handler(id);
+            output_handler(quote);
+        };
+
+        output_handler(b"<!DOCTYPE");
+
+        if let Some(name) = &self.name {
+            output_handler(b" ");
+            output_handler(name);
+        }
+
+        if let Some(public_id) = &self.public_id {
+            output_handler(b" PUBLIC");
+            serialize_id(public_id, output_handler);
+
+            if let Some(system_id) = &self.system_id {
+                serialize_id(system_id, output_handler);
+            }
+        } else if let Some(system_id) = &self.system_id {
+            output_handler(b" SYSTEM");
+            serialize_id(system_id, output_handler);
+        }
+
+        output_handler(b">");
     }
 }
 
+impl_user_data!(Doctype<'_>);
+
+impl_serialize!(Doctype);
+
 impl Debug for Doctype<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Doctype")
@@ -110,10 +369,11 @@ impl Debug for Doctype<'_> {
 
 #[cfg(test)]
 mod tests {
+    use crate::errors::*;
     use crate::html_content::*;
     use crate::rewritable_units::test_utils::*;
     use crate::*;
-    use encoding_rs::{Encoding, UTF_8};
+    use encoding_rs::{Encoding, EUC_JP, UTF_8};
 
     fn rewrite_doctype(
         html: &[u8],
@@ -152,11 +412,160 @@ mod tests {
     }
 
     #[test]
-    fn serialization() {
-        for (html, enc) in encoded(r#"<!DOCTYPE html SYSTEM "Ĥey">"#) {
-            let output = rewrite_doctype(&html, enc, |_| {});
+    fn invalid_name() {
+        rewrite_doctype(b"<!doctype html>", UTF_8, |d| {
+            assert_eq!(d.set_name("").unwrap_err(), DoctypeError::EmptyName);
+
+            assert_eq!(
+                d.set_name("foo bar").unwrap_err(),
+                DoctypeError::ForbiddenCharacterInName(' ')
+            );
+
+            assert_eq!(
+                d.set_name("foo>").unwrap_err(),
+                DoctypeError::ForbiddenCharacterInName('>')
+            );
+        });
+    }
+
+    #[test]
+    fn invalid_ids() {
+        rewrite_doctype(b"<!doctype html>", UTF_8, |d| {
+            assert_eq!(
+                d.set_public_id(Some("foo>")).unwrap_err(),
+                DoctypeError::ClosingCharacterInIdentifier
+            );
+
+            assert_eq!(
+                d.set_system_id(Some(r#"foo'"bar"#)).unwrap_err(),
+                DoctypeError::MixedQuotesInIdentifier
+            );
+        });
+    }
+
+    #[test]
+    fn encoding_unmappable_chars() {
+        rewrite_doctype(b"<!doctype html>", EUC_JP, |d| {
+            assert_eq!(
+                d.set_name("foo\u{00F8}bar").unwrap_err(),
+                DoctypeError::UnencodableCharacter
+            );
+
+            assert_eq!(
+                d.set_public_id(Some("foo\u{00F8}bar")).unwrap_err(),
+                DoctypeError::UnencodableCharacter
+            );
+        });
+    }
+
+    mod serialization {
+        use super::*;
+
+        const HTML: &str = r#"<!DOCTYPE html SYSTEM "Ĥey">"#;
+
+        macro_rules! test {
+            ($handler:expr, $expected:expr) => {
+                for (html, enc) in encoded(HTML) {
+                    assert_eq!(rewrite_doctype(&html, enc, $handler), $expected);
+                }
+            };
+        }
+
+        #[test]
+        fn parsed() {
+            test!(|_| {}, r#"<!DOCTYPE html SYSTEM "Ĥey">"#);
+        }
+
+        #[test]
+        fn modified_name() {
+            test!(
+                |d| {
+                    d.set_name("svgé").unwrap();
+
+                    assert_eq!(d.name(), Some("svgé".into()));
+                },
+                r#"<!DOCTYPE svgé SYSTEM "Ĥey">"#
+            );
+        }
+
+        #[test]
+        fn modified_ids() {
+            test!(
+                |d| {
+                    d.set_public_id(Some(r#"-//"Ĥey"//EN"#)).unwrap();
+
+                    assert_eq!(d.public_id(), Some(r#"-//"Ĥey"//EN"#.into()));
+                },
+                r#"<!DOCTYPE html PUBLIC '-//"Ĥey"//EN' "Ĥey">"#
+            );
+
+            test!(
+                |d| {
+                    d.set_system_id(None).unwrap();
+
+                    assert_eq!(d.system_id(), None);
+                },
+                "<!DOCTYPE html>"
+            );
+
+            test!(
+                |d| {
+                    d.set_public_id(Some("foo")).unwrap();
+                    d.set_system_id(None).unwrap();
+                },
+                r#"<!DOCTYPE html PUBLIC "foo">"#
+            );
+        }
+
+        #[test]
+        fn with_prepends_and_appends() {
+            test!(
+                |d| {
+                    d.before("<span>", ContentType::Text);
+                    d.before("<!-- 42é -->", ContentType::Html);
+                    d.after("<meta charset=utf-8>", ContentType::Html);
+                    d.after("<foo & bar>", ContentType::Text);
+                },
+                concat!(
+                    r#"&lt;span&gt;<!-- 42é --><!DOCTYPE html SYSTEM "Ĥey">"#,
+                    "&lt;foo &amp; bar&gt;<meta charset=utf-8>",
+                )
+            );
+        }
+
+        #[test]
+        fn removed() {
+            test!(
+                |d| {
+                    assert!(!d.removed());
+
+                    d.remove();
+
+                    assert!(d.removed());
+
+                    d.before("<before>", ContentType::Html);
+                    d.after("<after>", ContentType::Html);
+                },
+                "<before><after>"
+            );
+        }
+
+        #[test]
+        fn replaced() {
+            test!(
+                |d| {
+                    d.before("<before>", ContentType::Html);
+                    d.after("<after>", ContentType::Html);
+
+                    assert!(!d.removed());
+
+                    d.replace("<!DOCTYPE svg>", ContentType::Html);
+                    d.replace("<!DOCTYPE html>", ContentType::Html);
 
-            assert_eq!(output, r#"<!DOCTYPE html SYSTEM "Ĥey">"#);
+                    assert!(d.removed());
+                },
+                "<before><!DOCTYPE html><after>"
+            );
         }
     }
 }
@@ -1,13 +1,20 @@
 use super::{Mutations, Token};
 use crate::base::Bytes;
+use crate::rewritable_units::{tag_name_bytes_from_str, ContentType, SourceLocation, TagNameError};
 use encoding_rs::Encoding;
 use std::fmt::{self, Debug};
 
+/// An HTML end tag rewritable unit.
+///
+/// End tags are passed to the handlers registered with [`Element::on_end_tag`].
+///
+/// [`Element::on_end_tag`]: struct.Element.html#method.on_end_tag
 pub struct EndTag<'i> {
     name: Bytes<'i>,
     raw: Option<Bytes<'i>>,
     encoding: &'static Encoding,
-    pub mutations: Mutations,
+    location: SourceLocation,
+    pub(crate) mutations: Mutations,
 }
 
 impl<'i> EndTag<'i> {
@@ -15,26 +22,84 @@ impl<'i> EndTag<'i> {
         name: Bytes<'i>,
         raw: Bytes<'i>,
         encoding: &'static Encoding,
+        location: SourceLocation,
     ) -> Token<'i> {
         Token::EndTag(EndTag {
             name,
             raw: Some(raw),
             encoding,
+            location,
             mutations: Mutations::new(encoding),
         })
     }
 
+    /// Returns the name of the end tag.
     #[inline]
     pub fn name(&self) -> String {
         self.name.as_lowercase_string(self.encoding)
     }
 
+    /// Sets the name of the end tag.
     #[inline]
-    pub fn set_name(&mut self, name: Bytes<'static>) {
+    pub fn set_name(&mut self, name: &str) -> Result<(), TagNameError> {
+        let name = tag_name_bytes_from_str(name, self.encoding)?;
+
+        self.set_name_raw(name);
+
+        Ok(())
+    }
+
+    /// Returns the location of the end tag in the input.
+    ///
+    /// If the end tag was implicitly closed, then it's the location of the end tag that
+    /// closed it.
+    #[inline]
+    pub fn source_location(&self) -> SourceLocation {
+        self.location
+    }
+
+    #[inline]
+    pub(crate) fn set_name_raw(&mut self, name: Bytes<'static>) {
         self.name = name;
         self.raw = None;
     }
 
+    /// Inserts `content` before the end tag.
+    ///
+    /// Consequent calls to the method append `content` to the previously inserted content.
+    #[inline]
+    pub fn before(&mut self, content: &str, content_type: ContentType) {
+        self.mutations.before(content, content_type);
+    }
+
+    /// Inserts `content` after the end tag.
+    ///
+    /// Consequent calls to the method prepend `content` to the previously inserted content.
+    #[inline]
+    pub fn after(&mut self, content: &str, content_type: ContentType) {
+        self.mutations.after(content, content_type);
+    }
+
+    /// Replaces the end tag with the `content`.
+    ///
+    /// Consequent calls to the method overwrite previous replacement content.
+    #[inline]
+    pub fn replace(&mut self, content: &str, content_type: ContentType) {
+        self.mutations.replace(content, content_type);
+    }
+
+    /// Removes the end tag.
+    #[inline]
+    pub fn remove(&mut self) {
+        self.mutations.remove();
+    }
+
+    /// Returns `true` if the end tag has been replaced or removed.
+    #[inline]
+    pub fn removed(&self) -> bool {
+        self.mutations.removed()
+    }
+
     #[inline]
     fn raw(&self) -> Option<&Bytes> {
         self.raw.as_ref()
@@ -52,7 +52,7 @@ mod start_tag;
 mod text_chunk;
 
 pub use self::comment::{Comment, CommentTextError};
-pub use self::doctype::Doctype;
+pub use self::doctype::{Doctype, DoctypeError};
 pub use self::end_tag::EndTag;
 pub use self::start_tag::StartTag;
 pub use self::text_chunk::TextChunk;
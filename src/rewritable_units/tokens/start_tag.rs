@@ -2,6 +2,7 @@ use super::{Attribute, AttributeNameError, Attributes};
 use super::{Mutations, Serialize, Token};
 use crate::base::Bytes;
 use crate::html::Namespace;
+use crate::rewritable_units::SourceLocation;
 use encoding_rs::Encoding;
 use std::fmt::{self, Debug};
 
@@ -11,7 +12,9 @@ pub struct StartTag<'i> {
     ns: Namespace,
     self_closing: bool,
     raw: Option<Bytes<'i>>,
+    source: Bytes<'i>,
     encoding: &'static Encoding,
+    location: SourceLocation,
     pub mutations: Mutations,
 }
 
@@ -23,14 +26,17 @@ impl<'i> StartTag<'i> {
         self_closing: bool,
         raw: Bytes<'i>,
         encoding: &'static Encoding,
+        location: SourceLocation,
     ) -> Token<'i> {
         Token::StartTag(StartTag {
             name,
             attributes,
             ns,
             self_closing,
-            raw: Some(raw),
+            raw: Some(raw.clone()),
+            source: raw,
             encoding,
+            location,
             mutations: Mutations::new(encoding),
         })
     }
@@ -40,6 +46,16 @@ impl<'i> StartTag<'i> {
         self.encoding
     }
 
+    #[inline]
+    pub fn source_location(&self) -> SourceLocation {
+        self.location
+    }
+
+    #[inline]
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.source
+    }
+
     #[inline]
     pub fn name(&self) -> String {
         self.name.as_lowercase_string(self.encoding)
@@ -115,7 +131,7 @@ impl<'i> StartTag<'i> {
     }
 
     #[cfg(test)]
-    pub fn raw_attributes(&self) -> (&'i Bytes<'i>, crate::parser::SharedAttributeBuffer) {
+    pub fn raw_attributes(&self) -> (&'i Bytes<'i>, &'i [crate::parser::AttributeOutline]) {
         self.attributes.raw_attributes()
     }
 }
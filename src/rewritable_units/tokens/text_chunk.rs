@@ -1,6 +1,8 @@
 use super::{Mutations, Token};
 use crate::base::Bytes;
-use crate::html::TextType;
+use crate::html::{decode_char_refs, TextType};
+use crate::rewritable_units::{ContentType, SourceLocation};
+use crate::selectors_vm::{Ancestors, TreePosition};
 use encoding_rs::Encoding;
 use std::any::Any;
 use std::borrow::Cow;
@@ -55,26 +57,36 @@ use std::fmt::{self, Debug};
 /// [`last_in_text_node`]: #method.last_in_text_node
 pub struct TextChunk<'i> {
     text: Cow<'i, str>,
+    source: Bytes<'i>,
     text_type: TextType,
     last_in_text_node: bool,
     encoding: &'static Encoding,
+    location: SourceLocation,
     mutations: Mutations,
+    matched_rule_ids: Vec<usize>,
+    position: TreePosition,
     user_data: Box<dyn Any>,
 }
 
 impl<'i> TextChunk<'i> {
     pub(super) fn new_token(
-        text: &'i str,
+        text: Cow<'i, str>,
+        source: Bytes<'i>,
         text_type: TextType,
         last_in_text_node: bool,
         encoding: &'static Encoding,
+        location: SourceLocation,
     ) -> Token<'i> {
         Token::TextChunk(TextChunk {
-            text: text.into(),
+            text,
+            source,
             text_type,
             last_in_text_node,
             encoding,
+            location,
             mutations: Mutations::new(encoding),
+            matched_rule_ids: Vec::new(),
+            position: TreePosition::default(),
             user_data: Box::new(()),
         })
     }
@@ -85,6 +97,58 @@ impl<'i> TextChunk<'i> {
         &*self.text
     }
 
+    /// Returns the textual content of the chunk with the [character references] decoded.
+    ///
+    /// Character references are decoded only in the [`Data`] and [`RCData`] text types, for
+    /// the other text types the content is returned as is. Character references are never split
+    /// between chunks, so the decoded content of a text node is the concatenation of the decoded
+    /// content of its chunks.
+    ///
+    /// Use [`ContentType::DecodedText`] to insert content in the same form.
+    ///
+    /// [character references]: https://html.spec.whatwg.org/multipage/syntax.html#character-references
+    /// [`Data`]: enum.TextType.html#variant.Data
+    /// [`RCData`]: enum.TextType.html#variant.RCData
+    /// [`ContentType::DecodedText`]: enum.ContentType.html#variant.DecodedText
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lol_html::{rewrite_str, text, RewriteStrSettings};
+    /// use lol_html::html_content::ContentType;
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<div>Fish &amp; chips</div><script>"&amp;"</script>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             text!("div", |t| {
+    ///                 let text = t.decoded_text().replace("&", "and");
+    ///
+    ///                 t.replace(&text, ContentType::DecodedText);
+    ///
+    ///                 Ok(())
+    ///             }),
+    ///             text!("script", |t| {
+    ///                 assert_eq!(t.decoded_text(), t.as_str());
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<div>Fish and chips</div><script>"&amp;"</script>"#);
+    /// ```
+    #[inline]
+    pub fn decoded_text(&self) -> Cow<'_, str> {
+        if self.text_type.allows_html_entities() {
+            decode_char_refs(&self.text, false)
+        } else {
+            Cow::Borrowed(&self.text)
+        }
+    }
+
     /// Returns the type of the text in the chunk.
     ///
     /// The type of the text depends on the surrounding context of the text. E.g. regular visible
@@ -161,8 +225,9 @@ impl<'i> TextChunk<'i> {
     /// assert_eq!(html, r#"<div><!-- 42 -->Hello world</div>"#);
     /// ```
     #[inline]
-    pub fn before(&mut self, content: &str, content_type: crate::rewritable_units::ContentType) {
-        self.mutations.before(content, content_type);
+    pub fn before(&mut self, content: &str, content_type: ContentType) {
+        self.mutations
+            .before(content, self.resolve_content_type(content_type));
     }
 
     /// Inserts `content` after the text chunk.
@@ -195,8 +260,9 @@ impl<'i> TextChunk<'i> {
     /// assert_eq!(html, r#"<div>FooQuxBar</div>"#);
     /// ```
     #[inline]
-    pub fn after(&mut self, content: &str, content_type: crate::rewritable_units::ContentType) {
-        self.mutations.after(content, content_type);
+    pub fn after(&mut self, content: &str, content_type: ContentType) {
+        self.mutations
+            .after(content, self.resolve_content_type(content_type));
     }
 
     /// Replaces the text chunk with the `content`.
@@ -229,8 +295,9 @@ impl<'i> TextChunk<'i> {
     /// assert_eq!(html, r#"<div>Qux</div>"#);
     /// ```
     #[inline]
-    pub fn replace(&mut self, content: &str, content_type: crate::rewritable_units::ContentType) {
-        self.mutations.replace(content, content_type);
+    pub fn replace(&mut self, content: &str, content_type: ContentType) {
+        self.mutations
+            .replace(content, self.resolve_content_type(content_type));
     }
 
     /// Removes the text chunk.
@@ -245,6 +312,78 @@ impl<'i> TextChunk<'i> {
         self.mutations.removed()
     }
 
+    /// Returns the location of the text chunk in the input.
+    ///
+    /// The chunks of a text node have adjacent locations that cover the whole text node. Note
+    /// that the bytes of a character that is split between the chunks of the input belong to
+    /// the text chunk that precedes the character.
+    #[inline]
+    pub fn source_location(&self) -> SourceLocation {
+        self.location
+    }
+
+    /// Returns the bytes of the text chunk as they appear in the input, in the document's
+    /// [`encoding`], i.e. the bytes in the [`source_location`] of the text chunk.
+    ///
+    /// The bytes are not affected by the modifications of the text chunk.
+    ///
+    /// [`encoding`]: ../struct.Settings.html#structfield.encoding
+    /// [`source_location`]: #method.source_location
+    #[inline]
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.source
+    }
+
+    /// Returns the ids of the rules whose selectors matched one of the elements that contain
+    /// the text chunk, in ascending order.
+    ///
+    /// The id of a rule is the index of its selector and handlers pair in the
+    /// [`element_content_handlers`] setting.
+    ///
+    /// [`element_content_handlers`]: ../struct.Settings.html#structfield.element_content_handlers
+    #[inline]
+    pub fn matched_rule_ids(&self) -> &[usize] {
+        &self.matched_rule_ids
+    }
+
+    /// Returns an iterator over the elements that contain the text chunk, starting from
+    /// the innermost one.
+    ///
+    /// The ancestors are tracked only if the rewriter has [`element_content_handlers`] and
+    /// the [`track_ancestors`] setting is enabled.
+    ///
+    /// [`element_content_handlers`]: ../struct.Settings.html#structfield.element_content_handlers
+    /// [`track_ancestors`]: ../struct.Settings.html#structfield.track_ancestors
+    #[inline]
+    pub fn ancestors(&self) -> Ancestors<'_> {
+        self.position.ancestors()
+    }
+
+    /// Returns the number of the elements that contain the text chunk.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.position.depth()
+    }
+
+    #[inline]
+    pub(crate) fn set_matched_rule_ids(&mut self, matched_rule_ids: Vec<usize>) {
+        self.matched_rule_ids = matched_rule_ids;
+    }
+
+    #[inline]
+    pub(crate) fn set_position(&mut self, position: TreePosition) {
+        self.position = position;
+    }
+
+    /// Decoded text is inserted as is in the text types that don't have character references.
+    #[inline]
+    fn resolve_content_type(&self, content_type: ContentType) -> ContentType {
+        match content_type {
+            ContentType::DecodedText if !self.text_type.allows_html_entities() => ContentType::Html,
+            content_type => content_type,
+        }
+    }
+
     #[inline]
     fn raw(&self) -> Option<&Bytes> {
         None
@@ -313,6 +452,81 @@ mod tests {
         });
     }
 
+    #[test]
+    fn decoded_text() {
+        let html = "<p>Fish &amp; chips&#x21; &notin; &notit &#169</p><title>&lt;3</title>\
+                    <script>&amp;</script>";
+
+        // NOTE: the input is written byte by byte, so the character references span
+        // multiple input chunks.
+        for chunk_size in [1, 2, 7, html.len()].iter() {
+            let mut decoded = String::new();
+            let mut raw = String::new();
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        document_content_handlers: vec![doc_text!(|c| {
+                            decoded += &c.decoded_text();
+                            raw += c.as_str();
+
+                            if c.last_in_text_node() {
+                                decoded += "|";
+                            }
+
+                            Ok(())
+                        })],
+                        ..Settings::default()
+                    },
+                    |_: &[u8]| {},
+                )
+                .unwrap();
+
+                for chunk in html.as_bytes().chunks(*chunk_size) {
+                    rewriter.write(chunk).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            assert_eq!(decoded, "Fish & chips! ∉ ¬it ©|<3|&amp;|");
+            assert_eq!(raw, "Fish &amp; chips&#x21; &notin; &notit &#169&lt;3&amp;");
+        }
+    }
+
+    #[test]
+    fn long_numeric_char_ref() {
+        let zeros = "0".repeat(100);
+        let html = format!("&#{0}65;&#x{0}41 &#{0}", zeros);
+
+        for chunk_size in [1, 2, 7, html.len()].iter() {
+            let mut decoded = String::new();
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        document_content_handlers: vec![doc_text!(|c| {
+                            decoded += &c.decoded_text();
+
+                            Ok(())
+                        })],
+                        ..Settings::default()
+                    },
+                    |_: &[u8]| {},
+                )
+                .unwrap();
+
+                for chunk in html.as_bytes().chunks(*chunk_size) {
+                    rewriter.write(chunk).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            assert_eq!(decoded, "AA \u{FFFD}");
+        }
+    }
+
     mod serialization {
         use super::*;
 
@@ -366,6 +580,20 @@ mod tests {
             );
         }
 
+        #[test]
+        fn replaced_with_decoded_text() {
+            test!(
+                |c| {
+                    skip_eof_chunk!(c);
+                    c.replace(&c.decoded_text().to_uppercase(), ContentType::DecodedText);
+                },
+                "LOREM IPSUM DOLOR SIT AMET, CÔNSECTETUR ADIPISCING ELIT, SED DO EIUSMOD TEMPOR \
+                 INCIDIDUNT UT LABORE ET DOLORE MAGNA ALIQUA. UT ENIM AD MINIM VENIAM, QUIS \
+                 NOSTRUD EXERCITATION &amp; ULLAMCO LABORIS NISI UT ALIQUIP EX EA COMMODO &gt; \
+                 CONSEQUAT."
+            );
+        }
+
         #[test]
         fn removed() {
             test!(
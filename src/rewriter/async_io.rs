@@ -0,0 +1,533 @@
+use super::reader::OutputBuffer;
+use super::{ContentHandlerTypes, HtmlRewriter, LocalHandlerTypes, SettingsError};
+use super::{RewritingError, Settings};
+use bytes::Bytes;
+use futures_core::Stream;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+const INPUT_CHUNK_SIZE: usize = 8192;
+
+/// A stream that rewrites the chunks of the underlying stream, e.g. an HTTP body.
+///
+/// The source stream is polled only when all the rewritten output produced so far has been
+/// consumed, so the backpressure of the consumer is propagated to the source. Each item of the
+/// stream contains the output that the rewriter has produced for a chunk of the source. Some
+/// chunks might produce no output, e.g. if they contain an incomplete tag, in which case the
+/// next chunk is polled. The rewriting is finalized once the source stream ends.
+///
+/// The errors of the source stream and [`RewritingError`]s (converted into the error type of the
+/// stream with `From`) are yielded as the last item of the stream, i.e. the stream ends after
+/// such an error and doesn't poll the source again. The output that the rewriter has produced
+/// for the chunk that caused a [`RewritingError`] is discarded.
+///
+/// This type is available only with the `async` feature.
+///
+/// # Example
+/// ```
+/// use lol_html::{element, RewritingStream, Settings};
+/// # use futures_core::Stream;
+/// # use std::pin::Pin;
+/// # use std::task::{Context, Poll};
+/// # struct Chunks(Vec<&'static [u8]>);
+/// # impl Stream for Chunks {
+/// #     type Item = Result<&'static [u8], std::io::Error>;
+/// #     fn poll_next(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<Option<Self::Item>> {
+/// #         Poll::Ready(if self.0.is_empty() { None } else { Some(Ok(self.0.remove(0))) })
+/// #     }
+/// # }
+/// # let body = Chunks(vec![b"<div><a href=", b"http://example.com>", b"</a></div>"]);
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+///
+/// let mut stream = RewritingStream::try_new(
+///     Settings {
+///         element_content_handlers: vec![element!("a[href]", |el| {
+///             let href = el.get_attribute("href").unwrap().replace("http:", "https:");
+///
+///             el.set_attribute("href", &href).unwrap();
+///
+///             Ok(())
+///         })],
+///         ..Settings::default()
+///     },
+///     body,
+/// ).unwrap();
+///
+/// let mut output = vec![];
+///
+/// while let Some(chunk) = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+///     output.extend_from_slice(&chunk.unwrap());
+/// }
+///
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     r#"<div><a href="https://example.com"></a></div>"#
+/// );
+/// # });
+/// ```
+///
+/// [`RewritingError`]: errors/enum.RewritingError.html
+pub struct RewritingStream<'h, S, H: ContentHandlerTypes<'h> = LocalHandlerTypes> {
+    rewriter: HtmlRewriter<'h, OutputBuffer, H>,
+    source: S,
+    finished: bool,
+}
+
+impl<'h, S, H: ContentHandlerTypes<'h>> RewritingStream<'h, S, H> {
+    /// Constructs a new stream that rewrites the chunks of the `source` stream with
+    /// the provided `settings`.
+    pub fn try_new<'s>(settings: Settings<'h, 's, H>, source: S) -> Result<Self, SettingsError> {
+        Ok(RewritingStream {
+            rewriter: HtmlRewriter::try_new(settings, OutputBuffer::default())?,
+            source,
+            finished: false,
+        })
+    }
+}
+
+impl<'h, S, B, E, H> Stream for RewritingStream<'h, S, H>
+where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: From<RewritingError<H::Error>>,
+    H: ContentHandlerTypes<'h>,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            let output = this.rewriter.stream.with_output_sink(OutputBuffer::take);
+
+            if !output.is_empty() {
+                return Poll::Ready(Some(Ok(Bytes::from(output))));
+            } else if this.finished {
+                return Poll::Ready(None);
+            }
+
+            let res = match Pin::new(&mut this.source).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => this.rewriter.write(chunk.as_ref()),
+                Poll::Ready(Some(Err(err))) => {
+                    this.finished = true;
+
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Ready(None) => {
+                    this.finished = true;
+                    this.rewriter.end()
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+
+            if let Err(err) = res {
+                this.finished = true;
+                this.rewriter.stream.with_output_sink(OutputBuffer::take);
+
+                return Poll::Ready(Some(Err(err.into())));
+            }
+        }
+    }
+}
+
+/// A reader that pulls the input from the underlying [`AsyncRead`] source and yields
+/// the rewritten output.
+///
+/// This is an asynchronous counterpart of [`RewritingReader`]: the source is read only when
+/// all the rewritten output produced so far has been consumed, and the rewriting is finalized
+/// once the source reaches EOF. Errors of the source are returned as is, and [`RewritingError`]s
+/// are converted into [`io::Error`]s of the [`Other`] kind. After a [`RewritingError`] the reader
+/// yields no more data: the output produced for the chunk that caused the error is discarded, and
+/// all the reads that follow fail with an error of the same kind.
+///
+/// This type is available only with the `async` feature.
+///
+/// [`AsyncRead`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncRead.html
+/// [`RewritingReader`]: struct.RewritingReader.html
+/// [`RewritingError`]: errors/enum.RewritingError.html
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+/// [`Other`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.Other
+pub struct AsyncRewritingReader<'h, R, H: ContentHandlerTypes<'h> = LocalHandlerTypes> {
+    rewriter: HtmlRewriter<'h, OutputBuffer, H>,
+    source: R,
+    input_buffer: Vec<u8>,
+    finished: bool,
+}
+
+impl<'h, R, H: ContentHandlerTypes<'h>> AsyncRewritingReader<'h, R, H> {
+    /// Constructs a new reader that rewrites the input from the `source` with
+    /// the provided `settings`.
+    pub fn try_new<'s>(settings: Settings<'h, 's, H>, source: R) -> Result<Self, SettingsError> {
+        Ok(AsyncRewritingReader {
+            rewriter: HtmlRewriter::try_new(settings, OutputBuffer::default())?,
+            source,
+            input_buffer: vec![0; INPUT_CHUNK_SIZE],
+            finished: false,
+        })
+    }
+
+    /// Unwraps the reader, returning the underlying source.
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+}
+
+impl<'h, R, H> AsyncRead for AsyncRewritingReader<'h, R, H>
+where
+    R: AsyncRead + Unpin,
+    H: ContentHandlerTypes<'h>,
+    io::Error: From<RewritingError<H::Error>>,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if let Some(err) = this.rewriter.poisoned_io_error() {
+            return Poll::Ready(Err(err));
+        }
+
+        loop {
+            let read_len = this
+                .rewriter
+                .stream
+                .with_output_sink(|o| o.read_with(buf.remaining(), |data| buf.put_slice(data)));
+
+            if read_len > 0 || this.finished || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut input = ReadBuf::new(&mut this.input_buffer);
+
+            match Pin::new(&mut this.source).poll_read(cx, &mut input) {
+                Poll::Ready(Ok(())) => (),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+
+            let input_len = input.filled().len();
+
+            let res = if input_len > 0 {
+                this.rewriter.write(&this.input_buffer[..input_len])
+            } else {
+                this.finished = true;
+                this.rewriter.end()
+            };
+
+            if let Err(err) = res {
+                this.rewriter.stream.with_output_sink(OutputBuffer::take);
+
+                return Poll::Ready(Err(err.into()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_content::ContentType;
+    use crate::*;
+    use bytes::Bytes;
+    use std::future::{poll_fn, Future};
+    use tokio::io::AsyncReadExt;
+
+    // NOTE: a source that yields the input in chunks of the given size and is pending
+    // before each chunk.
+    struct ChunkedSource<'i> {
+        input: &'i [u8],
+        chunk_size: usize,
+        ready: bool,
+    }
+
+    impl ChunkedSource<'_> {
+        fn poll_chunk(&mut self, cx: &mut Context) -> Poll<&[u8]> {
+            if self.ready {
+                let len = self.chunk_size.min(self.input.len());
+                let (chunk, rest) = self.input.split_at(len);
+
+                self.input = rest;
+                self.ready = false;
+
+                Poll::Ready(chunk)
+            } else {
+                self.ready = true;
+                cx.waker().wake_by_ref();
+
+                Poll::Pending
+            }
+        }
+    }
+
+    impl<'i> Stream for ChunkedSource<'i> {
+        type Item = Result<Vec<u8>, io::Error>;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+            self.get_mut().poll_chunk(cx).map(|chunk| {
+                if chunk.is_empty() {
+                    None
+                } else {
+                    Some(Ok(chunk.to_vec()))
+                }
+            })
+        }
+    }
+
+    impl AsyncRead for ChunkedSource<'_> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut ReadBuf,
+        ) -> Poll<io::Result<()>> {
+            self.get_mut().poll_chunk(cx).map(|chunk| {
+                buf.put_slice(chunk);
+
+                Ok(())
+            })
+        }
+    }
+
+    // NOTE: a stream that fails after yielding the input.
+    struct FailingSource(Option<&'static [u8]>);
+
+    impl Stream for FailingSource {
+        type Item = Result<&'static [u8], io::Error>;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<Option<Self::Item>> {
+            Poll::Ready(Some(match self.0.take() {
+                Some(chunk) => Ok(chunk),
+                None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "Broken body")),
+            }))
+        }
+    }
+
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    macro_rules! settings {
+        () => {
+            settings!(Settings::default())
+        };
+        ($defaults:expr) => {
+            Settings {
+                element_content_handlers: vec![
+                    element!("div", |el| {
+                        el.set_tag_name("span").unwrap();
+
+                        Ok(())
+                    }),
+                    element!("p", |_| Err("Error in handler".into())),
+                ],
+                document_content_handlers: vec![end!(|end| {
+                    end.append("<!-- end -->", ContentType::Html);
+
+                    Ok(())
+                })],
+                ..$defaults
+            }
+        };
+    }
+
+    async fn collect<S, E>(mut stream: S) -> (Vec<u8>, Option<E>)
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+    {
+        let mut output = vec![];
+
+        while let Some(chunk) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+            match chunk {
+                Ok(chunk) => output.extend_from_slice(&chunk),
+                Err(err) => {
+                    assert!(poll_fn(|cx| Pin::new(&mut stream).poll_next(cx))
+                        .await
+                        .is_none());
+
+                    return (output, Some(err));
+                }
+            }
+        }
+
+        (output, None)
+    }
+
+    #[test]
+    fn stream_rewriting() {
+        let input = "<div>foo</div><span>bar</span>";
+
+        for chunk_size in 1..=input.len() {
+            let stream = RewritingStream::try_new(
+                settings!(),
+                ChunkedSource {
+                    input: input.as_bytes(),
+                    chunk_size,
+                    ready: false,
+                },
+            )
+            .unwrap();
+
+            let (output, err) = block_on(collect::<_, io::Error>(stream));
+
+            assert!(err.is_none());
+
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "<span>foo</span><span>bar</span><!-- end -->",
+                "Chunk size: {}",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn stream_rewriting_error() {
+        let stream = RewritingStream::try_new(
+            settings!(),
+            ChunkedSource {
+                input: b"<div></div><p></p>",
+                chunk_size: 6,
+                ready: false,
+            },
+        )
+        .unwrap();
+
+        let (output, err) = block_on(collect::<_, io::Error>(stream));
+        let err = err.unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "<span></span>");
+
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(err.to_string(), "Error in handler");
+    }
+
+    #[test]
+    fn stream_source_error() {
+        let stream =
+            RewritingStream::try_new(settings!(), FailingSource(Some(b"<div>foo"))).unwrap();
+        let (output, err) = block_on(collect::<_, io::Error>(stream));
+
+        assert_eq!(String::from_utf8(output).unwrap(), "<span>foo");
+        assert_eq!(err.unwrap().kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn async_reader_rewriting() {
+        let input = "<div>foo</div><span>bar</span>";
+
+        for chunk_size in 1..=input.len() {
+            let mut reader = AsyncRewritingReader::try_new(
+                settings!(),
+                ChunkedSource {
+                    input: input.as_bytes(),
+                    chunk_size,
+                    ready: false,
+                },
+            )
+            .unwrap();
+
+            let mut output = String::new();
+
+            block_on(reader.read_to_string(&mut output)).unwrap();
+
+            assert_eq!(
+                output, "<span>foo</span><span>bar</span><!-- end -->",
+                "Chunk size: {}",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn async_reader_rewriting_error() {
+        let mut reader =
+            AsyncRewritingReader::try_new(settings!(), &b"<div></div><p></p>"[..]).unwrap();
+        let mut output = vec![];
+        let err = block_on(reader.read_to_end(&mut output)).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(err.to_string(), "Error in handler");
+
+        for _ in 0..2 {
+            let err = block_on(reader.read(&mut [0; 16])).unwrap_err();
+
+            assert_eq!(err.kind(), io::ErrorKind::Other);
+            assert!(err.to_string().ends_with("Error in handler"));
+        }
+    }
+
+    #[test]
+    fn async_reader_end_handler_error() {
+        let mut reader = AsyncRewritingReader::try_new(
+            Settings {
+                document_content_handlers: vec![end!(|_| Err("Error in end handler".into()))],
+                ..Settings::default()
+            },
+            &b"<div></div>"[..],
+        )
+        .unwrap();
+
+        let err = block_on(reader.read_to_end(&mut vec![])).unwrap_err();
+
+        assert_eq!(err.to_string(), "Error in end handler");
+
+        // NOTE: the source has reached EOF, but the reader must not report the end of the output.
+        let err = block_on(reader.read(&mut [0; 16])).unwrap_err();
+
+        assert!(err.to_string().ends_with("Error in end handler"));
+    }
+
+    #[test]
+    fn send_stream_and_async_reader() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let input = "<div>foo</div><span>bar</span>";
+
+        let stream = RewritingStream::try_new(
+            settings!(Settings::new_send()),
+            ChunkedSource {
+                input: input.as_bytes(),
+                chunk_size: 4,
+                ready: false,
+            },
+        )
+        .unwrap();
+
+        assert_send(&stream);
+
+        let collect_future = collect::<_, io::Error>(stream);
+
+        assert_send(&collect_future);
+
+        let (output, err) = block_on(collect_future);
+
+        assert!(err.is_none());
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<span>foo</span><span>bar</span><!-- end -->"
+        );
+
+        let mut reader =
+            AsyncRewritingReader::try_new(settings!(Settings::new_send()), input.as_bytes())
+                .unwrap();
+
+        assert_send(&reader);
+
+        let mut output = String::new();
+        let read_future = reader.read_to_string(&mut output);
+
+        assert_send(&read_future);
+
+        block_on(read_future).unwrap();
+
+        assert_eq!(output, "<span>foo</span><span>bar</span><!-- end -->");
+    }
+}
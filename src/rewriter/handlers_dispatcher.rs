@@ -1,10 +1,15 @@
 use super::settings::*;
 use super::ElementDescriptor;
+use crate::memory::{MemoryLimitExceededError, SharedMemoryLimiter};
 use crate::rewritable_units::{DocumentEnd, Element, StartTag, Token, TokenCaptureFlags};
-use crate::selectors_vm::MatchInfo;
+use crate::selectors_vm::{MatchInfo, TreePosition};
+use encoding_rs::Encoding;
+use std::mem;
 
 #[derive(Copy, Clone, Default, Debug, PartialEq, Eq, Hash)]
 pub struct SelectorHandlersLocator {
+    /// The index of the rule in the `element_content_handlers` setting.
+    pub rule_id: usize,
     pub element_handler_idx: Option<usize>,
     pub comment_handler_idx: Option<usize>,
     pub text_handler_idx: Option<usize>,
@@ -13,6 +18,7 @@ pub struct SelectorHandlersLocator {
 struct HandlerVecItem<H> {
     handler: H,
     user_count: usize,
+    always_active: bool,
 }
 
 struct HandlerVec<H> {
@@ -35,6 +41,7 @@ impl<H> HandlerVec<H> {
         let item = HandlerVecItem {
             handler,
             user_count: if always_active { 1 } else { 0 },
+            always_active,
         };
 
         self.user_count += item.user_count;
@@ -46,6 +53,23 @@ impl<H> HandlerVec<H> {
         self.items.len()
     }
 
+    /// Deactivates all the handlers, except the ones that are always active.
+    #[inline]
+    pub fn reset_user_counts(&mut self) {
+        self.user_count = 0;
+
+        for item in self.items.iter_mut() {
+            item.user_count = if item.always_active { 1 } else { 0 };
+            self.user_count += item.user_count;
+        }
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.user_count = 0;
+    }
+
     #[inline]
     pub fn inc_user_count(&mut self, idx: usize) {
         self.items[idx].user_count += 1;
@@ -64,10 +88,10 @@ impl<H> HandlerVec<H> {
     }
 
     #[inline]
-    pub fn for_each_active(
+    pub fn for_each_active<E: ?Sized>(
         &mut self,
-        mut cb: impl FnMut(&mut H) -> HandlerResult,
-    ) -> HandlerResult {
+        mut cb: impl FnMut(&mut H) -> HandlerResult<E>,
+    ) -> HandlerResult<E> {
         for item in self.items.iter_mut() {
             if item.user_count > 0 {
                 cb(&mut item.handler)?;
@@ -78,10 +102,10 @@ impl<H> HandlerVec<H> {
     }
 
     #[inline]
-    pub fn do_for_each_active_and_deactivate(
+    pub fn do_for_each_active_and_deactivate<E: ?Sized>(
         &mut self,
-        mut cb: impl FnMut(&mut H) -> HandlerResult,
-    ) -> HandlerResult {
+        mut cb: impl FnMut(&mut H) -> HandlerResult<E>,
+    ) -> HandlerResult<E> {
         for item in self.items.iter_mut() {
             if item.user_count > 0 {
                 cb(&mut item.handler)?;
@@ -94,10 +118,10 @@ impl<H> HandlerVec<H> {
     }
 
     #[inline]
-    pub fn do_for_each_active_and_remove(
+    pub fn do_for_each_active_and_remove<E: ?Sized>(
         &mut self,
-        mut cb: impl FnMut(H) -> HandlerResult,
-    ) -> HandlerResult {
+        mut cb: impl FnMut(H) -> HandlerResult<E>,
+    ) -> HandlerResult<E> {
         for i in (0..self.items.len()).rev() {
             if self.items[i].user_count > 0 {
                 let item = self.items.remove(i);
@@ -112,21 +136,148 @@ impl<H> HandlerVec<H> {
     }
 }
 
-#[derive(Default)]
-pub struct ContentHandlersDispatcher<'h> {
-    doctype_handlers: HandlerVec<DoctypeHandler<'h>>,
-    comment_handlers: HandlerVec<CommentHandler<'h>>,
-    text_handlers: HandlerVec<TextHandler<'h>>,
-    end_tag_handlers: HandlerVec<EndTagHandler<'h>>,
-    element_handlers: HandlerVec<ElementHandler<'h>>,
-    end_handlers: HandlerVec<EndHandler<'h>>,
+struct InnerContentCapture<H: HandlerTypes> {
+    content: Vec<u8>,
+    transforms: Vec<H::InnerContentTransform>,
+    /// The number of the captured bytes counted against the memory limit.
+    memory_usage: usize,
+}
+
+/// The inner content of the open elements that is buffered for the transforms set with
+/// `Element::transform_inner_content`.
+struct InnerContentCaptures<H: HandlerTypes> {
+    items: Vec<InnerContentCapture<H>>,
+    /// The number of the captures that receive the output. The capture of an element becomes
+    /// active once its start tag has been emitted.
+    active_count: usize,
+    /// The number of the captures of the elements that have been closed, but whose end tag
+    /// hasn't been emitted yet.
+    closed_count: usize,
+    memory_limiter: SharedMemoryLimiter,
+    encoding: &'static Encoding,
+}
+
+impl<H: HandlerTypes> InnerContentCaptures<H> {
+    fn new(memory_limiter: SharedMemoryLimiter, encoding: &'static Encoding) -> Self {
+        InnerContentCaptures {
+            items: Vec::new(),
+            active_count: 0,
+            closed_count: 0,
+            memory_limiter,
+            encoding,
+        }
+    }
+
+    fn clear(&mut self) {
+        for capture in self.items.drain(..) {
+            self.memory_limiter.decrease_usage(capture.memory_usage);
+        }
+
+        self.active_count = 0;
+        self.closed_count = 0;
+    }
+
+    #[inline]
+    fn push(&mut self, content: Vec<u8>, transforms: Vec<H::InnerContentTransform>) {
+        self.items.push(InnerContentCapture {
+            content,
+            transforms,
+            memory_usage: 0,
+        });
+    }
+
+    #[inline]
+    fn append(&mut self, chunk: &[u8]) -> Result<(), MemoryLimitExceededError> {
+        self.memory_limiter.increase_usage(chunk.len())?;
+
+        let capture = &mut self.items[self.active_count - 1];
+
+        capture.content.extend_from_slice(chunk);
+        capture.memory_usage += chunk.len();
+
+        Ok(())
+    }
+
+    /// Applies the transforms of the `count` innermost captures, given that the outermost of
+    /// them ends with the `content_before` of the end tag, and returns the result.
+    fn complete(&mut self, count: usize, mut content_before: Vec<u8>) -> Vec<u8> {
+        let mut inner_content = Vec::new();
+
+        for i in 0..count {
+            let InnerContentCapture {
+                mut content,
+                transforms,
+                memory_usage,
+            } = self.items.pop().expect("Capture should be open");
+
+            self.memory_limiter.decrease_usage(memory_usage);
+
+            content.append(&mut inner_content);
+
+            if i == count - 1 {
+                content.append(&mut content_before);
+            }
+
+            let content = self
+                .encoding
+                .decode_without_bom_handling(&content)
+                .0
+                .into_owned();
+
+            let content = transforms.into_iter().fold(content, |c, t| t(c));
+
+            inner_content = self.encoding.encode(&content).0.into_owned();
+        }
+
+        self.active_count = self.items.len();
+
+        inner_content
+    }
+}
+
+pub struct ContentHandlersDispatcher<'h, H: ContentHandlerTypes<'h>> {
+    doctype_handlers: HandlerVec<H::DoctypeHandler>,
+    comment_handlers: HandlerVec<H::CommentHandler>,
+    text_handlers: HandlerVec<H::TextHandler>,
+    /// Whether the text handler with the same index receives whole text nodes.
+    text_node_handlers: Vec<bool>,
+    /// The number of the users of the active text handlers that receive whole text nodes.
+    active_text_node_handler_count: usize,
+    end_tag_handlers: HandlerVec<H::EndTagHandler>,
+    element_handlers: HandlerVec<H::ElementHandler>,
+    end_handlers: HandlerVec<H::EndHandler>,
     next_element_can_have_content: bool,
+    next_element_matched_rule_ids: Vec<usize>,
+    /// The number of the open elements matched by each rule.
+    rule_match_counts: Vec<usize>,
+    /// The ids of the rules that matched one of the open elements, in ascending order.
+    matched_rule_ids: Vec<usize>,
     matched_elements_with_removed_content: usize,
+    inner_content_captures: InnerContentCaptures<H>,
 }
 
-impl<'h> ContentHandlersDispatcher<'h> {
+impl<'h, H: ContentHandlerTypes<'h>> ContentHandlersDispatcher<'h, H> {
+    pub fn new(memory_limiter: SharedMemoryLimiter, encoding: &'static Encoding) -> Self {
+        ContentHandlersDispatcher {
+            doctype_handlers: HandlerVec::default(),
+            comment_handlers: HandlerVec::default(),
+            text_handlers: HandlerVec::default(),
+            text_node_handlers: Vec::new(),
+            active_text_node_handler_count: 0,
+            end_tag_handlers: HandlerVec::default(),
+            element_handlers: HandlerVec::default(),
+            end_handlers: HandlerVec::default(),
+            next_element_can_have_content: false,
+            next_element_matched_rule_ids: Vec::new(),
+            rule_match_counts: Vec::new(),
+            matched_rule_ids: Vec::new(),
+            matched_elements_with_removed_content: 0,
+            inner_content_captures: InnerContentCaptures::new(memory_limiter, encoding),
+        }
+    }
+
     #[inline]
-    pub fn add_document_content_handlers(&mut self, handlers: DocumentContentHandlers<'h>) {
+    pub fn add_document_content_handlers(&mut self, handlers: DocumentContentHandlers<'h, H>) {
         if let Some(handler) = handlers.doctype {
             self.doctype_handlers.push(handler, true);
         }
@@ -137,6 +288,11 @@ impl<'h> ContentHandlersDispatcher<'h> {
 
         if let Some(handler) = handlers.text {
             self.text_handlers.push(handler, true);
+            self.text_node_handlers.push(handlers.text_nodes);
+
+            if handlers.text_nodes {
+                self.active_text_node_handler_count += 1;
+            }
         }
 
         if let Some(handler) = handlers.end {
@@ -147,9 +303,15 @@ impl<'h> ContentHandlersDispatcher<'h> {
     #[inline]
     pub fn add_selector_associated_handlers(
         &mut self,
-        handlers: ElementContentHandlers<'h>,
+        handlers: ElementContentHandlers<'h, H>,
     ) -> SelectorHandlersLocator {
+        let rule_id = self.rule_match_counts.len();
+        let text_nodes = handlers.text_nodes;
+
+        self.rule_match_counts.push(0);
+
         SelectorHandlersLocator {
+            rule_id,
             element_handler_idx: handlers.element.map(|h| {
                 self.element_handlers.push(h, false);
                 self.element_handlers.len() - 1
@@ -160,27 +322,113 @@ impl<'h> ContentHandlersDispatcher<'h> {
             }),
             text_handler_idx: handlers.text.map(|h| {
                 self.text_handlers.push(h, false);
+                self.text_node_handlers.push(text_nodes);
                 self.text_handlers.len() - 1
             }),
         }
     }
 
+    /// Drops all the handlers, so the dispatcher can be filled with new ones.
+    pub fn clear_handlers(&mut self) {
+        self.doctype_handlers.clear();
+        self.comment_handlers.clear();
+        self.text_handlers.clear();
+        self.text_node_handlers.clear();
+        self.active_text_node_handler_count = 0;
+        self.end_tag_handlers.clear();
+        self.element_handlers.clear();
+        self.end_handlers.clear();
+        self.rule_match_counts.clear();
+    }
+
+    /// Deactivates the handlers of the selectors and drops the handlers and the captures of
+    /// the open elements, so the handlers can be used for another document.
+    pub fn reset(&mut self) {
+        self.doctype_handlers.reset_user_counts();
+        self.comment_handlers.reset_user_counts();
+        self.text_handlers.reset_user_counts();
+        self.element_handlers.reset_user_counts();
+        self.end_handlers.reset_user_counts();
+        self.end_tag_handlers.clear();
+
+        self.active_text_node_handler_count = self
+            .text_handlers
+            .items
+            .iter()
+            .zip(&self.text_node_handlers)
+            .filter(|(item, &text_nodes)| item.always_active && text_nodes)
+            .count();
+
+        self.next_element_can_have_content = false;
+        self.next_element_matched_rule_ids.clear();
+
+        for count in self.rule_match_counts.iter_mut() {
+            *count = 0;
+        }
+
+        self.matched_rule_ids.clear();
+        self.matched_elements_with_removed_content = 0;
+        self.inner_content_captures.clear();
+    }
+
     #[inline]
     pub fn has_matched_elements_with_removed_content(&self) -> bool {
         self.matched_elements_with_removed_content > 0
     }
 
+    #[inline]
+    pub fn has_active_text_node_handlers(&self) -> bool {
+        self.active_text_node_handler_count > 0
+    }
+
+    #[inline]
+    fn inc_rule_match_count(&mut self, rule_id: usize) {
+        self.rule_match_counts[rule_id] += 1;
+
+        if self.rule_match_counts[rule_id] == 1 {
+            if let Err(pos) = self.matched_rule_ids.binary_search(&rule_id) {
+                self.matched_rule_ids.insert(pos, rule_id);
+            }
+        }
+    }
+
+    #[inline]
+    fn dec_rule_match_count(&mut self, rule_id: usize) {
+        self.rule_match_counts[rule_id] -= 1;
+
+        if self.rule_match_counts[rule_id] == 0 {
+            if let Ok(pos) = self.matched_rule_ids.binary_search(&rule_id) {
+                self.matched_rule_ids.remove(pos);
+            }
+        }
+    }
+
+    /// Resets the matching state of the previous start tag before the selectors
+    /// are matched against the next one.
+    #[inline]
+    pub fn prepare_for_start_tag(&mut self) {
+        self.next_element_matched_rule_ids.clear();
+    }
+
     #[inline]
     pub fn start_matching(&mut self, match_info: MatchInfo<SelectorHandlersLocator>) {
         let locator = match_info.payload;
 
+        self.next_element_matched_rule_ids.push(locator.rule_id);
+
         if match_info.with_content {
+            self.inc_rule_match_count(locator.rule_id);
+
             if let Some(idx) = locator.comment_handler_idx {
                 self.comment_handlers.inc_user_count(idx);
             }
 
             if let Some(idx) = locator.text_handler_idx {
                 self.text_handlers.inc_user_count(idx);
+
+                if self.text_node_handlers[idx] {
+                    self.active_text_node_handler_count += 1;
+                }
             }
         }
 
@@ -194,12 +442,18 @@ impl<'h> ContentHandlersDispatcher<'h> {
     #[inline]
     pub fn stop_matching(&mut self, elem_desc: ElementDescriptor) {
         for locator in elem_desc.matched_content_handlers {
+            self.dec_rule_match_count(locator.rule_id);
+
             if let Some(idx) = locator.comment_handler_idx {
                 self.comment_handlers.dec_user_count(idx);
             }
 
             if let Some(idx) = locator.text_handler_idx {
                 self.text_handlers.dec_user_count(idx);
+
+                if self.text_node_handlers[idx] {
+                    self.active_text_node_handler_count -= 1;
+                }
             }
         }
 
@@ -210,24 +464,55 @@ impl<'h> ContentHandlersDispatcher<'h> {
         if elem_desc.remove_content {
             self.matched_elements_with_removed_content -= 1;
         }
+
+        if elem_desc.capture_inner_content {
+            self.inner_content_captures.closed_count += 1;
+        }
+    }
+
+    #[inline]
+    pub fn is_capturing_content(&self) -> bool {
+        self.inner_content_captures.active_count > 0
+    }
+
+    #[inline]
+    pub fn capture_content(&mut self, chunk: &[u8]) -> Result<(), MemoryLimitExceededError> {
+        self.inner_content_captures.append(chunk)
+    }
+
+    #[inline]
+    pub fn activate_content_captures(&mut self) {
+        self.inner_content_captures.active_count = self.inner_content_captures.items.len();
     }
 
     pub fn handle_start_tag(
         &mut self,
         start_tag: &mut StartTag,
         current_element_data: Option<&mut ElementDescriptor>,
-    ) -> HandlerResult {
+        position: TreePosition,
+    ) -> HandlerResult<H::Error> {
         if self.matched_elements_with_removed_content > 0 {
             start_tag.mutations.remove();
         }
 
-        let mut element = Element::new(start_tag, self.next_element_can_have_content);
+        let mut matched_rule_ids = mem::take(&mut self.next_element_matched_rule_ids);
+
+        matched_rule_ids.sort_unstable();
+
+        let mut element = Element::new(
+            start_tag,
+            self.next_element_can_have_content,
+            matched_rule_ids,
+            position,
+        );
 
         self.element_handlers
             .do_for_each_active_and_deactivate(|h| h(&mut element))?;
 
         if self.next_element_can_have_content {
             if let Some(elem_desc) = current_element_data {
+                let transforms = element.take_inner_content_transforms();
+
                 if element.should_remove_content() {
                     elem_desc.remove_content = true;
                     self.matched_elements_with_removed_content += 1;
@@ -238,6 +523,15 @@ impl<'h> ContentHandlersDispatcher<'h> {
 
                     self.end_tag_handlers.push(handler, false);
                 }
+
+                // NOTE: the content inserted after the start tag is the beginning of
+                // the inner content, so it's moved to the capture.
+                if !transforms.is_empty() && !start_tag.mutations.removed() {
+                    let content = mem::take(&mut start_tag.mutations.content_after);
+
+                    elem_desc.capture_inner_content = true;
+                    self.inner_content_captures.push(content, transforms);
+                }
             }
         }
 
@@ -248,19 +542,51 @@ impl<'h> ContentHandlersDispatcher<'h> {
         &mut self,
         token: &mut Token,
         current_element_data: Option<&mut ElementDescriptor>,
-    ) -> HandlerResult {
+        position: TreePosition,
+    ) -> HandlerResult<H::Error> {
         match token {
             Token::Doctype(doctype) => self.doctype_handlers.for_each_active(|h| h(doctype)),
-            Token::StartTag(start_tag) => self.handle_start_tag(start_tag, current_element_data),
-            Token::EndTag(end_tag) => self
-                .end_tag_handlers
-                .do_for_each_active_and_remove(|h| h(end_tag)),
-            Token::TextChunk(text) => self.text_handlers.for_each_active(|h| h(text)),
-            Token::Comment(comment) => self.comment_handlers.for_each_active(|h| h(comment)),
+            Token::StartTag(start_tag) => {
+                self.handle_start_tag(start_tag, current_element_data, position)
+            }
+            Token::EndTag(end_tag) => {
+                self.end_tag_handlers
+                    .do_for_each_active_and_remove(|h| h(end_tag))?;
+
+                let closed_count = mem::take(&mut self.inner_content_captures.closed_count);
+
+                if closed_count > 0 {
+                    let content_before = mem::take(&mut end_tag.mutations.content_before);
+
+                    end_tag.mutations.content_before = self
+                        .inner_content_captures
+                        .complete(closed_count, content_before);
+                }
+
+                Ok(())
+            }
+            Token::TextChunk(text) => {
+                text.set_matched_rule_ids(self.matched_rule_ids.clone());
+                text.set_position(position);
+                self.text_handlers.for_each_active(|h| h(text))
+            }
+            Token::Comment(comment) => {
+                comment.set_matched_rule_ids(self.matched_rule_ids.clone());
+                comment.set_position(position);
+                self.comment_handlers.for_each_active(|h| h(comment))
+            }
         }
     }
 
-    pub fn handle_end(&mut self, document_end: &mut DocumentEnd) -> HandlerResult {
+    pub fn handle_end(&mut self, document_end: &mut DocumentEnd) -> HandlerResult<H::Error> {
+        let open_count = self.inner_content_captures.items.len();
+
+        if open_count > 0 {
+            let content = self.inner_content_captures.complete(open_count, Vec::new());
+
+            document_end.append_raw(&content);
+        }
+
         self.end_handlers
             .do_for_each_active_and_remove(|h| h(document_end))
     }
@@ -281,7 +607,7 @@ impl<'h> ContentHandlersDispatcher<'h> {
             flags |= TokenCaptureFlags::TEXT;
         }
 
-        if self.end_tag_handlers.has_active() {
+        if self.end_tag_handlers.has_active() || self.inner_content_captures.closed_count > 0 {
             flags |= TokenCaptureFlags::NEXT_END_TAG;
         }
 
@@ -1,23 +1,38 @@
+#[cfg(feature = "async")]
+mod async_io;
 mod handlers_dispatcher;
+mod reader;
 mod rewrite_controller;
+mod template;
+mod text_replacer;
 
 #[macro_use]
 mod settings;
 
 use self::handlers_dispatcher::ContentHandlersDispatcher;
 use self::rewrite_controller::*;
+use self::template::{CompiledSelectors, HandlersFactories, RewriterOptions};
 use crate::memory::MemoryLimitExceededError;
-use crate::memory::MemoryLimiter;
+use crate::memory::{MemoryLimiter, SharedMemoryLimiter};
 use crate::parser::ParsingAmbiguityError;
-use crate::selectors_vm::{self, SelectorMatchingVm};
+use crate::selectors_vm::{LookaheadFactsScanner, SelectorMatchingVm};
 use crate::transform_stream::*;
 use encoding_rs::Encoding;
 use std::error::Error as StdError;
 use std::fmt::{self, Debug};
-use std::rc::Rc;
+use std::io;
+use std::sync::Arc;
 use thiserror::Error;
 
+#[cfg(feature = "async")]
+pub use self::async_io::{AsyncRewritingReader, RewritingStream};
+pub use self::reader::RewritingReader;
 pub use self::settings::*;
+pub use self::template::{
+    DocumentContentHandlersFactory, ElementContentHandlersFactory, HandlersKindMismatchError,
+    RewriterTemplate, TemplateSettings,
+};
+pub use self::text_replacer::TextReplacer;
 
 fn try_encoding_from_str(encoding: &str) -> Result<&'static Encoding, EncodingError> {
     let encoding = Encoding::for_label_no_replacement(encoding.as_bytes())
@@ -48,16 +63,51 @@ pub enum EncodingError {
     NonAsciiCompatibleEncoding,
 }
 
+/// An error that occurs if invalid [`Settings`] were provided to the rewriter.
+///
+/// [`Settings`]: ../struct.Settings.html
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum SettingsError {
+    /// See [`EncodingError`].
+    ///
+    /// [`EncodingError`]: enum.EncodingError.html
+    #[error("{0}")]
+    Encoding(EncodingError),
+
+    /// Some of the selectors contain pseudo-classes that require lookahead (e.g. `:last-child`
+    /// or `:has()`), but the [`lookahead_mode`] setting is [`LookaheadMode::Disabled`].
+    ///
+    /// [`lookahead_mode`]: ../struct.Settings.html#structfield.lookahead_mode
+    /// [`LookaheadMode::Disabled`]: ../enum.LookaheadMode.html#variant.Disabled
+    #[error(
+        "Selectors with pseudo-classes that depend on the following content require \
+         the lookahead to be enabled with the `lookahead_mode` setting."
+    )]
+    LookaheadRequired,
+
+    /// A selector contains a user-defined pseudo-class that isn't registered with
+    /// the [`custom_pseudo_classes`] setting.
+    ///
+    /// [`custom_pseudo_classes`]: ../struct.Settings.html#structfield.custom_pseudo_classes
+    #[error("Unknown pseudo-class `:{0}` in selector.")]
+    UnknownPseudoClass(String),
+}
+
 /// A compound error type that can be returned by [`write`] and [`end`] methods of the rewriter.
 ///
+/// The type parameter is the type of the errors of the content handlers, which is
+/// `dyn Error + Send + Sync` for a rewriter with [`SendHandlerTypes`].
+///
 /// # Note
 /// This error is unrecoverable. The rewriter instance will panic on attempt to use it after such an
-/// error.
+/// error, unless it's [`reset`].
 ///
 /// [`write`]: ../struct.HtmlRewriter.html#method.write
 /// [`end`]: ../struct.HtmlRewriter.html#method.end
+/// [`reset`]: ../struct.HtmlRewriter.html#method.reset
+/// [`SendHandlerTypes`]: ../struct.SendHandlerTypes.html
 #[derive(Error, Debug)]
-pub enum RewritingError {
+pub enum RewritingError<E: StdError + ?Sized = dyn StdError> {
     /// See [`MemoryLimitExceededError`].
     ///
     /// [`MemoryLimitExceededError`]: struct.MemoryLimitExceededError.html
@@ -71,8 +121,68 @@ pub enum RewritingError {
     ParsingAmbiguity(ParsingAmbiguityError),
 
     /// An error that was propagated from one of the content handlers.
+    ///
+    /// Only the message of the error is retained on conversion into [`io::Error`], unless
+    /// the errors of the content handlers are required to be `Send + Sync`, as with
+    /// [`SendHandlerTypes`].
+    ///
+    /// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+    /// [`SendHandlerTypes`]: ../struct.SendHandlerTypes.html
     #[error("{0}")]
-    ContentHandlerError(Box<dyn StdError>),
+    ContentHandlerError(Box<E>),
+
+    /// See [`SettingsError`]. Returned only by [`rewrite_str`], since the constructors of
+    /// the rewriter return [`SettingsError`] directly.
+    ///
+    /// [`SettingsError`]: enum.SettingsError.html
+    /// [`rewrite_str`]: ../fn.rewrite_str.html
+    #[error("{0}")]
+    InvalidSettings(SettingsError),
+}
+
+impl RewritingError {
+    /// Converts the error of a content handler propagated through the rewriter back to
+    /// the error type of the handlers.
+    fn into_handler_error<H: HandlerTypes>(self) -> RewritingError<H::Error> {
+        match self {
+            RewritingError::MemoryLimitExceeded(err) => RewritingError::MemoryLimitExceeded(err),
+            RewritingError::ParsingAmbiguity(err) => RewritingError::ParsingAmbiguity(err),
+            RewritingError::ContentHandlerError(err) => {
+                RewritingError::ContentHandlerError(H::from_dyn_error(err))
+            }
+            RewritingError::InvalidSettings(err) => RewritingError::InvalidSettings(err),
+        }
+    }
+}
+
+impl<E: StdError + ?Sized> RewritingError<E> {
+    /// Returns a copy of the error that can be sent between threads. The error of a content
+    /// handler is reduced to its message.
+    fn to_send_copy(&self) -> RewritingError<dyn StdError + Send + Sync> {
+        match self {
+            RewritingError::MemoryLimitExceeded(err) => RewritingError::MemoryLimitExceeded(*err),
+            RewritingError::ParsingAmbiguity(err) => RewritingError::ParsingAmbiguity(err.clone()),
+            RewritingError::ContentHandlerError(err) => {
+                RewritingError::ContentHandlerError(err.to_string().into())
+            }
+            RewritingError::InvalidSettings(err) => RewritingError::InvalidSettings(err.clone()),
+        }
+    }
+}
+
+// NOTE: the error can be obtained with `io::Error::get_ref` and downcast to
+// `RewritingError<dyn Error + Send + Sync>`. The errors of the content handlers are not
+// required to be `Send + Sync`, so only their message is retained.
+impl From<RewritingError> for io::Error {
+    fn from(err: RewritingError) -> Self {
+        io::Error::new(io::ErrorKind::Other, err.to_send_copy())
+    }
+}
+
+impl From<RewritingError<dyn StdError + Send + Sync>> for io::Error {
+    fn from(err: RewritingError<dyn StdError + Send + Sync>) -> Self {
+        io::Error::new(io::ErrorKind::Other, err)
+    }
 }
 
 /// A streaming HTML rewriter.
@@ -115,30 +225,34 @@ pub enum RewritingError {
 ///     r#"<div><a href="https://example.com"></a></div>"#
 /// );
 /// ```
-pub struct HtmlRewriter<'h, O: OutputSink> {
-    stream: TransformStream<HtmlRewriteController<'h>, O>,
+pub struct HtmlRewriter<'h, O: OutputSink, H: ContentHandlerTypes<'h> = LocalHandlerTypes> {
+    stream: TransformStream<HtmlRewriteController<'h, H>, O>,
+    /// The content handlers factories of the template the rewriter was instantiated from,
+    /// if any. They are used to recreate the content handlers on reset.
+    handlers_factories: Option<Arc<HandlersFactories<'h, H>>>,
     finished: bool,
-    poisoned: bool,
+    /// A copy of the fatal error that poisoned the rewriter, if any.
+    poisoned: Option<RewritingError<dyn StdError + Send + Sync>>,
 }
 
 macro_rules! guarded {
     ($self:ident, $expr:expr) => {{
         assert!(
-            !$self.poisoned,
+            $self.poisoned.is_none(),
             "Attempt to use the HtmlRewriter after a fatal error."
         );
 
         let res = $expr;
 
-        if res.is_err() {
-            $self.poisoned = true;
+        if let Err(ref err) = res {
+            $self.poisoned = Some(err.to_send_copy());
         }
 
-        res
+        res.map_err(RewritingError::into_handler_error::<H>)
     }};
 }
 
-impl<'h, O: OutputSink> HtmlRewriter<'h, O> {
+impl<'h, O: OutputSink, H: ContentHandlerTypes<'h>> HtmlRewriter<'h, O, H> {
     /// Constructs a new rewriter with the provided `settings` that writes
     /// the output to the `output_sink`.
     ///
@@ -146,54 +260,157 @@ impl<'h, O: OutputSink> HtmlRewriter<'h, O> {
     ///
     /// For the convenience the [`OutputSink`] trait is implemented for closures.
     ///
+    /// # Errors
+    ///  * [`SettingsError`] if the settings are invalid.
+    ///
     /// [`OutputSink`]: trait.OutputSink.html
-    pub fn try_new<'s>(settings: Settings<'h, 's>, output_sink: O) -> Result<Self, EncodingError> {
-        let encoding = try_encoding_from_str(settings.encoding)?;
-        let mut selectors_ast = selectors_vm::Ast::default();
-        let mut dispatcher = ContentHandlersDispatcher::default();
+    /// [`SettingsError`]: errors/enum.SettingsError.html
+    pub fn try_new<'s>(
+        settings: Settings<'h, 's, H>,
+        output_sink: O,
+    ) -> Result<Self, SettingsError> {
+        let encoding = try_encoding_from_str(settings.encoding).map_err(SettingsError::Encoding)?;
+        let mut selectors = Vec::new();
+        let memory_limiter =
+            MemoryLimiter::new_shared(settings.memory_settings.max_allowed_memory_usage);
+
+        let mut dispatcher = ContentHandlersDispatcher::new(Arc::clone(&memory_limiter), encoding);
         let has_selectors = !settings.element_content_handlers.is_empty();
 
         for (selector, handlers) in settings.element_content_handlers {
             let locator = dispatcher.add_selector_associated_handlers(handlers);
 
-            selectors_ast.add_selector(selector, locator);
+            selectors.push((selector, locator));
         }
 
         for handlers in settings.document_content_handlers {
             dispatcher.add_document_content_handlers(handlers);
         }
 
-        let memory_limiter =
-            MemoryLimiter::new_shared(settings.memory_settings.max_allowed_memory_usage);
-
-        let selector_matching_vm = if has_selectors {
-            Some(SelectorMatchingVm::new(
-                selectors_ast,
+        let selectors = if has_selectors {
+            Some(CompiledSelectors::compile(
+                selectors,
                 encoding,
-                Rc::clone(&memory_limiter),
-            ))
+                &settings.namespace_prefixes,
+                settings.custom_pseudo_classes,
+                settings.track_ancestors,
+                &settings.retained_ancestor_attributes,
+                settings.lookahead_mode,
+            )?)
         } else {
             None
         };
 
+        let options = RewriterOptions {
+            encoding,
+            memory_settings: settings.memory_settings,
+            strict: settings.strict,
+            lookahead_mode: settings.lookahead_mode,
+        };
+
+        Ok(Self::from_parts(
+            dispatcher,
+            None,
+            selectors.as_ref(),
+            options,
+            memory_limiter,
+            output_sink,
+        ))
+    }
+
+    /// Constructs a new rewriter from the precompiled `template` that writes the output to
+    /// the `output_sink`.
+    ///
+    /// The content handlers of the rewriter are produced by the factories of the template.
+    ///
+    /// # Errors
+    ///  * [`HandlersKindMismatchError`] if a factory of the template produces handlers of
+    ///    different kinds than on the template compilation.
+    ///
+    /// [`HandlersKindMismatchError`]: errors/struct.HandlersKindMismatchError.html
+    pub fn from_template(
+        template: &RewriterTemplate<'h, H>,
+        output_sink: O,
+    ) -> Result<Self, HandlersKindMismatchError> {
+        let options = template.options;
+        let memory_limiter =
+            MemoryLimiter::new_shared(options.memory_settings.max_allowed_memory_usage);
+
+        let mut dispatcher =
+            ContentHandlersDispatcher::new(Arc::clone(&memory_limiter), options.encoding);
+
+        template.handlers_factories.add_handlers(&mut dispatcher)?;
+
+        Ok(Self::from_parts(
+            dispatcher,
+            Some(Arc::clone(&template.handlers_factories)),
+            template.selectors.as_ref(),
+            options,
+            memory_limiter,
+            output_sink,
+        ))
+    }
+
+    fn from_parts(
+        dispatcher: ContentHandlersDispatcher<'h, H>,
+        handlers_factories: Option<Arc<HandlersFactories<'h, H>>>,
+        selectors: Option<&CompiledSelectors>,
+        options: RewriterOptions,
+        memory_limiter: SharedMemoryLimiter,
+        output_sink: O,
+    ) -> Self {
+        let mut lookahead = None;
+
+        let selector_matching_vm = selectors.map(|selectors| {
+            let mut vm = SelectorMatchingVm::with_program(
+                Arc::clone(&selectors.program),
+                Arc::clone(&memory_limiter),
+            );
+
+            if selectors.track_ancestors {
+                vm.enable_ancestor_tracking(
+                    options.encoding,
+                    Arc::clone(&selectors.retained_ancestor_attributes),
+                );
+            }
+
+            if vm.requires_lookahead() && options.lookahead_mode != LookaheadMode::Disabled {
+                let scanner = LookaheadFactsScanner::new(
+                    &mut vm,
+                    Arc::clone(&memory_limiter),
+                    options.strict,
+                );
+
+                lookahead = Some(Lookahead::new(
+                    Box::new(scanner),
+                    Arc::clone(&memory_limiter),
+                    options.lookahead_mode == LookaheadMode::PassThroughOnMemoryLimit,
+                ));
+            }
+
+            vm
+        });
+
         let controller = HtmlRewriteController::new(dispatcher, selector_matching_vm);
 
         let stream = TransformStream::new(TransformStreamSettings {
             transform_controller: controller,
             output_sink,
-            preallocated_parsing_buffer_size: settings
+            preallocated_parsing_buffer_size: options
                 .memory_settings
                 .preallocated_parsing_buffer_size,
             memory_limiter,
-            encoding,
-            strict: settings.strict,
+            encoding: options.encoding,
+            strict: options.strict,
+            lookahead,
         });
 
-        Ok(HtmlRewriter {
+        HtmlRewriter {
             stream,
+            handlers_factories,
             finished: false,
-            poisoned: false,
-        })
+            poisoned: None,
+        }
     }
 
     /// Writes a chunk of input data to the rewriter.
@@ -203,10 +420,13 @@ impl<'h, O: OutputSink> HtmlRewriter<'h, O> {
     ///    (these errors are unrecovarable).
     ///  * If called after [`end`].
     ///
+    /// Both are allowed again after [`reset`].
+    ///
     /// [`RewritingError`]: errors/enum.RewritingError.html
     /// [`end`]: struct.HtmlRewriter.html#method.end
+    /// [`reset`]: struct.HtmlRewriter.html#method.reset
     #[inline]
-    pub fn write(&mut self, data: &[u8]) -> Result<(), RewritingError> {
+    pub fn write(&mut self, data: &[u8]) -> Result<(), RewritingError<H::Error>> {
         assert!(
             !self.finished,
             "Data was written into the stream after it has ended."
@@ -227,18 +447,180 @@ impl<'h, O: OutputSink> HtmlRewriter<'h, O> {
     /// [`RewritingError`]: errors/enum.RewritingError.html
     /// [`write`]: struct.HtmlRewriter.html#method.write
     #[inline]
-    pub fn end(&mut self) -> Result<(), RewritingError> {
+    pub fn end(&mut self) -> Result<(), RewritingError<H::Error>> {
         assert!(!self.finished, "Stream was ended twice.");
         self.finished = true;
 
         guarded!(self, self.stream.end())
     }
+
+    /// Returns the rewriter to its initial state, so it can be used to rewrite another document.
+    ///
+    /// The allocations of the parsing buffers and of the open element stack are kept, which
+    /// makes reusing the rewriter cheaper than constructing a new one. The rewriter can be reset
+    /// at any time: the input written since the last reset is abandoned and the rewriter is no
+    /// longer poisoned by a [`RewritingError`]. The output of the next document is written to
+    /// the same output sink.
+    ///
+    /// The content handlers of a rewriter instantiated with [`from_template`] are dropped and
+    /// recreated with the factories of the template, so no state of the handlers leaks into
+    /// the next document. A rewriter constructed with [`try_new`] keeps its content handlers
+    /// with their state, except for the handlers set with [`Element::on_end_tag`] that are
+    /// dropped. Note that the [`end`] handlers of such a rewriter are invoked only once, so
+    /// they are not called for the documents that follow the reset.
+    ///
+    /// # Errors
+    ///  * [`HandlersKindMismatchError`] if a factory of the template produces handlers of
+    ///    different kinds than on the template compilation. The rewriter stays poisoned until
+    ///    it's successfully reset.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut output = vec![];
+    ///
+    /// {
+    ///     let mut rewriter = HtmlRewriter::try_new(
+    ///         Settings {
+    ///             element_content_handlers: vec![element!("a[href]", |el| {
+    ///                 el.set_attribute("rel", "noopener").unwrap();
+    ///
+    ///                 Ok(())
+    ///             })],
+    ///             ..Settings::default()
+    ///         },
+    ///         |c: &[u8]| output.extend_from_slice(c)
+    ///     ).unwrap();
+    ///
+    ///     for html in &["<a href=/foo></a>", "<a href=/bar></a>"] {
+    ///         rewriter.write(html.as_bytes()).unwrap();
+    ///         rewriter.end().unwrap();
+    ///         rewriter.reset().unwrap();
+    ///     }
+    /// }
+    ///
+    /// assert_eq!(
+    ///     String::from_utf8(output).unwrap(),
+    ///     r#"<a href=/foo rel="noopener"></a><a href=/bar rel="noopener"></a>"#
+    /// );
+    /// ```
+    ///
+    /// [`RewritingError`]: errors/enum.RewritingError.html
+    /// [`from_template`]: struct.HtmlRewriter.html#method.from_template
+    /// [`try_new`]: struct.HtmlRewriter.html#method.try_new
+    /// [`Element::on_end_tag`]: html_content/struct.Element.html#method.on_end_tag
+    /// [`end`]: struct.DocumentContentHandlers.html#method.end
+    /// [`HandlersKindMismatchError`]: errors/struct.HandlersKindMismatchError.html
+    pub fn reset(&mut self) -> Result<(), HandlersKindMismatchError> {
+        // NOTE: the handlers need to be recreated before the reset of the stream, as the initial
+        // capture flags of the next document depend on the document content handlers.
+        if let Some(ref handlers_factories) = self.handlers_factories {
+            let dispatcher = self
+                .stream
+                .transform_controller_mut()
+                .handlers_dispatcher_mut();
+
+            dispatcher.clear_handlers();
+
+            if let Err(err) = handlers_factories.add_handlers(dispatcher) {
+                self.poisoned = Some(RewritingError::ContentHandlerError(Box::new(err)));
+
+                return Err(err);
+            }
+        }
+
+        self.stream.reset();
+        self.finished = false;
+        self.poisoned = None;
+
+        Ok(())
+    }
+
+    /// Returns an error that reports the fatal error that poisoned the rewriter, so the I/O
+    /// adapters can fail on every call that follows such an error instead of panicking.
+    fn poisoned_io_error(&self) -> Option<io::Error> {
+        self.poisoned
+            .as_ref()
+            .map(|err| io::Error::new(io::ErrorKind::Other, err.to_send_copy()))
+    }
+}
+
+/// Writes the input to the rewriter, so it can be used with `io::copy`, `write!` and
+/// other APIs that accept [`io::Write`].
+///
+/// [`RewritingError`]s are converted into [`io::Error`]s of the [`Other`] kind, which wrap
+/// the error as `RewritingError<dyn Error + Send + Sync>`, so it can be obtained with `get_ref`
+/// and `downcast_ref`. Unlike [`write`], writes that follow such an error or [`end`] don't panic,
+/// but return an error of the same kind, so the rewriter can be safely wrapped into buffered
+/// writers. `flush` is a no-op, as the rewriter emits the output as soon as it is available.
+/// [`end`] should be called explicitly once all the input is written.
+///
+/// # Example
+/// ```
+/// use lol_html::{element, HtmlRewriter, Settings};
+/// use std::io;
+///
+/// let mut output = vec![];
+///
+/// {
+///     let mut rewriter = HtmlRewriter::try_new(
+///         Settings {
+///             element_content_handlers: vec![element!("script[src]", |el| {
+///                 el.set_attribute("defer", "").unwrap();
+///
+///                 Ok(())
+///             })],
+///             ..Settings::default()
+///         },
+///         |c: &[u8]| output.extend_from_slice(c)
+///     ).unwrap();
+///
+///     io::copy(&mut &b"<script src=/app.js></script>"[..], &mut rewriter).unwrap();
+///     rewriter.end().unwrap();
+/// }
+///
+/// assert_eq!(String::from_utf8(output).unwrap(), r#"<script src=/app.js defer=""></script>"#);
+/// ```
+///
+/// [`RewritingError`]: errors/enum.RewritingError.html
+/// [`io::Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+/// [`Other`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.Other
+/// [`write`]: struct.HtmlRewriter.html#method.write
+/// [`end`]: struct.HtmlRewriter.html#method.end
+impl<'h, O: OutputSink, H: ContentHandlerTypes<'h>> io::Write for HtmlRewriter<'h, O, H>
+where
+    io::Error: From<RewritingError<H::Error>>,
+{
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(err) = self.poisoned_io_error() {
+            return Err(err);
+        }
+
+        if self.finished {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Data was written into the stream after it has ended.",
+            ));
+        }
+
+        HtmlRewriter::write(self, buf)?;
+
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 // NOTE: this opaque Debug implementation is required to make
 // `.unwrap()` and `.expect()` methods available on Result
 // returned by the `HtmlRewriterBuilder.build()` method.
-impl<O: OutputSink> Debug for HtmlRewriter<'_, O> {
+impl<'h, O: OutputSink, H: ContentHandlerTypes<'h>> Debug for HtmlRewriter<'h, O, H> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "HtmlRewriter")
     }
@@ -279,11 +661,10 @@ pub fn rewrite_str<'h, 's>(
 ) -> Result<String, RewritingError> {
     let mut output = vec![];
 
-    // NOTE: never panics because encoding is always "utf-8".
     let mut rewriter = HtmlRewriter::try_new(settings.into(), |c: &[u8]| {
         output.extend_from_slice(c);
     })
-    .unwrap();
+    .map_err(RewritingError::InvalidSettings)?;
 
     rewriter.write(html.as_bytes())?;
     rewriter.end()?;
@@ -295,10 +676,15 @@ pub fn rewrite_str<'h, 's>(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::html_content::ContentType;
+    use crate::html_content::{ContentType, SourceLocation};
+    use crate::selectors_vm::{CustomPseudoClasses, Selector, SelectorError};
     use crate::test_utils::{Output, ASCII_COMPATIBLE_ENCODINGS};
+    use encoding_rs::{UTF_8, WINDOWS_1251};
     use std::cell::RefCell;
     use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
 
     fn write_chunks<O: OutputSink>(
         rewriter: &mut HtmlRewriter<O>,
@@ -348,7 +734,7 @@ mod tests {
         )
         .unwrap_err();
 
-        assert_eq!(err, EncodingError::UnknownEncoding);
+        assert_eq!(err, SettingsError::Encoding(EncodingError::UnknownEncoding));
     }
 
     #[test]
@@ -362,7 +748,7 @@ mod tests {
         )
         .unwrap_err();
 
-        assert_eq!(err, EncodingError::NonAsciiCompatibleEncoding);
+        assert_eq!(err, SettingsError::Encoding(EncodingError::NonAsciiCompatibleEncoding));
     }
 
     #[test]
@@ -564,66 +950,1483 @@ mod tests {
         assert_eq!(*handlers_executed.borrow(), vec![0, 1, 2, 3, 4]);
     }
 
-    mod fatal_errors {
-        use super::*;
-        use crate::errors::MemoryLimitExceededError;
+    #[test]
+    fn lookahead_selectors() {
+        let rewrite = |lookahead_mode| {
+            let mut output = Vec::new();
 
-        fn create_rewriter<O: OutputSink>(
-            max_allowed_memory_usage: usize,
-            output_sink: O,
-        ) -> HtmlRewriter<'static, O> {
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![
+                            element!("li:last-child", |el| {
+                                el.set_attribute("last", "").unwrap();
+                                Ok(())
+                            }),
+                            element!("li:nth-last-of-type(2)", |el| {
+                                el.set_attribute("nth", "").unwrap();
+                                Ok(())
+                            }),
+                            element!("p:empty", |el| {
+                                el.set_attribute("empty", "").unwrap();
+                                Ok(())
+                            }),
+                            element!("span:only-child", |el| {
+                                el.set_attribute("only", "").unwrap();
+                                Ok(())
+                            }),
+                        ],
+                        lookahead_mode,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                let html = concat!(
+                    "<ul><li>1</li><li>2</li><li>3</li></ul>",
+                    "<p></p><p><!--x--></p><p>4</p>",
+                    "<div><span></span></div><div><span></span><i></i></div>"
+                );
+
+                // NOTE: feed the input byte by byte to make sure that facts
+                // are collected across the chunk boundaries.
+                for byte in html.as_bytes().chunks(1) {
+                    rewriter.write(byte).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            rewrite(LookaheadMode::FailOnMemoryLimit),
+            concat!(
+                r#"<ul><li>1</li><li nth="">2</li><li last="">3</li></ul>"#,
+                r#"<p empty=""></p><p empty=""><!--x--></p><p>4</p>"#,
+                r#"<div><span only=""></span></div><div><span></span><i></i></div>"#
+            )
+        );
+    }
+
+    #[test]
+    fn negated_lookahead_selectors() {
+        let rewrite = |lookahead_mode| {
+            let mut output = Vec::new();
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![
+                            element!("li:not(:last-child)", |el| {
+                                el.set_attribute("not-last", "").unwrap();
+                                Ok(())
+                            }),
+                            element!("p:not(:empty)", |el| {
+                                el.set_attribute("not-empty", "").unwrap();
+                                Ok(())
+                            }),
+                        ],
+                        lookahead_mode,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                rewriter
+                    .write(b"<ul><li>1</li><li>2</li></ul><p></p><p>3</p>")
+                    .unwrap();
+
+                rewriter.end().unwrap();
+            }
+
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            rewrite(LookaheadMode::FailOnMemoryLimit),
+            r#"<ul><li not-last="">1</li><li>2</li></ul><p></p><p not-empty="">3</p>"#
+        );
+    }
+
+    #[test]
+    fn lookahead_required() {
+        let try_new = |selector: &str, lookahead_mode| {
             HtmlRewriter::try_new(
                 Settings {
-                    element_content_handlers: vec![element!("*", |_| Ok(()))],
-                    memory_settings: MemorySettings {
-                        max_allowed_memory_usage,
-                        preallocated_parsing_buffer_size: 0,
-                    },
+                    element_content_handlers: vec![element!(selector, |_| Ok(()))],
+                    lookahead_mode,
                     ..Settings::default()
                 },
-                output_sink,
+                |_: &[u8]| {},
             )
-            .unwrap()
+            .err()
+        };
+
+        for selector in &["li:last-child", "p:empty", "ul > li:not(:only-child)"] {
+            assert_eq!(
+                try_new(selector, LookaheadMode::Disabled),
+                Some(SettingsError::LookaheadRequired)
+            );
+
+            assert_eq!(try_new(selector, LookaheadMode::FailOnMemoryLimit), None);
         }
 
-        #[test]
-        fn buffer_capacity_limit() {
-            const MAX: usize = 100;
+        assert_eq!(try_new("li:first-child", LookaheadMode::Disabled), None);
 
-            let mut rewriter = create_rewriter(MAX, |_: &[u8]| {});
+        let err = rewrite_str(
+            "<p></p>",
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("p:empty", |_| Ok(()))],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap_err();
 
-            // Use two chunks for the stream to force the usage of the buffer and
-            // make sure to overflow it.
-            let chunk_1 = format!("<img alt=\"{}", "l".repeat(MAX / 2));
-            let chunk_2 = format!("{}\" />", "r".repeat(MAX / 2));
+        assert!(matches!(
+            err,
+            RewritingError::InvalidSettings(SettingsError::LookaheadRequired)
+        ));
+    }
 
-            rewriter.write(chunk_1.as_bytes()).unwrap();
+    #[test]
+    fn has_selectors() {
+        let rewrite = |lookahead_mode| {
+            let mut output = Vec::new();
 
-            let write_err = rewriter.write(chunk_2.as_bytes()).unwrap_err();
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![
+                            element!("figure:has(> img)", |el| {
+                                el.set_attribute("img", "").unwrap();
+                                Ok(())
+                            }),
+                            element!("div:has(+ p, ~ i)", |el| {
+                                el.set_attribute("next", "").unwrap();
+                                Ok(())
+                            }),
+                            element!("section:not(:has(a[href]))", |el| {
+                                el.set_attribute("nolink", "").unwrap();
+                                Ok(())
+                            }),
+                        ],
+                        lookahead_mode,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
 
-            match write_err {
-                RewritingError::MemoryLimitExceeded(e) => assert_eq!(e, MemoryLimitExceededError),
-                _ => panic!("{}", write_err),
-            }
-        }
+                let html = concat!(
+                    "<figure><img></figure><figure><p><img></p></figure>",
+                    "<div></div><p></p><div><i></i></div><span></span><i></i>",
+                    "<section><p><a href='#'></a></p></section><section><a></a></section>"
+                );
 
-        #[test]
-        #[should_panic(expected = "Data was written into the stream after it has ended.")]
-        fn write_after_end() {
-            let mut rewriter = create_rewriter(512, |_: &[u8]| {});
+                for byte in html.as_bytes().chunks(1) {
+                    rewriter.write(byte).unwrap();
+                }
 
-            rewriter.end().unwrap();
-            rewriter.write(b"foo").unwrap();
-        }
+                rewriter.end().unwrap();
+            }
 
-        #[test]
-        #[should_panic(expected = "Stream was ended twice.")]
-        fn end_twice() {
-            let mut rewriter = create_rewriter(512, |_: &[u8]| {});
+            String::from_utf8(output).unwrap()
+        };
 
-            rewriter.end().unwrap();
-            rewriter.end().unwrap();
-        }
+        assert_eq!(
+            rewrite(LookaheadMode::FailOnMemoryLimit),
+            concat!(
+                r#"<figure img=""><img></figure><figure><p><img></p></figure>"#,
+                r#"<div next=""></div><p></p><div next=""><i></i></div><span></span><i></i>"#,
+                r#"<section><p><a href='#'></a></p></section><section nolink=""><a></a></section>"#
+            )
+        );
+    }
+
+    #[test]
+    fn has_selectors_require_lookahead() {
+        for selector in &[
+            "figure:has(> img)",
+            "div:has(+ p, ~ i)",
+            "section:not(:has(a[href]))",
+            "a:has(svg) > span",
+        ] {
+            let err = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!(selector, |_| Ok(()))],
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .err();
+
+            assert_eq!(err, Some(SettingsError::LookaheadRequired), "{}", selector);
+        }
+    }
+
+    #[test]
+    fn namespaced_selectors() {
+        let output = rewrite_str(
+            concat!(
+                r#"<a href="/1"></a><svg><a xlink:href="/2"></a><a href="/3"></a></svg>"#,
+                r#"<math><mrow xlink:href="/4"></mrow></math><p xlink:href="/5"></p>"#
+            ),
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("svg|a", |el| {
+                        el.set_attribute("svg", "").unwrap();
+                        Ok(())
+                    }),
+                    element!("[xlink|href]", |el| {
+                        el.set_attribute("xlink", "").unwrap();
+                        Ok(())
+                    }),
+                    element!("[*|href]", |el| {
+                        el.set_attribute("any", "").unwrap();
+                        Ok(())
+                    }),
+                    element!("unknown|a, [unknown|href]", |el| {
+                        el.set_attribute("unknown", "").unwrap();
+                        Ok(())
+                    }),
+                ],
+                namespace_prefixes: vec![
+                    ("svg", "http://www.w3.org/2000/svg"),
+                    ("xlink", "http://www.w3.org/1999/xlink"),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            output,
+            concat!(
+                r#"<a href="/1" any=""></a><svg>"#,
+                r#"<a xlink:href="/2" svg="" xlink="" any=""></a>"#,
+                r#"<a href="/3" svg="" any=""></a></svg>"#,
+                r#"<math><mrow xlink:href="/4" xlink="" any=""></mrow></math>"#,
+                r#"<p xlink:href="/5"></p>"#
+            )
+        );
+    }
+
+    #[test]
+    fn custom_pseudo_classes() {
+        let output = rewrite_str(
+            concat!(
+                r#"<a href="/1"></a><a href="https://example.com/"></a>"#,
+                r#"<img width="1" height="1"><img width="100" height="1">"#,
+                r#"<div><img width="1" height="1"></div><svg><a href="https://example.com/"></a></svg>"#
+            ),
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("a:External-Link", |el| {
+                        el.set_attribute("external", "").unwrap();
+                        Ok(())
+                    }),
+                    element!("a:not(:external-link)", |el| {
+                        el.set_attribute("internal", "").unwrap();
+                        Ok(())
+                    }),
+                    element!(":tracking-pixel", |el| {
+                        el.remove();
+                        Ok(())
+                    }),
+                    element!(":svg", |el| {
+                        el.set_attribute("svg", "").unwrap();
+                        Ok(())
+                    }),
+                ],
+                custom_pseudo_classes: CustomPseudoClasses::default()
+                    .add(
+                        "external-link",
+                        |el| matches!(el.get_attribute("HREF"), Some(href) if href.starts_with("https://")),
+                    )
+                    .add("tracking-pixel", |el| {
+                        el.tag_name() == "img"
+                            && el.get_attribute("width").as_deref() == Some("1")
+                            && el.get_attribute("height").as_deref() == Some("1")
+                    })
+                    .add("svg", |el| {
+                        el.namespace_uri() == "http://www.w3.org/2000/svg"
+                    }),
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            output,
+            concat!(
+                r#"<a href="/1" internal=""></a><a href="https://example.com/" external=""></a>"#,
+                r#"<img width="100" height="1">"#,
+                r#"<div></div><svg svg=""><a href="https://example.com/" external="" svg=""></a></svg>"#
+            )
+        );
+    }
+
+    #[test]
+    fn unregistered_custom_pseudo_class() {
+        for selector in [
+            ":extrnal-link",
+            "a:not(:Extrnal-Link)",
+            "div:is(p, :extrnal-link)",
+            "div:has(> :extrnal-link)",
+        ] {
+            let err = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!(selector, |_| Ok(()))],
+                    custom_pseudo_classes: CustomPseudoClasses::default()
+                        .add("external-link", |_| true),
+                    lookahead_mode: LookaheadMode::FailOnMemoryLimit,
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .err();
+
+            assert_eq!(
+                err,
+                Some(SettingsError::UnknownPseudoClass("extrnal-link".into()))
+            );
+        }
+
+        assert_eq!(
+            ":hover".parse::<Selector>().unwrap_err(),
+            SelectorError::UnsupportedPseudoClassOrElement
+        );
+    }
+
+    #[test]
+    fn standard_name_for_custom_pseudo_class() {
+        let output = rewrite_str(
+            r#"<input type="checkbox" checked><input type="checkbox">"#,
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("input:checked:first-child", |el| {
+                    el.set_attribute("matched", "").unwrap();
+                    Ok(())
+                })],
+                custom_pseudo_classes: CustomPseudoClasses::default()
+                    .add("checked", |_| false)
+                    .add("First-Child", |_| false),
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            output,
+            r#"<input type="checkbox" checked matched=""><input type="checkbox">"#
+        );
+    }
+
+    #[test]
+    fn lang_and_dir_pseudo_classes() {
+        let output = rewrite_str(
+            concat!(
+                r#"<div lang="DE-ch"><p></p><p lang="en"><b></b></p></div>"#,
+                r#"<p lang="de"></p><p lang="deu"></p><p></p>"#,
+                r#"<div dir="RTL"><p><i dir="ltr"></i></p><p dir="auto"><i></i></p></div>"#,
+            ),
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("p:lang(de)", |el| {
+                        el.set_attribute("de", "").unwrap();
+                        Ok(())
+                    }),
+                    element!(":lang(en, de-ch)", |el| {
+                        el.set_attribute("en-or-de-ch", "").unwrap();
+                        Ok(())
+                    }),
+                    element!("p:not(:lang(\"*\"))", |el| {
+                        el.set_attribute("no-lang", "").unwrap();
+                        Ok(())
+                    }),
+                    element!(":dir(rtl)", |el| {
+                        el.set_attribute("rtl", "").unwrap();
+                        Ok(())
+                    }),
+                    element!("i:dir(ltr)", |el| {
+                        el.set_attribute("ltr", "").unwrap();
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            output,
+            concat!(
+                r#"<div lang="DE-ch" en-or-de-ch=""><p de="" en-or-de-ch=""></p>"#,
+                r#"<p lang="en" en-or-de-ch=""><b en-or-de-ch=""></b></p></div>"#,
+                r#"<p lang="de" de=""></p><p lang="deu"></p><p no-lang=""></p>"#,
+                r#"<div dir="RTL" rtl=""><p no-lang="" rtl=""><i dir="ltr" ltr=""></i></p>"#,
+                r#"<p dir="auto" no-lang=""><i></i></p></div>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn state_pseudo_classes() {
+        let output = rewrite_str(
+            concat!(
+                r#"<form><input type="password"><input type="PASSWORD" disabled>"#,
+                r#"<input type="checkbox" checked><svg><a href="/"></a></svg></form>"#,
+            ),
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("input:not(:disabled)[type=password]", |el| {
+                        el.set_attribute("autocomplete", "off").unwrap();
+                        Ok(())
+                    }),
+                    element!(":checked", |el| {
+                        el.remove_attribute("checked");
+                        Ok(())
+                    }),
+                    element!(":any-link", |el| {
+                        el.set_attribute("link", "").unwrap();
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            output,
+            concat!(
+                r#"<form><input type="password" autocomplete="off"><input type="PASSWORD" disabled>"#,
+                r#"<input type="checkbox"><svg><a href="/"></a></svg></form>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn matched_rule_ids() {
+        let ids = RefCell::new(Vec::new());
+
+        let record = |unit: &str, matched_rule_ids: &[usize]| {
+            ids.borrow_mut()
+                .push(format!("{}: {:?}", unit, matched_rule_ids));
+        };
+
+        rewrite_str(
+            r#"<div id="foo"><p class="bar">Hi<br><!-- 42 --></p></div>"#,
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("div", |el| {
+                        record(&el.tag_name(), el.matched_rule_ids());
+                        Ok(())
+                    }),
+                    text!("div > *", |t| {
+                        if !t.as_str().is_empty() {
+                            record(t.as_str(), t.matched_rule_ids());
+                        }
+                        Ok(())
+                    }),
+                    element!("#foo, .bar, br", |el| {
+                        record(&el.tag_name(), el.matched_rule_ids());
+                        Ok(())
+                    }),
+                    comments!("*", |c| {
+                        record(&c.text(), c.matched_rule_ids());
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            ids.into_inner(),
+            vec![
+                "div: [0, 2, 3]",
+                "div: [0, 2, 3]",
+                "p: [1, 2, 3]",
+                "Hi: [0, 1, 2, 3]",
+                "br: [2, 3]",
+                " 42 : [0, 1, 2, 3]",
+            ]
+        );
+    }
+
+    #[test]
+    fn ancestors() {
+        let log = RefCell::new(Vec::new());
+
+        let record = |unit: &str, ancestors: crate::html_content::Ancestors, depth: usize| {
+            let ancestors = ancestors
+                .map(|a| match a.get_attribute("ID") {
+                    Some(id) => format!("{}#{}[{}]", a.tag_name(), id, a.child_index()),
+                    None => format!("{}[{}]", a.tag_name(), a.child_index()),
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(ancestors.len(), depth);
+
+            log.borrow_mut()
+                .push(format!("{} < {}", unit, ancestors.join(" < ")));
+        };
+
+        rewrite_str(
+            concat!(
+                r#"<div id="foo"><span></span><my-long-custom-element class="bar">"#,
+                r#"<br><svg><circle/><g>Hi</g></svg><!-- 42 --></my-long-custom-element></div>"#,
+            ),
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("br, g", |el| {
+                        let unit = format!("{}[{}]", el.tag_name(), el.child_index());
+
+                        record(&unit, el.ancestors(), el.depth());
+                        Ok(())
+                    }),
+                    element!("g", |el| {
+                        let parent = el.ancestors().next().unwrap();
+
+                        assert_eq!(parent.namespace_uri(), "http://www.w3.org/2000/svg");
+                        assert_eq!(parent.depth(), 2);
+                        Ok(())
+                    }),
+                    text!("g", |t| {
+                        if !t.as_str().is_empty() {
+                            record(t.as_str(), t.ancestors(), t.depth());
+                        }
+                        Ok(())
+                    }),
+                    comments!("div", |c| {
+                        record(&c.text(), c.ancestors(), c.depth());
+                        Ok(())
+                    }),
+                ],
+                retained_ancestor_attributes: vec!["Id"],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            log.into_inner(),
+            vec![
+                "br[1] < my-long-custom-element[2] < div#foo[1]",
+                "g[2] < svg[2] < my-long-custom-element[2] < div#foo[1]",
+                "Hi < g[2] < svg[2] < my-long-custom-element[2] < div#foo[1]",
+                " 42  < my-long-custom-element[2] < div#foo[1]",
+            ]
+        );
+    }
+
+    #[test]
+    fn ancestors_tracking_opt_in() {
+        let rewrite = |track_ancestors| {
+            let mut ancestors = Vec::new();
+
+            rewrite_str(
+                "<div><p><b></b></p></div>",
+                RewriteStrSettings {
+                    element_content_handlers: vec![element!("b", |el| {
+                        assert_eq!((el.depth(), el.child_index()), (2, 1));
+
+                        ancestors.extend(el.ancestors().map(|a| a.tag_name()));
+                        Ok(())
+                    })],
+                    track_ancestors,
+                    ..RewriteStrSettings::default()
+                },
+            )
+            .unwrap();
+
+            ancestors
+        };
+
+        assert!(rewrite(false).is_empty());
+        assert_eq!(rewrite(true), ["p", "div"]);
+    }
+
+    #[test]
+    fn end_tag_handlers() {
+        let res = rewrite_str(
+            r#"<div><span>Hi</span><br><img/><p>Bye</div><foo>"#,
+            RewriteStrSettings {
+                element_content_handlers: vec![
+                    element!("*", |el| {
+                        let tag_name = el.tag_name();
+
+                        el.on_end_tag(move |end| {
+                            end.before(
+                                &format!("[{}:{}]", tag_name, end.name()),
+                                ContentType::Text,
+                            );
+
+                            Ok(())
+                        });
+
+                        Ok(())
+                    }),
+                    element!("div", |el| {
+                        el.on_end_tag(|end| {
+                            end.set_name("section")?;
+                            end.after("<hr>", ContentType::Html);
+
+                            Ok(())
+                        });
+
+                        Ok(())
+                    }),
+                    element!("span", |el| {
+                        el.append("!", ContentType::Text);
+
+                        el.on_end_tag(|end| {
+                            end.remove();
+
+                            Ok(())
+                        });
+
+                        Ok(())
+                    }),
+                ],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res,
+            "<div><span>Hi![span:span]<br><img/><p>Bye[p:div][div:div]</section><hr><foo>"
+        );
+
+        let err = rewrite_str(
+            "<div></div>",
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("div", |el| {
+                    el.on_end_tag(|end| {
+                        end.set_name("")?;
+
+                        Ok(())
+                    });
+
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        )
+        .unwrap_err();
+
+        assert_eq!(err.to_string(), "Tag name can't be empty.");
+    }
+
+    #[test]
+    fn inner_content_transforms() {
+        let html = "<div id=a>foo<b>bar</b><!--c--><p>baz</div><br><i>qux";
+
+        for chunk_size in 1..=html.len() {
+            let mut output = Output::new(UTF_8);
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![
+                            element!("div, p, b, i, br", |el| {
+                                let tag_name = el.tag_name();
+
+                                el.transform_inner_content(move |content| {
+                                    format!("[{}:{}]", tag_name, content)
+                                });
+
+                                Ok(())
+                            }),
+                            element!("div", |el| {
+                                el.prepend("<", ContentType::Text);
+                                el.append(">", ContentType::Html);
+                                el.transform_inner_content(|content| content.replace("[p:", "[P:"));
+
+                                el.on_end_tag(|end| {
+                                    end.after("!", ContentType::Text);
+
+                                    Ok(())
+                                });
+
+                                Ok(())
+                            }),
+                            text!("b", |t| {
+                                t.replace(&t.as_str().to_uppercase(), ContentType::Text);
+
+                                Ok(())
+                            }),
+                        ],
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.push(c),
+                )
+                .unwrap();
+
+                for chunk in html.as_bytes().chunks(chunk_size) {
+                    rewriter.write(chunk).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            let output: String = output.into();
+
+            assert_eq!(
+                output,
+                "<div id=a>[div:&lt;foo<b>[b:BAR]</b><!--c--><p>[P:baz]>]</div>!<br><i>[i:qux]"
+            );
+        }
+    }
+
+    #[test]
+    fn text_nodes() {
+        let html = "<div>Hello &amp; wor<!--c-->ld <b>world</b></div>world";
+
+        for chunk_size in 1..=html.len() {
+            let mut output = Output::new(UTF_8);
+            let mut nodes = Vec::new();
+            let mut chunks = Vec::new();
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![
+                            text_nodes!("div", |t| {
+                                let text = t.as_str().to_owned();
+
+                                assert!(t.last_in_text_node());
+                                assert_eq!(
+                                    t.raw_bytes(),
+                                    &html.as_bytes()[t.source_location().bytes()]
+                                );
+
+                                t.replace(&text.replace("world", "there"), ContentType::Html);
+                                nodes.push(text);
+
+                                Ok(())
+                            }),
+                            text!("div", |t| {
+                                chunks.push(t.as_str().to_owned());
+
+                                Ok(())
+                            }),
+                        ],
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.push(c),
+                )
+                .unwrap();
+
+                for chunk in html.as_bytes().chunks(chunk_size) {
+                    rewriter.write(chunk).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            let output: String = output.into();
+
+            assert_eq!(
+                output,
+                "<div>Hello &amp; wor<!--c-->ld <b>there</b></div>world"
+            );
+
+            assert_eq!(nodes, ["Hello &amp; wor", "ld ", "world"]);
+            assert_eq!(chunks, nodes);
+        }
+    }
+
+    #[test]
+    fn text_nodes_with_text_handlers() {
+        let html = "<div>Hello <b>wor&amp;ld</b> there</div>";
+
+        for chunk_size in 1..=html.len() {
+            let mut nodes = Vec::new();
+            let mut chunks = Vec::new();
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![
+                            text_nodes!("b", |t| {
+                                nodes.push(t.as_str().to_owned());
+
+                                Ok(())
+                            }),
+                            text!("div", |t| {
+                                chunks.push((t.as_str().to_owned(), t.last_in_text_node()));
+
+                                Ok(())
+                            }),
+                        ],
+                        ..Settings::default()
+                    },
+                    |_: &[u8]| {},
+                )
+                .unwrap();
+
+                for chunk in html.as_bytes().chunks(chunk_size) {
+                    rewriter.write(chunk).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            assert_eq!(nodes, ["wor&amp;ld"]);
+
+            // NOTE: the text handler receives whole text nodes only while the text node
+            // handler is active.
+            assert!(chunks.contains(&("wor&amp;ld".into(), true)));
+
+            if chunk_size == 1 {
+                assert!(chunks.contains(&("H".into(), false)));
+                assert!(chunks.contains(&(" ".into(), false)));
+            }
+
+            let text: String = chunks.into_iter().map(|(c, _)| c).collect();
+
+            assert_eq!(text, "Hello wor&amp;ld there");
+        }
+    }
+
+    #[test]
+    fn source_locations() {
+        let html = "<!DOCTYPE html>\n<div id=\"a\"\n  class=b>Привет &amp;\nмир</div>\n<!-- c -->\r\n<p>x<br>";
+
+        let expected = [
+            "doctype <!DOCTYPE html> 1:1-1:16",
+            "text \n 1:16-2:1",
+            "element <div id=\"a\"\n  class=b> 2:1-3:11",
+            "attribute id=\"a\" 2:6-2:12",
+            "attribute class=b 3:3-3:10",
+            "text Привет &amp;\nмир 3:11-4:7",
+            "end </div> 4:7-4:13",
+            "text \n 4:13-5:1",
+            "comment <!-- c --> 5:1-5:11",
+            "text \r\n 5:11-6:1",
+            "element <p> 6:1-6:4",
+            "text x 6:4-6:5",
+            "element <br> 6:5-6:9",
+        ];
+
+        for chunk_size in 1..=html.len() {
+            let locations = Rc::new(RefCell::new(Vec::new()));
+            let mut text_node: Option<SourceLocation> = None;
+
+            {
+                let push = |kind: &str, location: SourceLocation| {
+                    locations.borrow_mut().push(format!(
+                        "{} {} {}:{}-{}:{}",
+                        kind,
+                        &html[location.bytes()],
+                        location.line(),
+                        location.column(),
+                        location.end_line(),
+                        location.end_column()
+                    ));
+                };
+
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![element!("*", |el| {
+                            push("element", el.source_location());
+
+                            for attr in el.attributes() {
+                                push("attribute", attr.source_location().unwrap());
+                            }
+
+                            let locations = Rc::clone(&locations);
+
+                            el.on_end_tag(move |end| {
+                                let location = end.source_location();
+
+                                locations.borrow_mut().push(format!(
+                                    "end {} {}:{}-{}:{}",
+                                    &html[location.bytes()],
+                                    location.line(),
+                                    location.column(),
+                                    location.end_line(),
+                                    location.end_column()
+                                ));
+
+                                Ok(())
+                            });
+
+                            Ok(())
+                        })],
+                        document_content_handlers: vec![
+                            doctype!(|d| {
+                                push("doctype", d.source_location());
+                                Ok(())
+                            }),
+                            doc_comments!(|c| {
+                                push("comment", c.source_location());
+                                Ok(())
+                            }),
+                            doc_text!(|t| {
+                                let location = t.source_location();
+
+                                // NOTE: the chunks of a text node have adjacent locations.
+                                let node_location = match text_node.take() {
+                                    Some(node_location) => {
+                                        assert_eq!(node_location.end(), location.start());
+
+                                        SourceLocation::new(
+                                            node_location.start_position(),
+                                            location.end_position(),
+                                        )
+                                    }
+                                    None => location,
+                                };
+
+                                if t.last_in_text_node() {
+                                    push("text", node_location);
+                                } else {
+                                    text_node = Some(node_location);
+                                }
+
+                                Ok(())
+                            }),
+                        ],
+                        ..Settings::default()
+                    },
+                    |_: &[u8]| {},
+                )
+                .unwrap();
+
+                for chunk in html.as_bytes().chunks(chunk_size) {
+                    rewriter.write(chunk).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            assert_eq!(*locations.borrow(), expected, "Chunk size: {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn raw_bytes() {
+        let (html, _, _) = WINDOWS_1251
+            .encode("<!doctype HTML><P Title='Привет &amp; мир'>Привет &amp; мир<!--мир--><br/>");
+
+        for chunk_size in 1..=html.len() {
+            let units = RefCell::new(Vec::new());
+            let mut text = Vec::new();
+
+            {
+                let check = |raw_bytes: &[u8], location: SourceLocation| {
+                    assert_eq!(raw_bytes, &html[location.bytes()]);
+
+                    units.borrow_mut().push(raw_bytes.to_vec());
+                };
+
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![element!("*", |el| {
+                            check(el.raw_bytes(), el.source_location());
+
+                            for attr in el.attributes() {
+                                check(attr.raw_bytes().unwrap(), attr.source_location().unwrap());
+                            }
+
+                            el.set_attribute("title", "foo")?;
+
+                            if el.tag_name() == "p" {
+                                let attr = &el.attributes()[0];
+
+                                assert_eq!(attr.raw_bytes().unwrap()[..6], b"Title="[..]);
+                            } else {
+                                assert_eq!(el.attributes()[0].raw_bytes(), None);
+                            }
+
+                            Ok(())
+                        })],
+                        document_content_handlers: vec![
+                            doctype!(|d| {
+                                check(d.raw_bytes(), d.source_location());
+                                Ok(())
+                            }),
+                            doc_comments!(|c| {
+                                check(c.raw_bytes(), c.source_location());
+                                Ok(())
+                            }),
+                            doc_text!(|t| {
+                                assert_eq!(t.raw_bytes(), &html[t.source_location().bytes()]);
+
+                                text.extend_from_slice(t.raw_bytes());
+                                Ok(())
+                            }),
+                        ],
+                        encoding: "windows-1251",
+                        ..Settings::default()
+                    },
+                    |_: &[u8]| {},
+                )
+                .unwrap();
+
+                for chunk in html.chunks(chunk_size) {
+                    rewriter.write(chunk).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            let (expected_text, _, _) = WINDOWS_1251.encode("Привет &amp; мир");
+            let (expected_comment, _, _) = WINDOWS_1251.encode("<!--мир-->");
+            let (expected_start_tag, _, _) = WINDOWS_1251.encode("<P Title='Привет &amp; мир'>");
+            let (expected_attr, _, _) = WINDOWS_1251.encode("Title='Привет &amp; мир'");
+
+            assert_eq!(text, &*expected_text);
+            assert_eq!(
+                *units.borrow(),
+                vec![
+                    b"<!doctype HTML>".to_vec(),
+                    expected_start_tag.to_vec(),
+                    expected_attr.to_vec(),
+                    expected_comment.to_vec(),
+                    b"<br/>".to_vec(),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let output = RefCell::new(Vec::new());
+
+        let template = RewriterTemplate::try_new(TemplateSettings {
+            element_content_handlers: vec![
+                (
+                    &"li:nth-child(2)".parse().unwrap(),
+                    Box::new(|| {
+                        let mut count = 0;
+
+                        ElementContentHandlers::default().element(move |el| {
+                            count += 1;
+                            el.set_attribute("count", &count.to_string())?;
+
+                            Ok(())
+                        })
+                    }),
+                ),
+                (
+                    &"div".parse().unwrap(),
+                    Box::new(|| {
+                        ElementContentHandlers::default()
+                            .element(|el| {
+                                el.on_end_tag(|end| {
+                                    end.after("<!-- end -->", ContentType::Html);
+
+                                    Ok(())
+                                });
+
+                                el.transform_inner_content(|content| content.to_uppercase());
+
+                                Ok(())
+                            })
+                            .text(
+                                TextReplacer::default()
+                                    .replace("foobar", "baz", ContentType::Text)
+                                    .into_handler(),
+                            )
+                    }),
+                ),
+            ],
+            document_content_handlers: vec![Box::new(|| {
+                DocumentContentHandlers::default().end(|end| {
+                    end.append("<!-- doc end -->", ContentType::Html);
+
+                    Ok(())
+                })
+            })],
+            lookahead_mode: LookaheadMode::FailOnMemoryLimit,
+            ..TemplateSettings::default()
+        })
+        .unwrap();
+
+        let mut rewriter = HtmlRewriter::from_template(&template, |c: &[u8]| {
+            output.borrow_mut().extend_from_slice(c)
+        })
+        .unwrap();
+
+        let html = "<ul><li>1</li><li>2</li><li>3</li></ul><div><p>foobar</p>bar</div>";
+
+        let expected = concat!(
+            r#"<ul><li>1</li><li count="1">2</li><li>3</li></ul>"#,
+            "<div><P>BAZ</P>BAR</div><!-- end --><!-- doc end -->"
+        );
+
+        let take_output = || String::from_utf8(output.borrow_mut().split_off(0)).unwrap();
+
+        for byte in html.as_bytes().chunks(1) {
+            rewriter.write(byte).unwrap();
+        }
+
+        rewriter.end().unwrap();
+        assert_eq!(take_output(), expected);
+
+        // NOTE: abandon a document in the middle of an element with an end tag handler,
+        // a captured inner content and a pending match of the text replacer.
+        rewriter.reset().unwrap();
+        rewriter
+            .write(b"<ul><li>1</li><li>2</li><div><svg><p>foo")
+            .unwrap();
+        take_output();
+
+        rewriter.reset().unwrap();
+        rewriter.write(html.as_bytes()).unwrap();
+        rewriter.end().unwrap();
+        assert_eq!(take_output(), expected);
+    }
+
+    #[test]
+    fn reset_poisoned_rewriter_without_template() {
+        let output = RefCell::new(Vec::new());
+        let mut count = 0;
+
+        let mut rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![
+                    element!("a", move |el| {
+                        count += 1;
+                        el.set_attribute("count", &count.to_string())?;
+
+                        Ok(())
+                    }),
+                    element!("b", |_| Err("Error in b".into())),
+                ],
+                ..Settings::default()
+            },
+            |c: &[u8]| output.borrow_mut().extend_from_slice(c),
+        )
+        .unwrap();
+
+        rewriter.write(b"<a></a><b></b>").unwrap_err();
+        rewriter.reset().unwrap();
+        output.borrow_mut().clear();
+
+        rewriter.write(b"<a></a>").unwrap();
+        rewriter.end().unwrap();
+
+        // NOTE: the handlers of a rewriter constructed without a template keep their state.
+        assert_eq!(
+            String::from_utf8(output.into_inner()).unwrap(),
+            r#"<a count="2"></a>"#
+        );
+    }
+
+    #[test]
+    fn reset_with_handlers_kind_mismatch() {
+        let call_count = AtomicUsize::new(0);
+
+        let template = RewriterTemplate::try_new(TemplateSettings {
+            element_content_handlers: vec![(
+                &"a".parse().unwrap(),
+                Box::new(|| {
+                    let handlers = ElementContentHandlers::default();
+
+                    if call_count.fetch_add(1, Ordering::Relaxed) < 2 {
+                        handlers.element(|_| Ok(()))
+                    } else {
+                        handlers.comments(|_| Ok(()))
+                    }
+                }),
+            )],
+            ..TemplateSettings::default()
+        })
+        .unwrap();
+
+        let mut rewriter = HtmlRewriter::from_template(&template, |_: &[u8]| {}).unwrap();
+
+        rewriter.end().unwrap();
+
+        assert_eq!(rewriter.reset().unwrap_err(), HandlersKindMismatchError);
+
+        assert_eq!(
+            rewriter.poisoned_io_error().unwrap().to_string(),
+            "Element content handlers factory produced handlers of different kinds."
+        );
+    }
+
+    #[test]
+    fn send_rewriter() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let (sender, receiver) = mpsc::channel();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let mut rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![element!("div", move |el| {
+                    let count = count.fetch_add(1, Ordering::Relaxed);
+
+                    el.set_attribute("count", &count.to_string())?;
+                    el.transform_inner_content(|content| content.to_uppercase());
+
+                    el.on_end_tag(|end| {
+                        end.before("!", ContentType::Text);
+
+                        Ok(())
+                    });
+
+                    Ok(())
+                })],
+                document_content_handlers: vec![end!(|end| {
+                    end.append("<!-- end -->", ContentType::Html);
+
+                    Ok(())
+                })],
+                ..Settings::new_send()
+            },
+            move |c: &[u8]| sender.send(c.to_vec()).unwrap(),
+        )
+        .unwrap();
+
+        assert_send(&rewriter);
+
+        thread::spawn(move || {
+            rewriter.write(b"<div>foo</div><div>bar").unwrap();
+            rewriter.end().unwrap();
+        })
+        .join()
+        .unwrap();
+
+        let output = receiver.try_iter().flatten().collect::<Vec<_>>();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"<div count="0">FOO!</div><div count="1">BAR<!-- end -->"#
+        );
+    }
+
+    mod fatal_errors {
+        use super::*;
+        use crate::errors::MemoryLimitExceededError;
+
+        fn create_rewriter<O: OutputSink>(
+            max_allowed_memory_usage: usize,
+            output_sink: O,
+        ) -> HtmlRewriter<'static, O> {
+            HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!("*", |_| Ok(()))],
+                    memory_settings: MemorySettings {
+                        max_allowed_memory_usage,
+                        preallocated_parsing_buffer_size: 0,
+                    },
+                    ..Settings::default()
+                },
+                output_sink,
+            )
+            .unwrap()
+        }
+
+        #[test]
+        fn buffer_capacity_limit() {
+            const MAX: usize = 100;
+
+            let mut rewriter = create_rewriter(MAX, |_: &[u8]| {});
+
+            // Use two chunks for the stream to force the usage of the buffer and
+            // make sure to overflow it.
+            let chunk_1 = format!("<img alt=\"{}", "l".repeat(MAX / 2));
+            let chunk_2 = format!("{}\" />", "r".repeat(MAX / 2));
+
+            rewriter.write(chunk_1.as_bytes()).unwrap();
+
+            let write_err = rewriter.write(chunk_2.as_bytes()).unwrap_err();
+
+            match write_err {
+                RewritingError::MemoryLimitExceeded(e) => assert_eq!(e, MemoryLimitExceededError),
+                _ => panic!("{}", write_err),
+            }
+        }
+
+        #[test]
+        fn ancestors_memory_limit() {
+            const MAX: usize = 4096;
+
+            let html = format!("<div id=\"{}\">", "i".repeat(MAX / 4)).repeat(5);
+
+            let rewrite = |retained_ancestor_attributes| {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![element!("*", |_| Ok(()))],
+                        memory_settings: MemorySettings {
+                            max_allowed_memory_usage: MAX,
+                            preallocated_parsing_buffer_size: 0,
+                        },
+                        retained_ancestor_attributes,
+                        ..Settings::default()
+                    },
+                    |_: &[u8]| {},
+                )
+                .unwrap();
+
+                rewriter.write(html.as_bytes())
+            };
+
+            assert!(rewrite(vec![]).is_ok());
+
+            match rewrite(vec!["id"]).unwrap_err() {
+                RewritingError::MemoryLimitExceeded(e) => assert_eq!(e, MemoryLimitExceededError),
+                err => panic!("{}", err),
+            }
+        }
+
+        #[test]
+        fn inner_content_memory_limit() {
+            const MAX: usize = 1024;
+
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!("div", |el| {
+                        el.transform_inner_content(|content| content);
+
+                        Ok(())
+                    })],
+                    memory_settings: MemorySettings {
+                        max_allowed_memory_usage: MAX,
+                        preallocated_parsing_buffer_size: 0,
+                    },
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .unwrap();
+
+            rewriter.write(b"<div>").unwrap();
+
+            let text = "t".repeat(MAX * 2);
+
+            let write_err = text
+                .as_bytes()
+                .chunks(10)
+                .try_for_each(|chunk| rewriter.write(chunk))
+                .unwrap_err();
+
+            match write_err {
+                RewritingError::MemoryLimitExceeded(e) => assert_eq!(e, MemoryLimitExceededError),
+                _ => panic!("{}", write_err),
+            }
+        }
+
+        #[test]
+        fn text_nodes_memory_limit() {
+            const MAX: usize = 1024;
+
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    document_content_handlers: vec![doc_text_nodes!(|_| Ok(()))],
+                    memory_settings: MemorySettings {
+                        max_allowed_memory_usage: MAX,
+                        preallocated_parsing_buffer_size: 0,
+                    },
+                    ..Settings::default()
+                },
+                |_: &[u8]| {},
+            )
+            .unwrap();
+
+            let text = "t".repeat(MAX * 2);
+
+            let write_err = text
+                .as_bytes()
+                .chunks(10)
+                .try_for_each(|chunk| rewriter.write(chunk))
+                .unwrap_err();
+
+            match write_err {
+                RewritingError::MemoryLimitExceeded(e) => assert_eq!(e, MemoryLimitExceededError),
+                _ => panic!("{}", write_err),
+            }
+        }
+
+        #[test]
+        fn lookahead_memory_limit() {
+            const MAX: usize = 1024;
+
+            let rewrite = |lookahead_mode, output: &mut Vec<u8>| {
+                let mut handler_called = false;
+
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![element!("div:last-child", |_| {
+                            handler_called = true;
+                            Ok(())
+                        })],
+                        memory_settings: MemorySettings {
+                            max_allowed_memory_usage: MAX,
+                            preallocated_parsing_buffer_size: 0,
+                        },
+                        lookahead_mode,
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.extend_from_slice(c),
+                )
+                .unwrap();
+
+                // NOTE: split the text into chunks, but keep the tags intact, so
+                // the parser itself doesn't need to buffer anything.
+                let text = "t".repeat(MAX * 2);
+                let html = format!("<div>{}</div>", text);
+
+                let res = std::iter::once("<div>".as_bytes())
+                    .chain(text.as_bytes().chunks(10))
+                    .chain(std::iter::once("</div>".as_bytes()))
+                    .try_for_each(|chunk| rewriter.write(chunk))
+                    .and_then(|_| rewriter.end());
+
+                drop(rewriter);
+
+                (res, handler_called, html)
+            };
+
+            let mut output = Vec::new();
+            let (res, handler_called, _) = rewrite(LookaheadMode::FailOnMemoryLimit, &mut output);
+
+            match res.unwrap_err() {
+                RewritingError::MemoryLimitExceeded(e) => assert_eq!(e, MemoryLimitExceededError),
+                err => panic!("{}", err),
+            }
+
+            assert!(!handler_called);
+
+            let mut output = Vec::new();
+            let (res, handler_called, html) =
+                rewrite(LookaheadMode::PassThroughOnMemoryLimit, &mut output);
+
+            res.unwrap();
+
+            assert!(!handler_called);
+            assert_eq!(String::from_utf8(output).unwrap(), html);
+        }
+
+        #[test]
+        #[should_panic(expected = "Data was written into the stream after it has ended.")]
+        fn write_after_end() {
+            let mut rewriter = create_rewriter(512, |_: &[u8]| {});
+
+            rewriter.end().unwrap();
+            rewriter.write(b"foo").unwrap();
+        }
+
+        #[test]
+        #[should_panic(expected = "Stream was ended twice.")]
+        fn end_twice() {
+            let mut rewriter = create_rewriter(512, |_: &[u8]| {});
+
+            rewriter.end().unwrap();
+            rewriter.end().unwrap();
+        }
 
         #[test]
         #[should_panic(expected = "Attempt to use the HtmlRewriter after a fatal error.")]
@@ -637,11 +2440,65 @@ mod tests {
             rewriter.end().unwrap_err();
         }
 
+        #[test]
+        fn reset_after_fatal_error() {
+            const MAX: usize = 1024;
+
+            let mut output = vec![];
+
+            let template = RewriterTemplate::try_new(TemplateSettings {
+                element_content_handlers: vec![(
+                    &"div".parse().unwrap(),
+                    Box::new(|| {
+                        ElementContentHandlers::default().element(|el| {
+                            el.transform_inner_content(|content| content);
+
+                            Ok(())
+                        })
+                    }),
+                )],
+                memory_settings: MemorySettings {
+                    max_allowed_memory_usage: MAX,
+                    preallocated_parsing_buffer_size: 0,
+                },
+                ..TemplateSettings::default()
+            })
+            .unwrap();
+
+            {
+                let mut rewriter =
+                    HtmlRewriter::from_template(&template, |c: &[u8]| output.extend_from_slice(c))
+                        .unwrap();
+
+                rewriter.write(b"<div>").unwrap();
+
+                "t".repeat(MAX * 2)
+                    .as_bytes()
+                    .chunks(10)
+                    .try_for_each(|chunk| rewriter.write(chunk))
+                    .unwrap_err();
+
+                // NOTE: the content captured for the first document should be released
+                // on reset, otherwise the content of the second one doesn't fit the limit.
+                rewriter.reset().unwrap();
+
+                rewriter
+                    .write(format!("<div>{}</div>", "t".repeat(MAX / 2)).as_bytes())
+                    .unwrap();
+
+                rewriter.end().unwrap();
+            }
+
+            assert!(String::from_utf8(output)
+                .unwrap()
+                .ends_with(&format!("<div>{}</div>", "t".repeat(MAX / 2))));
+        }
+
         #[test]
         fn content_handler_error_propagation() {
-            fn assert_err(
-                element_handlers: ElementContentHandlers,
-                document_handlers: DocumentContentHandlers,
+            fn assert_err<'h>(
+                element_handlers: ElementContentHandlers<'h>,
+                document_handlers: DocumentContentHandlers<'h>,
                 expected_err: &'static str,
             ) {
                 let mut rewriter = HtmlRewriter::try_new(
@@ -0,0 +1,486 @@
+use super::{ContentHandlerTypes, HtmlRewriter, LocalHandlerTypes};
+use super::{RewritingError, Settings, SettingsError};
+use crate::transform_stream::OutputSink;
+use std::io::{self, Read};
+
+const INPUT_CHUNK_SIZE: usize = 8192;
+
+#[derive(Default)]
+pub(super) struct OutputBuffer {
+    data: Vec<u8>,
+    read_pos: usize,
+}
+
+impl OutputSink for OutputBuffer {
+    #[inline]
+    fn handle_chunk(&mut self, chunk: &[u8]) {
+        self.data.extend_from_slice(chunk);
+    }
+}
+
+impl OutputBuffer {
+    /// Passes at most `max_len` bytes of the unread data to `read` and marks them as read.
+    #[inline]
+    pub fn read_with(&mut self, max_len: usize, read: impl FnOnce(&[u8])) -> usize {
+        let available = &self.data[self.read_pos..];
+        let len = available.len().min(max_len);
+
+        read(&available[..len]);
+        self.read_pos += len;
+
+        if self.read_pos == self.data.len() {
+            self.data.clear();
+            self.read_pos = 0;
+        }
+
+        len
+    }
+
+    #[inline]
+    pub fn read_to(&mut self, buf: &mut [u8]) -> usize {
+        self.read_with(buf.len(), |data| buf[..data.len()].copy_from_slice(data))
+    }
+
+    #[cfg(feature = "async")]
+    #[inline]
+    pub fn take(&mut self) -> Vec<u8> {
+        let mut data = std::mem::take(&mut self.data);
+
+        data.drain(..self.read_pos);
+        self.read_pos = 0;
+
+        data
+    }
+}
+
+/// A reader that pulls the input from the underlying [`io::Read`] source and yields
+/// the rewritten output.
+///
+/// The input is read from the source in chunks as the output is consumed, so the reader can be
+/// plugged into `io::copy`, compression layers and other APIs that accept [`io::Read`]. The
+/// rewriting is finalized once the source reaches EOF.
+///
+/// Errors of the source are returned as is, so reading can be retried if the source allows that.
+/// [`RewritingError`]s are converted into [`io::Error`]s of the [`Other`] kind and, as with
+/// [`HtmlRewriter`], are unrecoverable: all the reads that follow such an error fail with
+/// an error of the same kind.
+///
+/// # Example
+/// ```
+/// use lol_html::{element, RewritingReader, Settings};
+/// use std::io::Read;
+///
+/// let input = &b"<div><a href=http://example.com></a></div>"[..];
+///
+/// let mut reader = RewritingReader::try_new(
+///     Settings {
+///         element_content_handlers: vec![element!("a[href]", |el| {
+///             let href = el.get_attribute("href").unwrap().replace("http:", "https:");
+///
+///             el.set_attribute("href", &href).unwrap();
+///
+///             Ok(())
+///         })],
+///         ..Settings::default()
+///     },
+///     input,
+/// ).unwrap();
+///
+/// let mut output = String::new();
+///
+/// reader.read_to_string(&mut output).unwrap();
+///
+/// assert_eq!(output, r#"<div><a href="https://example.com"></a></div>"#);
+/// ```
+///
+/// [`io::Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+/// [`io::Error`]: https://doc.rust-lang.org/std/io/struct.Error.html
+/// [`Other`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.Other
+/// [`RewritingError`]: errors/enum.RewritingError.html
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+pub struct RewritingReader<'h, R: Read, H: ContentHandlerTypes<'h> = LocalHandlerTypes> {
+    rewriter: HtmlRewriter<'h, OutputBuffer, H>,
+    source: R,
+    input_buffer: Vec<u8>,
+    finished: bool,
+}
+
+impl<'h, R: Read, H: ContentHandlerTypes<'h>> RewritingReader<'h, R, H> {
+    /// Constructs a new reader that rewrites the input from the `source` with
+    /// the provided `settings`.
+    pub fn try_new<'s>(settings: Settings<'h, 's, H>, source: R) -> Result<Self, SettingsError> {
+        Ok(RewritingReader {
+            rewriter: HtmlRewriter::try_new(settings, OutputBuffer::default())?,
+            source,
+            input_buffer: vec![0; INPUT_CHUNK_SIZE],
+            finished: false,
+        })
+    }
+
+    /// Unwraps the reader, returning the underlying source.
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+}
+
+impl<'h, R: Read, H: ContentHandlerTypes<'h>> Read for RewritingReader<'h, R, H>
+where
+    io::Error: From<RewritingError<H::Error>>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(err) = self.rewriter.poisoned_io_error() {
+            return Err(err);
+        }
+
+        loop {
+            let read_len = self.rewriter.stream.with_output_sink(|o| o.read_to(buf));
+
+            if read_len > 0 || self.finished || buf.is_empty() {
+                return Ok(read_len);
+            }
+
+            let input_len = self.source.read(&mut self.input_buffer)?;
+
+            if input_len > 0 {
+                self.rewriter.write(&self.input_buffer[..input_len])?;
+            } else {
+                self.finished = true;
+                self.rewriter.end()?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_content::ContentType;
+    use crate::*;
+    use std::error::Error;
+    use std::fmt::{self, Display};
+    use std::io::Write;
+    use std::thread;
+
+    type SendRewritingError = RewritingError<dyn Error + Send + Sync>;
+
+    fn downcast_rewriting_error(err: &io::Error) -> &SendRewritingError {
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+
+        err.get_ref()
+            .and_then(|err| err.downcast_ref::<SendRewritingError>())
+            .expect("Rewriting error expected")
+    }
+
+    // NOTE: a source that yields the input in chunks of the given size.
+    struct ChunkedSource<'i> {
+        input: &'i [u8],
+        chunk_size: usize,
+    }
+
+    impl Read for ChunkedSource<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.chunk_size.min(buf.len()).min(self.input.len());
+
+            buf[..len].copy_from_slice(&self.input[..len]);
+            self.input = &self.input[len..];
+
+            Ok(len)
+        }
+    }
+
+    fn rewrite_chunked(input: &str, chunk_size: usize, read_size: usize) -> String {
+        let mut reader = RewritingReader::try_new(
+            Settings {
+                element_content_handlers: vec![
+                    element!("div", |el| {
+                        el.set_tag_name("span").unwrap();
+
+                        Ok(())
+                    }),
+                    text!("span", |t| {
+                        t.replace(&t.as_str().to_uppercase(), ContentType::Html);
+
+                        Ok(())
+                    }),
+                ],
+                document_content_handlers: vec![end!(|end| {
+                    end.append("<!-- end -->", ContentType::Html);
+
+                    Ok(())
+                })],
+                ..Settings::default()
+            },
+            ChunkedSource {
+                input: input.as_bytes(),
+                chunk_size,
+            },
+        )
+        .unwrap();
+
+        let mut output = vec![];
+        let mut buf = vec![0; read_size];
+
+        loop {
+            let len = reader.read(&mut buf).unwrap();
+
+            if len == 0 {
+                break;
+            }
+
+            output.extend_from_slice(&buf[..len]);
+        }
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn rewriting() {
+        let input = "<div>foo</div><span>bar</span><p>baz</p>";
+        let expected = "<span>foo</span><span>BAR</span><p>baz</p><!-- end -->";
+
+        for chunk_size in 1..=input.len() {
+            for &read_size in &[1, 3, 1024] {
+                assert_eq!(
+                    rewrite_chunked(input, chunk_size, read_size),
+                    expected,
+                    "Chunk size: {}, read size: {}",
+                    chunk_size,
+                    read_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rewriting_error() {
+        let mut reader = RewritingReader::try_new(
+            Settings {
+                element_content_handlers: vec![element!("div", |_| Err("Error in handler".into()))],
+                ..Settings::default()
+            },
+            &b"<p></p><div></div>"[..],
+        )
+        .unwrap();
+
+        let err = reader.read_to_end(&mut vec![]).unwrap_err();
+
+        assert_eq!(err.to_string(), "Error in handler");
+
+        match downcast_rewriting_error(&err) {
+            RewritingError::ContentHandlerError(err) => {
+                assert_eq!(err.to_string(), "Error in handler")
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn send_handler_error() {
+        #[derive(Debug, PartialEq)]
+        struct HandlerError(usize);
+
+        impl Display for HandlerError {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "Error {}", self.0)
+            }
+        }
+
+        impl Error for HandlerError {}
+
+        let mut reader = RewritingReader::try_new(
+            Settings {
+                element_content_handlers: vec![element!("div", |_| Err(HandlerError(42).into()))],
+                ..Settings::new_send()
+            },
+            &b"<p></p><div></div>"[..],
+        )
+        .unwrap();
+
+        let err = reader.read_to_end(&mut vec![]).unwrap_err();
+
+        assert_eq!(err.to_string(), "Error 42");
+
+        match downcast_rewriting_error(&err) {
+            RewritingError::ContentHandlerError(err) => {
+                assert_eq!(err.downcast_ref::<HandlerError>(), Some(&HandlerError(42)))
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parsing_ambiguity_error() {
+        let mut reader = RewritingReader::try_new(
+            Settings {
+                element_content_handlers: vec![element!("script", |_| Ok(()))],
+                ..Settings::default()
+            },
+            &b"<select><xmp><script>"[..],
+        )
+        .unwrap();
+
+        let err = reader.read_to_end(&mut vec![]).unwrap_err();
+
+        assert!(matches!(
+            downcast_rewriting_error(&err),
+            RewritingError::ParsingAmbiguity(_)
+        ));
+    }
+
+    #[test]
+    fn memory_limit_error() {
+        let input = format!("<img alt=\"{}\">", "a".repeat(100));
+
+        let mut reader = RewritingReader::try_new(
+            Settings {
+                element_content_handlers: vec![element!("img", |_| Ok(()))],
+                memory_settings: MemorySettings {
+                    max_allowed_memory_usage: 50,
+                    preallocated_parsing_buffer_size: 0,
+                },
+                ..Settings::default()
+            },
+            ChunkedSource {
+                input: input.as_bytes(),
+                chunk_size: 10,
+            },
+        )
+        .unwrap();
+
+        let err = reader.read_to_end(&mut vec![]).unwrap_err();
+
+        assert!(matches!(
+            downcast_rewriting_error(&err),
+            RewritingError::MemoryLimitExceeded(_)
+        ));
+    }
+
+    #[test]
+    fn read_after_rewriting_error() {
+        let mut reader = RewritingReader::try_new(
+            Settings {
+                document_content_handlers: vec![end!(|_| Err("Error in end handler".into()))],
+                ..Settings::default()
+            },
+            &b"<p></p>"[..],
+        )
+        .unwrap();
+
+        let err = reader.read_to_end(&mut vec![]).unwrap_err();
+
+        assert_eq!(err.to_string(), "Error in end handler");
+
+        let mut buf = [0; 1024];
+
+        for _ in 0..2 {
+            let err = reader.read(&mut buf).unwrap_err();
+
+            assert_eq!(err.to_string(), "Error in end handler");
+
+            assert!(matches!(
+                downcast_rewriting_error(&err),
+                RewritingError::ContentHandlerError(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn write_adapter() {
+        let mut output = vec![];
+
+        {
+            let mut rewriter = HtmlRewriter::try_new(
+                Settings {
+                    element_content_handlers: vec![element!("div", |el| {
+                        el.set_tag_name("span").unwrap();
+
+                        Ok(())
+                    })],
+                    ..Settings::default()
+                },
+                |c: &[u8]| output.extend_from_slice(c),
+            )
+            .unwrap();
+
+            write!(rewriter, "<div>{}</div>", 42).unwrap();
+            io::Write::flush(&mut rewriter).unwrap();
+            rewriter.end().unwrap();
+        }
+
+        assert_eq!(String::from_utf8(output).unwrap(), "<span>42</span>");
+    }
+
+    #[test]
+    fn write_adapter_after_rewriting_error() {
+        let mut rewriter = HtmlRewriter::try_new(
+            Settings {
+                element_content_handlers: vec![element!("div", |_| Err("Error in handler".into()))],
+                ..Settings::default()
+            },
+            |_: &[u8]| {},
+        )
+        .unwrap();
+
+        let err = write!(rewriter, "<div></div>").unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+
+        for _ in 0..2 {
+            let err = io::Write::write(&mut rewriter, b"<p></p>").unwrap_err();
+
+            assert_eq!(err.kind(), io::ErrorKind::Other);
+            assert!(err.to_string().ends_with("Error in handler"));
+        }
+
+        // NOTE: the buffered data is written on drop, which must not panic.
+        let mut writer = io::BufWriter::new(rewriter);
+
+        writer.write_all(b"<p></p>").unwrap();
+        assert!(writer.flush().is_err());
+    }
+
+    #[test]
+    fn write_adapter_after_end() {
+        let mut rewriter = HtmlRewriter::try_new(Settings::default(), |_: &[u8]| {}).unwrap();
+
+        rewriter.write_all(b"<p></p>").unwrap();
+        rewriter.end().unwrap();
+
+        let err = io::Write::write(&mut rewriter, b"<p></p>").unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn send_reader() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let mut reader = RewritingReader::try_new(
+            Settings {
+                element_content_handlers: vec![element!("div", |el| {
+                    el.set_tag_name("span")?;
+
+                    Ok(())
+                })],
+                ..Settings::new_send()
+            },
+            &b"<div>foo</div>"[..],
+        )
+        .unwrap();
+
+        assert_send(&reader);
+
+        let output = thread::spawn(move || {
+            let mut output = String::new();
+
+            reader.read_to_string(&mut output).unwrap();
+
+            output
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(output, "<span>foo</span>");
+    }
+}
@@ -1,18 +1,20 @@
 use super::handlers_dispatcher::{ContentHandlersDispatcher, SelectorHandlersLocator};
-use super::RewritingError;
+use super::{ContentHandlerTypes, RewritingError};
 use crate::html::{LocalName, Namespace};
 use crate::rewritable_units::{DocumentEnd, Token, TokenCaptureFlags};
-use crate::selectors_vm::{AuxStartTagInfoRequest, ElementData, SelectorMatchingVm, VmError};
+use crate::selectors_vm::{
+    AuxStartTagInfoRequest, ElementData, LookaheadFactsMap, SelectorMatchingVm, TreePosition,
+    VmError,
+};
 use crate::transform_stream::*;
 use hashbrown::HashSet;
-use std::cell::RefCell;
-use std::rc::Rc;
 
 #[derive(Default)]
 pub struct ElementDescriptor {
     pub matched_content_handlers: HashSet<SelectorHandlersLocator>,
     pub end_tag_handler_idx: Option<usize>,
     pub remove_content: bool,
+    pub capture_inner_content: bool,
 }
 
 impl ElementData for ElementDescriptor {
@@ -24,35 +26,40 @@ impl ElementData for ElementDescriptor {
     }
 }
 
-pub struct HtmlRewriteController<'h> {
-    handlers_dispatcher: Rc<RefCell<ContentHandlersDispatcher<'h>>>,
+pub struct HtmlRewriteController<'h, H: ContentHandlerTypes<'h>> {
+    handlers_dispatcher: ContentHandlersDispatcher<'h, H>,
     selector_matching_vm: Option<SelectorMatchingVm<ElementDescriptor>>,
 }
 
-impl<'h> HtmlRewriteController<'h> {
+impl<'h, H: ContentHandlerTypes<'h>> HtmlRewriteController<'h, H> {
     #[inline]
     pub fn new(
-        handlers_dispatcher: ContentHandlersDispatcher<'h>,
+        handlers_dispatcher: ContentHandlersDispatcher<'h, H>,
         selector_matching_vm: Option<SelectorMatchingVm<ElementDescriptor>>,
     ) -> Self {
         HtmlRewriteController {
-            handlers_dispatcher: Rc::new(RefCell::new(handlers_dispatcher)),
+            handlers_dispatcher,
             selector_matching_vm,
         }
     }
+
+    #[inline]
+    pub fn handlers_dispatcher_mut(&mut self) -> &mut ContentHandlersDispatcher<'h, H> {
+        &mut self.handlers_dispatcher
+    }
 }
 
 // NOTE: it's a macro instead of an instance method, so it can be executed
 // when we hold a mutable reference for the selector matching VM.
 macro_rules! create_match_handler {
     ($self:tt) => {{
-        let handlers_dispatcher = Rc::clone(&$self.handlers_dispatcher);
+        let handlers_dispatcher = &mut $self.handlers_dispatcher;
 
-        move |m| handlers_dispatcher.borrow_mut().start_matching(m)
+        move |m| handlers_dispatcher.start_matching(m)
     }};
 }
 
-impl<'h> HtmlRewriteController<'h> {
+impl<'h, H: ContentHandlerTypes<'h>> HtmlRewriteController<'h, H> {
     #[inline]
     fn respond_to_aux_info_request(
         aux_info_req: AuxStartTagInfoRequest<ElementDescriptor, SelectorHandlersLocator>,
@@ -73,11 +80,11 @@ impl<'h> HtmlRewriteController<'h> {
 
     #[inline]
     fn get_capture_flags(&self) -> TokenCaptureFlags {
-        self.handlers_dispatcher.borrow().get_token_capture_flags()
+        self.handlers_dispatcher.get_token_capture_flags()
     }
 }
 
-impl TransformController for HtmlRewriteController<'_> {
+impl<'h, H: ContentHandlerTypes<'h>> TransformController for HtmlRewriteController<'h, H> {
     #[inline]
     fn initial_capture_flags(&self) -> TokenCaptureFlags {
         self.get_capture_flags()
@@ -90,6 +97,8 @@ impl TransformController for HtmlRewriteController<'_> {
     ) -> StartTagHandlingResult<Self> {
         match self.selector_matching_vm {
             Some(ref mut vm) => {
+                self.handlers_dispatcher.prepare_for_start_tag();
+
                 let mut match_handler = create_match_handler!(self);
 
                 match vm.exec_for_start_tag(local_name, ns, &mut match_handler) {
@@ -108,10 +117,10 @@ impl TransformController for HtmlRewriteController<'_> {
 
     fn handle_end_tag(&mut self, local_name: LocalName) -> TokenCaptureFlags {
         if let Some(ref mut vm) = self.selector_matching_vm {
-            let handlers_dispatcher = Rc::clone(&self.handlers_dispatcher);
+            let handlers_dispatcher = &mut self.handlers_dispatcher;
 
             vm.exec_for_end_tag(local_name, move |elem_desc| {
-                handlers_dispatcher.borrow_mut().stop_matching(elem_desc);
+                handlers_dispatcher.stop_matching(elem_desc);
             });
         }
 
@@ -120,29 +129,69 @@ impl TransformController for HtmlRewriteController<'_> {
 
     #[inline]
     fn handle_token(&mut self, token: &mut Token) -> Result<(), RewritingError> {
+        let position = match (&self.selector_matching_vm, &token) {
+            (Some(vm), Token::StartTag(_)) => vm.element_position().clone(),
+            (Some(vm), _) => vm.content_position(),
+            (None, _) => TreePosition::default(),
+        };
+
         let current_element_data = self
             .selector_matching_vm
             .as_mut()
             .and_then(SelectorMatchingVm::current_element_data_mut);
 
         self.handlers_dispatcher
-            .borrow_mut()
-            .handle_token(token, current_element_data)
-            .map_err(RewritingError::ContentHandlerError)
+            .handle_token(token, current_element_data, position)
+            .map_err(|err| RewritingError::ContentHandlerError(H::into_dyn_error(err)))
     }
 
     fn handle_end(&mut self, document_end: &mut DocumentEnd) -> Result<(), RewritingError> {
         self.handlers_dispatcher
-            .borrow_mut()
             .handle_end(document_end)
-            .map_err(RewritingError::ContentHandlerError)
+            .map_err(|err| RewritingError::ContentHandlerError(H::into_dyn_error(err)))
+    }
+
+    #[inline]
+    fn is_capturing_content(&self) -> bool {
+        self.handlers_dispatcher.is_capturing_content()
+    }
+
+    #[inline]
+    fn capture_content(&mut self, chunk: &[u8]) -> Result<(), RewritingError> {
+        self.handlers_dispatcher
+            .capture_content(chunk)
+            .map_err(RewritingError::MemoryLimitExceeded)
+    }
+
+    #[inline]
+    fn activate_content_captures(&mut self) {
+        self.handlers_dispatcher.activate_content_captures();
+    }
+
+    #[inline]
+    fn captures_text_nodes(&self) -> bool {
+        self.handlers_dispatcher.has_active_text_node_handlers()
     }
 
     #[inline]
     fn should_emit_content(&self) -> bool {
         !self
             .handlers_dispatcher
-            .borrow()
             .has_matched_elements_with_removed_content()
     }
+
+    #[inline]
+    fn lookahead_facts_mut(&mut self) -> Option<&mut LookaheadFactsMap> {
+        self.selector_matching_vm
+            .as_mut()
+            .and_then(|vm| vm.lookahead_facts_mut())
+    }
+
+    fn reset(&mut self) {
+        self.handlers_dispatcher.reset();
+
+        if let Some(ref mut vm) = self.selector_matching_vm {
+            vm.reset();
+        }
+    }
 }
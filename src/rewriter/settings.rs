@@ -1,44 +1,305 @@
 use crate::rewritable_units::{Comment, Doctype, DocumentEnd, Element, EndTag, TextChunk};
-use crate::selectors_vm::Selector;
+use crate::selectors_vm::{CustomPseudoClasses, Selector};
 use std::error::Error;
 
-pub(super) type HandlerResult = Result<(), Box<dyn Error>>;
+pub(crate) type HandlerResult<E = dyn Error> = Result<(), Box<E>>;
 pub type DoctypeHandler<'h> = Box<dyn FnMut(&mut Doctype) -> HandlerResult + 'h>;
 pub type CommentHandler<'h> = Box<dyn FnMut(&mut Comment) -> HandlerResult + 'h>;
 pub type TextHandler<'h> = Box<dyn FnMut(&mut TextChunk) -> HandlerResult + 'h>;
 pub type ElementHandler<'h> = Box<dyn FnMut(&mut Element) -> HandlerResult + 'h>;
 pub type EndTagHandler<'h> = Box<dyn FnOnce(&mut EndTag) -> HandlerResult + 'h>;
+pub(crate) type InnerContentTransform<'h> = Box<dyn FnOnce(String) -> String + 'h>;
 pub type EndHandler<'h> = Box<dyn FnOnce(&mut DocumentEnd) -> HandlerResult + 'h>;
 
+type SendHandlerResult = HandlerResult<dyn Error + Send + Sync>;
+pub type DoctypeHandlerSend<'h> = Box<dyn FnMut(&mut Doctype) -> SendHandlerResult + Send + 'h>;
+pub type CommentHandlerSend<'h> = Box<dyn FnMut(&mut Comment) -> SendHandlerResult + Send + 'h>;
+pub type TextHandlerSend<'h> = Box<dyn FnMut(&mut TextChunk) -> SendHandlerResult + Send + 'h>;
+pub type ElementHandlerSend<'h> =
+    Box<dyn FnMut(&mut Element<'_, '_, SendHandlerTypes>) -> SendHandlerResult + Send + 'h>;
+pub type EndTagHandlerSend<'h> = Box<dyn FnOnce(&mut EndTag) -> SendHandlerResult + Send + 'h>;
+pub(crate) type InnerContentTransformSend<'h> = Box<dyn FnOnce(String) -> String + Send + 'h>;
+pub type EndHandlerSend<'h> = Box<dyn FnOnce(&mut DocumentEnd) -> SendHandlerResult + Send + 'h>;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// Specifies the types of the content handlers.
+///
+/// The rewriter is [`Send`] only if all of its content handlers are `Send`. With
+/// [`LocalHandlerTypes`], which is used by default, the handlers are not required to be `Send`.
+/// With [`SendHandlerTypes`] the handlers and their errors are required to be `Send`, so
+/// the rewriter can be moved to another thread. Use [`Settings::new_send`] to construct
+/// the settings for such a rewriter.
+///
+/// [`Send`]: https://doc.rust-lang.org/std/marker/trait.Send.html
+/// [`LocalHandlerTypes`]: struct.LocalHandlerTypes.html
+/// [`SendHandlerTypes`]: struct.SendHandlerTypes.html
+/// [`Settings::new_send`]: struct.Settings.html#method.new_send
+pub trait HandlerTypes: private::Sealed + Sized + 'static {
+    /// The type of the errors returned by the content handlers.
+    type Error: Error + ?Sized + 'static;
+
+    #[doc(hidden)]
+    type EndTagHandler: FnOnce(&mut EndTag) -> HandlerResult<Self::Error> + Unpin + 'static;
+
+    #[doc(hidden)]
+    type InnerContentTransform: FnOnce(String) -> String + Unpin + 'static;
+
+    #[doc(hidden)]
+    fn new_end_tag_handler(
+        handler: impl FnOnce(&mut EndTag) -> HandlerResult<Self::Error> + Send + 'static,
+    ) -> Self::EndTagHandler;
+
+    #[doc(hidden)]
+    fn combine_end_tag_handlers(handlers: Vec<Self::EndTagHandler>) -> Self::EndTagHandler;
+
+    // NOTE: the rewriter propagates the errors of the content handlers as `Box<dyn Error>`,
+    // so the errors are converted back to `Self::Error` once they leave the rewriter.
+    #[doc(hidden)]
+    fn into_dyn_error(err: Box<Self::Error>) -> Box<dyn Error>;
+
+    #[doc(hidden)]
+    fn from_dyn_error(err: Box<dyn Error>) -> Box<Self::Error>;
+}
+
+/// Specifies the types of the content handlers that live for `'h`.
+///
+/// Refer to [`HandlerTypes`] documentation for more information.
+///
+/// [`HandlerTypes`]: trait.HandlerTypes.html
+pub trait ContentHandlerTypes<'h>: HandlerTypes {
+    #[doc(hidden)]
+    type DoctypeHandler: FnMut(&mut Doctype) -> HandlerResult<Self::Error> + Unpin + 'h;
+
+    #[doc(hidden)]
+    type CommentHandler: FnMut(&mut Comment) -> HandlerResult<Self::Error> + Unpin + 'h;
+
+    #[doc(hidden)]
+    type TextHandler: FnMut(&mut TextChunk) -> HandlerResult<Self::Error> + Unpin + 'h;
+
+    #[doc(hidden)]
+    type ElementHandler: FnMut(&mut Element<'_, '_, Self>) -> HandlerResult<Self::Error>
+        + Unpin
+        + 'h;
+
+    #[doc(hidden)]
+    type EndHandler: FnOnce(&mut DocumentEnd) -> HandlerResult<Self::Error> + Unpin + 'h;
+}
+
+/// Handler types that are not required to be [`Send`].
+///
+/// [`Send`]: https://doc.rust-lang.org/std/marker/trait.Send.html
+#[derive(Debug)]
+pub struct LocalHandlerTypes;
+
+impl private::Sealed for LocalHandlerTypes {}
+
+impl HandlerTypes for LocalHandlerTypes {
+    type Error = dyn Error;
+    type EndTagHandler = EndTagHandler<'static>;
+    type InnerContentTransform = InnerContentTransform<'static>;
+
+    #[inline]
+    fn new_end_tag_handler(
+        handler: impl FnOnce(&mut EndTag) -> HandlerResult + Send + 'static,
+    ) -> Self::EndTagHandler {
+        Box::new(handler)
+    }
+
+    #[inline]
+    fn combine_end_tag_handlers(handlers: Vec<Self::EndTagHandler>) -> Self::EndTagHandler {
+        Box::new(move |end_tag| handlers.into_iter().try_for_each(|h| h(end_tag)))
+    }
+
+    #[inline]
+    fn into_dyn_error(err: Box<dyn Error>) -> Box<dyn Error> {
+        err
+    }
+
+    #[inline]
+    fn from_dyn_error(err: Box<dyn Error>) -> Box<dyn Error> {
+        err
+    }
+}
+
+impl<'h> ContentHandlerTypes<'h> for LocalHandlerTypes {
+    type DoctypeHandler = DoctypeHandler<'h>;
+    type CommentHandler = CommentHandler<'h>;
+    type TextHandler = TextHandler<'h>;
+    type ElementHandler = ElementHandler<'h>;
+    type EndHandler = EndHandler<'h>;
+}
+
+/// Handler types that are required to be [`Send`].
+///
+/// The errors of such handlers are required to be `Send + Sync`.
+///
+/// [`Send`]: https://doc.rust-lang.org/std/marker/trait.Send.html
+#[derive(Debug)]
+pub struct SendHandlerTypes;
+
+impl private::Sealed for SendHandlerTypes {}
+
+/// An error of a `Send` content handler on its way through the rewriter.
+#[derive(thiserror::Error, Debug)]
+#[error("{0}")]
+struct SendHandlerError(Box<dyn Error + Send + Sync>);
+
+impl HandlerTypes for SendHandlerTypes {
+    type Error = dyn Error + Send + Sync;
+    type EndTagHandler = EndTagHandlerSend<'static>;
+    type InnerContentTransform = InnerContentTransformSend<'static>;
+
+    #[inline]
+    fn new_end_tag_handler(
+        handler: impl FnOnce(&mut EndTag) -> SendHandlerResult + Send + 'static,
+    ) -> Self::EndTagHandler {
+        Box::new(handler)
+    }
+
+    #[inline]
+    fn combine_end_tag_handlers(handlers: Vec<Self::EndTagHandler>) -> Self::EndTagHandler {
+        Box::new(move |end_tag| handlers.into_iter().try_for_each(|h| h(end_tag)))
+    }
+
+    #[inline]
+    fn into_dyn_error(err: Box<dyn Error + Send + Sync>) -> Box<dyn Error> {
+        Box::new(SendHandlerError(err))
+    }
+
+    #[inline]
+    fn from_dyn_error(err: Box<dyn Error>) -> Box<dyn Error + Send + Sync> {
+        // NOTE: all the errors of the content handlers are wrapped by `into_dyn_error`.
+        match err.downcast::<SendHandlerError>() {
+            Ok(err) => err.0,
+            Err(err) => err.to_string().into(),
+        }
+    }
+}
+
+impl<'h> ContentHandlerTypes<'h> for SendHandlerTypes {
+    type DoctypeHandler = DoctypeHandlerSend<'h>;
+    type CommentHandler = CommentHandlerSend<'h>;
+    type TextHandler = TextHandlerSend<'h>;
+    type ElementHandler = ElementHandlerSend<'h>;
+    type EndHandler = EndHandlerSend<'h>;
+}
+
+/// Converts a closure into a content handler of the type `T`.
+///
+/// The trait is implemented for all the closures with a suitable signature, so the handler
+/// setters accept only `Send` closures if the handlers are required to be `Send`.
+pub trait IntoHandler<T> {
+    #[doc(hidden)]
+    fn into_handler(self) -> T;
+}
+
+macro_rules! impl_into_handler {
+    ($Handler:ident, $($bounds:tt)+) => {
+        impl<'h, F> IntoHandler<$Handler<'h>> for F
+        where
+            F: $($bounds)+,
+            F: 'h,
+        {
+            #[inline]
+            fn into_handler(self) -> $Handler<'h> {
+                Box::new(self)
+            }
+        }
+    };
+}
+
+impl_into_handler!(DoctypeHandler, FnMut(&mut Doctype) -> HandlerResult);
+impl_into_handler!(CommentHandler, FnMut(&mut Comment) -> HandlerResult);
+impl_into_handler!(TextHandler, FnMut(&mut TextChunk) -> HandlerResult);
+impl_into_handler!(ElementHandler, FnMut(&mut Element) -> HandlerResult);
+impl_into_handler!(EndTagHandler, FnOnce(&mut EndTag) -> HandlerResult);
+impl_into_handler!(InnerContentTransform, FnOnce(String) -> String);
+impl_into_handler!(EndHandler, FnOnce(&mut DocumentEnd) -> HandlerResult);
+impl_into_handler!(DoctypeHandlerSend, FnMut(&mut Doctype) -> SendHandlerResult + Send);
+impl_into_handler!(CommentHandlerSend, FnMut(&mut Comment) -> SendHandlerResult + Send);
+impl_into_handler!(TextHandlerSend, FnMut(&mut TextChunk) -> SendHandlerResult + Send);
+impl_into_handler!(
+    ElementHandlerSend,
+    FnMut(&mut Element<'_, '_, SendHandlerTypes>) -> SendHandlerResult + Send
+);
+impl_into_handler!(EndTagHandlerSend, FnOnce(&mut EndTag) -> SendHandlerResult + Send);
+impl_into_handler!(InnerContentTransformSend, FnOnce(String) -> String + Send);
+impl_into_handler!(EndHandlerSend, FnOnce(&mut DocumentEnd) -> SendHandlerResult + Send);
+
 /// Specifies element content handlers associated with a selector.
-#[derive(Default)]
-pub struct ElementContentHandlers<'h> {
-    pub(super) element: Option<ElementHandler<'h>>,
-    pub(super) comments: Option<CommentHandler<'h>>,
-    pub(super) text: Option<TextHandler<'h>>,
+pub struct ElementContentHandlers<'h, H: ContentHandlerTypes<'h> = LocalHandlerTypes> {
+    pub(super) element: Option<H::ElementHandler>,
+    pub(super) comments: Option<H::CommentHandler>,
+    pub(super) text: Option<H::TextHandler>,
+    pub(super) text_nodes: bool,
 }
 
-impl<'h> ElementContentHandlers<'h> {
+impl<'h, H: ContentHandlerTypes<'h>> Default for ElementContentHandlers<'h, H> {
+    #[inline]
+    fn default() -> Self {
+        ElementContentHandlers {
+            element: None,
+            comments: None,
+            text: None,
+            text_nodes: false,
+        }
+    }
+}
+
+impl<'h, H: ContentHandlerTypes<'h>> ElementContentHandlers<'h, H> {
     /// Sets a handler for elements matched by a selector.
     #[inline]
-    pub fn element(mut self, handler: impl FnMut(&mut Element) -> HandlerResult + 'h) -> Self {
-        self.element = Some(Box::new(handler));
+    pub fn element(
+        mut self,
+        handler: impl FnMut(&mut Element<'_, '_, H>) -> HandlerResult<H::Error>
+            + IntoHandler<H::ElementHandler>,
+    ) -> Self {
+        self.element = Some(handler.into_handler());
 
         self
     }
 
     /// Sets a handler for HTML comments in the inner content of elements matched by a selector.
     #[inline]
-    pub fn comments(mut self, handler: impl FnMut(&mut Comment) -> HandlerResult + 'h) -> Self {
-        self.comments = Some(Box::new(handler));
+    pub fn comments(
+        mut self,
+        handler: impl FnMut(&mut Comment) -> HandlerResult<H::Error> + IntoHandler<H::CommentHandler>,
+    ) -> Self {
+        self.comments = Some(handler.into_handler());
 
         self
     }
 
     /// Sets a handler for text chunks in the inner content of elements matched by a selector.
     #[inline]
-    pub fn text(mut self, handler: impl FnMut(&mut TextChunk) -> HandlerResult + 'h) -> Self {
-        self.text = Some(Box::new(handler));
+    pub fn text(
+        mut self,
+        handler: impl FnMut(&mut TextChunk) -> HandlerResult<H::Error> + IntoHandler<H::TextHandler>,
+    ) -> Self {
+        self.text = Some(handler.into_handler());
+        self.text_nodes = false;
+
+        self
+    }
+
+    /// Sets a handler for text nodes in the inner content of elements matched by a selector.
+    ///
+    /// Unlike the handler set with [`text`], the handler receives each text node as a single
+    /// [`TextChunk`]. See [`text_nodes!`] for the details.
+    ///
+    /// [`text`]: #method.text
+    /// [`TextChunk`]: html_content/struct.TextChunk.html
+    /// [`text_nodes!`]: macro.text_nodes.html
+    #[inline]
+    pub fn text_nodes(
+        mut self,
+        handler: impl FnMut(&mut TextChunk) -> HandlerResult<H::Error> + IntoHandler<H::TextHandler>,
+    ) -> Self {
+        self.text = Some(handler.into_handler());
+        self.text_nodes = true;
 
         self
     }
@@ -59,45 +320,90 @@ impl<'h> ElementContentHandlers<'h> {
 /// <!-- I can be captured with a selector -->
 /// </html>
 /// ```
-#[derive(Default)]
-pub struct DocumentContentHandlers<'h> {
-    pub(super) doctype: Option<DoctypeHandler<'h>>,
-    pub(super) comments: Option<CommentHandler<'h>>,
-    pub(super) text: Option<TextHandler<'h>>,
-    pub(super) end: Option<EndHandler<'h>>,
+pub struct DocumentContentHandlers<'h, H: ContentHandlerTypes<'h> = LocalHandlerTypes> {
+    pub(super) doctype: Option<H::DoctypeHandler>,
+    pub(super) comments: Option<H::CommentHandler>,
+    pub(super) text: Option<H::TextHandler>,
+    pub(super) text_nodes: bool,
+    pub(super) end: Option<H::EndHandler>,
 }
 
-impl<'h> DocumentContentHandlers<'h> {
+impl<'h, H: ContentHandlerTypes<'h>> Default for DocumentContentHandlers<'h, H> {
+    #[inline]
+    fn default() -> Self {
+        DocumentContentHandlers {
+            doctype: None,
+            comments: None,
+            text: None,
+            text_nodes: false,
+            end: None,
+        }
+    }
+}
+
+impl<'h, H: ContentHandlerTypes<'h>> DocumentContentHandlers<'h, H> {
     /// Sets a handler for the [document type declaration].
     ///
     /// [document type declaration]: https://developer.mozilla.org/en-US/docs/Glossary/Doctype
     #[inline]
-    pub fn doctype(mut self, handler: impl FnMut(&mut Doctype) -> HandlerResult + 'h) -> Self {
-        self.doctype = Some(Box::new(handler));
+    pub fn doctype(
+        mut self,
+        handler: impl FnMut(&mut Doctype) -> HandlerResult<H::Error> + IntoHandler<H::DoctypeHandler>,
+    ) -> Self {
+        self.doctype = Some(handler.into_handler());
 
         self
     }
 
     /// Sets a handler for all HTML comments present in the input HTML markup.
     #[inline]
-    pub fn comments(mut self, handler: impl FnMut(&mut Comment) -> HandlerResult + 'h) -> Self {
-        self.comments = Some(Box::new(handler));
+    pub fn comments(
+        mut self,
+        handler: impl FnMut(&mut Comment) -> HandlerResult<H::Error> + IntoHandler<H::CommentHandler>,
+    ) -> Self {
+        self.comments = Some(handler.into_handler());
 
         self
     }
 
     /// Sets a handler for all text chunks present in the input HTML markup.
     #[inline]
-    pub fn text(mut self, handler: impl FnMut(&mut TextChunk) -> HandlerResult + 'h) -> Self {
-        self.text = Some(Box::new(handler));
+    pub fn text(
+        mut self,
+        handler: impl FnMut(&mut TextChunk) -> HandlerResult<H::Error> + IntoHandler<H::TextHandler>,
+    ) -> Self {
+        self.text = Some(handler.into_handler());
+        self.text_nodes = false;
+
+        self
+    }
+
+    /// Sets a handler for all text nodes present in the input HTML markup.
+    ///
+    /// Unlike the handler set with [`text`], the handler receives each text node as a single
+    /// [`TextChunk`]. See [`text_nodes!`] for the details.
+    ///
+    /// [`text`]: #method.text
+    /// [`TextChunk`]: html_content/struct.TextChunk.html
+    /// [`text_nodes!`]: macro.text_nodes.html
+    #[inline]
+    pub fn text_nodes(
+        mut self,
+        handler: impl FnMut(&mut TextChunk) -> HandlerResult<H::Error> + IntoHandler<H::TextHandler>,
+    ) -> Self {
+        self.text = Some(handler.into_handler());
+        self.text_nodes = true;
 
         self
     }
 
     /// Sets a handler for the document end, which is called after the last chunk is processed.
     #[inline]
-    pub fn end(mut self, handler: impl FnMut(&mut DocumentEnd) -> HandlerResult + 'h) -> Self {
-        self.end = Some(Box::new(handler));
+    pub fn end(
+        mut self,
+        handler: impl FnMut(&mut DocumentEnd) -> HandlerResult<H::Error> + IntoHandler<H::EndHandler>,
+    ) -> Self {
+        self.end = Some(handler.into_handler());
 
         self
     }
@@ -148,6 +454,9 @@ macro_rules! element {
 /// A convenience macro to construct a rewriting handler for text chunks in the inner content of an
 /// element that can be matched by the specified CSS selector.
 ///
+/// While a handler constructed with [`text_nodes!`] is active, the handler receives whole text
+/// nodes rather than chunks.
+///
 /// # Example
 /// ```
 /// use lol_html::{rewrite_str, text, RewriteStrSettings};
@@ -171,6 +480,8 @@ macro_rules! element {
 ///
 /// assert_eq!(html, r#"<span>Hello world</span>"#);
 /// ```
+///
+/// [`text_nodes!`]: macro.text_nodes.html
 #[macro_export(local_inner_macros)]
 macro_rules! text {
     ($selector:expr, $handler:expr) => {
@@ -178,6 +489,49 @@ macro_rules! text {
     };
 }
 
+/// A convenience macro to construct a rewriting handler for text nodes in the inner content of an
+/// element that can be matched by the specified CSS selector.
+///
+/// Unlike the handler constructed with [`text!`], the handler receives each text node as a single
+/// [`TextChunk`], so the text can be searched and replaced without tracking the chunk boundaries.
+/// The rewriter buffers the text until the end of the text node, and the buffered text is
+/// counted against the [`max_allowed_memory_usage`] limit. While such a handler is active, the
+/// other text handlers receive whole text nodes as well.
+///
+/// # Example
+/// ```
+/// use lol_html::{rewrite_str, text_nodes, RewriteStrSettings};
+/// use lol_html::html_content::ContentType;
+///
+/// let html = rewrite_str(
+///     r#"<span>Hello world</span>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![
+///             text_nodes!("span", |t| {
+///                 assert!(t.last_in_text_node());
+///
+///                 t.replace(&t.as_str().replace("world", "there"), ContentType::Text);
+///
+///                 Ok(())
+///             })
+///         ],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(html, r#"<span>Hello there</span>"#);
+/// ```
+///
+/// [`text!`]: macro.text.html
+/// [`TextChunk`]: html_content/struct.TextChunk.html
+/// [`max_allowed_memory_usage`]: struct.MemorySettings.html#structfield.max_allowed_memory_usage
+#[macro_export(local_inner_macros)]
+macro_rules! text_nodes {
+    ($selector:expr, $handler:expr) => {
+        __element_content_handler!($selector, text_nodes, $handler);
+    };
+}
+
 /// A convenience macro to construct a rewriting handler for HTML comments in the inner content of
 /// an element that can be matched by the specified CSS selector.
 ///
@@ -279,6 +633,42 @@ macro_rules! doc_text {
     };
 }
 
+/// A convenience macro to construct a rewriting handler for all text nodes in the HTML document.
+///
+/// The handler receives each text node as a single [`TextChunk`]. See [`text_nodes!`] for
+/// the details.
+///
+/// # Example
+/// ```
+/// use lol_html::{rewrite_str, doc_text_nodes, RewriteStrSettings};
+/// use lol_html::html_content::ContentType;
+///
+/// let html = rewrite_str(
+///     r#"Hello<span>Hello</span>Hello"#,
+///     RewriteStrSettings {
+///         document_content_handlers: vec![
+///             doc_text_nodes!(|t| {
+///                 t.after(" world", ContentType::Text);
+///
+///                 Ok(())
+///             })
+///         ],
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(html, r#"Hello world<span>Hello world</span>Hello world"#);
+/// ```
+///
+/// [`TextChunk`]: html_content/struct.TextChunk.html
+/// [`text_nodes!`]: macro.text_nodes.html
+#[macro_export(local_inner_macros)]
+macro_rules! doc_text_nodes {
+    ($handler:expr) => {
+        __document_content_handler!(text_nodes, $handler);
+    };
+}
+
 /// A convenience macro to construct a rewriting handler for all HTML comments in the HTML document.
 ///
 /// # Example
@@ -353,6 +743,7 @@ macro_rules! end {
 /// [`HtmlRewriter`]: struct.HtmlRewriter.html
 // NOTE: exposed in C API as well, thus repr(C).
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct MemorySettings {
     /// Specifies the number of bytes that should be preallocated on [`HtmlRewriter`] instantiation
     /// for the internal parsing buffer.
@@ -404,10 +795,52 @@ impl Default for MemorySettings {
     }
 }
 
+/// Specifies whether and how the rewriter buffers the input to match selectors that
+/// depend on the content following the start tag of an element.
+///
+/// Pseudo-classes like `:last-child`, `:empty` or `:has()` can't be matched once the
+/// start tag of an element is parsed, since the decision depends on the element's content or
+/// following siblings. To match such selectors the rewriter withholds the input starting from the
+/// start tag of a potentially matching element until the decision can be made. The withheld input
+/// is accounted in [`max_allowed_memory_usage`].
+///
+/// [`max_allowed_memory_usage`]: struct.MemorySettings.html#structfield.max_allowed_memory_usage
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum LookaheadMode {
+    /// The input is never withheld. The construction of a rewriter fails with
+    /// [`SettingsError::LookaheadRequired`] if some of the selectors require lookahead,
+    /// including the ones with negated pseudo-classes, e.g. `:not(:last-child)`.
+    ///
+    /// [`SettingsError::LookaheadRequired`]: errors/enum.SettingsError.html#variant.LookaheadRequired
+    Disabled,
+
+    /// The input is withheld until the decision can be made. If the withheld input exceeds
+    /// the memory limit, rewriter's [`write`] and [`end`] methods error with
+    /// [`MemoryLimitExceeded`].
+    ///
+    /// [`write`]: struct.HtmlRewriter.html#method.write
+    /// [`end`]: struct.HtmlRewriter.html#method.end
+    /// [`MemoryLimitExceeded`]: errors/enum.RewritingError.html#variant.MemoryLimitExceeded
+    FailOnMemoryLimit,
+
+    /// The input is withheld until the decision can be made. If the withheld input exceeds
+    /// the memory limit, it's passed through and elements for which the decision hasn't been
+    /// made yet are considered to be non-matching, even by selectors with negated
+    /// pseudo-classes.
+    PassThroughOnMemoryLimit,
+}
+
+impl Default for LookaheadMode {
+    #[inline]
+    fn default() -> Self {
+        LookaheadMode::Disabled
+    }
+}
+
 /// Specifies settings for [`HtmlRewriter`].
 ///
 /// [`HtmlRewriter`]: struct.HtmlRewriter.html
-pub struct Settings<'h, 's> {
+pub struct Settings<'h, 's, H: ContentHandlerTypes<'h> = LocalHandlerTypes> {
     /// Specifies CSS selectors and rewriting handlers for elements and their inner content.
     ///
     /// ### Hint
@@ -445,7 +878,7 @@ pub struct Settings<'h, 's> {
     /// [`element`]: macro.element.html
     /// [`comments`]: macro.comments.html
     /// [`text`]: macro.text.html
-    pub element_content_handlers: Vec<(&'s Selector, ElementContentHandlers<'h>)>,
+    pub element_content_handlers: Vec<(&'s Selector, ElementContentHandlers<'h, H>)>,
 
     /// Specifies rewriting handlers for the content without associating it to a particular
     /// CSS selector.
@@ -460,7 +893,7 @@ pub struct Settings<'h, 's> {
     /// [`doctype`]: macro.doctype.html
     /// [`doc_comments`]: macro.doc_comments.html
     /// [`doc_text`]: macro.doc_text.html
-    pub document_content_handlers: Vec<DocumentContentHandlers<'h>>,
+    pub document_content_handlers: Vec<DocumentContentHandlers<'h, H>>,
 
     /// Specifies the [character encoding] for the input and the output of the rewriter.
     ///
@@ -512,17 +945,169 @@ pub struct Settings<'h, 's> {
     ///
     /// `true` when constructed with `Settings::default()`.
     pub strict: bool,
+
+    /// Specifies whether the rewriter buffers the input to match selectors that
+    /// depend on the content following the start tag of an element.
+    ///
+    /// Refer to [`LookaheadMode`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// [`LookaheadMode::Disabled`] when constructed with `Settings::default()`.
+    ///
+    /// [`LookaheadMode`]: enum.LookaheadMode.html
+    /// [`LookaheadMode::Disabled`]: enum.LookaheadMode.html#variant.Disabled
+    pub lookahead_mode: LookaheadMode,
+
+    /// Specifies the namespace prefixes that can be used in the namespaced type and attribute
+    /// selectors (e.g. `svg|a` or `[xlink|href]`) as `(prefix, namespace URI)` pairs.
+    ///
+    /// Selectors that use a prefix which is not registered here never match.
+    ///
+    /// ### Example
+    /// ```
+    /// use lol_html::{element, rewrite_str, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<a href="/foo"></a><svg><a xlink:href="/bar"></a></svg>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("svg|a[xlink|href]", |el| {
+    ///                 el.set_attribute("xlink:href", "/baz")?;
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         namespace_prefixes: vec![
+    ///             ("svg", "http://www.w3.org/2000/svg"),
+    ///             ("xlink", "http://www.w3.org/1999/xlink"),
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<a href="/foo"></a><svg><a xlink:href="/baz"></a></svg>"#);
+    /// ```
+    ///
+    /// ### Default
+    ///
+    /// No prefixes are registered when constructed with `Settings::default()`.
+    pub namespace_prefixes: Vec<(&'s str, &'s str)>,
+
+    /// Specifies the user-defined pseudo-classes that can be used in the selectors.
+    ///
+    /// Refer to [`CustomPseudoClasses`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// No pseudo-classes are registered when constructed with `Settings::default()`.
+    ///
+    /// [`CustomPseudoClasses`]: struct.CustomPseudoClasses.html
+    pub custom_pseudo_classes: CustomPseudoClasses,
+
+    /// Enables tracking of the ancestor elements that are available via the
+    /// [`Element::ancestors`] method and its [`TextChunk`] and [`Comment`] counterparts.
+    ///
+    /// ### Note
+    ///
+    /// The tracking allocates a descriptor for every open element, so it comes with a
+    /// performance cost. The descriptors are accounted in the [`memory_settings`]. The ancestors
+    /// are tracked only if the rewriter has [`element_content_handlers`], and they are also
+    /// tracked if the [`retained_ancestor_attributes`] are specified.
+    ///
+    /// ### Default
+    ///
+    /// `false` when constructed with `Settings::default()`.
+    ///
+    /// [`Element::ancestors`]: html_content/struct.Element.html#method.ancestors
+    /// [`TextChunk`]: html_content/struct.TextChunk.html#method.ancestors
+    /// [`Comment`]: html_content/struct.Comment.html#method.ancestors
+    /// [`memory_settings`]: struct.Settings.html#structfield.memory_settings
+    /// [`element_content_handlers`]: #structfield.element_content_handlers
+    /// [`retained_ancestor_attributes`]: #structfield.retained_ancestor_attributes
+    pub track_ancestors: bool,
+
+    /// Specifies the names of the attributes of the ancestor elements that are available via
+    /// the [`Element::ancestors`] method and its [`TextChunk`] and [`Comment`] counterparts.
+    ///
+    /// ### Note
+    ///
+    /// Retaining attributes requires the rewriter to examine the attributes of every element,
+    /// so it comes with a performance cost. Non-empty list of the attributes enables the
+    /// [`track_ancestors`] setting.
+    ///
+    /// ### Default
+    ///
+    /// No attributes are retained when constructed with `Settings::default()`.
+    ///
+    /// [`Element::ancestors`]: html_content/struct.Element.html#method.ancestors
+    /// [`TextChunk`]: html_content/struct.TextChunk.html#method.ancestors
+    /// [`Comment`]: html_content/struct.Comment.html#method.ancestors
+    /// [`track_ancestors`]: #structfield.track_ancestors
+    pub retained_ancestor_attributes: Vec<&'s str>,
 }
 
 impl Default for Settings<'_, '_> {
     #[inline]
     fn default() -> Self {
+        Self::new_for_handler_types()
+    }
+}
+
+impl<'h> Settings<'h, '_, SendHandlerTypes> {
+    /// Constructs the settings of a rewriter that is [`Send`], with the same defaults as
+    /// `Settings::default()`.
+    ///
+    /// The content handlers of such a rewriter and their errors are required to be `Send`.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{element, HtmlRewriter, Settings};
+    ///
+    /// let mut output = vec![];
+    ///
+    /// let mut rewriter = HtmlRewriter::try_new(
+    ///     Settings {
+    ///         element_content_handlers: vec![element!("a[href]", |el| {
+    ///             el.set_attribute("rel", "noopener")?;
+    ///
+    ///             Ok(())
+    ///         })],
+    ///         ..Settings::new_send()
+    ///     },
+    ///     move |c: &[u8]| output.extend_from_slice(c),
+    /// )
+    /// .unwrap();
+    ///
+    /// std::thread::spawn(move || {
+    ///     rewriter.write(b"<a href=/foo></a>").unwrap();
+    ///     rewriter.end().unwrap();
+    /// })
+    /// .join()
+    /// .unwrap();
+    /// ```
+    ///
+    /// [`Send`]: https://doc.rust-lang.org/std/marker/trait.Send.html
+    #[inline]
+    pub fn new_send() -> Self {
+        Self::new_for_handler_types()
+    }
+}
+
+impl<'h, H: ContentHandlerTypes<'h>> Settings<'h, '_, H> {
+    #[inline]
+    fn new_for_handler_types() -> Self {
         Settings {
             element_content_handlers: vec![],
             document_content_handlers: vec![],
             encoding: "utf-8",
             memory_settings: MemorySettings::default(),
             strict: true,
+            lookahead_mode: LookaheadMode::default(),
+            namespace_prefixes: vec![],
+            custom_pseudo_classes: CustomPseudoClasses::default(),
+            track_ancestors: false,
+            retained_ancestor_attributes: vec![],
         }
     }
 }
@@ -534,6 +1119,11 @@ impl<'h, 's> From<RewriteStrSettings<'h, 's>> for Settings<'h, 's> {
             element_content_handlers: settings.element_content_handlers,
             document_content_handlers: settings.document_content_handlers,
             strict: settings.strict,
+            lookahead_mode: settings.lookahead_mode,
+            namespace_prefixes: settings.namespace_prefixes,
+            custom_pseudo_classes: settings.custom_pseudo_classes,
+            track_ancestors: settings.track_ancestors,
+            retained_ancestor_attributes: settings.retained_ancestor_attributes,
             ..Settings::default()
         }
     }
@@ -630,6 +1220,106 @@ pub struct RewriteStrSettings<'h, 's> {
     ///
     /// `true` when constructed with `Settings::default()`.
     pub strict: bool,
+
+    /// Specifies whether the rewriter buffers the input to match selectors that
+    /// depend on the content following the start tag of an element.
+    ///
+    /// Refer to [`LookaheadMode`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// [`LookaheadMode::Disabled`] when constructed with `RewriteStrSettings::default()`.
+    ///
+    /// [`LookaheadMode`]: enum.LookaheadMode.html
+    /// [`LookaheadMode::Disabled`]: enum.LookaheadMode.html#variant.Disabled
+    pub lookahead_mode: LookaheadMode,
+
+    /// Specifies the namespace prefixes that can be used in the namespaced type and attribute
+    /// selectors (e.g. `svg|a` or `[xlink|href]`) as `(prefix, namespace URI)` pairs.
+    ///
+    /// Selectors that use a prefix which is not registered here never match.
+    ///
+    /// ### Example
+    /// ```
+    /// use lol_html::{element, rewrite_str, RewriteStrSettings};
+    ///
+    /// let html = rewrite_str(
+    ///     r#"<a href="/foo"></a><svg><a xlink:href="/bar"></a></svg>"#,
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![
+    ///             element!("svg|a[xlink|href]", |el| {
+    ///                 el.set_attribute("xlink:href", "/baz")?;
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         ],
+    ///         namespace_prefixes: vec![
+    ///             ("svg", "http://www.w3.org/2000/svg"),
+    ///             ("xlink", "http://www.w3.org/1999/xlink"),
+    ///         ],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, r#"<a href="/foo"></a><svg><a xlink:href="/baz"></a></svg>"#);
+    /// ```
+    ///
+    /// ### Default
+    ///
+    /// No prefixes are registered when constructed with `RewriteStrSettings::default()`.
+    pub namespace_prefixes: Vec<(&'s str, &'s str)>,
+
+    /// Specifies the user-defined pseudo-classes that can be used in the selectors.
+    ///
+    /// Refer to [`CustomPseudoClasses`] documentation for more information.
+    ///
+    /// ### Default
+    ///
+    /// No pseudo-classes are registered when constructed with `RewriteStrSettings::default()`.
+    ///
+    /// [`CustomPseudoClasses`]: struct.CustomPseudoClasses.html
+    pub custom_pseudo_classes: CustomPseudoClasses,
+
+    /// Enables tracking of the ancestor elements that are available via the
+    /// [`Element::ancestors`] method and its [`TextChunk`] and [`Comment`] counterparts.
+    ///
+    /// ### Note
+    ///
+    /// The tracking allocates a descriptor for every open element, so it comes with a
+    /// performance cost. The descriptors are accounted in the [`memory_settings`]. The ancestors
+    /// are tracked only if the rewriter has [`element_content_handlers`], and they are also
+    /// tracked if the [`retained_ancestor_attributes`] are specified.
+    ///
+    /// ### Default
+    ///
+    /// `false` when constructed with `RewriteStrSettings::default()`.
+    ///
+    /// [`Element::ancestors`]: html_content/struct.Element.html#method.ancestors
+    /// [`TextChunk`]: html_content/struct.TextChunk.html#method.ancestors
+    /// [`Comment`]: html_content/struct.Comment.html#method.ancestors
+    /// [`memory_settings`]: struct.Settings.html#structfield.memory_settings
+    /// [`element_content_handlers`]: #structfield.element_content_handlers
+    /// [`retained_ancestor_attributes`]: #structfield.retained_ancestor_attributes
+    pub track_ancestors: bool,
+
+    /// Specifies the names of the attributes of the ancestor elements that are available via
+    /// the [`Element::ancestors`] method and its [`TextChunk`] and [`Comment`] counterparts.
+    ///
+    /// ### Note
+    ///
+    /// Retaining attributes requires the rewriter to examine the attributes of every element,
+    /// so it comes with a performance cost. Non-empty list of the attributes enables the
+    /// [`track_ancestors`] setting.
+    ///
+    /// ### Default
+    ///
+    /// No attributes are retained when constructed with `RewriteStrSettings::default()`.
+    ///
+    /// [`Element::ancestors`]: html_content/struct.Element.html#method.ancestors
+    /// [`TextChunk`]: html_content/struct.TextChunk.html#method.ancestors
+    /// [`Comment`]: html_content/struct.Comment.html#method.ancestors
+    /// [`track_ancestors`]: #structfield.track_ancestors
+    pub retained_ancestor_attributes: Vec<&'s str>,
 }
 
 impl Default for RewriteStrSettings<'_, '_> {
@@ -639,6 +1329,11 @@ impl Default for RewriteStrSettings<'_, '_> {
             element_content_handlers: vec![],
             document_content_handlers: vec![],
             strict: true,
+            lookahead_mode: LookaheadMode::default(),
+            namespace_prefixes: vec![],
+            custom_pseudo_classes: CustomPseudoClasses::default(),
+            track_ancestors: false,
+            retained_ancestor_attributes: vec![],
         }
     }
 }
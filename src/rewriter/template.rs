@@ -0,0 +1,552 @@
+use super::handlers_dispatcher::{ContentHandlersDispatcher, SelectorHandlersLocator};
+use super::settings::*;
+use super::{try_encoding_from_str, SettingsError};
+use crate::base::Bytes;
+use crate::memory::MemoryLimiter;
+use crate::selectors_vm::{Ast, CompilationCtx, Compiler, CustomPseudoClasses, Program, Selector};
+use encoding_rs::Encoding;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+use thiserror::Error;
+
+pub type ElementContentHandlersFactory<'h, H = LocalHandlerTypes> =
+    Box<dyn Fn() -> ElementContentHandlers<'h, H> + Send + Sync + 'h>;
+pub type DocumentContentHandlersFactory<'h, H = LocalHandlerTypes> =
+    Box<dyn Fn() -> DocumentContentHandlers<'h, H> + Send + Sync + 'h>;
+
+/// An error that occurs if a factory of [`RewriterTemplate`] produces handlers of different kinds
+/// than on the template compilation.
+///
+/// [`RewriterTemplate`]: ../struct.RewriterTemplate.html
+#[derive(Error, Debug, PartialEq, Copy, Clone)]
+#[error("Element content handlers factory produced handlers of different kinds.")]
+pub struct HandlersKindMismatchError;
+
+/// The compiled selectors that are shared by the rewriters.
+pub(super) struct CompiledSelectors {
+    pub program: Arc<Program<SelectorHandlersLocator>>,
+    pub retained_ancestor_attributes: Arc<[Bytes<'static>]>,
+    pub track_ancestors: bool,
+}
+
+impl CompiledSelectors {
+    pub fn compile(
+        selectors: Vec<(&Selector, SelectorHandlersLocator)>,
+        encoding: &'static Encoding,
+        namespace_prefixes: &[(&str, &str)],
+        custom_pseudo_classes: CustomPseudoClasses,
+        track_ancestors: bool,
+        retained_ancestor_attributes: &[&str],
+        lookahead_mode: LookaheadMode,
+    ) -> Result<Self, SettingsError> {
+        let mut ast = Ast::default();
+
+        for (selector, locator) in selectors {
+            let unknown_pseudo_class = selector
+                .custom_pseudo_classes()
+                .into_iter()
+                .find(|name| custom_pseudo_classes.get(name).is_none());
+
+            if let Some(name) = unknown_pseudo_class {
+                return Err(SettingsError::UnknownPseudoClass(name.to_owned()));
+            }
+
+            ast.add_selector(selector, locator);
+        }
+
+        let namespace_prefixes = namespace_prefixes
+            .iter()
+            .map(|&(prefix, uri)| (prefix.to_owned(), uri.to_owned()))
+            .collect();
+
+        let ctx = CompilationCtx {
+            encoding,
+            namespace_prefixes: Arc::new(namespace_prefixes),
+            custom_pseudo_classes: Arc::new(custom_pseudo_classes),
+        };
+
+        let track_ancestors = track_ancestors || !retained_ancestor_attributes.is_empty();
+
+        let retained_ancestor_attributes = retained_ancestor_attributes
+            .iter()
+            .map(|name| Bytes::from_str(&name.to_ascii_lowercase(), encoding).into_owned())
+            .collect();
+
+        let program = Compiler::with_ctx(ctx).compile(ast);
+
+        if program.requires_lookahead && lookahead_mode == LookaheadMode::Disabled {
+            return Err(SettingsError::LookaheadRequired);
+        }
+
+        Ok(CompiledSelectors {
+            program: Arc::new(program),
+            track_ancestors,
+            retained_ancestor_attributes,
+        })
+    }
+}
+
+/// The content handlers factories of a template. They are shared with the rewriters instantiated
+/// from the template, so the rewriters can recreate their handlers on reset.
+pub(super) struct HandlersFactories<'h, H: ContentHandlerTypes<'h>> {
+    element_content_handlers: Vec<ElementContentHandlersFactory<'h, H>>,
+    document_content_handlers: Vec<DocumentContentHandlersFactory<'h, H>>,
+    /// The locators of the handlers produced by the element content handlers factories.
+    locators: Vec<SelectorHandlersLocator>,
+}
+
+impl<'h, H: ContentHandlerTypes<'h>> HandlersFactories<'h, H> {
+    /// Adds the handlers produced by the factories to the `dispatcher` that has no handlers.
+    pub fn add_handlers(
+        &self,
+        dispatcher: &mut ContentHandlersDispatcher<'h, H>,
+    ) -> Result<(), HandlersKindMismatchError> {
+        for (factory, &locator) in self.element_content_handlers.iter().zip(&self.locators) {
+            if dispatcher.add_selector_associated_handlers(factory()) != locator {
+                return Err(HandlersKindMismatchError);
+            }
+        }
+
+        for factory in &self.document_content_handlers {
+            dispatcher.add_document_content_handlers(factory());
+        }
+
+        Ok(())
+    }
+}
+
+/// The settings of a rewriter that are not related to the content handlers and the selectors.
+#[derive(Copy, Clone)]
+pub(super) struct RewriterOptions {
+    pub encoding: &'static Encoding,
+    pub memory_settings: MemorySettings,
+    pub strict: bool,
+    pub lookahead_mode: LookaheadMode,
+}
+
+/// Specifies settings for [`RewriterTemplate`].
+///
+/// Unlike [`Settings`], the content handlers are specified with factories that are invoked for
+/// each rewriter instantiated from the template. A factory should produce the same kinds of
+/// handlers on each invocation, e.g. an element content handlers factory that sets an element
+/// handler should always set it.
+///
+/// [`RewriterTemplate`]: struct.RewriterTemplate.html
+/// [`Settings`]: struct.Settings.html
+pub struct TemplateSettings<'h, 's, H: ContentHandlerTypes<'h> = LocalHandlerTypes> {
+    /// Specifies CSS selectors and factories of the rewriting handlers for elements and their
+    /// inner content.
+    ///
+    /// ### Example
+    /// ```
+    /// use lol_html::{ElementContentHandlers, TemplateSettings};
+    ///
+    /// let settings = TemplateSettings {
+    ///     element_content_handlers: vec![(
+    ///         &"div[foo]".parse().unwrap(),
+    ///         Box::new(|| {
+    ///             ElementContentHandlers::default().element(|el| {
+    ///                 // ...
+    ///
+    ///                 Ok(())
+    ///             })
+    ///         }),
+    ///     )],
+    ///     ..TemplateSettings::default()
+    /// };
+    /// ```
+    pub element_content_handlers: Vec<(&'s Selector, ElementContentHandlersFactory<'h, H>)>,
+
+    /// Specifies factories of the rewriting handlers for the content without associating it
+    /// to a particular CSS selector.
+    pub document_content_handlers: Vec<DocumentContentHandlersFactory<'h, H>>,
+
+    /// Refer to [`Settings::encoding`].
+    ///
+    /// [`Settings::encoding`]: struct.Settings.html#structfield.encoding
+    pub encoding: &'s str,
+
+    /// Refer to [`Settings::memory_settings`].
+    ///
+    /// [`Settings::memory_settings`]: struct.Settings.html#structfield.memory_settings
+    pub memory_settings: MemorySettings,
+
+    /// Refer to [`Settings::strict`].
+    ///
+    /// [`Settings::strict`]: struct.Settings.html#structfield.strict
+    pub strict: bool,
+
+    /// Refer to [`Settings::lookahead_mode`].
+    ///
+    /// [`Settings::lookahead_mode`]: struct.Settings.html#structfield.lookahead_mode
+    pub lookahead_mode: LookaheadMode,
+
+    /// Refer to [`Settings::namespace_prefixes`].
+    ///
+    /// [`Settings::namespace_prefixes`]: struct.Settings.html#structfield.namespace_prefixes
+    pub namespace_prefixes: Vec<(&'s str, &'s str)>,
+
+    /// Refer to [`Settings::custom_pseudo_classes`].
+    ///
+    /// [`Settings::custom_pseudo_classes`]: struct.Settings.html#structfield.custom_pseudo_classes
+    pub custom_pseudo_classes: CustomPseudoClasses,
+
+    /// Refer to [`Settings::track_ancestors`].
+    ///
+    /// [`Settings::track_ancestors`]: struct.Settings.html#structfield.track_ancestors
+    pub track_ancestors: bool,
+
+    /// Refer to [`Settings::retained_ancestor_attributes`].
+    ///
+    /// [`Settings::retained_ancestor_attributes`]: struct.Settings.html#structfield.retained_ancestor_attributes
+    pub retained_ancestor_attributes: Vec<&'s str>,
+}
+
+impl Default for TemplateSettings<'_, '_> {
+    #[inline]
+    fn default() -> Self {
+        Self::new_for_handler_types()
+    }
+}
+
+impl<'h> TemplateSettings<'h, '_, SendHandlerTypes> {
+    /// Constructs the settings of a template of rewriters that are [`Send`], with the same
+    /// defaults as `TemplateSettings::default()`.
+    ///
+    /// Refer to [`Settings::new_send`] for more information.
+    ///
+    /// [`Send`]: https://doc.rust-lang.org/std/marker/trait.Send.html
+    /// [`Settings::new_send`]: struct.Settings.html#method.new_send
+    #[inline]
+    pub fn new_send() -> Self {
+        Self::new_for_handler_types()
+    }
+}
+
+impl<'h, H: ContentHandlerTypes<'h>> TemplateSettings<'h, '_, H> {
+    #[inline]
+    fn new_for_handler_types() -> Self {
+        TemplateSettings {
+            element_content_handlers: vec![],
+            document_content_handlers: vec![],
+            encoding: "utf-8",
+            memory_settings: MemorySettings::default(),
+            strict: true,
+            lookahead_mode: LookaheadMode::default(),
+            namespace_prefixes: vec![],
+            custom_pseudo_classes: CustomPseudoClasses::default(),
+            track_ancestors: false,
+            retained_ancestor_attributes: vec![],
+        }
+    }
+}
+
+/// A precompiled template of [`HtmlRewriter`].
+///
+/// The selectors of the template are compiled once, so instantiation of a rewriter with
+/// [`HtmlRewriter::from_template`] only allocates the state of the rewriter and the content
+/// handlers produced by the factories. The template is `Send` and `Sync`, so it can be shared
+/// between threads with an [`Arc`].
+///
+/// # Example
+/// ```
+/// use lol_html::{ElementContentHandlers, HtmlRewriter, RewriterTemplate, TemplateSettings};
+/// use std::sync::Arc;
+///
+/// let template = Arc::new(
+///     RewriterTemplate::try_new(TemplateSettings {
+///         element_content_handlers: vec![(
+///             &"a[href]".parse().unwrap(),
+///             Box::new(|| {
+///                 ElementContentHandlers::default().element(|el| {
+///                     let href = el.get_attribute("href").unwrap().replace("http:", "https:");
+///
+///                     el.set_attribute("href", &href)?;
+///
+///                     Ok(())
+///                 })
+///             }),
+///         )],
+///         ..TemplateSettings::default()
+///     })
+///     .unwrap(),
+/// );
+///
+/// for _ in 0..3 {
+///     let mut output = vec![];
+///     let mut rewriter = HtmlRewriter::from_template(&template, |c: &[u8]| {
+///         output.extend_from_slice(c)
+///     })
+///     .unwrap();
+///
+///     rewriter.write(b"<a href=http://example.com></a>").unwrap();
+///     rewriter.end().unwrap();
+///
+///     assert_eq!(
+///         String::from_utf8(output).unwrap(),
+///         r#"<a href="https://example.com"></a>"#
+///     );
+/// }
+/// ```
+///
+/// [`HtmlRewriter`]: struct.HtmlRewriter.html
+/// [`HtmlRewriter::from_template`]: struct.HtmlRewriter.html#method.from_template
+/// [`Arc`]: https://doc.rust-lang.org/std/sync/struct.Arc.html
+pub struct RewriterTemplate<'h, H: ContentHandlerTypes<'h> = LocalHandlerTypes> {
+    pub(super) handlers_factories: Arc<HandlersFactories<'h, H>>,
+    pub(super) selectors: Option<CompiledSelectors>,
+    pub(super) options: RewriterOptions,
+}
+
+impl<'h, H: ContentHandlerTypes<'h>> RewriterTemplate<'h, H> {
+    /// Compiles a template with the provided `settings`.
+    ///
+    /// # Errors
+    ///  * [`SettingsError`] if the settings are invalid.
+    ///
+    /// [`SettingsError`]: errors/enum.SettingsError.html
+    pub fn try_new(settings: TemplateSettings<'h, '_, H>) -> Result<Self, SettingsError> {
+        let encoding = try_encoding_from_str(settings.encoding).map_err(SettingsError::Encoding)?;
+        let mut selectors = Vec::new();
+        let mut element_content_handlers = Vec::new();
+        let mut locators = Vec::new();
+
+        // NOTE: the locators depend only on the kinds of the handlers, so they are obtained
+        // from a dispatcher with the handlers produced by the factories.
+        let mut dispatcher = ContentHandlersDispatcher::new(
+            MemoryLimiter::new_shared(settings.memory_settings.max_allowed_memory_usage),
+            encoding,
+        );
+
+        for (selector, factory) in settings.element_content_handlers {
+            let locator = dispatcher.add_selector_associated_handlers(factory());
+
+            selectors.push((selector, locator));
+            element_content_handlers.push(factory);
+            locators.push(locator);
+        }
+
+        let selectors = if locators.is_empty() {
+            None
+        } else {
+            Some(CompiledSelectors::compile(
+                selectors,
+                encoding,
+                &settings.namespace_prefixes,
+                settings.custom_pseudo_classes,
+                settings.track_ancestors,
+                &settings.retained_ancestor_attributes,
+                settings.lookahead_mode,
+            )?)
+        };
+
+        Ok(RewriterTemplate {
+            handlers_factories: Arc::new(HandlersFactories {
+                element_content_handlers,
+                document_content_handlers: settings.document_content_handlers,
+                locators,
+            }),
+            selectors,
+            options: RewriterOptions {
+                encoding,
+                memory_settings: settings.memory_settings,
+                strict: settings.strict,
+                lookahead_mode: settings.lookahead_mode,
+            },
+        })
+    }
+}
+
+impl<'h, H: ContentHandlerTypes<'h>> Debug for RewriterTemplate<'h, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RewriterTemplate")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{EncodingError, HandlersKindMismatchError, SettingsError};
+    use crate::html_content::ContentType;
+    use crate::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    fn rewrite(template: &RewriterTemplate, html: &str) -> String {
+        let mut output = vec![];
+        let mut rewriter = HtmlRewriter::from_template(template, |c: &[u8]| {
+            output.extend_from_slice(c);
+        })
+        .unwrap();
+
+        for chunk in html.as_bytes().chunks(3) {
+            rewriter.write(chunk).unwrap();
+        }
+
+        rewriter.end().unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn instantiation() {
+        let instance_count = AtomicUsize::new(0);
+
+        let template = RewriterTemplate::try_new(TemplateSettings {
+            element_content_handlers: vec![
+                (
+                    &"div:last-child".parse().unwrap(),
+                    Box::new(|| {
+                        let mut count = 0;
+
+                        ElementContentHandlers::default().element(move |el| {
+                            count += 1;
+                            el.set_attribute("count", &count.to_string())?;
+
+                            Ok(())
+                        })
+                    }),
+                ),
+                (
+                    &"p".parse().unwrap(),
+                    Box::new(|| {
+                        ElementContentHandlers::default().text(|t| {
+                            t.replace(&t.as_str().to_uppercase(), ContentType::Text);
+
+                            Ok(())
+                        })
+                    }),
+                ),
+            ],
+            document_content_handlers: vec![Box::new(|| {
+                let instance = instance_count.fetch_add(1, Ordering::Relaxed);
+
+                DocumentContentHandlers::default().end(move |end| {
+                    end.append(&format!("<!-- {} -->", instance), ContentType::Html);
+
+                    Ok(())
+                })
+            })],
+            lookahead_mode: LookaheadMode::FailOnMemoryLimit,
+            ..TemplateSettings::default()
+        })
+        .unwrap();
+
+        for i in 0..3 {
+            assert_eq!(
+                rewrite(&template, "<div><div></div><div><p>foo</p></div></div>"),
+                format!(
+                    r#"<div count="1"><div></div><div count="2"><p>FOO</p></div></div><!-- {} -->"#,
+                    i
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn no_selectors() {
+        let template = RewriterTemplate::try_new(TemplateSettings {
+            document_content_handlers: vec![Box::new(|| {
+                DocumentContentHandlers::default().comments(|c| {
+                    c.remove();
+
+                    Ok(())
+                })
+            })],
+            ..TemplateSettings::default()
+        })
+        .unwrap();
+
+        assert_eq!(rewrite(&template, "<div><!-- foo --></div>"), "<div></div>");
+    }
+
+    #[test]
+    fn encoding_error() {
+        let err = RewriterTemplate::try_new(TemplateSettings {
+            encoding: "utf-16",
+            ..TemplateSettings::default()
+        })
+        .unwrap_err();
+
+        assert_eq!(
+            err,
+            SettingsError::Encoding(EncodingError::NonAsciiCompatibleEncoding)
+        );
+    }
+
+    #[test]
+    fn lookahead_required() {
+        let err = RewriterTemplate::try_new(TemplateSettings {
+            element_content_handlers: vec![(
+                &"figure:has(> img)".parse().unwrap(),
+                Box::new(ElementContentHandlers::default),
+            )],
+            ..TemplateSettings::default()
+        })
+        .unwrap_err();
+
+        assert_eq!(err, SettingsError::LookaheadRequired);
+    }
+
+    #[test]
+    fn shared_template() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        let template = Arc::new(
+            RewriterTemplate::try_new(TemplateSettings {
+                element_content_handlers: vec![(
+                    &"a".parse().unwrap(),
+                    Box::new(|| {
+                        ElementContentHandlers::default().element(|el| {
+                            el.set_tag_name("b")?;
+
+                            Ok(())
+                        })
+                    }),
+                )],
+                ..TemplateSettings::default()
+            })
+            .unwrap(),
+        );
+
+        assert_send_sync(&*template);
+
+        let threads = (0..3)
+            .map(|_| {
+                let template = Arc::clone(&template);
+
+                thread::spawn(move || rewrite(&template, "<a>foo</a>"))
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), "<b>foo</b>");
+        }
+    }
+
+    #[test]
+    fn factory_with_different_handler_kinds() {
+        let call_count = AtomicUsize::new(0);
+
+        let template = RewriterTemplate::try_new(TemplateSettings {
+            element_content_handlers: vec![(
+                &"a".parse().unwrap(),
+                Box::new(|| {
+                    let handlers = ElementContentHandlers::default();
+
+                    if call_count.fetch_add(1, Ordering::Relaxed) == 0 {
+                        handlers.element(|_| Ok(()))
+                    } else {
+                        handlers.comments(|_| Ok(()))
+                    }
+                }),
+            )],
+            ..TemplateSettings::default()
+        })
+        .unwrap();
+
+        let err = HtmlRewriter::from_template(&template, |_: &[u8]| {}).unwrap_err();
+
+        assert_eq!(err, HandlersKindMismatchError);
+    }
+}
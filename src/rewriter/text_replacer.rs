@@ -0,0 +1,489 @@
+use super::HandlerResult;
+use crate::html::TextType;
+use crate::rewritable_units::{ContentType, TextChunk};
+use std::ops::Range;
+
+type Matcher = Box<dyn FnMut(&str) -> Option<(Range<usize>, String)>>;
+
+enum Pattern {
+    Literal {
+        pattern: String,
+        replacement: String,
+    },
+    Custom {
+        matcher: Matcher,
+    },
+}
+
+struct Rule {
+    pattern: Pattern,
+    content_type: ContentType,
+}
+
+struct Match {
+    range: Range<usize>,
+    replacement: String,
+    rule_idx: usize,
+}
+
+impl Rule {
+    fn find(&mut self, text: &str) -> Option<(Range<usize>, String)> {
+        match self.pattern {
+            Pattern::Literal {
+                ref pattern,
+                ref replacement,
+            } => text
+                .find(pattern.as_str())
+                .map(|start| (start..start + pattern.len(), replacement.to_owned())),
+            Pattern::Custom { ref mut matcher } => matcher(text),
+        }
+    }
+}
+
+/// Appends the `text` to the HTML `output`, escaping it in the same way as the content of
+/// the given type is escaped on insertion into text of the `text_type`.
+fn push_escaped(output: &mut String, text: &str, content_type: &ContentType, text_type: TextType) {
+    let escape = match content_type {
+        ContentType::Html => false,
+        ContentType::Text => true,
+        ContentType::DecodedText => text_type.allows_html_entities(),
+    };
+
+    if escape {
+        for ch in text.chars() {
+            match ch {
+                '<' => output.push_str("&lt;"),
+                '>' => output.push_str("&gt;"),
+                '&' => output.push_str("&amp;"),
+                _ => output.push(ch),
+            }
+        }
+    } else {
+        output.push_str(text);
+    }
+}
+
+/// A text handler that replaces patterns in text, including the matches that span
+/// multiple text chunks.
+///
+/// Patterns are matched against the text with the character references decoded (see
+/// [`TextChunk::decoded_text`]) and never span multiple text nodes. To find the matches that
+/// continue in the next chunk, the replacer withholds the end of a chunk that is shorter than the
+/// longest pattern and emits it with the next chunk. The rest of the text is emitted as soon as
+/// the chunk is handled. Chunks without matches and without withheld text are left intact.
+///
+/// By default, patterns are replaced only in regular text and in text of `<title>` and
+/// `<textarea>` elements, so e.g. scripts and stylesheets are never modified unless requested
+/// with [`text_types`].
+///
+/// # Example
+/// ```
+/// use lol_html::{HtmlRewriter, Settings, TextReplacer, text};
+/// use lol_html::html_content::ContentType;
+///
+/// let mut output = vec![];
+///
+/// {
+///     let replacer = TextReplacer::default()
+///         .replace("Acme", "ACME Corp.", ContentType::Text)
+///         .replace("555-0100", r#"<a href="tel:555-0100">555-0100</a>"#, ContentType::Html);
+///
+///     let mut rewriter = HtmlRewriter::try_new(
+///         Settings {
+///             element_content_handlers: vec![text!("body", replacer.into_handler())],
+///             ..Settings::default()
+///         },
+///         |c: &[u8]| output.extend_from_slice(c)
+///     ).unwrap();
+///
+///     rewriter.write(b"<body>Call Ac").unwrap();
+///     rewriter.write(b"me at 555-").unwrap();
+///     rewriter.write(b"0100<script>Acme()</script></body>").unwrap();
+///     rewriter.end().unwrap();
+/// }
+///
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     r#"<body>Call ACME Corp. at <a href="tel:555-0100">555-0100</a><script>Acme()</script></body>"#
+/// );
+/// ```
+///
+/// [`TextChunk::decoded_text`]: html_content/struct.TextChunk.html#method.decoded_text
+/// [`text_types`]: #method.text_types
+pub struct TextReplacer {
+    rules: Vec<Rule>,
+    max_match_len: usize,
+    text_types: Vec<TextType>,
+    pending_text: String,
+}
+
+impl Default for TextReplacer {
+    fn default() -> Self {
+        TextReplacer {
+            rules: Vec::new(),
+            max_match_len: 0,
+            text_types: vec![TextType::Data, TextType::RCData],
+            pending_text: String::new(),
+        }
+    }
+}
+
+impl TextReplacer {
+    /// Adds a rule that replaces the occurrences of the `pattern` with the `replacement` of
+    /// the given `content_type`.
+    ///
+    /// If multiple patterns match at the same position, the longest of them is replaced.
+    ///
+    /// # Panics
+    ///
+    /// If the `pattern` is empty.
+    #[inline]
+    pub fn replace(mut self, pattern: &str, replacement: &str, content_type: ContentType) -> Self {
+        assert!(!pattern.is_empty(), "Pattern can't be empty.");
+
+        self.max_match_len = self.max_match_len.max(pattern.len());
+
+        self.rules.push(Rule {
+            pattern: Pattern::Literal {
+                pattern: pattern.to_owned(),
+                replacement: replacement.to_owned(),
+            },
+            content_type,
+        });
+
+        self
+    }
+
+    /// Adds a rule that replaces the matches found by the `matcher`, e.g. a regular expression.
+    ///
+    /// The `matcher` receives the text that follows the previous match and returns the byte range
+    /// of the leftmost match in the text along with its replacement of the given `content_type`.
+    /// The matches must not be longer than `max_match_len` bytes, otherwise they might be missed
+    /// if they span multiple chunks.
+    ///
+    /// # Example
+    /// ```
+    /// use lol_html::{rewrite_str, text, RewriteStrSettings, TextReplacer};
+    /// use lol_html::html_content::ContentType;
+    ///
+    /// let replacer = TextReplacer::default().replace_matches(
+    ///     10,
+    ///     |text| {
+    ///         let start = text.find(|c: char| c.is_ascii_digit())?;
+    ///         let len = text[start..]
+    ///             .find(|c: char| !c.is_ascii_digit())
+    ///             .unwrap_or(text.len() - start);
+    ///
+    ///         Some((start..start + len, "#".repeat(len)))
+    ///     },
+    ///     ContentType::Text,
+    /// );
+    ///
+    /// let html = rewrite_str(
+    ///     "<p>PIN: 1234</p>",
+    ///     RewriteStrSettings {
+    ///         element_content_handlers: vec![text!("p", replacer.into_handler())],
+    ///         ..RewriteStrSettings::default()
+    ///     }
+    /// ).unwrap();
+    ///
+    /// assert_eq!(html, "<p>PIN: ####</p>");
+    /// ```
+    #[inline]
+    pub fn replace_matches(
+        mut self,
+        max_match_len: usize,
+        matcher: impl FnMut(&str) -> Option<(Range<usize>, String)> + 'static,
+        content_type: ContentType,
+    ) -> Self {
+        self.max_match_len = self.max_match_len.max(max_match_len);
+
+        self.rules.push(Rule {
+            pattern: Pattern::Custom {
+                matcher: Box::new(matcher),
+            },
+            content_type,
+        });
+
+        self
+    }
+
+    /// Sets the types of the text in which the patterns are replaced.
+    ///
+    /// The default types are [`TextType::Data`] and [`TextType::RCData`].
+    ///
+    /// [`TextType::Data`]: html_content/enum.TextType.html#variant.Data
+    /// [`TextType::RCData`]: html_content/enum.TextType.html#variant.RCData
+    #[inline]
+    pub fn text_types(mut self, text_types: &[TextType]) -> Self {
+        self.text_types = text_types.to_vec();
+
+        self
+    }
+
+    /// Converts the replacer into a text handler.
+    #[inline]
+    pub fn into_handler(mut self) -> impl FnMut(&mut TextChunk) -> HandlerResult {
+        move |chunk| {
+            self.handle_chunk(chunk);
+
+            Ok(())
+        }
+    }
+
+    fn find_leftmost_match(&mut self, text: &str) -> Option<Match> {
+        let mut leftmost: Option<Match> = None;
+
+        for (rule_idx, rule) in self.rules.iter_mut().enumerate() {
+            if let Some((range, replacement)) = rule.find(text) {
+                let is_leftmost = match leftmost {
+                    Some(ref m) => {
+                        range.start < m.range.start
+                            || (range.start == m.range.start && range.end > m.range.end)
+                    }
+                    None => true,
+                };
+
+                if is_leftmost {
+                    leftmost = Some(Match {
+                        range,
+                        replacement,
+                        rule_idx,
+                    });
+                }
+            }
+        }
+
+        leftmost
+    }
+
+    fn handle_chunk(&mut self, chunk: &mut TextChunk) {
+        let text_type = chunk.text_type();
+
+        if !self.text_types.contains(&text_type) {
+            return;
+        }
+
+        let last = chunk.last_in_text_node();
+        let had_pending_text = !self.pending_text.is_empty();
+        let mut text = std::mem::take(&mut self.pending_text);
+
+        text.push_str(&chunk.decoded_text());
+
+        let mut output = String::new();
+        let mut pos = 0;
+        let mut has_matches = false;
+        let mut deferred_match_start = None;
+
+        while pos < text.len() {
+            let m = match self.find_leftmost_match(&text[pos..]) {
+                Some(m) => m,
+                None => break,
+            };
+
+            let start = pos + m.range.start;
+            let end = pos + m.range.end;
+
+            // NOTE: a longer match might start at the same position, if the text continues
+            // in the next chunk.
+            if !last && start + self.max_match_len > text.len() {
+                deferred_match_start = Some(start);
+                break;
+            }
+
+            push_escaped(
+                &mut output,
+                &text[pos..start],
+                &ContentType::DecodedText,
+                text_type,
+            );
+
+            push_escaped(
+                &mut output,
+                &m.replacement,
+                &self.rules[m.rule_idx].content_type,
+                text_type,
+            );
+
+            has_matches = true;
+
+            // NOTE: skip a character after an empty match, so the matching doesn't get stuck.
+            pos = if end > start {
+                end
+            } else {
+                match text[end..].chars().next() {
+                    Some(ch) => {
+                        let next = end + ch.len_utf8();
+
+                        push_escaped(
+                            &mut output,
+                            &text[end..next],
+                            &ContentType::DecodedText,
+                            text_type,
+                        );
+
+                        next
+                    }
+                    None => end,
+                }
+            };
+        }
+
+        let mut withheld_start = if last {
+            text.len()
+        } else {
+            text.len()
+                .saturating_sub(self.max_match_len.saturating_sub(1))
+                .max(pos)
+        };
+
+        if let Some(start) = deferred_match_start {
+            withheld_start = withheld_start.min(start);
+        }
+
+        while !text.is_char_boundary(withheld_start) {
+            withheld_start -= 1;
+        }
+
+        if !has_matches && !had_pending_text && withheld_start == text.len() {
+            return;
+        }
+
+        push_escaped(
+            &mut output,
+            &text[pos..withheld_start],
+            &ContentType::DecodedText,
+            text_type,
+        );
+
+        self.pending_text.push_str(&text[withheld_start..]);
+
+        chunk.replace(&output, ContentType::Html);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::Output;
+    use crate::*;
+    use encoding_rs::UTF_8;
+
+    fn replace_in_chunks(html: &str, replacer: impl Fn() -> TextReplacer, expected: &str) {
+        for chunk_size in 1..=html.len() {
+            let mut output = Output::new(UTF_8);
+
+            {
+                let mut rewriter = HtmlRewriter::try_new(
+                    Settings {
+                        element_content_handlers: vec![text!("*", replacer().into_handler())],
+                        ..Settings::default()
+                    },
+                    |c: &[u8]| output.push(c),
+                )
+                .unwrap();
+
+                for chunk in html.as_bytes().chunks(chunk_size) {
+                    rewriter.write(chunk).unwrap();
+                }
+
+                rewriter.end().unwrap();
+            }
+
+            let output: String = output.into();
+
+            assert_eq!(output, expected, "Chunk size: {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn literal_patterns() {
+        replace_in_chunks(
+            "<p>foo bar foobar baz</p><p>fo</p><p>o</p>",
+            || {
+                TextReplacer::default()
+                    .replace("foo", "<1>", ContentType::Text)
+                    .replace("foobar", "<2>", ContentType::Html)
+                    .replace("baz", "3", ContentType::Text)
+            },
+            "<p>&lt;1&gt; bar <2> 3</p><p>fo</p><p>o</p>",
+        );
+    }
+
+    #[test]
+    fn decoded_text() {
+        replace_in_chunks(
+            "<p>AT&amp;T &lt;&#x41;T&amp;T&gt; &notit;</p>",
+            || {
+                TextReplacer::default()
+                    .replace("AT&T", "AT&T Inc.", ContentType::Text)
+                    .replace("¬it;", "-", ContentType::Text)
+            },
+            "<p>AT&amp;T Inc. &lt;AT&amp;T Inc.&gt; -</p>",
+        );
+    }
+
+    #[test]
+    fn multibyte_characters() {
+        replace_in_chunks(
+            "<p>Привет, мир! Привет!</p>",
+            || TextReplacer::default().replace("мир", "world", ContentType::Text),
+            "<p>Привет, world! Привет!</p>",
+        );
+    }
+
+    #[test]
+    fn text_types() {
+        let html = "<p>foo</p><script>foo</script><style>foo</style><title>foo</title>";
+
+        replace_in_chunks(
+            html,
+            || TextReplacer::default().replace("foo", "<b>", ContentType::DecodedText),
+            "<p>&lt;b&gt;</p><script>foo</script><style>foo</style><title>&lt;b&gt;</title>",
+        );
+
+        replace_in_chunks(
+            html,
+            || {
+                TextReplacer::default()
+                    .replace("foo", "<b>", ContentType::DecodedText)
+                    .text_types(&[TextType::ScriptData])
+            },
+            "<p>foo</p><script><b></script><style>foo</style><title>foo</title>",
+        );
+    }
+
+    #[test]
+    fn custom_matchers() {
+        replace_in_chunks(
+            "<p>1 22 333 4444</p>",
+            || {
+                TextReplacer::default().replace_matches(
+                    3,
+                    |text| {
+                        let start = text.find(|c: char| c.is_ascii_digit())?;
+                        let len = text[start..]
+                            .find(|c: char| !c.is_ascii_digit())
+                            .unwrap_or(text.len() - start);
+
+                        Some((start..start + len.min(3), len.min(3).to_string()))
+                    },
+                    ContentType::Text,
+                )
+            },
+            "<p>1 2 3 31</p>",
+        );
+
+        replace_in_chunks(
+            "<p>ab</p>",
+            || {
+                TextReplacer::default().replace_matches(
+                    0,
+                    |_| Some((0..0, "-".into())),
+                    ContentType::Text,
+                )
+            },
+            "<p>-a-b</p>",
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use crate::html::{LocalName, Namespace};
+use encoding_rs::Encoding;
+use std::mem::size_of;
+use std::sync::Arc;
+
+/// An ancestor element of an [`Element`], [`TextChunk`] or [`Comment`].
+///
+/// Only the tag name, the namespace and the attributes listed in the
+/// [`retained_ancestor_attributes`] setting are available for the ancestors.
+///
+/// [`Element`]: struct.Element.html
+/// [`TextChunk`]: struct.TextChunk.html
+/// [`Comment`]: struct.Comment.html
+/// [`retained_ancestor_attributes`]: ../struct.Settings.html#structfield.retained_ancestor_attributes
+pub struct Ancestor {
+    local_name: LocalName<'static>,
+    ns: Namespace,
+    encoding: &'static Encoding,
+    attributes: Vec<(String, String)>,
+    position: TreePosition,
+}
+
+impl Ancestor {
+    #[inline]
+    pub(crate) fn new(
+        local_name: LocalName<'static>,
+        ns: Namespace,
+        encoding: &'static Encoding,
+        attributes: Vec<(String, String)>,
+        position: TreePosition,
+    ) -> Self {
+        Ancestor {
+            local_name,
+            ns,
+            encoding,
+            attributes,
+            position,
+        }
+    }
+
+    /// Returns the number of bytes allocated for the ancestor.
+    pub(crate) fn memory_usage(&self) -> usize {
+        let name_len = match &self.local_name {
+            LocalName::Hash(_) => 0,
+            LocalName::Bytes(bytes) => bytes.len(),
+        };
+
+        let attributes_len: usize = self
+            .attributes
+            .iter()
+            .map(|(name, value)| size_of::<(String, String)>() + name.len() + value.len())
+            .sum();
+
+        size_of::<Arc<Ancestor>>() + size_of::<Ancestor>() + name_len + attributes_len
+    }
+
+    /// Returns the tag name of the ancestor.
+    #[inline]
+    pub fn tag_name(&self) -> String {
+        self.local_name.as_lowercase_string(self.encoding)
+    }
+
+    /// Returns the [namespace URI] of the ancestor.
+    ///
+    /// [namespace URI]: https://developer.mozilla.org/en-US/docs/Web/API/Element/namespaceURI
+    #[inline]
+    pub fn namespace_uri(&self) -> &'static str {
+        self.ns.uri()
+    }
+
+    /// Returns the value of an attribute with the `name`.
+    ///
+    /// Returns `None` if the ancestor doesn't have an attribute with the `name` or if the
+    /// attribute is not listed in the [`retained_ancestor_attributes`] setting.
+    ///
+    /// [`retained_ancestor_attributes`]: ../struct.Settings.html#structfield.retained_ancestor_attributes
+    #[inline]
+    pub fn get_attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the number of the ancestor's own ancestors.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.position.depth()
+    }
+
+    /// Returns the 1-based index of the ancestor among the elements that share its parent.
+    #[inline]
+    pub fn child_index(&self) -> usize {
+        self.position.child_index()
+    }
+}
+
+/// An iterator over the ancestors of an [`Element`], [`TextChunk`] or [`Comment`], starting
+/// from the parent.
+///
+/// [`Element`]: struct.Element.html
+/// [`TextChunk`]: struct.TextChunk.html
+/// [`Comment`]: struct.Comment.html
+pub struct Ancestors<'a> {
+    next: Option<&'a Ancestor>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = &'a Ancestor;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a Ancestor> {
+        let ancestor = self.next?;
+
+        self.next = ancestor.position.parent.as_deref();
+
+        Some(ancestor)
+    }
+}
+
+/// The position of an element or a text node in the tree of the open elements.
+#[derive(Clone, Default)]
+pub struct TreePosition {
+    parent: Option<Arc<Ancestor>>,
+    depth: usize,
+    child_index: usize,
+}
+
+impl TreePosition {
+    #[inline]
+    pub fn new(parent: Option<Arc<Ancestor>>, depth: usize, child_index: usize) -> Self {
+        TreePosition {
+            parent,
+            depth,
+            child_index,
+        }
+    }
+
+    #[inline]
+    pub fn ancestors(&self) -> Ancestors<'_> {
+        Ancestors {
+            next: self.parent.as_deref(),
+        }
+    }
+
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    #[inline]
+    pub fn child_index(&self) -> usize {
+        self.child_index
+    }
+}
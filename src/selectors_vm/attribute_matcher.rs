@@ -1,8 +1,8 @@
 use super::compiler::AttrExprOperands;
 use crate::base::Bytes;
-use crate::html::Namespace;
-use crate::parser::{AttributeOutline, SharedAttributeBuffer};
-use encoding_rs::UTF_8;
+use crate::html::{decode_char_refs, Namespace};
+use crate::parser::AttributeOutline;
+use encoding_rs::{Encoding, UTF_8};
 use lazy_static::lazy_static;
 use lazycell::LazyCell;
 use memchr::{memchr, memchr2};
@@ -11,6 +11,8 @@ use selectors::attr::CaseSensitivity;
 lazy_static! {
     static ref ID_ATTR: Bytes<'static> = Bytes::from_str("id", UTF_8);
     static ref CLASS_ATTR: Bytes<'static> = Bytes::from_str("class", UTF_8);
+    static ref LANG_ATTR: Bytes<'static> = Bytes::from_str("lang", UTF_8);
+    static ref DIR_ATTR: Bytes<'static> = Bytes::from_str("dir", UTF_8);
 }
 
 #[inline]
@@ -22,28 +24,49 @@ type MemoizedAttrValue<'i> = LazyCell<Option<Bytes<'i>>>;
 
 pub struct AttributeMatcher<'i> {
     input: &'i Bytes<'i>,
-    attributes: SharedAttributeBuffer,
+    tag_name: Bytes<'i>,
+    attributes: &'i [AttributeOutline],
     id: MemoizedAttrValue<'i>,
     class: MemoizedAttrValue<'i>,
+    ns: Namespace,
     is_html_element: bool,
+    encoding: &'static Encoding,
 }
 
 impl<'i> AttributeMatcher<'i> {
     #[inline]
-    pub fn new(input: &'i Bytes<'i>, attributes: SharedAttributeBuffer, ns: Namespace) -> Self {
+    pub fn new(
+        input: &'i Bytes<'i>,
+        tag_name: Bytes<'i>,
+        attributes: &'i [AttributeOutline],
+        ns: Namespace,
+        encoding: &'static Encoding,
+    ) -> Self {
         AttributeMatcher {
             input,
+            tag_name,
             attributes,
             id: LazyCell::default(),
             class: LazyCell::default(),
+            ns,
             is_html_element: ns == Namespace::Html,
+            encoding,
         }
     }
 
+    #[inline]
+    pub fn tag_name(&self) -> &Bytes<'i> {
+        &self.tag_name
+    }
+
+    #[inline]
+    pub fn ns(&self) -> Namespace {
+        self.ns
+    }
+
     #[inline]
     fn find(&self, lowercased_name: &Bytes) -> Option<AttributeOutline> {
         self.attributes
-            .borrow()
             .iter()
             .find(|a| {
                 if lowercased_name.len() != a.name.end - a.name.start {
@@ -63,17 +86,70 @@ impl<'i> AttributeMatcher<'i> {
             .cloned()
     }
 
+    /// Returns the value of the attribute as it appears in the markup.
     #[inline]
-    fn get_value(&self, lowercased_name: &Bytes) -> Option<Bytes<'i>> {
+    pub fn get_raw_value(&self, lowercased_name: &Bytes) -> Option<Bytes<'i>> {
         self.find(lowercased_name)
             .map(|a| self.input.slice(a.value))
     }
 
+    /// Returns the value of the attribute with the character references decoded, so
+    /// the value is compared in the same way as it's seen by a browser.
+    #[inline]
+    pub fn get_value(&self, lowercased_name: &Bytes) -> Option<Bytes<'i>> {
+        self.get_raw_value(lowercased_name).map(|value| {
+            // NOTE: `&` is encoded as a single ASCII byte in all the supported encodings,
+            // so the values without it can be compared without decoding.
+            if memchr(b'&', &value).is_none() {
+                return value;
+            }
+
+            let value = value.as_string(self.encoding);
+            let decoded = decode_char_refs(&value, true);
+
+            match Bytes::from_str_without_replacements(&decoded, self.encoding) {
+                Ok(decoded) => decoded.into_owned(),
+                Err(_) => {
+                    // NOTE: the encoder replaces the characters that can't be represented
+                    // in the encoding with numeric character references that might match
+                    // the operands. Instead, such characters are replaced with NUL, that
+                    // never occurs in the operands, as the CSS parser replaces it.
+                    let decoded = decoded
+                        .chars()
+                        .map(|c| {
+                            let mut buf = [0; 4];
+                            let (_, _, has_replacements) =
+                                self.encoding.encode(c.encode_utf8(&mut buf));
+
+                            if has_replacements {
+                                '\0'
+                            } else {
+                                c
+                            }
+                        })
+                        .collect::<String>();
+
+                    Bytes::from_str(&decoded, self.encoding).into_owned()
+                }
+            }
+        })
+    }
+
     #[inline]
     pub fn has_attribute(&self, lowercased_name: &Bytes) -> bool {
         self.find(lowercased_name).is_some()
     }
 
+    #[inline]
+    pub fn lang(&self) -> Option<Bytes<'i>> {
+        self.get_value(&LANG_ATTR)
+    }
+
+    #[inline]
+    pub fn dir(&self) -> Option<Bytes<'i>> {
+        self.get_value(&DIR_ATTR)
+    }
+
     #[inline]
     pub fn has_id(&self, id: &Bytes) -> bool {
         match self.id.borrow_with(|| self.get_value(&ID_ATTR)) {
@@ -1,25 +1,94 @@
 use super::attribute_matcher::AttributeMatcher;
+use super::custom_pseudo_class::{CustomPseudoClasses, PseudoClassElement};
+use super::lookahead::{scope_local_name, HasMatcher, SiblingCount};
 use super::program::{
     AddressRange, ExecutionBranch, Program, Instruction
 };
 use super::{Ast, AstNode, Expr, AttributeComparisonExpr, OnTagNameExpr, OnAttributesExpr, Predicate, SelectorState};
+use super::{AttributeNamespace, InheritedAttributes, LookaheadFacts, LookaheadRequirements, NthChild, OnLookaheadExpr};
 use crate::base::{Bytes, HasReplacementsError};
-use crate::html::LocalName;
+use crate::html::{LocalName, Namespace};
 use encoding_rs::Encoding;
+use hashbrown::HashMap;
 use selectors::attr::{ParsedCaseSensitivity, AttrSelectorOperator};
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::iter;
+use std::sync::Arc;
 
 /// An expression using only the tag name of an element.
-pub type CompiledLocalNameExpr = Box<dyn Fn(&SelectorState, &LocalName) -> bool>;
+pub type CompiledLocalNameExpr = Box<dyn Fn(&SelectorState, &LocalName) -> bool + Send + Sync>;
 /// An expression using the attributes of an element.
-pub type CompiledAttributeExpr = Box<dyn Fn(&SelectorState, &AttributeMatcher) -> bool>;
+pub type CompiledAttributeExpr =
+    Box<dyn Fn(&SelectorState, &AttributeMatcher) -> bool + Send + Sync>;
+/// An expression using the facts about the input that follows the start tag of an element.
+pub type CompiledLookaheadExpr = Box<dyn Fn(&SelectorState, &LookaheadFacts) -> bool + Send + Sync>;
+
+/// Namespace URIs keyed by the prefixes that can be used for them in selectors.
+pub type NamespacePrefixes = Arc<HashMap<String, String>>;
+
+/// Attributes of foreign elements that the HTML parser places in a namespace, as
+/// (qualified name in the markup, local name, namespace URI).
+///
+/// See: https://html.spec.whatwg.org/multipage/parsing.html#adjust-foreign-attributes
+const ADJUSTED_FOREIGN_ATTRIBUTES: [(&str, &str, &str); 11] = [
+    ("xlink:actuate", "actuate", XLINK_NAMESPACE),
+    ("xlink:arcrole", "arcrole", XLINK_NAMESPACE),
+    ("xlink:href", "href", XLINK_NAMESPACE),
+    ("xlink:role", "role", XLINK_NAMESPACE),
+    ("xlink:show", "show", XLINK_NAMESPACE),
+    ("xlink:title", "title", XLINK_NAMESPACE),
+    ("xlink:type", "type", XLINK_NAMESPACE),
+    ("xml:lang", "lang", XML_NAMESPACE),
+    ("xml:space", "space", XML_NAMESPACE),
+    ("xmlns", "xmlns", XMLNS_NAMESPACE),
+    ("xmlns:xlink", "xlink", XMLNS_NAMESPACE),
+];
+
+const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+/// The context shared by all the expressions of the compiled program.
+#[derive(Clone)]
+pub struct CompilationCtx {
+    pub encoding: &'static Encoding,
+    pub namespace_prefixes: NamespacePrefixes,
+    pub custom_pseudo_classes: Arc<CustomPseudoClasses>,
+}
+
+impl CompilationCtx {
+    #[cfg(test)]
+    pub fn new(encoding: &'static Encoding) -> Self {
+        CompilationCtx {
+            encoding,
+            namespace_prefixes: NamespacePrefixes::default(),
+            custom_pseudo_classes: Arc::default(),
+        }
+    }
+
+    #[inline]
+    fn namespace_uri(&self, prefix: &str) -> Option<&str> {
+        self.namespace_prefixes.get(prefix).map(String::as_str)
+    }
+}
+
+/// Features of the VM that are required by the compiled expressions and are
+/// disabled otherwise, as they come with a runtime cost.
+#[derive(Default)]
+struct RequiredFeatures {
+    pub nth_of_type: bool,
+    pub inherited_attributes: bool,
+    /// The number of the `:has()` matchers compiled so far.
+    pub has_matcher_count: usize,
+}
 
 #[derive(Default)]
 struct ExprSet {
     pub local_name_exprs: Vec<CompiledLocalNameExpr>,
     pub attribute_exprs: Vec<CompiledAttributeExpr>,
+    pub lookahead_exprs: Vec<CompiledLookaheadExpr>,
+    pub lookahead_requirements: LookaheadRequirements,
 }
 
 pub struct AttrExprOperands {
@@ -30,7 +99,7 @@ pub struct AttrExprOperands {
 
 impl Expr<OnTagNameExpr> {
     #[inline]
-    pub fn compile_expr<F: Fn(&SelectorState, &LocalName) -> bool + 'static>(&self, f: F) -> CompiledLocalNameExpr {
+    pub fn compile_expr<F: Fn(&SelectorState, &LocalName) -> bool + Send + Sync + 'static>(&self, f: F) -> CompiledLocalNameExpr {
         if self.negation {
             Box::new(move |s, a| !f(s, a))
         } else {
@@ -40,21 +109,17 @@ impl Expr<OnTagNameExpr> {
 }
 
 trait Compilable {
-    fn compile(&self, encoding: &'static Encoding, exprs: &mut ExprSet, enable_nth_of_type: &mut bool);
+    fn compile(&self, ctx: &CompilationCtx, exprs: &mut ExprSet, features: &mut RequiredFeatures);
 }
 
 impl Compilable for Expr<OnTagNameExpr> {
-    fn compile(
-        &self,
-        encoding: &'static Encoding,
-        exprs: &mut ExprSet,
-        enable_nth_of_type: &mut bool,
-    ) {
+    fn compile(&self, ctx: &CompilationCtx, exprs: &mut ExprSet, features: &mut RequiredFeatures) {
         let expr = match &self.simple_expr {
             OnTagNameExpr::ExplicitAny => self.compile_expr(|_, _| true),
             OnTagNameExpr::Unmatchable => self.compile_expr(|_, _| false),
+            OnTagNameExpr::Scope => self.compile_expr(|_, actual| *actual == scope_local_name()),
             OnTagNameExpr::LocalName(local_name) => {
-                match LocalName::from_str_without_replacements(&local_name, encoding)
+                match LocalName::from_str_without_replacements(local_name, ctx.encoding)
                     .map(LocalName::into_owned)
                 {
                     Ok(local_name) => {
@@ -65,11 +130,20 @@ impl Compilable for Expr<OnTagNameExpr> {
                     Err(_) => self.compile_expr(|_, _| false),
                 }
             }
+            OnTagNameExpr::Namespace(prefix) => match ctx.namespace_uri(prefix) {
+                Some(uri) => {
+                    let uri = uri.to_owned();
+
+                    self.compile_expr(move |state, _| state.ns.uri() == uri)
+                }
+                // NOTE: the prefix is not registered, so it won't ever match.
+                None => self.compile_expr(|_, _| false),
+            },
             &OnTagNameExpr::NthChild(nth) => {
                 self.compile_expr(move |state, _| state.cumulative.is_nth(nth))
             }
             &OnTagNameExpr::NthOfType(nth) => {
-                *enable_nth_of_type = true;
+                features.nth_of_type = true;
                 self.compile_expr(move |state, _| state.typed.expect("Counter for type required at this point").is_nth(nth))
             }
         };
@@ -80,7 +154,7 @@ impl Compilable for Expr<OnTagNameExpr> {
 
 impl Expr<OnAttributesExpr> {
     #[inline]
-    pub fn compile_expr<F: Fn(&SelectorState, &AttributeMatcher) -> bool + 'static>(&self, f: F) -> CompiledAttributeExpr {
+    pub fn compile_expr<F: Fn(&SelectorState, &AttributeMatcher) -> bool + Send + Sync + 'static>(&self, f: F) -> CompiledAttributeExpr {
         if self.negation {
             Box::new(move |s, a| !f(s, a))
         } else {
@@ -104,50 +178,273 @@ fn compile_operands(encoding: &'static Encoding, name: &str, value: &str) -> Res
     Ok((compile_literal_lowercase(encoding, name)?, compile_literal(encoding, value)?))
 }
 
+fn compile_attribute_expr(
+    ctx: &CompilationCtx,
+    expr: &OnAttributesExpr,
+) -> Result<CompiledAttributeExpr, HasReplacementsError> {
+    let encoding = ctx.encoding;
+
+    Ok(match expr {
+        OnAttributesExpr::Id(id) => {
+            let id = compile_literal(encoding, id)?;
+
+            Box::new(move |_, m| m.has_id(&id))
+        }
+        OnAttributesExpr::Class(class) => {
+            let class = compile_literal(encoding, class)?;
+
+            Box::new(move |_, m| m.has_class(&class))
+        }
+        OnAttributesExpr::AttributeExists(name) => {
+            let name = compile_literal(encoding, name)?;
+
+            Box::new(move |_, m| m.has_attribute(&name))
+        }
+        &OnAttributesExpr::AttributeComparisonExpr(AttributeComparisonExpr {
+            ref name,
+            ref value,
+            case_sensitivity,
+            operator,
+        }) => {
+            let (name, value) = compile_operands(encoding, name, value)?;
+
+            let operands = AttrExprOperands {
+                name,
+                value,
+                case_sensitivity,
+            };
+
+            match operator {
+                AttrSelectorOperator::Equal => Box::new(move |_, m| m.attr_eq(&operands)),
+                AttrSelectorOperator::Includes => {
+                    Box::new(move |_, m| m.matches_splitted_by_whitespace(&operands))
+                }
+                AttrSelectorOperator::DashMatch => {
+                    Box::new(move |_, m| m.has_dash_matching_attr(&operands))
+                }
+                AttrSelectorOperator::Prefix => {
+                    Box::new(move |_, m| m.has_attr_with_prefix(&operands))
+                }
+                AttrSelectorOperator::Suffix => {
+                    Box::new(move |_, m| m.has_attr_with_suffix(&operands))
+                }
+                AttrSelectorOperator::Substring => {
+                    Box::new(move |_, m| m.has_attr_with_substring(&operands))
+                }
+            }
+        }
+        OnAttributesExpr::NamespacedAttribute(namespace, expr) => {
+            compile_namespaced_attribute_expr(ctx, namespace, expr)?
+        }
+        OnAttributesExpr::CustomPseudoClass(name) => {
+            let predicate = ctx
+                .custom_pseudo_classes
+                .get(name)
+                .map(Arc::clone)
+                .expect("Unregistered pseudo-classes should be rejected before compilation");
+            let encoding = ctx.encoding;
+
+            Box::new(move |_, m| predicate(&PseudoClassElement::new(m, encoding)))
+        }
+        &OnAttributesExpr::StatePseudoClass(pseudo_class) => {
+            Box::new(move |_, m| pseudo_class.matches(m))
+        }
+        OnAttributesExpr::Lang(ranges) => {
+            let ranges = ranges
+                .iter()
+                .map(|range| compile_literal(encoding, range))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Box::new(move |state, m| {
+                let lang = match m.lang() {
+                    Some(lang) => lang,
+                    None => match &state.inherited_attributes.lang {
+                        Some(lang) => Bytes::from(&**lang),
+                        None => return false,
+                    },
+                };
+
+                ranges.iter().any(|range| lang_matches(&lang, range))
+            })
+        }
+        &OnAttributesExpr::Dir(dir) => Box::new(move |state, m| {
+            InheritedAttributes::element_dir(state.inherited_attributes.dir, m) == Some(dir)
+        }),
+    })
+}
+
+/// Matches the language against the language range using the [basic filtering] of BCP 47.
+///
+/// [basic filtering]: https://tools.ietf.org/html/rfc4647#section-3.3.1
+#[inline]
+fn lang_matches(lang: &[u8], range: &[u8]) -> bool {
+    if range == b"*" {
+        !lang.is_empty()
+    } else {
+        lang.len() >= range.len()
+            && lang[..range.len()].eq_ignore_ascii_case(range)
+            && (lang.len() == range.len() || lang[range.len()] == b'-')
+    }
+}
+
+#[inline]
+fn with_attribute_name(expr: &OnAttributesExpr, name: &str) -> OnAttributesExpr {
+    match expr {
+        OnAttributesExpr::AttributeComparisonExpr(expr) => {
+            OnAttributesExpr::AttributeComparisonExpr(AttributeComparisonExpr::new(
+                name.to_owned(),
+                expr.value.to_owned(),
+                expr.case_sensitivity,
+                expr.operator,
+            ))
+        }
+        _ => OnAttributesExpr::AttributeExists(name.to_owned()),
+    }
+}
+
+fn compile_namespaced_attribute_expr(
+    ctx: &CompilationCtx,
+    namespace: &AttributeNamespace,
+    expr: &OnAttributesExpr,
+) -> Result<CompiledAttributeExpr, HasReplacementsError> {
+    let local_name = match expr {
+        OnAttributesExpr::AttributeExists(name) => name,
+        OnAttributesExpr::AttributeComparisonExpr(expr) => &expr.name,
+        _ => unreachable!("Only attribute selectors can have a namespace."),
+    };
+
+    let uri = match namespace {
+        AttributeNamespace::Any => None,
+        // NOTE: the prefix is not registered, so it won't ever match.
+        AttributeNamespace::Prefix(prefix) => match ctx.namespace_uri(prefix) {
+            Some(uri) => Some(uri),
+            None => return Ok(Box::new(|_, _| false)),
+        },
+    };
+
+    // NOTE: the parser places attributes in a namespace only for the handful of
+    // attributes of foreign elements. Everything else is in no namespace.
+    let foreign_exprs = ADJUSTED_FOREIGN_ATTRIBUTES
+        .iter()
+        .filter(|(_, name, attr_uri)| {
+            name == local_name && (uri.is_none() || uri == Some(attr_uri))
+        })
+        .map(|(qualified_name, ..)| {
+            compile_attribute_expr(ctx, &with_attribute_name(expr, qualified_name))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let no_namespace_expr = match namespace {
+        AttributeNamespace::Any => Some(compile_attribute_expr(ctx, expr)?),
+        AttributeNamespace::Prefix(_) => None,
+    };
+
+    Ok(Box::new(move |state, m| {
+        matches!(&no_namespace_expr, Some(e) if e(state, m))
+            || (state.ns != Namespace::Html && foreign_exprs.iter().any(|e| e(state, m)))
+    }))
+}
+
 impl Compilable for Expr<OnAttributesExpr> {
-    fn compile(
+    fn compile(&self, ctx: &CompilationCtx, exprs: &mut ExprSet, features: &mut RequiredFeatures) {
+        if let OnAttributesExpr::Lang(_) | OnAttributesExpr::Dir(_) = self.simple_expr {
+            features.inherited_attributes = true;
+        }
+
+        // NOTE: selector value can't be converted to the given encoding, so
+        // it won't ever match.
+        let expr = match compile_attribute_expr(ctx, &self.simple_expr) {
+            Ok(expr) => self.compile_expr(expr),
+            Err(_) => self.compile_expr(|_, _| false),
+        };
+
+        exprs.attribute_exprs.push(expr);
+    }
+}
+
+impl Expr<OnLookaheadExpr> {
+    #[inline]
+    pub fn compile_expr<F: Fn(&SelectorState, &LookaheadFacts) -> bool + Send + Sync + 'static>(
         &self,
-        encoding: &'static Encoding,
-        exprs: &mut ExprSet,
-        _: &mut bool,
-    ) {
-        let expr_result = match &self.simple_expr {
-            OnAttributesExpr::Id(id) =>
-                compile_literal(encoding, id)
-                    .map(|id| self.compile_expr(move |_, m| m.has_id(&id))),
-
-            OnAttributesExpr::Class(class) =>
-                compile_literal(encoding, class)
-                    .map(|class| self.compile_expr(move |_, m| m.has_class(&class))),
-
-            OnAttributesExpr::AttributeExists(name) =>
-                compile_literal(encoding, name)
-                    .map(|name| self.compile_expr(move |_, m| m.has_attribute(&name))),
-
-            &OnAttributesExpr::AttributeComparisonExpr(
-                AttributeComparisonExpr {
-                    ref name,
-                    ref value,
-                    case_sensitivity,
-                    operator
-                }
-            ) => {
-                compile_operands(encoding, name, value)
-                    .map(move |(name, value)| {
-                        let operands = AttrExprOperands { name, value, case_sensitivity };
-                        match operator {
-                            AttrSelectorOperator::Equal     => self.compile_expr(move |_, m| m.attr_eq(&operands)),
-                            AttrSelectorOperator::Includes  => self.compile_expr(move |_, m| m.matches_splitted_by_whitespace(&operands)),
-                            AttrSelectorOperator::DashMatch => self.compile_expr(move |_, m| m.has_dash_matching_attr(&operands)),
-                            AttrSelectorOperator::Prefix    => self.compile_expr(move |_, m| m.has_attr_with_prefix(&operands)),
-                            AttrSelectorOperator::Suffix    => self.compile_expr(move |_, m| m.has_attr_with_suffix(&operands)),
-                            AttrSelectorOperator::Substring => self.compile_expr(move |_, m| m.has_attr_with_substring(&operands)),
-                        }
+        f: F,
+    ) -> CompiledLookaheadExpr {
+        if self.negation {
+            Box::new(move |s, l| !f(s, l))
+        } else {
+            Box::new(f)
+        }
+    }
+}
+
+#[inline]
+fn index_from_end(following_sibling_count: usize) -> i32 {
+    (following_sibling_count as i32).saturating_add(1)
+}
+
+impl Compilable for Expr<OnLookaheadExpr> {
+    fn compile(&self, ctx: &CompilationCtx, exprs: &mut ExprSet, features: &mut RequiredFeatures) {
+        let requirements = &mut exprs.lookahead_requirements;
+
+        let expr = match &self.simple_expr {
+            &OnLookaheadExpr::NthLastChild(nth) => {
+                requirements.require_following_siblings(SiblingCount::for_nth(nth));
+
+                self.compile_expr(move |_, facts| {
+                    nth.has_index(index_from_end(facts.following_sibling_count))
+                })
+            }
+            &OnLookaheadExpr::NthLastOfType(nth) => {
+                requirements.require_following_siblings_of_type(SiblingCount::for_nth(nth));
+
+                self.compile_expr(move |_, facts| {
+                    nth.has_index(index_from_end(facts.following_sibling_of_type_count))
+                })
+            }
+            OnLookaheadExpr::OnlyChild => {
+                requirements.require_following_siblings(SiblingCount::UpTo(1));
+
+                self.compile_expr(|state, facts| {
+                    state.cumulative.is_nth(NthChild::first()) && facts.following_sibling_count == 0
+                })
+            }
+            OnLookaheadExpr::OnlyOfType => {
+                requirements.require_following_siblings_of_type(SiblingCount::UpTo(1));
+                features.nth_of_type = true;
+
+                self.compile_expr(|state, facts| {
+                    state
+                        .typed
+                        .expect("Counter for type required at this point")
+                        .is_nth(NthChild::first())
+                        && facts.following_sibling_of_type_count == 0
+                })
+            }
+            OnLookaheadExpr::Empty => {
+                requirements.emptiness = true;
+
+                self.compile_expr(|_, facts| facts.is_empty)
+            }
+            OnLookaheadExpr::Has(selectors) => {
+                let matchers = selectors
+                    .iter()
+                    .map(|selector| {
+                        let idx = features.has_matcher_count;
+
+                        features.has_matcher_count += 1;
+
+                        Arc::new(HasMatcher::new(selector, ctx, idx))
                     })
+                    .collect::<Vec<_>>();
+
+                matchers
+                    .iter()
+                    .for_each(|matcher| requirements.require_has_match(matcher));
+
+                self.compile_expr(move |_, facts| matchers.iter().any(|m| facts.has_match(m)))
             }
         };
 
-        exprs.attribute_exprs.push(expr_result.unwrap_or_else(|_| self.compile_expr(|_, _| false)));
+        exprs.lookahead_exprs.push(expr);
     }
 }
 
@@ -155,7 +452,7 @@ pub struct Compiler<P>
 where
     P: PartialEq + Eq + Copy + Debug + Hash,
 {
-    encoding: &'static Encoding,
+    ctx: CompilationCtx,
     instructions: Box<[Option<Instruction<P>>]>,
     free_space_start: usize,
 }
@@ -164,9 +461,9 @@ impl<P: 'static> Compiler<P>
 where
     P: PartialEq + Eq + Copy + Debug + Hash,
 {
-    pub fn new(encoding: &'static Encoding) -> Self {
+    pub fn with_ctx(ctx: CompilationCtx) -> Self {
         Compiler {
-            encoding,
+            ctx,
             instructions: Default::default(),
             free_space_start: 0,
         }
@@ -177,26 +474,33 @@ where
         Predicate {
             on_tag_name_exprs,
             on_attr_exprs,
+            on_lookahead_exprs,
         }: &Predicate,
-        branch: ExecutionBranch<P>,
-        enable_nth_of_type: &mut bool,
+        mut branch: ExecutionBranch<P>,
+        features: &mut RequiredFeatures,
     ) -> Instruction<P> {
         let mut exprs = ExprSet::default();
 
-        on_tag_name_exprs.iter().for_each(|c| c.compile(self.encoding, &mut exprs, enable_nth_of_type));
-        on_attr_exprs.iter().for_each(|c| c.compile(self.encoding, &mut exprs, enable_nth_of_type));
+        on_tag_name_exprs.iter().for_each(|c| c.compile(&self.ctx, &mut exprs, features));
+        on_attr_exprs.iter().for_each(|c| c.compile(&self.ctx, &mut exprs, features));
+        on_lookahead_exprs.iter().for_each(|c| c.compile(&self.ctx, &mut exprs, features));
 
         let ExprSet {
             local_name_exprs,
             attribute_exprs,
+            lookahead_exprs,
+            lookahead_requirements,
         } = exprs;
 
-        debug_assert!(!local_name_exprs.is_empty() || !attribute_exprs.is_empty(), "Predicate should contain expressions");
+        debug_assert!(!local_name_exprs.is_empty() || !attribute_exprs.is_empty() || !lookahead_exprs.is_empty(), "Predicate should contain expressions");
+
+        branch.lookahead_requirements = lookahead_requirements;
 
         Instruction {
             associated_branch: branch,
             local_name_exprs: local_name_exprs.into(),
             attribute_exprs: attribute_exprs.into(),
+            lookahead_exprs: lookahead_exprs.into(),
         }
     }
 
@@ -213,15 +517,15 @@ where
     }
 
     #[inline]
-    fn compile_descendants(&mut self, nodes: Vec<AstNode<P>>, enable_nth_of_type: &mut bool) -> Option<AddressRange> {
+    fn compile_descendants(&mut self, nodes: Vec<AstNode<P>>, features: &mut RequiredFeatures) -> Option<AddressRange> {
         if nodes.is_empty() {
             None
         } else {
-            Some(self.compile_nodes(nodes, enable_nth_of_type))
+            Some(self.compile_nodes(nodes, features))
         }
     }
 
-    fn compile_nodes(&mut self, nodes: Vec<AstNode<P>>, enable_nth_of_type: &mut bool) -> AddressRange {
+    fn compile_nodes(&mut self, nodes: Vec<AstNode<P>>, features: &mut RequiredFeatures) -> AddressRange {
         // NOTE: we need sibling nodes to be in a contiguous region, so
         // we can reference them by range instead of vector of addresses.
         let addr_range = self.reserve(&nodes);
@@ -229,26 +533,39 @@ where
         for (node, position) in nodes.into_iter().zip(addr_range.clone()) {
             let branch = ExecutionBranch {
                 matched_payload: node.payload,
-                jumps: self.compile_descendants(node.children, enable_nth_of_type),
-                hereditary_jumps: self.compile_descendants(node.descendants, enable_nth_of_type),
+                jumps: self.compile_descendants(node.children, features),
+                hereditary_jumps: self.compile_descendants(node.descendants, features),
+                next_sibling_jumps: self.compile_descendants(node.next_siblings, features),
+                later_sibling_jumps: self.compile_descendants(node.later_siblings, features),
+                // NOTE: set once the lookahead expressions are compiled.
+                lookahead_requirements: LookaheadRequirements::default(),
             };
 
-            self.instructions[position] = Some(self.compile_predicate(&node.predicate, branch, enable_nth_of_type));
+            self.instructions[position] = Some(self.compile_predicate(&node.predicate, branch, features));
         }
 
         addr_range
     }
 
     pub fn compile(mut self, ast: Ast<P>) -> Program<P> {
-        let mut enable_nth_of_type = false;
+        let mut features = RequiredFeatures::default();
         self.instructions = iter::repeat_with(|| None).take(ast.cumulative_node_count).collect();
 
-        let entry_points = self.compile_nodes(ast.root, &mut enable_nth_of_type);
+        let entry_points = self.compile_nodes(ast.root, &mut features);
+
+        let instructions: Box<[Instruction<P>]> = self.instructions.into_vec().into_iter().map(|o| o.unwrap()).collect();
+
+        let requires_lookahead = instructions
+            .iter()
+            .any(|instr| !instr.lookahead_exprs.is_empty());
 
         Program {
-            instructions: self.instructions.into_vec().into_iter().map(|o| o.unwrap()).collect(),
+            instructions,
             entry_points,
-            enable_nth_of_type,
+            enable_nth_of_type: features.nth_of_type,
+            enable_inherited_attributes: features.inherited_attributes,
+            requires_lookahead,
+            encoding: self.ctx.encoding,
         }
     }
 }
@@ -258,9 +575,9 @@ mod tests {
     use super::*;
     use crate::html::Namespace;
     use crate::rewritable_units::Token;
-    use crate::selectors_vm::{TryExecResult, tests::test_with_token};
+    use crate::selectors_vm::{TryExecResult, tests::test_with_token, LookaheadState};
     use crate::test_utils::ASCII_COMPATIBLE_ENCODINGS;
-    use encoding_rs::UTF_8;
+    use encoding_rs::{UTF_8, WINDOWS_1252};
     use hashbrown::HashSet;
 
     macro_rules! assert_instr_res {
@@ -297,7 +614,7 @@ mod tests {
             ast.add_selector(&selector.parse().unwrap(), idx);
         }
 
-        let program = Compiler::new(encoding).compile(ast);
+        let program = Compiler::with_ctx(CompilationCtx::new(encoding)).compile(ast);
 
         assert_eq!(
             program.entry_points.end - program.entry_points.start,
@@ -332,7 +649,13 @@ mod tests {
             Token::StartTag(t) => {
                 let (input, attrs) = t.raw_attributes();
                 let tag_name = t.name();
-                let attr_matcher = AttributeMatcher::new(input, attrs, Namespace::Html);
+                let attr_matcher = AttributeMatcher::new(
+                    input,
+                    Bytes::from_str(&tag_name, encoding),
+                    attrs,
+                    Namespace::Html,
+                    encoding,
+                );
                 let local_name =
                     LocalName::from_str_without_replacements(&tag_name, encoding).unwrap();
 
@@ -350,7 +673,13 @@ mod tests {
         for (input, matching_data) in test_cases.iter() {
             with_start_tag(input, encoding, |local_name, attr_matcher| {
                 let counter = Default::default();
-                let state = SelectorState { cumulative: &counter, typed: None };
+                let state = SelectorState {
+                    cumulative: &counter,
+                    typed: None,
+                    ns: Namespace::Html,
+                    inherited_attributes: &InheritedAttributes::default(),
+                    lookahead: LookaheadState::Unavailable,
+                };
                 action(input, matching_data, &state, local_name, attr_matcher);
             });
         }
@@ -804,6 +1133,188 @@ mod tests {
         }
     }
 
+    #[test]
+    fn compiled_attr_expression_with_char_refs() {
+        for encoding in ASCII_COMPATIBLE_ENCODINGS.iter() {
+            assert_attr_expr_matches_and_negation_reverses_match(
+                r#"[href^="https://x.com/?a=1&b"]"#,
+                encoding,
+                &[
+                    ("<a href='https://x.com/?a=1&amp;b=2'>", true),
+                    ("<a href='https://x.com/?a=1&#38;b=2'>", true),
+                    ("<a href='https://x.com/?a=1&b=2'>", true),
+                    ("<a href='https://x.com/?a=1&ampb=2'>", false),
+                ],
+            );
+
+            assert_attr_expr_matches_and_negation_reverses_match(
+                "#a\\<b",
+                encoding,
+                &[("<div id='a&lt;b'>", true), ("<div id='a&ltb'>", false)],
+            );
+
+            assert_attr_expr_matches_and_negation_reverses_match(
+                r#"[title~="<"]"#,
+                encoding,
+                &[
+                    ("<div title='foo &lt; bar'>", true),
+                    ("<div title='foo &lt=bar'>", false),
+                ],
+            );
+        }
+    }
+
+    #[test]
+    fn compiled_attr_expression_with_unencodable_char_refs() {
+        assert_attr_expr_matches_and_negation_reverses_match(
+            r#"[title*="&"]"#,
+            WINDOWS_1252,
+            &[
+                ("<div title='&#x4E2D;'>", false),
+                ("<div title='&amp;&#x4E2D;'>", true),
+            ],
+        );
+
+        assert_attr_expr_matches_and_negation_reverses_match(
+            r#"[title="&#20013;"]"#,
+            WINDOWS_1252,
+            &[("<div title='&#x4E2D;'>", false)],
+        );
+
+        assert_attr_expr_matches_and_negation_reverses_match(
+            r#"[title$="a"]"#,
+            WINDOWS_1252,
+            &[
+                ("<div title='&#x4E2D;a'>", true),
+                ("<div title='a&#x4E2D;'>", false),
+            ],
+        );
+    }
+
+    #[test]
+    fn compiled_state_pseudo_class_expression() {
+        for encoding in ASCII_COMPATIBLE_ENCODINGS.iter() {
+            let encoding = *encoding;
+
+            assert_attr_expr_matches_and_negation_reverses_match(
+                ":checked",
+                encoding,
+                &[
+                    ("<input type=checkbox checked>", true),
+                    ("<INPUT TYPE=Radio CHECKED=false>", true),
+                    ("<input checked>", false),
+                    ("<input type=checkbox>", false),
+                    ("<option selected>", true),
+                    ("<option>", false),
+                    ("<div checked selected>", false),
+                ],
+            );
+
+            assert_attr_expr_matches_and_negation_reverses_match(
+                ":default",
+                encoding,
+                &[
+                    ("<input type=radio checked>", true),
+                    ("<option selected>", true),
+                    ("<input type=submit>", false),
+                ],
+            );
+
+            assert_attr_expr_matches_and_negation_reverses_match(
+                ":disabled",
+                encoding,
+                &[
+                    ("<input disabled>", true),
+                    ("<button disabled=''>", true),
+                    ("<fieldset disabled>", true),
+                    ("<optgroup disabled>", true),
+                    ("<select>", false),
+                    ("<a disabled>", false),
+                ],
+            );
+
+            assert_attr_expr_matches_and_negation_reverses_match(
+                ":required",
+                encoding,
+                &[
+                    ("<input required>", true),
+                    ("<input type=email required>", true),
+                    ("<input type=foo required>", true),
+                    ("<textarea required>", true),
+                    ("<input type=hidden required>", false),
+                    ("<input type=range required>", false),
+                    ("<select>", false),
+                    ("<div required>", false),
+                ],
+            );
+
+            assert_attr_expr_matches_and_negation_reverses_match(
+                ":optional",
+                encoding,
+                &[
+                    ("<input>", true),
+                    ("<select>", true),
+                    ("<input type=checkbox>", true),
+                    ("<textarea required>", false),
+                    ("<input type=submit>", false),
+                    ("<div>", false),
+                ],
+            );
+
+            assert_attr_expr_matches_and_negation_reverses_match(
+                ":any-link",
+                encoding,
+                &[
+                    ("<a href=''>", true),
+                    ("<area href='/foo'>", true),
+                    ("<a>", false),
+                    ("<link href='/foo'>", false),
+                ],
+            );
+
+            assert_attr_expr_matches_and_negation_reverses_match(
+                ":placeholder-shown",
+                encoding,
+                &[
+                    ("<input placeholder='foo'>", true),
+                    ("<input type=search placeholder value=''>", true),
+                    ("<textarea placeholder='foo'>", true),
+                    ("<input placeholder='foo' value='bar'>", false),
+                    ("<input type=date placeholder='foo'>", false),
+                    ("<div placeholder='foo'>", false),
+                ],
+            );
+
+            assert_attr_expr_matches_and_negation_reverses_match(
+                ":read-write",
+                encoding,
+                &[
+                    ("<input>", true),
+                    ("<textarea>", true),
+                    ("<div contenteditable>", true),
+                    ("<p contenteditable=PlainText-Only>", true),
+                    ("<input readonly>", false),
+                    ("<textarea disabled>", false),
+                    ("<input type=checkbox>", false),
+                    ("<input type=checkbox contenteditable>", false),
+                    ("<div contenteditable=false>", false),
+                    ("<div>", false),
+                ],
+            );
+
+            assert_attr_expr_matches_and_negation_reverses_match(
+                ":read-only",
+                encoding,
+                &[
+                    ("<input readonly>", true),
+                    ("<div>", true),
+                    ("<input>", false),
+                    ("<span contenteditable=true>", false),
+                ],
+            );
+        }
+    }
+
     #[test]
     fn generic_expressions() {
         for encoding in ASCII_COMPATIBLE_ENCODINGS.iter() {
@@ -891,7 +1402,13 @@ mod tests {
                 let mut jumps = Vec::default();
                 let mut hereditary_jumps = Vec::default();
                 let counter = Default::default();
-                let state = SelectorState { cumulative: &counter, typed: None };
+                let state = SelectorState {
+                    cumulative: &counter,
+                    typed: None,
+                    ns: Namespace::Html,
+                    inherited_attributes: &InheritedAttributes::default(),
+                    lookahead: LookaheadState::Unavailable,
+                };
 
                 with_start_tag($html, UTF_8, |local_name, attr_matcher| {
                     let res = exec_instr_range!($add_range, program, &state, local_name, attr_matcher);
@@ -963,4 +1480,65 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn sibling_jumps() {
+        let selectors = ["div + span", "div ~ #foo", "div ~ p > .c1"];
+        let program = compile(&selectors, UTF_8, 1);
+        let counter = Default::default();
+        let state = SelectorState {
+            cumulative: &counter,
+            typed: None,
+            ns: Namespace::Html,
+            inherited_attributes: &InheritedAttributes::default(),
+            lookahead: LookaheadState::Unavailable,
+        };
+
+        macro_rules! exec {
+            ($html:expr, $addr_range:expr, $expected_payload:expr) => {{
+                let mut branch = None;
+
+                with_start_tag($html, UTF_8, |local_name, attr_matcher| {
+                    let mut matched_payload = HashSet::default();
+
+                    for addr in $addr_range.clone() {
+                        let instr = &program.instructions[addr];
+
+                        if let Some(res) =
+                            exec_generic_instr!(instr, &state, local_name, attr_matcher)
+                        {
+                            matched_payload.extend(res.matched_payload.iter().cloned());
+                            branch = Some(res);
+                        }
+                    }
+
+                    assert_payload!(matched_payload, $expected_payload, selectors, $html);
+                });
+
+                branch
+            }};
+        }
+
+        let div_branch = exec!("<div>", program.entry_points, []).unwrap();
+
+        assert_eq!(div_branch.jumps, None);
+        assert_eq!(div_branch.hereditary_jumps, None);
+
+        let next_sibling_jumps = div_branch.next_sibling_jumps.clone().unwrap();
+        let later_sibling_jumps = div_branch.later_sibling_jumps.clone().unwrap();
+
+        assert_eq!(next_sibling_jumps.len(), 1);
+        assert_eq!(later_sibling_jumps.len(), 2);
+
+        exec!("<span>", next_sibling_jumps, [0]);
+        exec!("<p>", next_sibling_jumps, []);
+        exec!("<span id=foo>", later_sibling_jumps, [1]);
+
+        let p_branch = exec!("<p>", later_sibling_jumps, []).unwrap();
+
+        assert_eq!(p_branch.next_sibling_jumps, None);
+        assert_eq!(p_branch.later_sibling_jumps, None);
+
+        exec!("<span class=c1>", p_branch.jumps.clone().unwrap(), [2]);
+    }
 }
@@ -0,0 +1,130 @@
+use super::AttributeMatcher;
+use crate::base::Bytes;
+use crate::html::decode_char_refs;
+use encoding_rs::Encoding;
+use hashbrown::HashMap;
+use std::sync::Arc;
+
+/// A predicate of a user-defined pseudo-class.
+pub type PseudoClassPredicate = Arc<dyn Fn(&PseudoClassElement) -> bool + Send + Sync>;
+
+/// Specifies user-defined pseudo-classes that can be used in selectors.
+///
+/// A pseudo-class is backed by a predicate over the start tag of an element. The element matches
+/// the pseudo-class if the predicate returns `true`. The pseudo-classes are registered with the
+/// [`custom_pseudo_classes`] setting of the rewriter and the names used in the selectors are
+/// resolved on the construction of the rewriter, which fails if a name isn't registered.
+///
+/// # Example
+/// ```
+/// use lol_html::{element, rewrite_str, CustomPseudoClasses, RewriteStrSettings};
+///
+/// let html = rewrite_str(
+///     r#"<a href="/foo"></a><a href="https://example.com"></a>"#,
+///     RewriteStrSettings {
+///         element_content_handlers: vec![element!("a:external-link", |el| {
+///             el.set_attribute("rel", "noopener")?;
+///
+///             Ok(())
+///         })],
+///         custom_pseudo_classes: CustomPseudoClasses::default().add("external-link", |el| {
+///             matches!(el.get_attribute("href"), Some(href) if href.starts_with("https://"))
+///         }),
+///         ..RewriteStrSettings::default()
+///     }
+/// ).unwrap();
+///
+/// assert_eq!(
+///     html,
+///     r#"<a href="/foo"></a><a href="https://example.com" rel="noopener"></a>"#
+/// );
+/// ```
+///
+/// [`custom_pseudo_classes`]: struct.Settings.html#structfield.custom_pseudo_classes
+#[derive(Default)]
+pub struct CustomPseudoClasses {
+    predicates: HashMap<String, PseudoClassPredicate>,
+}
+
+impl CustomPseudoClasses {
+    /// Registers a pseudo-class with the `name` (without the leading colon) that is backed
+    /// by the `predicate`.
+    ///
+    /// Names are ASCII case-insensitive. Consequent calls to the method with the same name
+    /// overwrite the previously registered predicate. Names of the standard pseudo-classes
+    /// always refer to the standard pseudo-classes, so predicates registered with such names
+    /// are never used.
+    #[inline]
+    pub fn add(
+        mut self,
+        name: &str,
+        predicate: impl Fn(&PseudoClassElement) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.predicates
+            .insert(name.to_ascii_lowercase(), Arc::new(predicate));
+
+        self
+    }
+
+    /// Returns the predicate of the pseudo-class with the lowercased `name`.
+    #[inline]
+    pub(crate) fn get(&self, name: &str) -> Option<&PseudoClassPredicate> {
+        self.predicates.get(name)
+    }
+}
+
+/// An element examined by a user-defined pseudo-class predicate.
+///
+/// Only the information available in the element's start tag can be used by the predicate.
+pub struct PseudoClassElement<'a, 'i> {
+    attr_matcher: &'a AttributeMatcher<'i>,
+    encoding: &'static Encoding,
+}
+
+impl<'a, 'i> PseudoClassElement<'a, 'i> {
+    #[inline]
+    pub(crate) fn new(attr_matcher: &'a AttributeMatcher<'i>, encoding: &'static Encoding) -> Self {
+        PseudoClassElement {
+            attr_matcher,
+            encoding,
+        }
+    }
+
+    /// Returns the tag name of the element.
+    #[inline]
+    pub fn tag_name(&self) -> String {
+        self.attr_matcher
+            .tag_name()
+            .as_lowercase_string(self.encoding)
+    }
+
+    /// Returns the [namespace URI] of the element.
+    ///
+    /// [namespace URI]: https://developer.mozilla.org/en-US/docs/Web/API/Element/namespaceURI
+    #[inline]
+    pub fn namespace_uri(&self) -> &'static str {
+        self.attr_matcher.ns().uri()
+    }
+
+    /// Returns the value of an attribute with the `name` with the character references decoded.
+    ///
+    /// Returns `None` if the element doesn't have an attribute with the `name`.
+    #[inline]
+    pub fn get_attribute(&self, name: &str) -> Option<String> {
+        let name = name.to_ascii_lowercase();
+        let name = Bytes::from_str(&name, self.encoding);
+
+        self.attr_matcher
+            .get_raw_value(&name)
+            .map(|value| decode_char_refs(&value.as_string(self.encoding), true).into_owned())
+    }
+
+    /// Returns `true` if the element has an attribute with `name`.
+    #[inline]
+    pub fn has_attribute(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        let name = Bytes::from_str(&name, self.encoding);
+
+        self.attr_matcher.has_attribute(&name)
+    }
+}
@@ -37,8 +37,8 @@ pub enum SelectorError {
     #[error("Nested negation in selector.")]
     NestedNegation,
 
-    /// Selectors with explicit namespaces are not supported.
-    #[error("Selectors with explicit namespaces are not supported.")]
+    /// Selectors with the default namespace are not supported.
+    #[error("Selectors with the default namespace are not supported.")]
     NamespacedSelector,
 
     /// Invalid or unescaped class name in selector.
@@ -0,0 +1,816 @@
+use super::ast::NthChild;
+use super::compiler::{CompilationCtx, Compiler};
+use super::parser::RelativeSelector;
+use super::program::Program;
+use super::{Ast, ElementData, MatchInfo, SelectorMatchingVm, VmError};
+use crate::html::{LocalName, LocalNameHash, Namespace};
+use crate::memory::{LimitedVec, SharedMemoryLimiter};
+use crate::parser::{
+    LexemeSink, NonTagContentLexeme, NonTagContentTokenOutline, Parser, ParserDirective,
+    ParserOutputSink, TagHintSink, TagLexeme, TagTokenOutline,
+};
+use crate::rewriter::RewritingError;
+use crate::transform_stream::{AuxStartTagInfo, LookaheadScanner};
+use hashbrown::{HashMap, HashSet};
+use std::cmp::max;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+/// Returns the local name that stands for the anchor element of a relative selector.
+///
+/// NOTE: the hash of an empty local name is never produced by the tokenizer, as
+/// tag names always start with an ASCII alpha character.
+#[inline]
+pub fn scope_local_name() -> LocalName<'static> {
+    LocalName::Hash(LocalNameHash::new())
+}
+
+/// The number of following siblings of an element that need to be counted to make a match decision.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub enum SiblingCount {
+    NotRequired,
+    /// Counting can stop once the specified number of siblings is reached, as any greater
+    /// number produces the same result.
+    UpTo(usize),
+    All,
+}
+
+impl SiblingCount {
+    #[inline]
+    pub fn for_nth(nth: NthChild) -> Self {
+        match nth.max_index() {
+            Some(index) => SiblingCount::UpTo(max(index, 0) as usize),
+            None => SiblingCount::All,
+        }
+    }
+
+    #[inline]
+    fn is_reached_by(self, count: usize) -> bool {
+        match self {
+            SiblingCount::NotRequired => true,
+            SiblingCount::UpTo(limit) => count >= limit,
+            SiblingCount::All => false,
+        }
+    }
+}
+
+/// A compiled relative selector of the `:has()` pseudo-class.
+///
+/// The program is executed by a separate VM instance that receives the anchor element
+/// (see [`scope_local_name`]) and the input that follows its start tag.
+pub struct HasMatcher {
+    /// The index of the matcher among the matchers of the program.
+    idx: usize,
+    program: Arc<Program<()>>,
+    matches_siblings: bool,
+}
+
+impl HasMatcher {
+    pub fn new(selector: &RelativeSelector, ctx: &CompilationCtx, idx: usize) -> Self {
+        let mut ast = Ast::default();
+
+        ast.add_relative_selector(selector, ());
+
+        HasMatcher {
+            idx,
+            program: Arc::new(Compiler::with_ctx(ctx.to_owned()).compile(ast)),
+            matches_siblings: selector.matches_siblings(),
+        }
+    }
+}
+
+// NOTE: matchers are compared by index, so facts collected for one
+// instruction are never mistaken for the facts of another one.
+impl PartialEq for HasMatcher {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.idx == other.idx
+    }
+}
+
+impl Eq for HasMatcher {}
+
+impl Debug for HasMatcher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HasMatcher")
+            .field("idx", &self.idx)
+            .field("matches_siblings", &self.matches_siblings)
+            .finish()
+    }
+}
+
+/// Information about the input that follows the start tag of an element which is
+/// required to evaluate lookahead expressions of an instruction.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LookaheadRequirements {
+    pub following_siblings: SiblingCount,
+    pub following_siblings_of_type: SiblingCount,
+    pub emptiness: bool,
+    pub has_matchers: Vec<Arc<HasMatcher>>,
+}
+
+impl Default for LookaheadRequirements {
+    #[inline]
+    fn default() -> Self {
+        LookaheadRequirements {
+            following_siblings: SiblingCount::NotRequired,
+            following_siblings_of_type: SiblingCount::NotRequired,
+            emptiness: false,
+            has_matchers: Vec::default(),
+        }
+    }
+}
+
+impl LookaheadRequirements {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    #[inline]
+    pub fn merge(&mut self, other: &Self) {
+        self.require_following_siblings(other.following_siblings);
+        self.require_following_siblings_of_type(other.following_siblings_of_type);
+        self.emptiness |= other.emptiness;
+
+        for matcher in &other.has_matchers {
+            self.require_has_match(matcher);
+        }
+    }
+
+    #[inline]
+    pub fn require_following_siblings(&mut self, count: SiblingCount) {
+        self.following_siblings = max(self.following_siblings, count);
+    }
+
+    #[inline]
+    pub fn require_following_siblings_of_type(&mut self, count: SiblingCount) {
+        self.following_siblings_of_type = max(self.following_siblings_of_type, count);
+    }
+
+    #[inline]
+    pub fn require_has_match(&mut self, matcher: &Arc<HasMatcher>) {
+        if !self.has_matchers.contains(matcher) {
+            self.has_matchers.push(Arc::clone(matcher));
+        }
+    }
+}
+
+/// Information about the input that follows the start tag of an element.
+///
+/// NOTE: sibling counts are capped by the corresponding [`SiblingCount`] requirement.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct LookaheadFacts {
+    pub following_sibling_count: usize,
+    pub following_sibling_of_type_count: usize,
+    pub is_empty: bool,
+    /// The indices of the `:has()` matchers that have found a matching element.
+    pub has_matches: Vec<usize>,
+}
+
+impl LookaheadFacts {
+    #[inline]
+    pub fn has_match(&self, matcher: &HasMatcher) -> bool {
+        self.has_matches.contains(&matcher.idx)
+    }
+}
+
+/// Lookahead facts keyed by the index of the corresponding start tag in the input.
+pub type LookaheadFactsMap = HashMap<usize, LookaheadFacts>;
+
+/// The state of the lookahead for the element that is being matched.
+#[derive(Debug, Copy, Clone)]
+pub enum LookaheadState<'i> {
+    /// The input is being scanned ahead and the facts are not known yet, so lookahead
+    /// expressions are considered to be matching.
+    Scanning,
+    Resolved(&'i LookaheadFacts),
+    /// Lookahead is disabled or has been abandoned for the element, so lookahead
+    /// expressions are considered to be non-matching, whether they are negated or not.
+    Unavailable,
+}
+
+pub enum VmLookahead {
+    Disabled,
+    Scanning {
+        requirements: LookaheadRequirements,
+    },
+    Resolving {
+        facts: LookaheadFactsMap,
+        start_tag_count: usize,
+        current_facts: Option<LookaheadFacts>,
+    },
+}
+
+impl VmLookahead {
+    #[inline]
+    pub fn reset(&mut self) {
+        match self {
+            VmLookahead::Disabled => (),
+            VmLookahead::Scanning { requirements } => *requirements = Default::default(),
+            VmLookahead::Resolving {
+                facts,
+                start_tag_count,
+                current_facts,
+            } => {
+                facts.clear();
+                *start_tag_count = 0;
+                *current_facts = None;
+            }
+        }
+    }
+
+    #[inline]
+    pub fn start_element(&mut self) {
+        match self {
+            VmLookahead::Disabled => (),
+            VmLookahead::Scanning { requirements } => *requirements = Default::default(),
+            VmLookahead::Resolving {
+                facts,
+                start_tag_count,
+                current_facts,
+            } => {
+                *start_tag_count += 1;
+                *current_facts = facts.remove(start_tag_count);
+            }
+        }
+    }
+
+    #[inline]
+    pub fn complete_element(&mut self, element_requirements: LookaheadRequirements) {
+        if let VmLookahead::Scanning { requirements } = self {
+            *requirements = element_requirements;
+        }
+    }
+
+    #[inline]
+    pub fn facts_mut(&mut self) -> Option<&mut LookaheadFactsMap> {
+        match self {
+            VmLookahead::Resolving { facts, .. } => Some(facts),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn state(&self) -> LookaheadState<'_> {
+        match self {
+            VmLookahead::Disabled => LookaheadState::Unavailable,
+            VmLookahead::Scanning { .. } => LookaheadState::Scanning,
+            VmLookahead::Resolving { current_facts, .. } => match current_facts {
+                Some(facts) => LookaheadState::Resolved(facts),
+                None => LookaheadState::Unavailable,
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+struct RelativeElementData(HashSet<()>);
+
+impl ElementData for RelativeElementData {
+    type MatchPayload = ();
+
+    #[inline]
+    fn matched_payload_mut(&mut self) -> &mut HashSet<()> {
+        &mut self.0
+    }
+}
+
+/// Executes the program of a `:has()` matcher for the input that follows the start
+/// tag of the anchor element.
+struct HasMatching {
+    matcher: Arc<HasMatcher>,
+    vm: SelectorMatchingVm<RelativeElementData>,
+}
+
+impl HasMatching {
+    /// Executes the VM for the start tag and returns `true` if the tag has been matched.
+    fn exec_for_start_tag<'i>(
+        &mut self,
+        local_name: LocalName,
+        ns: Namespace,
+        aux_info: impl FnOnce() -> AuxStartTagInfo<'i>,
+    ) -> Result<bool, RewritingError> {
+        let mut matched = false;
+
+        match self
+            .vm
+            .exec_for_start_tag(local_name, ns, &mut |_| matched = true)
+        {
+            Ok(()) => (),
+            Err(VmError::InfoRequest(req)) => {
+                req(&mut self.vm, aux_info(), &mut |_: MatchInfo<()>| {
+                    matched = true
+                })
+                .map_err(RewritingError::MemoryLimitExceeded)?;
+            }
+            Err(VmError::MemoryLimitExceeded(e)) => {
+                return Err(RewritingError::MemoryLimitExceeded(e))
+            }
+        }
+
+        Ok(matched)
+    }
+}
+
+/// An element whose match can't be decided until the following input is scanned.
+struct Candidate {
+    start_tag_idx: usize,
+    start_tag_offset: usize,
+    /// The number of open elements at the moment the candidate's start tag was encountered.
+    depth: usize,
+    local_name: LocalName<'static>,
+    requirements: LookaheadRequirements,
+    facts: LookaheadFacts,
+    siblings_decided: bool,
+    siblings_of_type_decided: bool,
+    emptiness_decided: bool,
+    /// The `:has()` matchers that are still looking for a matching element.
+    has_matching: Vec<HasMatching>,
+    closed: bool,
+}
+
+impl Candidate {
+    #[inline]
+    fn is_decided(&self) -> bool {
+        self.siblings_decided
+            && self.siblings_of_type_decided
+            && self.emptiness_decided
+            && self.has_matching.is_empty()
+    }
+
+    fn add_following_sibling(&mut self, local_name: &LocalName) {
+        if !self.siblings_decided {
+            self.facts.following_sibling_count += 1;
+
+            self.siblings_decided = self
+                .requirements
+                .following_siblings
+                .is_reached_by(self.facts.following_sibling_count);
+        }
+
+        if !self.siblings_of_type_decided && *local_name == self.local_name {
+            self.facts.following_sibling_of_type_count += 1;
+
+            self.siblings_of_type_decided = self
+                .requirements
+                .following_siblings_of_type
+                .is_reached_by(self.facts.following_sibling_of_type_count);
+        }
+    }
+
+    #[inline]
+    fn add_content(&mut self) {
+        if !self.emptiness_decided {
+            self.facts.is_empty = false;
+            self.emptiness_decided = true;
+        }
+    }
+
+    fn add_start_tag<'i>(
+        &mut self,
+        local_name: &LocalName,
+        ns: Namespace,
+        aux_info: &dyn Fn() -> AuxStartTagInfo<'i>,
+    ) -> Result<(), RewritingError> {
+        let mut i = 0;
+
+        while i < self.has_matching.len() {
+            if self.has_matching[i].exec_for_start_tag(local_name.clone(), ns, aux_info)? {
+                let matching = self.has_matching.swap_remove(i);
+
+                self.facts.has_matches.push(matching.matcher.idx);
+            } else {
+                i += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_end_tag(&mut self, local_name: &LocalName) {
+        for matching in &mut self.has_matching {
+            matching.vm.exec_for_end_tag(local_name.clone(), |_| ());
+        }
+    }
+
+    #[inline]
+    fn close(&mut self) {
+        if !self.emptiness_decided {
+            self.facts.is_empty = true;
+            self.emptiness_decided = true;
+        }
+
+        if !self.closed {
+            self.closed = true;
+
+            // NOTE: only the sibling combinators can match
+            // elements once the anchor element is closed.
+            self.has_matching
+                .retain(|matching| matching.matcher.matches_siblings);
+
+            self.add_end_tag(&scope_local_name());
+        }
+    }
+
+    #[inline]
+    fn close_parent(&mut self) {
+        self.close();
+        self.siblings_decided = true;
+        self.siblings_of_type_decided = true;
+        self.has_matching.clear();
+    }
+}
+
+struct ScannerSink<E: ElementData> {
+    vm: SelectorMatchingVm<E>,
+    facts: LookaheadFactsMap,
+    candidates: LimitedVec<Candidate>,
+    start_tag_count: usize,
+    input_offset: usize,
+    memory_limiter: SharedMemoryLimiter,
+}
+
+impl<E: ElementData> ScannerSink<E> {
+    fn add_candidate<'i>(
+        &mut self,
+        lexeme: &TagLexeme,
+        depth: usize,
+        local_name: LocalName,
+        ns: Namespace,
+        aux_info: &dyn Fn() -> AuxStartTagInfo<'i>,
+        requirements: LookaheadRequirements,
+    ) -> Result<(), RewritingError> {
+        let mut has_matching = Vec::with_capacity(requirements.has_matchers.len());
+
+        for matcher in &requirements.has_matchers {
+            let mut matching = HasMatching {
+                matcher: Arc::clone(matcher),
+                vm: SelectorMatchingVm::with_program(
+                    Arc::clone(&matcher.program),
+                    self.memory_limiter.clone(),
+                ),
+            };
+
+            matching.exec_for_start_tag(scope_local_name(), ns, aux_info)?;
+
+            has_matching.push(matching);
+        }
+
+        let mut candidate = Candidate {
+            start_tag_idx: self.start_tag_count,
+            start_tag_offset: self.input_offset + lexeme.raw_range().start,
+            depth,
+            local_name: local_name.into_owned(),
+            facts: LookaheadFacts::default(),
+            siblings_decided: requirements.following_siblings.is_reached_by(0),
+            siblings_of_type_decided: requirements.following_siblings_of_type.is_reached_by(0),
+            emptiness_decided: !requirements.emptiness,
+            requirements,
+            has_matching,
+            closed: false,
+        };
+
+        // NOTE: void and self-closing elements don't get any content.
+        if self.vm.open_element_count() == depth {
+            candidate.close();
+        }
+
+        self.candidates
+            .push(candidate)
+            .map_err(RewritingError::MemoryLimitExceeded)
+    }
+
+    fn handle_start_tag(&mut self, lexeme: &TagLexeme) -> Result<(), RewritingError> {
+        if let TagTokenOutline::StartTag {
+            name,
+            name_hash,
+            ns,
+            ref attributes,
+            self_closing,
+        } = *lexeme.token_outline()
+        {
+            let input = lexeme.input();
+            let local_name = LocalName::new(input, name, name_hash);
+            let depth = self.vm.open_element_count();
+
+            let aux_info = || AuxStartTagInfo {
+                input,
+                name: input.slice(name),
+                attr_buffer: attributes,
+                self_closing,
+            };
+
+            for candidate in self.candidates.iter_mut() {
+                if candidate.depth == depth {
+                    candidate.add_following_sibling(&local_name);
+                } else if candidate.depth + 1 == depth {
+                    candidate.add_content();
+                }
+
+                candidate.add_start_tag(&local_name, ns, &aux_info)?;
+            }
+
+            self.start_tag_count += 1;
+
+            match self
+                .vm
+                .exec_for_start_tag(local_name.clone(), ns, &mut |_| ())
+            {
+                Ok(()) => (),
+                Err(VmError::InfoRequest(req)) => {
+                    req(&mut self.vm, aux_info(), &mut |_| ())
+                        .map_err(RewritingError::MemoryLimitExceeded)?;
+                }
+                Err(VmError::MemoryLimitExceeded(e)) => {
+                    return Err(RewritingError::MemoryLimitExceeded(e))
+                }
+            }
+
+            if let VmLookahead::Scanning { requirements } = &self.vm.lookahead {
+                if !requirements.is_empty() {
+                    let requirements = requirements.clone();
+
+                    self.add_candidate(lexeme, depth, local_name, ns, &aux_info, requirements)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_end_tag(&mut self, lexeme: &TagLexeme) {
+        if let TagTokenOutline::EndTag { name, name_hash } = *lexeme.token_outline() {
+            let local_name = LocalName::new(lexeme.input(), name, name_hash);
+
+            self.vm.exec_for_end_tag(local_name.clone(), |_| ());
+
+            let depth = self.vm.open_element_count();
+
+            for candidate in self.candidates.iter_mut() {
+                if candidate.depth > depth {
+                    candidate.close_parent();
+                } else if candidate.depth == depth && !candidate.closed {
+                    candidate.close();
+                } else {
+                    candidate.add_end_tag(&local_name);
+                }
+            }
+        }
+    }
+
+    fn handle_text(&mut self) {
+        let depth = self.vm.open_element_count();
+
+        for candidate in self.candidates.iter_mut() {
+            if candidate.depth + 1 == depth {
+                candidate.add_content();
+            }
+        }
+    }
+
+    fn flush_decided_candidates(&mut self) {
+        let facts = &mut self.facts;
+
+        self.candidates.retain(|candidate| {
+            if candidate.is_decided() {
+                facts.insert(candidate.start_tag_idx, candidate.facts.clone());
+
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn finish(&mut self) {
+        for candidate in self.candidates.iter_mut() {
+            candidate.close_parent();
+        }
+
+        self.flush_decided_candidates();
+    }
+}
+
+impl<E: ElementData> LexemeSink for ScannerSink<E> {
+    fn handle_tag(&mut self, lexeme: &TagLexeme) -> Result<ParserDirective, RewritingError> {
+        match lexeme.token_outline() {
+            TagTokenOutline::StartTag { .. } => self.handle_start_tag(lexeme)?,
+            TagTokenOutline::EndTag { .. } => self.handle_end_tag(lexeme),
+        }
+
+        self.flush_decided_candidates();
+
+        Ok(ParserDirective::Lex)
+    }
+
+    fn handle_non_tag_content(
+        &mut self,
+        lexeme: &NonTagContentLexeme,
+    ) -> Result<(), RewritingError> {
+        match lexeme.token_outline() {
+            Some(NonTagContentTokenOutline::Text(_)) => {
+                self.handle_text();
+                self.flush_decided_candidates();
+            }
+            Some(NonTagContentTokenOutline::Eof) => self.finish(),
+            // NOTE: comments and other non-text content don't make the element non-empty.
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+impl<E: ElementData> TagHintSink for ScannerSink<E> {
+    fn handle_start_tag_hint(
+        &mut self,
+        _: LocalName,
+        _: crate::html::Namespace,
+    ) -> Result<ParserDirective, RewritingError> {
+        unreachable!("Lookahead scanner should always operate in the lexer mode")
+    }
+
+    fn handle_end_tag_hint(&mut self, _: LocalName) -> Result<ParserDirective, RewritingError> {
+        unreachable!("Lookahead scanner should always operate in the lexer mode")
+    }
+}
+
+impl<E: ElementData> ParserOutputSink for ScannerSink<E> {}
+
+/// Scans the input ahead of the main parser to find elements whose match depends on
+/// the following content and to collect facts about this content for them.
+///
+/// The scanner runs its own instance of the selector matching VM that shares the program
+/// with the main VM, but considers lookahead expressions to be matching. Therefore, it finds
+/// a superset of the elements that require lookahead facts in the main VM.
+pub struct LookaheadFactsScanner<E: ElementData> {
+    parser: Parser<ScannerSink<E>>,
+}
+
+impl<E: ElementData> LookaheadFactsScanner<E> {
+    /// Creates a scanner for the provided VM and switches the VM to use the facts
+    /// collected by the scanner.
+    pub fn new(
+        vm: &mut SelectorMatchingVm<E>,
+        memory_limiter: SharedMemoryLimiter,
+        strict: bool,
+    ) -> Self {
+        vm.lookahead = VmLookahead::Resolving {
+            facts: LookaheadFactsMap::default(),
+            start_tag_count: 0,
+            current_facts: None,
+        };
+
+        let mut scanner_vm =
+            SelectorMatchingVm::with_program(Arc::clone(&vm.program), memory_limiter.clone());
+
+        scanner_vm.lookahead = VmLookahead::Scanning {
+            requirements: LookaheadRequirements::default(),
+        };
+
+        let sink = ScannerSink {
+            vm: scanner_vm,
+            facts: LookaheadFactsMap::default(),
+            candidates: LimitedVec::new(Arc::clone(&memory_limiter)),
+            start_tag_count: 0,
+            input_offset: 0,
+            memory_limiter,
+        };
+
+        LookaheadFactsScanner {
+            parser: Parser::new(sink, ParserDirective::Lex, strict),
+        }
+    }
+}
+
+impl<E: ElementData> LookaheadScanner for LookaheadFactsScanner<E> {
+    fn scan(&mut self, input: &[u8], offset: usize, last: bool) -> Result<usize, RewritingError> {
+        self.parser.output_sink_mut().input_offset = offset;
+
+        let consumed_byte_count = self.parser.parse(input, last)?;
+
+        if last {
+            self.parser.output_sink_mut().finish();
+        }
+
+        Ok(consumed_byte_count)
+    }
+
+    #[inline]
+    fn withheld_input_start(&self) -> Option<usize> {
+        self.parser
+            .output_sink()
+            .candidates
+            .first()
+            .map(|c| c.start_tag_offset)
+    }
+
+    #[inline]
+    fn take_facts(&mut self, facts: &mut LookaheadFactsMap) {
+        facts.extend(self.parser.output_sink_mut().facts.drain());
+    }
+
+    #[inline]
+    fn abandon_withheld_input(&mut self) {
+        // NOTE: facts are not available for the abandoned
+        // candidates, so they will not match in the main VM.
+        self.parser.output_sink_mut().candidates.clear();
+    }
+
+    fn reset(&mut self) {
+        let sink = self.parser.output_sink_mut();
+
+        sink.vm.reset();
+        sink.facts.clear();
+        sink.candidates.clear();
+        sink.start_tag_count = 0;
+        sink.input_offset = 0;
+
+        self.parser.reset(ParserDirective::Lex);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selectors_vm::Selector;
+    use encoding_rs::UTF_8;
+
+    fn requirements(selector: &str) -> LookaheadRequirements {
+        let selector: Selector = selector.parse().unwrap();
+        let mut ast = Ast::default();
+
+        ast.add_selector(&selector, 0);
+
+        let program = Compiler::<usize>::with_ctx(CompilationCtx::new(UTF_8)).compile(ast);
+
+        program.instructions[program.entry_points.start]
+            .associated_branch
+            .lookahead_requirements
+            .clone()
+    }
+
+    #[test]
+    fn requirements_for_exprs() {
+        use SiblingCount::*;
+
+        macro_rules! assert_requirements {
+            ($selector:expr, $siblings:expr, $siblings_of_type:expr, $emptiness:expr) => {
+                assert_eq!(
+                    requirements($selector),
+                    LookaheadRequirements {
+                        following_siblings: $siblings,
+                        following_siblings_of_type: $siblings_of_type,
+                        emptiness: $emptiness,
+                        has_matchers: vec![],
+                    },
+                    "Requirements mismatch for `{}`",
+                    $selector
+                );
+            };
+        }
+
+        assert_requirements!("div", NotRequired, NotRequired, false);
+        assert_requirements!("div:last-child", UpTo(1), NotRequired, false);
+        assert_requirements!(":nth-last-child(3)", UpTo(3), NotRequired, false);
+        assert_requirements!(":nth-last-child(-n+2)", UpTo(2), NotRequired, false);
+        assert_requirements!(":nth-last-child(2n+1)", All, NotRequired, false);
+        assert_requirements!(":not(:only-child)", UpTo(1), NotRequired, false);
+        assert_requirements!(":last-of-type", NotRequired, UpTo(1), false);
+        assert_requirements!(":nth-last-of-type(odd)", NotRequired, All, false);
+        assert_requirements!(":only-of-type:empty", NotRequired, UpTo(1), true);
+        assert_requirements!(
+            ":nth-last-child(5):nth-last-child(even):empty",
+            All,
+            NotRequired,
+            true
+        );
+    }
+
+    #[test]
+    fn has_requirements() {
+        let requirements = requirements("div:has(> img, + p):not(:has(a))");
+
+        assert_eq!(requirements.has_matchers.len(), 3);
+
+        assert_eq!(
+            requirements
+                .has_matchers
+                .iter()
+                .filter(|m| m.matches_siblings)
+                .count(),
+            1
+        );
+
+        let mut indices = requirements
+            .has_matchers
+            .iter()
+            .map(|m| m.idx)
+            .collect::<Vec<_>>();
+
+        indices.sort_unstable();
+
+        assert_eq!(indices, [0, 1, 2]);
+    }
+}
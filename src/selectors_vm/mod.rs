@@ -1,25 +1,40 @@
+mod ancestors;
 mod ast;
 mod attribute_matcher;
 mod compiler;
+mod custom_pseudo_class;
 mod error;
+mod lookahead;
 mod parser;
 mod program;
 mod stack;
+mod state_pseudo_class;
 
+use self::lookahead::VmLookahead;
 use self::program::AddressRange;
 use self::stack::StackDirective;
-use crate::html::{LocalName, Namespace};
+use crate::base::Bytes;
+use crate::html::{decode_char_refs, LocalName, Namespace};
 use crate::memory::{MemoryLimitExceededError, SharedMemoryLimiter};
 use crate::transform_stream::AuxStartTagInfo;
 use encoding_rs::Encoding;
+use std::mem;
+use std::sync::Arc;
 
+pub use self::ancestors::{Ancestor, Ancestors, TreePosition};
 pub use self::ast::*;
 pub use self::attribute_matcher::AttributeMatcher;
-pub use self::compiler::Compiler;
+pub use self::compiler::{CompilationCtx, Compiler};
+pub use self::custom_pseudo_class::{CustomPseudoClasses, PseudoClassElement};
 pub use self::error::SelectorError;
+pub use self::lookahead::{
+    LookaheadFacts, LookaheadFactsMap, LookaheadFactsScanner, LookaheadRequirements, LookaheadState,
+};
 pub use self::parser::Selector;
 pub use self::program::{ExecutionBranch, Program, TryExecResult};
-pub use self::stack::{ElementData, Stack, StackItem, ChildCounter};
+pub use self::stack::{
+    ChildCounter, ElementData, InheritedAttributes, SiblingJumps, Stack, StackItem,
+};
 
 pub struct MatchInfo<P> {
     pub payload: P,
@@ -28,10 +43,11 @@ pub struct MatchInfo<P> {
 
 pub type AuxStartTagInfoRequest<E, P> = Box<
     dyn FnOnce(
-        &mut SelectorMatchingVm<E>,
-        AuxStartTagInfo,
-        &mut dyn FnMut(MatchInfo<P>),
-    ) -> Result<(), MemoryLimitExceededError>,
+            &mut SelectorMatchingVm<E>,
+            AuxStartTagInfo,
+            &mut dyn FnMut(MatchInfo<P>),
+        ) -> Result<(), MemoryLimitExceededError>
+        + Send,
 >;
 
 pub enum VmError<E: ElementData, MatchPayload> {
@@ -69,10 +85,16 @@ struct Bailout<T> {
 pub struct SelectorState<'i> {
     pub cumulative: &'i ChildCounter,
     pub typed: Option<&'i ChildCounter>,
+    pub ns: Namespace,
+    /// Inherited attributes of the parent element.
+    pub inherited_attributes: &'i InheritedAttributes,
+    pub lookahead: LookaheadState<'i>,
 }
 
 struct ExecutionCtx<'i, E: ElementData> {
     stack_item: StackItem<'i, E>,
+    sibling_jumps: SiblingJumps,
+    lookahead_requirements: LookaheadRequirements,
     with_content: bool,
     ns: Namespace,
 }
@@ -82,6 +104,8 @@ impl<'i, E: ElementData> ExecutionCtx<'i, E> {
     pub fn new(local_name: LocalName<'i>, ns: Namespace) -> Self {
         ExecutionCtx {
             stack_item: StackItem::new(local_name),
+            sibling_jumps: SiblingJumps::default(),
+            lookahead_requirements: LookaheadRequirements::default(),
             with_content: true,
             ns,
         }
@@ -92,6 +116,9 @@ impl<'i, E: ElementData> ExecutionCtx<'i, E> {
         branch: &ExecutionBranch<E::MatchPayload>,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) {
+        self.lookahead_requirements
+            .merge(&branch.lookahead_requirements);
+
         for &payload in branch.matched_payload.iter() {
             let element_payload = self.stack_item.element_data.matched_payload_mut();
 
@@ -116,12 +143,26 @@ impl<'i, E: ElementData> ExecutionCtx<'i, E> {
                     .push(hereditary_jumps.to_owned());
             }
         }
+
+        // NOTE: siblings don't depend on the element's content, so
+        // we record sibling jumps for void and self-closing elements as well.
+        if let Some(ref next_sibling_jumps) = branch.next_sibling_jumps {
+            self.sibling_jumps.next.push(next_sibling_jumps.to_owned());
+        }
+
+        if let Some(ref later_sibling_jumps) = branch.later_sibling_jumps {
+            self.sibling_jumps
+                .later
+                .push(later_sibling_jumps.to_owned());
+        }
     }
 
     #[inline]
     pub fn into_owned(self) -> ExecutionCtx<'static, E> {
         ExecutionCtx {
             stack_item: self.stack_item.into_owned(),
+            sibling_jumps: self.sibling_jumps,
+            lookahead_requirements: self.lookahead_requirements,
             with_content: self.with_content,
             ns: self.ns,
         }
@@ -134,27 +175,134 @@ macro_rules! aux_info_request {
     };
 }
 
+/// The settings of the ancestors view.
+struct AncestorTracking {
+    encoding: &'static Encoding,
+    /// Lowercased names of the attributes that are retained for the ancestors.
+    retained_attributes: Arc<[Bytes<'static>]>,
+}
+
+impl AncestorTracking {
+    fn retain_attributes(&self, attr_matcher: &AttributeMatcher) -> Vec<(String, String)> {
+        self.retained_attributes
+            .iter()
+            .filter_map(|name| {
+                attr_matcher.get_raw_value(name).map(|value| {
+                    (
+                        name.as_string(self.encoding),
+                        decode_char_refs(&value.as_string(self.encoding), true).into_owned(),
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
 pub struct SelectorMatchingVm<E: ElementData> {
-    program: Program<E::MatchPayload>,
+    program: Arc<Program<E::MatchPayload>>,
     stack: Stack<E>,
+    lookahead: VmLookahead,
+    ancestor_tracking: Option<AncestorTracking>,
+    /// The position of the element of the last start tag.
+    element_position: TreePosition,
 }
 
 impl<E: ElementData> SelectorMatchingVm<E> {
-    #[inline]
+    #[cfg(test)]
     pub fn new(
         ast: Ast<E::MatchPayload>,
-        encoding: &'static Encoding,
+        ctx: CompilationCtx,
+        memory_limiter: SharedMemoryLimiter,
+    ) -> Self {
+        let program = Compiler::with_ctx(ctx).compile(ast);
+
+        Self::with_program(Arc::new(program), memory_limiter)
+    }
+
+    #[inline]
+    pub fn with_program(
+        program: Arc<Program<E::MatchPayload>>,
         memory_limiter: SharedMemoryLimiter,
     ) -> Self {
-        let program = Compiler::new(encoding).compile(ast);
         let enable_nth_of_type = program.enable_nth_of_type;
 
         SelectorMatchingVm {
             program,
             stack: Stack::new(memory_limiter, enable_nth_of_type),
+            lookahead: VmLookahead::Disabled,
+            ancestor_tracking: None,
+            element_position: TreePosition::default(),
         }
     }
 
+    /// Enables tracking of the ancestors of the elements and the values of
+    /// the given attributes of the ancestors.
+    #[inline]
+    pub fn enable_ancestor_tracking(
+        &mut self,
+        encoding: &'static Encoding,
+        retained_attributes: Arc<[Bytes<'static>]>,
+    ) {
+        self.ancestor_tracking = Some(AncestorTracking {
+            encoding,
+            retained_attributes,
+        });
+    }
+
+    /// Returns the VM to the state of the beginning of a document, keeping
+    /// the allocations of the stack.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.stack.reset();
+        self.lookahead.reset();
+        self.element_position = TreePosition::default();
+    }
+
+    /// Returns the position of the element of the last start tag.
+    #[inline]
+    pub fn element_position(&self) -> &TreePosition {
+        &self.element_position
+    }
+
+    /// Returns the position of the content (e.g. text or comments) inside the current element.
+    #[inline]
+    pub fn content_position(&self) -> TreePosition {
+        self.stack.position()
+    }
+
+    /// Returns `true` if the attributes of every element that is pushed
+    /// to the stack should be examined.
+    #[inline]
+    fn requires_attributes_of_open_elements(&self) -> bool {
+        self.program.enable_inherited_attributes
+            || matches!(&self.ancestor_tracking, Some(t) if !t.retained_attributes.is_empty())
+    }
+
+    /// Returns `true` if some of the selectors can't be matched without looking
+    /// ahead in the input (see [`LookaheadFactsScanner`]).
+    #[inline]
+    pub fn requires_lookahead(&self) -> bool {
+        self.program.requires_lookahead
+    }
+
+    /// Returns the facts collected by the [`LookaheadFactsScanner`] that haven't been used yet,
+    /// if the VM resolves lookahead expressions with the facts.
+    #[inline]
+    pub fn lookahead_facts_mut(&mut self) -> Option<&mut LookaheadFactsMap> {
+        self.lookahead.facts_mut()
+    }
+
+    #[inline]
+    fn open_element_count(&self) -> usize {
+        self.stack.items().len()
+    }
+
+    #[inline]
+    fn build_state<'i>(&'i self, local_name: &LocalName<'i>, ns: Namespace) -> SelectorState<'i> {
+        self.stack
+            .build_state(local_name, ns, self.lookahead.state())
+    }
+
     pub fn exec_for_start_tag(
         &mut self,
         local_name: LocalName,
@@ -164,6 +312,9 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         use StackDirective::*;
 
         self.stack.add_child(&local_name);
+        self.lookahead.start_element();
+
+        self.element_position = self.stack.position();
 
         let mut ctx = ExecutionCtx::new(local_name, ns);
 
@@ -173,6 +324,18 @@ impl<E: ElementData> SelectorMatchingVm<E> {
                 self.exec_without_attrs(ctx, match_handler)
             }
             PushIfNotSelfClosing => {
+                let mut ctx = ctx.into_owned();
+
+                aux_info_request!(move |this, aux_info, match_handler| {
+                    ctx.with_content = !aux_info.self_closing;
+
+                    this.exec_after_immediate_aux_info_request(ctx, ns, aux_info, match_handler)
+                })
+            }
+            // NOTE: inherited and retained attributes of the element can be determined
+            // only from its attributes, so we need to request them for every element that
+            // we push to the stack.
+            Push if self.requires_attributes_of_open_elements() => {
                 let ctx = ctx.into_owned();
 
                 aux_info_request!(move |this, aux_info, match_handler| this
@@ -204,9 +367,24 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         aux_info: AuxStartTagInfo,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) -> Result<(), MemoryLimitExceededError> {
-        let attr_matcher = AttributeMatcher::new(aux_info.input, aux_info.attr_buffer, ns);
+        let attr_matcher = AttributeMatcher::new(
+            aux_info.input,
+            aux_info.name,
+            aux_info.attr_buffer,
+            ns,
+            self.program.encoding,
+        );
+
+        if self.program.enable_inherited_attributes {
+            ctx.stack_item.inherited_attributes = self
+                .stack
+                .parent_inherited_attributes()
+                .for_child(&attr_matcher);
+        }
 
-        ctx.with_content = !aux_info.self_closing;
+        if let Some(ref tracking) = self.ancestor_tracking {
+            ctx.stack_item.retained_attributes = tracking.retain_attributes(&attr_matcher);
+        }
 
         self.exec_instr_set_with_attrs(
             &self.program.entry_points,
@@ -225,14 +403,41 @@ impl<E: ElementData> SelectorMatchingVm<E> {
             match_handler,
         );
 
+        self.exec_sibling_jumps_with_attrs(
+            &attr_matcher,
+            &mut ctx,
+            JumpPtr::default(),
+            match_handler,
+        );
+
+        self.complete_execution(ctx)
+    }
+
+    #[inline]
+    fn complete_execution(&mut self, ctx: ExecutionCtx<E>) -> Result<(), MemoryLimitExceededError> {
+        self.stack.add_sibling_jumps(ctx.sibling_jumps)?;
+        self.lookahead.complete_element(ctx.lookahead_requirements);
+
         if ctx.with_content {
-            self.stack.push_item(ctx.stack_item)?;
+            let mut item = ctx.stack_item.into_owned();
+
+            if let Some(ref tracking) = self.ancestor_tracking {
+                item.ancestor = Some(Arc::new(Ancestor::new(
+                    item.local_name.clone(),
+                    ctx.ns,
+                    tracking.encoding,
+                    mem::take(&mut item.retained_attributes),
+                    self.element_position.clone(),
+                )));
+            }
+
+            self.stack.push_item(item)?;
         }
 
         Ok(())
     }
 
-    fn bailout<T: 'static>(
+    fn bailout<T: Send + 'static>(
         ctx: ExecutionCtx<E>,
         bailout: Bailout<T>,
         recovery_point_handler: RecoveryPointHandler<T, E, E::MatchPayload>,
@@ -240,7 +445,13 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         let mut ctx = ctx.into_owned();
 
         aux_info_request!(move |this, aux_info, match_handler| {
-            let attr_matcher = AttributeMatcher::new(aux_info.input, aux_info.attr_buffer, ctx.ns);
+            let attr_matcher = AttributeMatcher::new(
+                aux_info.input,
+                aux_info.name,
+                aux_info.attr_buffer,
+                ctx.ns,
+                this.program.encoding,
+            );
 
             this.complete_instr_execution_with_attrs(
                 bailout.at_addr,
@@ -257,11 +468,7 @@ impl<E: ElementData> SelectorMatchingVm<E> {
                 match_handler,
             );
 
-            if ctx.with_content {
-                this.stack.push_item(ctx.stack_item)?;
-            }
-
-            Ok(())
+            this.complete_execution(ctx)
         })
     }
 
@@ -288,6 +495,8 @@ impl<E: ElementData> SelectorMatchingVm<E> {
             HereditaryJumpPtr::default(),
             match_handler,
         );
+
+        self.exec_sibling_jumps_with_attrs(attr_matcher, ctx, JumpPtr::default(), match_handler);
     }
 
     fn recover_after_bailout_in_jumps(
@@ -305,9 +514,10 @@ impl<E: ElementData> SelectorMatchingVm<E> {
             HereditaryJumpPtr::default(),
             match_handler,
         );
+
+        self.exec_sibling_jumps_with_attrs(attr_matcher, ctx, JumpPtr::default(), match_handler);
     }
 
-    #[inline]
     fn recover_after_bailout_in_hereditary_jumps(
         &mut self,
         ctx: &mut ExecutionCtx<'static, E>,
@@ -316,6 +526,18 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) {
         self.exec_hereditary_jumps_with_attrs(attr_matcher, ctx, recovery_point, match_handler);
+        self.exec_sibling_jumps_with_attrs(attr_matcher, ctx, JumpPtr::default(), match_handler);
+    }
+
+    #[inline]
+    fn recover_after_bailout_in_sibling_jumps(
+        &mut self,
+        ctx: &mut ExecutionCtx<'static, E>,
+        attr_matcher: &AttributeMatcher,
+        recovery_point: JumpPtr,
+        match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
+    ) {
+        self.exec_sibling_jumps_with_attrs(attr_matcher, ctx, recovery_point, match_handler);
     }
 
     fn exec_without_attrs(
@@ -339,13 +561,12 @@ impl<E: ElementData> SelectorMatchingVm<E> {
             return Self::bailout(ctx, b, Self::recover_after_bailout_in_hereditary_jumps);
         }
 
-        if ctx.with_content {
-            self.stack
-                .push_item(ctx.stack_item.into_owned())
-                .map_err(VmError::MemoryLimitExceeded)
-        } else {
-            Ok(())
+        if let Err(b) = self.try_exec_sibling_jumps_without_attrs(&mut ctx, match_handler) {
+            return Self::bailout(ctx, b, Self::recover_after_bailout_in_sibling_jumps);
         }
+
+        self.complete_execution(ctx)
+            .map_err(VmError::MemoryLimitExceeded)
     }
 
     #[inline]
@@ -356,7 +577,7 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         ctx: &mut ExecutionCtx<E>,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) {
-        let state = self.stack.build_state(&ctx.stack_item.local_name);
+        let state = self.build_state(&ctx.stack_item.local_name, ctx.ns);
         if let Some(branch) =
             self.program.instructions[addr].complete_exec_with_attrs(&state, &attr_matcher)
         {
@@ -372,20 +593,20 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) -> Result<(), Bailout<usize>> {
         let start = addr_range.start;
-        let state = self.stack.build_state(&ctx.stack_item.local_name);
+        let state = self.build_state(&ctx.stack_item.local_name, ctx.ns);
 
         for addr in addr_range {
-            match self.program.instructions[addr].try_exec_without_attrs(&state, &ctx.stack_item.local_name) {
-                TryExecResult::Branch(branch) => {
-                    ctx.add_execution_branch(branch, match_handler)
-                }
+            match self.program.instructions[addr]
+                .try_exec_without_attrs(&state, &ctx.stack_item.local_name)
+            {
+                TryExecResult::Branch(branch) => ctx.add_execution_branch(branch, match_handler),
                 TryExecResult::AttributesRequired => {
                     return Err(Bailout {
                         at_addr: addr,
                         recovery_point: addr - start + 1,
                     });
-                },
-                _ => ()
+                }
+                _ => (),
             }
         }
 
@@ -401,7 +622,7 @@ impl<E: ElementData> SelectorMatchingVm<E> {
         offset: usize,
         match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
     ) {
-        let state = self.stack.build_state(&ctx.stack_item.local_name);
+        let state = self.build_state(&ctx.stack_item.local_name, ctx.ns);
         for addr in addr_range.start + offset..addr_range.end {
             let instr = &self.program.instructions[addr];
 
@@ -535,11 +756,51 @@ impl<E: ElementData> SelectorMatchingVm<E> {
             }
         }
     }
+
+    fn try_exec_sibling_jumps_without_attrs(
+        &self,
+        ctx: &mut ExecutionCtx<E>,
+        match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
+    ) -> Result<(), Bailout<JumpPtr>> {
+        for (i, jumps) in self.stack.sibling_jumps().enumerate() {
+            self.try_exec_instr_set_without_attrs(jumps.clone(), ctx, match_handler)
+                .map_err(|b| Bailout {
+                    at_addr: b.at_addr,
+                    recovery_point: JumpPtr {
+                        instr_set_idx: i,
+                        offset: b.recovery_point,
+                    },
+                })?;
+        }
+
+        Ok(())
+    }
+
+    fn exec_sibling_jumps_with_attrs(
+        &self,
+        attr_matcher: &AttributeMatcher,
+        ctx: &mut ExecutionCtx<E>,
+        ptr: JumpPtr,
+        match_handler: &mut dyn FnMut(MatchInfo<E::MatchPayload>),
+    ) {
+        // NOTE: find pointed jumps instruction set and execute it with the offset.
+        let mut sibling_jumps = self.stack.sibling_jumps().skip(ptr.instr_set_idx);
+
+        if let Some(ptr_jumps) = sibling_jumps.next() {
+            self.exec_instr_set_with_attrs(ptr_jumps, attr_matcher, ctx, ptr.offset, match_handler);
+
+            // NOTE: execute remaining jumps instruction sets as usual.
+            for jumps in sibling_jumps {
+                self.exec_instr_set_with_attrs(jumps, attr_matcher, ctx, 0, match_handler);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::base::Bytes;
     use crate::errors::RewritingError;
     use crate::html::Namespace;
     use crate::memory::MemoryLimiter;
@@ -547,7 +808,7 @@ mod tests {
     use crate::transform_stream::{
         StartTagHandlingResult, TransformController, TransformStream, TransformStreamSettings,
     };
-    use encoding_rs::UTF_8;
+    use encoding_rs::{Encoding, UTF_8};
     use hashbrown::{HashMap, HashSet};
 
     struct Expectation {
@@ -626,6 +887,7 @@ mod tests {
             encoding,
             memory_limiter: MemoryLimiter::new_shared(2048),
             strict: true,
+            lookahead: None,
         });
 
         transform_stream.write(&*html).unwrap();
@@ -661,7 +923,7 @@ mod tests {
 
             let memory_limiter = MemoryLimiter::new_shared(2048);
             let vm: SelectorMatchingVm<TestElementData> =
-                SelectorMatchingVm::new(ast, UTF_8, memory_limiter);
+                SelectorMatchingVm::new(ast, CompilationCtx::new(UTF_8), memory_limiter);
 
             vm
         }};
@@ -692,6 +954,7 @@ mod tests {
                                         &mut $vm,
                                         AuxStartTagInfo {
                                             input,
+                                            name: Bytes::from_str(&t.name(), UTF_8),
                                             attr_buffer,
                                             self_closing: t.self_closing(),
                                         },
@@ -1728,4 +1991,278 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn is_and_where() {
+        let mut vm = create_vm!(&[
+            ":is(article, section) > :is(h1, h2)",
+            ":where(ul li, ol) span"
+        ]);
+
+        macro_rules! assert_start_tag {
+            ($html:expr, $matched_payload:expr) => {
+                exec_for_start_tag_and_assert!(
+                    vm,
+                    $html,
+                    Namespace::Html,
+                    Expectation {
+                        should_bailout: false,
+                        should_match_with_content: true,
+                        matched_payload: $matched_payload,
+                    }
+                );
+            };
+        }
+
+        // Stack after:
+        // - <section>
+        // - <h2> (0)
+        assert_start_tag!("<section>", set![]);
+        assert_start_tag!("<h2>", set![0]);
+
+        // Stack after:
+        // - <section>
+        exec_for_end_tag_and_assert!(vm, "</h2>", map![(0, 1)]);
+
+        // Stack after:
+        // - <section>
+        // - <ul>
+        // - <li>
+        // - <span> (1)
+        assert_start_tag!("<ul>", set![]);
+        assert_start_tag!("<li>", set![]);
+        assert_start_tag!("<span>", set![1]);
+
+        // Stack after:
+        // - <section>
+        exec_for_end_tag_and_assert!(vm, "</ul>", map![(1, 1)]);
+
+        // Stack after:
+        // - <section>
+        // - <ol>
+        // - <div>
+        // - <span> (1)
+        // - <h1>
+        assert_start_tag!("<ol>", set![]);
+        assert_start_tag!("<div>", set![]);
+        assert_start_tag!("<span>", set![1]);
+        assert_start_tag!("<h1>", set![]);
+    }
+
+    #[test]
+    fn complex_is_and_where_arguments() {
+        let mut vm = create_vm!(&["ul > :is(nav a)", "div :where(header h1)"]);
+
+        macro_rules! assert_start_tag {
+            ($html:expr, $matched_payload:expr) => {
+                exec_for_start_tag_and_assert!(
+                    vm,
+                    $html,
+                    Namespace::Html,
+                    Expectation {
+                        should_bailout: false,
+                        should_match_with_content: true,
+                        matched_payload: $matched_payload,
+                    }
+                );
+            };
+        }
+
+        // Stack after:
+        // - <nav>
+        // - <ul>
+        // - <a> (0)
+        assert_start_tag!("<nav>", set![]);
+        assert_start_tag!("<ul>", set![]);
+        assert_start_tag!("<a>", set![0]);
+
+        // Stack after:
+        // - <nav>
+        // - <ul>
+        // - <li>
+        // - <a>
+        exec_for_end_tag_and_assert!(vm, "</a>", map![(0, 1)]);
+        assert_start_tag!("<li>", set![]);
+        assert_start_tag!("<a>", set![]);
+
+        // Stack after:
+        // - <ul>
+        // - <a>
+        exec_for_end_tag_and_assert!(vm, "</nav>", map![]);
+        assert_start_tag!("<ul>", set![]);
+        assert_start_tag!("<a>", set![]);
+
+        // Stack after:
+        // - <header>
+        // - <div>
+        // - <h1> (1)
+        exec_for_end_tag_and_assert!(vm, "</ul>", map![]);
+        assert_start_tag!("<header>", set![]);
+        assert_start_tag!("<div>", set![]);
+        assert_start_tag!("<h1>", set![1]);
+
+        // Stack after:
+        // - <header>
+        // - <h1>
+        exec_for_end_tag_and_assert!(vm, "</div>", map![(1, 1)]);
+        assert_start_tag!("<h1>", set![]);
+
+        // Stack after:
+        // - <div>
+        // - <header>
+        // - <h1> (1)
+        exec_for_end_tag_and_assert!(vm, "</header>", map![]);
+        assert_start_tag!("<div>", set![]);
+        assert_start_tag!("<header>", set![]);
+        assert_start_tag!("<h1>", set![1]);
+    }
+
+    #[test]
+    fn sibling_jumps() {
+        let mut vm = create_vm!(&["div + span", "div ~ p", "img + .c1"]);
+
+        macro_rules! assert_start_tag {
+            ($html:expr, $should_bailout:expr, $should_match_with_content:expr, $matched_payload:expr) => {
+                exec_for_start_tag_and_assert!(
+                    vm,
+                    $html,
+                    Namespace::Html,
+                    Expectation {
+                        should_bailout: $should_bailout,
+                        should_match_with_content: $should_match_with_content,
+                        matched_payload: $matched_payload,
+                    }
+                );
+            };
+        }
+
+        // Stack after:
+        // - <body>
+        assert_start_tag!("<body>", false, true, set![]);
+
+        // Stack after:
+        // - <body>
+        // - <div>
+        assert_start_tag!("<div>", false, true, set![]);
+
+        // Stack after:
+        // - <body>
+        exec_for_end_tag_and_assert!(vm, "</div>", map![]);
+
+        // Stack after:
+        // - <body>
+        // - <span> (0)
+        assert_start_tag!("<span>", false, true, set![0]);
+
+        // Stack after:
+        // - <body>
+        exec_for_end_tag_and_assert!(vm, "</span>", map![(0, 1)]);
+
+        // NOTE: not an immediate sibling of the <div> anymore.
+        // Stack after:
+        // - <body>
+        // - <span>
+        assert_start_tag!("<span>", false, true, set![]);
+
+        // Stack after:
+        // - <body>
+        exec_for_end_tag_and_assert!(vm, "</span>", map![]);
+
+        // Stack after:
+        // - <body>
+        // - <p> (1)
+        assert_start_tag!("<p>", false, true, set![1]);
+
+        // Stack after:
+        // - <body>
+        exec_for_end_tag_and_assert!(vm, "</p>", map![(1, 1)]);
+
+        // Void element.
+        // Stack after:
+        // - <body>
+        assert_start_tag!("<img>", false, false, set![]);
+
+        // Stack after:
+        // - <body>
+        // - <div class=c1> (2)
+        assert_start_tag!("<div class=c1>", true, true, set![2]);
+
+        // Stack after:
+        // - <body>
+        exec_for_end_tag_and_assert!(vm, "</div>", map![(2, 1)]);
+
+        // Stack after:
+        // - <body>
+        // - <div>
+        assert_start_tag!("<div>", false, true, set![]);
+
+        // NOTE: first child of the <div>, so it doesn't have preceding siblings.
+        // Stack after:
+        // - <body>
+        // - <div>
+        // - <span>
+        assert_start_tag!("<span>", false, true, set![]);
+
+        // Stack after:
+        // - <body>
+        exec_for_end_tag_and_assert!(vm, "</div>", map![]);
+
+        // Stack after:
+        // - <body>
+        // - <span> (0)
+        assert_start_tag!("<span>", false, true, set![0]);
+
+        // Stack after: empty
+        exec_for_end_tag_and_assert!(vm, "</body>", map![(0, 1)]);
+    }
+
+    #[test]
+    fn bailout_in_sibling_jumps() {
+        let mut vm = create_vm!(&["div + .c1", "div ~ span", "div ~ #foo"]);
+
+        // Stack after:
+        // - <div>
+        exec_for_start_tag_and_assert!(
+            vm,
+            "<div>",
+            Namespace::Html,
+            Expectation {
+                should_bailout: false,
+                should_match_with_content: true,
+                matched_payload: set![],
+            }
+        );
+
+        // Stack after: empty
+        exec_for_end_tag_and_assert!(vm, "</div>", map![]);
+
+        // Stack after:
+        // - <span class=c1 id=foo> (0, 1, 2)
+        exec_for_start_tag_and_assert!(
+            vm,
+            "<span class=c1 id=foo>",
+            Namespace::Html,
+            Expectation {
+                should_bailout: true,
+                should_match_with_content: true,
+                matched_payload: set![0, 1, 2],
+            }
+        );
+
+        // Stack after: empty
+        exec_for_end_tag_and_assert!(vm, "</span>", map![(0, 1), (1, 1), (2, 1)]);
+
+        // Stack after:
+        // - <span class=c1> (1)
+        exec_for_start_tag_and_assert!(
+            vm,
+            "<span class=c1>",
+            Namespace::Html,
+            Expectation {
+                should_bailout: true,
+                should_match_with_content: true,
+                matched_payload: set![1],
+            }
+        );
+    }
 }
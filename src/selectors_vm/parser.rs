@@ -1,14 +1,17 @@
+use super::state_pseudo_class::StatePseudoClass;
 use super::SelectorError;
-use crate::html::Namespace;
-use cssparser::{Parser as CssParser, ParserInput, ToCss};
+use cssparser::{
+    serialize_identifier, serialize_string, CowRcStr, ParseError, Parser as CssParser, ParserInput,
+    SourceLocation, ToCss, Token,
+};
 use selectors::parser::{
-    Combinator, Component, NonTSPseudoClass, Parser, PseudoElement, SelectorImpl, SelectorList,
-    SelectorParseErrorKind,
+    Combinator, Component, NonTSPseudoClass, Parser, PseudoElement, Selector as ParsedSelector,
+    SelectorImpl, SelectorList, SelectorParseErrorKind,
 };
 use std::fmt;
 use std::str::FromStr;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SelectorImplDescriptor;
 
 impl SelectorImpl for SelectorImplDescriptor {
@@ -17,11 +20,13 @@ impl SelectorImpl for SelectorImplDescriptor {
     type ClassName = String;
     type LocalName = String;
     type NamespacePrefix = String;
-    type NamespaceUrl = Namespace;
-    type BorrowedNamespaceUrl = Namespace;
+    // NOTE: namespace prefixes are resolved by the compiler, since they are registered in the
+    // rewriter settings. So, the parser uses the prefix itself in place of the namespace URL.
+    type NamespaceUrl = String;
+    type BorrowedNamespaceUrl = String;
     type BorrowedLocalName = String;
 
-    type NonTSPseudoClass = NonTSPseudoClassStub;
+    type NonTSPseudoClass = PseudoClass;
     type PseudoElement = PseudoElementStub;
 
     type ExtraMatchingData = ();
@@ -40,29 +45,195 @@ impl PseudoElement for PseudoElementStub {
     type Impl = SelectorImplDescriptor;
 }
 
-#[derive(PartialEq, Eq, Clone, Debug, Hash)]
-pub enum NonTSPseudoClassStub {}
+/// A selector that is evaluated relatively to an anchor element (e.g. `> img` in `:has(> img)`).
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RelativeSelector {
+    /// The combinator between the anchor element and the leftmost compound selector.
+    pub combinator: Combinator,
+    pub selector: ParsedSelector<SelectorImplDescriptor>,
+}
+
+impl RelativeSelector {
+    fn parse<'i, 't>(
+        parser: &SelectorsParser,
+        input: &mut CssParser<'i, 't>,
+    ) -> Result<Self, ParseError<'i, SelectorParseErrorKind<'i>>> {
+        let combinator = input
+            .try_parse(|input| match input.next() {
+                Ok(&Token::Delim('>')) => Ok(Combinator::Child),
+                Ok(&Token::Delim('+')) => Ok(Combinator::NextSibling),
+                Ok(&Token::Delim('~')) => Ok(Combinator::LaterSibling),
+                _ => Err(()),
+            })
+            .unwrap_or(Combinator::Descendant);
+
+        Ok(RelativeSelector {
+            combinator,
+            selector: ParsedSelector::parse(parser, input)?,
+        })
+    }
+
+    /// Returns `true` if the selector can match elements outside of the anchor element.
+    #[inline]
+    pub fn matches_siblings(&self) -> bool {
+        matches!(
+            self.combinator,
+            Combinator::NextSibling | Combinator::LaterSibling
+        )
+    }
+}
+
+impl ToCss for RelativeSelector {
+    fn to_css<W: fmt::Write>(&self, dest: &mut W) -> fmt::Result {
+        match self.combinator {
+            Combinator::Child => dest.write_str("> ")?,
+            Combinator::NextSibling => dest.write_str("+ ")?,
+            Combinator::LaterSibling => dest.write_str("~ ")?,
+            _ => (),
+        }
+
+        self.selector.to_css(dest)
+    }
+}
 
-impl NonTSPseudoClass for NonTSPseudoClassStub {
+/// The directionality of an element.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum PseudoClass {
+    Has(Box<[RelativeSelector]>),
+    Is(Box<[ParsedSelector<SelectorImplDescriptor>]>),
+    /// Same as `Is`, the only difference is the specificity which doesn't matter for the rewriter.
+    Where(Box<[ParsedSelector<SelectorImplDescriptor>]>),
+    /// Language ranges that are matched against the inherited language of an element.
+    Lang(Box<[String]>),
+    Dir(Direction),
+    /// A pseudo-class that is determined by the attributes of an element.
+    State(StatePseudoClass),
+    /// A user-defined pseudo-class with the lowercased name. The name is resolved to
+    /// the predicate registered in `CustomPseudoClasses` on compilation.
+    Custom(String),
+}
+
+/// Standard pseudo-classes that are not supported by the rewriter. These names can't be used
+/// for the user-defined pseudo-classes.
+const UNSUPPORTED_STANDARD_PSEUDO_CLASSES: [&str; 33] = [
+    "active",
+    "autofill",
+    "blank",
+    "current",
+    "defined",
+    "drop",
+    "enabled",
+    "first",
+    "focus",
+    "focus-visible",
+    "focus-within",
+    "fullscreen",
+    "future",
+    "host",
+    "hover",
+    "in-range",
+    "indeterminate",
+    "invalid",
+    "left",
+    "link",
+    "local-link",
+    "modal",
+    "out-of-range",
+    "past",
+    "paused",
+    "playing",
+    "right",
+    "target",
+    "target-within",
+    "user-invalid",
+    "user-valid",
+    "valid",
+    "visited",
+];
+
+impl NonTSPseudoClass for PseudoClass {
     type Impl = SelectorImplDescriptor;
 
     fn is_active_or_hover(&self) -> bool {
-        match *self {}
+        false
     }
 }
 
-impl ToCss for NonTSPseudoClassStub {
-    fn to_css<W: fmt::Write>(&self, _dest: &mut W) -> fmt::Result {
-        match *self {}
+#[inline]
+fn write_selector_list<W: fmt::Write>(
+    name: &str,
+    selectors: &[impl ToCss],
+    dest: &mut W,
+) -> fmt::Result {
+    dest.write_str(name)?;
+    dest.write_str("(")?;
+
+    for (i, selector) in selectors.iter().enumerate() {
+        if i > 0 {
+            dest.write_str(", ")?;
+        }
+
+        selector.to_css(dest)?;
+    }
+
+    dest.write_str(")")
+}
+
+impl ToCss for PseudoClass {
+    fn to_css<W: fmt::Write>(&self, dest: &mut W) -> fmt::Result {
+        match self {
+            PseudoClass::Has(selectors) => write_selector_list(":has", selectors, dest),
+            PseudoClass::Is(selectors) => write_selector_list(":is", selectors, dest),
+            PseudoClass::Where(selectors) => write_selector_list(":where", selectors, dest),
+            PseudoClass::Lang(ranges) => {
+                dest.write_str(":lang(")?;
+
+                for (i, range) in ranges.iter().enumerate() {
+                    if i > 0 {
+                        dest.write_str(", ")?;
+                    }
+
+                    serialize_string(range, dest)?;
+                }
+
+                dest.write_str(")")
+            }
+            PseudoClass::Dir(Direction::Ltr) => dest.write_str(":dir(ltr)"),
+            PseudoClass::Dir(Direction::Rtl) => dest.write_str(":dir(rtl)"),
+            PseudoClass::State(pseudo_class) => {
+                dest.write_str(":")?;
+                dest.write_str(pseudo_class.name())
+            }
+            PseudoClass::Custom(name) => {
+                dest.write_str(":")?;
+                serialize_identifier(name, dest)
+            }
+        }
     }
 }
 
-#[allow(dead_code)]
 struct SelectorsParser;
 
+/// The kind of the selector that contains a component.
+#[derive(PartialEq, Eq, Copy, Clone)]
+enum ComponentContext {
+    Selector,
+    /// An argument of `:has()` that matches the descendants of the anchor element.
+    RelativeSelector,
+    /// An argument of `:has()` that matches the siblings of the anchor element.
+    SiblingRelativeSelector,
+}
+
 impl SelectorsParser {
     fn validate_component(
         component: &Component<SelectorImplDescriptor>,
+        context: ComponentContext,
     ) -> Result<(), SelectorError> {
         // NOTE: always use explicit variants in this match, so we
         // get compile-time error if new component types were added to
@@ -71,11 +242,12 @@ impl SelectorsParser {
         match component {
             Component::Combinator(combinator) => match combinator {
                 // Supported
-                Combinator::Child | Combinator::Descendant => Ok(()),
+                Combinator::Child
+                | Combinator::Descendant
+                | Combinator::NextSibling
+                | Combinator::LaterSibling => Ok(()),
 
                 // Unsupported
-                Combinator::NextSibling => Err(SelectorError::UnsupportedCombinator('+')),
-                Combinator::LaterSibling => Err(SelectorError::UnsupportedCombinator('~')),
                 Combinator::PseudoElement | Combinator::SlotAssignment => {
                     unreachable!("Pseudo element combinators should be filtered out at this point")
                 }
@@ -88,45 +260,120 @@ impl SelectorsParser {
             | Component::ExplicitNoNamespace
             | Component::ID(_)
             | Component::Class(_)
-            | Component::FirstChild
-            | Component::NthChild(_, _)
-            | Component::FirstOfType
-            | Component::NthOfType(_, _)
+            | Component::Namespace(_, _)
             | Component::AttributeInNoNamespaceExists { .. }
-            | Component::AttributeInNoNamespace { .. } => Ok(()),
+            | Component::AttributeInNoNamespace { .. }
+            | Component::AttributeOther(_) => Ok(()),
 
-            Component::Negation(components) => {
-                components.iter().map(Self::validate_component).collect()
-            }
+            // NOTE: relative selectors are matched starting from the anchor element,
+            // so the sibling indexes of the anchor element and its siblings are unknown.
+            Component::FirstChild
+            | Component::NthChild(_, _)
+            | Component::FirstOfType
+            | Component::NthOfType(_, _) => match context {
+                ComponentContext::Selector | ComponentContext::RelativeSelector => Ok(()),
+                ComponentContext::SiblingRelativeSelector => {
+                    Err(SelectorError::UnsupportedPseudoClassOrElement)
+                }
+            },
 
-            // Unsupported
-            Component::Empty
-            | Component::Host(_)
-            | Component::LastChild
-            | Component::LastOfType
+            // NOTE: relative selectors can't require lookahead on their own.
+            Component::LastChild
             | Component::NthLastChild(_, _)
+            | Component::LastOfType
             | Component::NthLastOfType(_, _)
             | Component::OnlyChild
             | Component::OnlyOfType
+            | Component::Empty => match context {
+                ComponentContext::Selector => Ok(()),
+                ComponentContext::RelativeSelector | ComponentContext::SiblingRelativeSelector => {
+                    Err(SelectorError::UnsupportedPseudoClassOrElement)
+                }
+            },
+
+            Component::Negation(components) => components.iter().try_for_each(|c| {
+                let is_selector_list = matches!(
+                    c,
+                    Component::NonTSPseudoClass(PseudoClass::Is(_))
+                        | Component::NonTSPseudoClass(PseudoClass::Where(_))
+                );
+
+                // NOTE: negated selector lists can't be expressed with compound selectors.
+                if is_selector_list {
+                    Err(SelectorError::UnsupportedPseudoClassOrElement)
+                } else {
+                    Self::validate_component(c, context)
+                }
+            }),
+
+            Component::NonTSPseudoClass(pseudo_class) => match pseudo_class {
+                // NOTE: `:has()` can't be nested.
+                PseudoClass::Has(_) if context != ComponentContext::Selector => {
+                    Err(SelectorError::UnsupportedPseudoClassOrElement)
+                }
+                PseudoClass::Has(selectors) => selectors.iter().try_for_each(|selector| {
+                    let context = if selector.matches_siblings() {
+                        ComponentContext::SiblingRelativeSelector
+                    } else {
+                        ComponentContext::RelativeSelector
+                    };
+
+                    Self::validate_selector(&selector.selector, context)
+                }),
+                PseudoClass::Is(selectors) | PseudoClass::Where(selectors) => {
+                    selectors.iter().try_for_each(|selector| {
+                        let is_complex = selector
+                            .iter_raw_match_order()
+                            .any(|c| matches!(c, Component::Combinator(_)));
+
+                        // NOTE: the compound selectors of a complex argument can match the
+                        // ancestors and the siblings of the anchor element of a relative selector.
+                        if is_complex && context != ComponentContext::Selector {
+                            Err(SelectorError::UnsupportedSyntax)
+                        } else {
+                            Self::validate_selector(selector, context)
+                        }
+                    })
+                }
+                // NOTE: the inherited attributes of the anchor element of a
+                // relative selector are unknown.
+                PseudoClass::Lang(_) | PseudoClass::Dir(_) => match context {
+                    ComponentContext::Selector => Ok(()),
+                    ComponentContext::RelativeSelector
+                    | ComponentContext::SiblingRelativeSelector => {
+                        Err(SelectorError::UnsupportedPseudoClassOrElement)
+                    }
+                },
+                PseudoClass::State(_) | PseudoClass::Custom(_) => Ok(()),
+            },
+
+            // Unsupported
+            Component::Host(_)
             | Component::Root
             | Component::Scope
             | Component::PseudoElement(_)
-            | Component::NonTSPseudoClass(_)
             | Component::Slotted(_) => Err(SelectorError::UnsupportedPseudoClassOrElement),
 
-            Component::DefaultNamespace(_)
-            | Component::Namespace(_, _)
-            | Component::AttributeOther(_) => Err(SelectorError::NamespacedSelector),
+            Component::DefaultNamespace(_) => Err(SelectorError::NamespacedSelector),
         }
     }
 
+    fn validate_selector(
+        selector: &ParsedSelector<SelectorImplDescriptor>,
+        context: ComponentContext,
+    ) -> Result<(), SelectorError> {
+        for component in selector.iter_raw_parse_order_from(0) {
+            Self::validate_component(component, context)?;
+        }
+
+        Ok(())
+    }
+
     fn validate(
         selector_list: SelectorList<SelectorImplDescriptor>,
     ) -> Result<SelectorList<SelectorImplDescriptor>, SelectorError> {
         for selector in selector_list.0.iter() {
-            for component in selector.iter_raw_match_order() {
-                Self::validate_component(component)?;
-            }
+            Self::validate_selector(selector, ComponentContext::Selector)?;
         }
 
         Ok(selector_list)
@@ -146,6 +393,78 @@ impl SelectorsParser {
 impl<'i> Parser<'i> for SelectorsParser {
     type Impl = SelectorImplDescriptor;
     type Error = SelectorParseErrorKind<'i>;
+
+    #[inline]
+    fn namespace_for_prefix(&self, prefix: &String) -> Option<String> {
+        Some(prefix.to_owned())
+    }
+
+    fn parse_non_ts_pseudo_class(
+        &self,
+        location: SourceLocation,
+        name: CowRcStr<'i>,
+    ) -> Result<PseudoClass, ParseError<'i, Self::Error>> {
+        let name_lower = name.to_ascii_lowercase();
+
+        if let Some(pseudo_class) = StatePseudoClass::from_name(&name_lower) {
+            return Ok(PseudoClass::State(pseudo_class));
+        }
+
+        // NOTE: names of the standard pseudo-classes can't be used by the user-defined ones.
+        if UNSUPPORTED_STANDARD_PSEUDO_CLASSES.contains(&name_lower.as_str()) {
+            Err(
+                location.new_custom_error(SelectorParseErrorKind::UnsupportedPseudoClassOrElement(
+                    name,
+                )),
+            )
+        } else {
+            Ok(PseudoClass::Custom(name_lower))
+        }
+    }
+
+    fn parse_non_ts_functional_pseudo_class<'t>(
+        &self,
+        name: CowRcStr<'i>,
+        arguments: &mut CssParser<'i, 't>,
+    ) -> Result<PseudoClass, ParseError<'i, Self::Error>> {
+        if name.eq_ignore_ascii_case("has") {
+            let selectors =
+                arguments.parse_comma_separated(|input| RelativeSelector::parse(self, input))?;
+
+            Ok(PseudoClass::Has(selectors.into_boxed_slice()))
+        } else if name.eq_ignore_ascii_case("is") || name.eq_ignore_ascii_case("where") {
+            let selectors =
+                arguments.parse_comma_separated(|input| ParsedSelector::parse(self, input))?;
+            let selectors = selectors.into_boxed_slice();
+
+            if name.eq_ignore_ascii_case("is") {
+                Ok(PseudoClass::Is(selectors))
+            } else {
+                Ok(PseudoClass::Where(selectors))
+            }
+        } else if name.eq_ignore_ascii_case("lang") {
+            let ranges = arguments.parse_comma_separated(|input| {
+                Ok(input.expect_ident_or_string()?.to_ascii_lowercase())
+            })?;
+
+            Ok(PseudoClass::Lang(ranges.into_boxed_slice()))
+        } else if name.eq_ignore_ascii_case("dir") {
+            let location = arguments.current_source_location();
+            let direction = arguments.expect_ident()?;
+
+            if direction.eq_ignore_ascii_case("ltr") {
+                Ok(PseudoClass::Dir(Direction::Ltr))
+            } else if direction.eq_ignore_ascii_case("rtl") {
+                Ok(PseudoClass::Dir(Direction::Rtl))
+            } else {
+                Err(location.new_unexpected_token_error(Token::Ident(direction.clone())))
+            }
+        } else {
+            Err(arguments.new_custom_error(
+                SelectorParseErrorKind::UnsupportedPseudoClassOrElement(name),
+            ))
+        }
+    }
 }
 
 /// Parsed CSS selector.
@@ -172,14 +491,38 @@ impl<'i> Parser<'i> for SelectorsParser {
 /// ------------------------------ | --------------------------------------------------------------------------------------------------------------------------- |
 /// `*`                            | any element                                                                                                                 |
 /// `E`                            | any element of type `E`                                                                                                     |
+/// <code>ns&#124;E</code>         | an element of type `E` in the namespace registered for the prefix `ns`                                                      |
 /// `E:nth-child(n)`               | an `E` element, the n-th child of its parent                                                                                |
 /// `E:first-child`                | an `E` element, first child of its parent                                                                                   |
 /// `E:nth-of-type(n)`             | an `E` element, the n-th sibling of its type                                                                                |
 /// `E:first-of-type`              | an `E` element, first sibling of its type                                                                                   |
+/// `E:nth-last-child(n)`          | an `E` element, the n-th child of its parent, counting from the last one                                                    |
+/// `E:last-child`                 | an `E` element, last child of its parent                                                                                    |
+/// `E:only-child`                 | an `E` element, only child of its parent                                                                                    |
+/// `E:nth-last-of-type(n)`        | an `E` element, the n-th sibling of its type, counting from the last one                                                    |
+/// `E:last-of-type`               | an `E` element, last sibling of its type                                                                                    |
+/// `E:only-of-type`               | an `E` element, only sibling of its type                                                                                    |
+/// `E:empty`                      | an `E` element that has no children (including text nodes)                                                                  |
+/// `E:has(s)`                     | an `E` element that has an element matching relative selector `s` (e.g. `> F` or `+ F`) after its start tag                 |
+/// `E:is(s)`                      | an `E` element that matches any of the selectors in the list `s`                                                            |
+/// `E:where(s)`                   | same as `E:is(s)`                                                                                                           |
+/// `E:lang(de)`                   | an `E` element in the language `de` (e.g. `de` or `de-CH`), as specified by its own or an ancestor's `lang` attribute       |
+/// `E:dir(rtl)`                   | an `E` element with the right-to-left directionality, as specified by its own or an ancestor's `dir` attribute              |
+/// `E:any-link`                   | an `E` element that is the source anchor of a hyperlink (`a` or `area` with an `href` attribute)                            |
+/// `E:checked`                    | an `E` element that is a checked checkbox or radio button, or a selected `option`                                           |
+/// `E:default`                    | an `E` element that is checked or selected by default, same as `E:checked`                                                  |
+/// `E:disabled`                   | an `E` element that is a form control or a `fieldset` with the `disabled` attribute                                         |
+/// `E:required`                   | an `E` element that is a form control with the `required` attribute                                                         |
+/// `E:optional`                   | an `E` element that is a form control to which the `required` attribute applies, but which doesn't have it                  |
+/// `E:placeholder-shown`          | an `E` element that is an `input` or a `textarea` with a placeholder and without a value                                    |
+/// `E:read-write`                 | an `E` element that is a mutable text field or has the `contenteditable` attribute                                          |
+/// `E:read-only`                  | an `E` element that doesn't match `E:read-write`                                                                            |
+/// `E:foo`                        | an `E` element that matches the predicate of the user-defined pseudo-class `foo`                                            |
 /// `E:not(s)`                     | an `E` element that does not match either compound selector `s`                                                             |
 /// `E.warning`                    | an `E` element belonging to the class `warning`                                                                             |
 /// `E#myid`                       | an `E` element with `ID` equal to `"myid"`.                                                                                 |
 /// `E[foo]`                       | an `E` element with a `foo` attribute                                                                                       |
+/// <code>E[ns&#124;foo]</code>    | an `E` element with a `foo` attribute in the namespace registered for the prefix `ns`                                       |
 /// `E[foo="bar"]`                 | an `E` element whose foo attribute value is exactly equal to `"bar"`                                                        |
 /// `E[foo="bar" i]`               | an `E` element whose foo attribute value is exactly equal to any (ASCII-range) case-permutation of `"bar"`                  |
 /// `E[foo="bar" s]`               | an `E` element whose foo attribute value is exactly and case-sensitively equal to `"bar"`                                   |
@@ -190,14 +533,90 @@ impl<'i> Parser<'i> for SelectorsParser {
 /// <code>E[foo&#124;="en"]</code> | an `E` element whose foo attribute value is a hyphen-separated list of values beginning with `"en"`                         |
 /// `E F`                          | an `F` element descendant of an `E` element                                                                                 |
 /// `E > F`                        | an `F` element child of an `E` element                                                                                      |
+/// `E + F`                        | an `F` element immediately preceded by an `E` element                                                                       |
+/// `E ~ F`                        | an `F` element preceded by an `E` element                                                                                   |
+///
+/// Pseudo-classes that depend on the content that follows the start tag of an element
+/// (`:nth-last-child(n)`, `:last-child`, `:only-child`, `:nth-last-of-type(n)`, `:last-of-type`,
+/// `:only-of-type`, `:empty` and `:has(s)`) require the rewriter to buffer the input. Selectors
+/// with such pseudo-classes, including the negated ones (e.g. `:not(:last-child)`), can be used
+/// only if buffering is enabled with the [`lookahead_mode`] setting. Otherwise, the construction
+/// of the rewriter fails.
+///
+/// The relative selectors of `:has(s)` can't contain these pseudo-classes. Relative selectors
+/// that start with the `+` or `~` combinator can't contain `:nth-child(n)`, `:first-child`,
+/// `:nth-of-type(n)` and `:first-of-type` pseudo-classes either.
+///
+/// The selectors of `:is(s)` and `:where(s)` can't contain combinators in the relative selectors
+/// of `:has(s)`.
+///
+/// Namespace prefixes used in type and attribute selectors should be registered with the
+/// [`namespace_prefixes`] setting. Selectors with an unregistered prefix never match.
+///
+/// The `:lang(l)` and `:dir(d)` pseudo-classes require the attributes of every element, so they
+/// come with a performance cost. They can't be used in the relative selectors of `:has(s)`.
+/// Elements with `dir="auto"` match neither `:dir(ltr)` nor `:dir(rtl)`.
+///
+/// The state pseudo-classes (`:checked`, `:disabled`, `:read-only`, etc.) are determined only by
+/// the element's tag name and attributes. For example, `:disabled` doesn't match the descendants of
+/// a disabled `fieldset` and `:placeholder-shown` doesn't examine the content of a `textarea`.
+///
+/// Pseudo-classes with non-standard names are user-defined. Their predicates are registered with
+/// the [`custom_pseudo_classes`] setting, and the construction of the rewriter fails if a name
+/// isn't registered.
 ///
 /// [`str`]: https://doc.rust-lang.org/std/primitive.str.html
 /// [`parse`]: https://doc.rust-lang.org/std/primitive.str.html#method.parse
 /// [element content handlers]: struct.Settings.html#structfield.element_content_handlers
 /// [`FromStr`]: https://doc.rust-lang.org/std/str/trait.FromStr.html
+/// [`lookahead_mode`]: struct.Settings.html#structfield.lookahead_mode
+/// [`namespace_prefixes`]: struct.Settings.html#structfield.namespace_prefixes
+/// [`custom_pseudo_classes`]: struct.Settings.html#structfield.custom_pseudo_classes
 #[derive(Debug)]
 pub struct Selector(pub(crate) SelectorList<SelectorImplDescriptor>);
 
+impl Selector {
+    /// Returns the lowercased names of the user-defined pseudo-classes used in the selector.
+    pub(crate) fn custom_pseudo_classes(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+
+        for selector in (self.0).0.iter() {
+            collect_custom_pseudo_classes(selector.iter_raw_parse_order_from(0), &mut names);
+        }
+
+        names
+    }
+}
+
+fn collect_custom_pseudo_classes<'s>(
+    components: impl Iterator<Item = &'s Component<SelectorImplDescriptor>>,
+    names: &mut Vec<&'s str>,
+) {
+    for component in components {
+        match component {
+            Component::NonTSPseudoClass(PseudoClass::Custom(name)) => names.push(name),
+            Component::NonTSPseudoClass(PseudoClass::Is(selectors))
+            | Component::NonTSPseudoClass(PseudoClass::Where(selectors)) => {
+                for selector in selectors.iter() {
+                    collect_custom_pseudo_classes(selector.iter_raw_parse_order_from(0), names);
+                }
+            }
+            Component::NonTSPseudoClass(PseudoClass::Has(selectors)) => {
+                for selector in selectors.iter() {
+                    collect_custom_pseudo_classes(
+                        selector.selector.iter_raw_parse_order_from(0),
+                        names,
+                    );
+                }
+            }
+            Component::Negation(components) => {
+                collect_custom_pseudo_classes(components.iter(), names)
+            }
+            _ => (),
+        }
+    }
+}
+
 impl FromStr for Selector {
     type Err = SelectorError;
 
@@ -1,7 +1,8 @@
-use super::SelectorState;
 use super::attribute_matcher::AttributeMatcher;
-use super::compiler::{CompiledAttributeExpr, CompiledLocalNameExpr};
+use super::compiler::{CompiledAttributeExpr, CompiledLocalNameExpr, CompiledLookaheadExpr};
+use super::{LookaheadRequirements, LookaheadState, SelectorState};
 use crate::html::LocalName;
+use encoding_rs::Encoding;
 use hashbrown::HashSet;
 use std::hash::Hash;
 use std::ops::Range;
@@ -16,12 +17,16 @@ where
     pub matched_payload: HashSet<P>,
     pub jumps: Option<AddressRange>,
     pub hereditary_jumps: Option<AddressRange>,
+    pub next_sibling_jumps: Option<AddressRange>,
+    pub later_sibling_jumps: Option<AddressRange>,
+    /// Lookahead facts required to decide whether the branch can be taken.
+    pub lookahead_requirements: LookaheadRequirements,
 }
 
 /// The result of trying to execute an instruction without having parsed all attributes
 pub enum TryExecResult<'i, P>
 where
-    P: Hash + Eq
+    P: Hash + Eq,
 {
     /// A successful match, contains the branch to move to
     Branch(&'i ExecutionBranch<P>),
@@ -38,22 +43,46 @@ where
     pub associated_branch: ExecutionBranch<P>,
     pub local_name_exprs: Box<[CompiledLocalNameExpr]>,
     pub attribute_exprs: Box<[CompiledAttributeExpr]>,
+    pub lookahead_exprs: Box<[CompiledLookaheadExpr]>,
 }
 
 impl<P> Instruction<P>
 where
     P: Hash + Eq,
 {
+    #[inline]
+    fn lookahead_exprs_match(&self, state: &SelectorState) -> bool {
+        if self.lookahead_exprs.is_empty() {
+            return true;
+        }
+
+        // NOTE: without the facts the result of each lookahead expression is unknown,
+        // including the negated ones (e.g. `:not(:last-child)`). An instruction with an
+        // unknown result is taken while scanning, so the scanner collects facts for a
+        // superset of the elements, and is never taken otherwise.
+        match state.lookahead {
+            LookaheadState::Scanning => true,
+            LookaheadState::Resolved(facts) => self.lookahead_exprs.iter().all(|e| e(state, facts)),
+            LookaheadState::Unavailable => false,
+        }
+    }
+
     pub fn try_exec_without_attrs<'i>(
         &'i self,
         state: &SelectorState,
         local_name: &LocalName,
     ) -> TryExecResult<'i, P> {
-        if self.local_name_exprs.iter().all(|e| e(&*state, &local_name)) {
-            if self.attribute_exprs.is_empty() {
+        if self
+            .local_name_exprs
+            .iter()
+            .all(|e| e(&*state, local_name))
+        {
+            if !self.attribute_exprs.is_empty() {
+                TryExecResult::AttributesRequired
+            } else if self.lookahead_exprs_match(state) {
                 TryExecResult::Branch(&self.associated_branch)
             } else {
-                TryExecResult::AttributesRequired
+                TryExecResult::Fail
             }
         } else {
             TryExecResult::Fail
@@ -65,7 +94,9 @@ where
         state: &SelectorState,
         attr_matcher: &AttributeMatcher,
     ) -> Option<&'i ExecutionBranch<P>> {
-        if self.attribute_exprs.iter().all(|e| e(state, attr_matcher)) {
+        if self.attribute_exprs.iter().all(|e| e(state, attr_matcher))
+            && self.lookahead_exprs_match(state)
+        {
             Some(&self.associated_branch)
         } else {
             None
@@ -78,13 +109,12 @@ where
         local_name: &LocalName,
         attr_matcher: &AttributeMatcher,
     ) -> Option<&'i ExecutionBranch<P>> {
-        let is_match =
-            self.local_name_exprs
+        let is_match = self.local_name_exprs.iter().all(|e| e(&*state, local_name))
+            && self
+                .attribute_exprs
                 .iter()
-                .all(|e| e(&*state, local_name)) &&
-            self.attribute_exprs
-                .iter()
-                .all(|e| e(&*state, attr_matcher));
+                .all(|e| e(&*state, attr_matcher))
+            && self.lookahead_exprs_match(state);
 
         if is_match {
             Some(&self.associated_branch)
@@ -103,4 +133,13 @@ where
     /// Enables tracking child types for nth-of-type selectors.
     /// This is disabled if no nth-of-type selectors are used in the program.
     pub enable_nth_of_type: bool,
-}
\ No newline at end of file
+    /// Enables tracking of the inherited attributes for `:lang()` and `:dir()` selectors.
+    /// This is disabled if no such selectors are used in the program, as it requires the
+    /// attributes of every element.
+    pub enable_inherited_attributes: bool,
+    /// Indicates that some of the instructions have expressions that can't be evaluated
+    /// without looking ahead in the input.
+    pub requires_lookahead: bool,
+    /// The encoding of the input, used to decode the attribute values.
+    pub encoding: &'static Encoding,
+}
@@ -1,12 +1,15 @@
-use super::SelectorState;
+use super::{AttributeMatcher, LookaheadState, SelectorState};
 use super::program::AddressRange;
 use super::ast::NthChild;
+use super::ancestors::{Ancestor, TreePosition};
+use super::parser::Direction;
 use crate::html::{LocalName, Namespace, Tag};
 use crate::memory::{LimitedVec, MemoryLimitExceededError, SharedMemoryLimiter};
 // use hashbrown for raw entry, switch back to std once it stablizes there
 use hashbrown::{HashSet, HashMap, hash_map::RawEntryMut};
 use std::fmt::Debug;
 use std::hash::{Hash, Hasher, BuildHasher};
+use std::sync::Arc;
 
 #[inline]
 fn is_void_element(local_name: &LocalName) -> bool {
@@ -24,8 +27,8 @@ fn is_void_element(local_name: &LocalName) -> bool {
     )
 }
 
-pub trait ElementData: Default + 'static {
-    type MatchPayload: PartialEq + Eq + Copy + Debug + Hash + 'static;
+pub trait ElementData: Default + Send + 'static {
+    type MatchPayload: PartialEq + Eq + Copy + Debug + Hash + Send + 'static;
 
     fn matched_payload_mut(&mut self) -> &mut HashSet<Self::MatchPayload>;
 }
@@ -36,6 +39,53 @@ pub enum StackDirective {
     PopImmediately,
 }
 
+/// The values of the attributes that are inherited by the descendants of an element.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InheritedAttributes {
+    /// The language, `None` if no ancestor has specified it.
+    pub lang: Option<Arc<[u8]>>,
+    /// The directionality, `None` if it can't be determined without examining the
+    /// content (i.e. `dir="auto"`).
+    pub dir: Option<Direction>,
+}
+
+impl Default for InheritedAttributes {
+    #[inline]
+    fn default() -> Self {
+        InheritedAttributes {
+            lang: None,
+            dir: Some(Direction::Ltr),
+        }
+    }
+}
+
+impl InheritedAttributes {
+    /// Returns the directionality of an element with the given attributes whose parent
+    /// has `parent_dir` directionality.
+    pub fn element_dir(
+        parent_dir: Option<Direction>,
+        attr_matcher: &AttributeMatcher,
+    ) -> Option<Direction> {
+        match attr_matcher.dir() {
+            Some(dir) if dir.eq_ignore_ascii_case(b"ltr") => Some(Direction::Ltr),
+            Some(dir) if dir.eq_ignore_ascii_case(b"rtl") => Some(Direction::Rtl),
+            Some(dir) if dir.eq_ignore_ascii_case(b"auto") => None,
+            _ => parent_dir,
+        }
+    }
+
+    /// Returns the values for a child element with the given attributes.
+    pub fn for_child(&self, attr_matcher: &AttributeMatcher) -> Self {
+        InheritedAttributes {
+            lang: match attr_matcher.lang() {
+                Some(lang) => Some(Arc::from(&*lang)),
+                None => self.lang.clone(),
+            },
+            dir: Self::element_dir(self.dir, attr_matcher),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct ChildCounter {
     cumulative: i32,
@@ -56,6 +106,13 @@ impl ChildCounter {
     pub fn is_nth(&self, nth: NthChild) -> bool {
         nth.has_index(self.cumulative)
     }
+
+    /// Returns the number of the children that have been added so far, which
+    /// is also the 1-based index of the last added child.
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.cumulative as usize
+    }
 }
 
 struct CounterItem {
@@ -146,14 +203,78 @@ impl TypedChildCounterMap {
     }
 }
 
+/// Jumps produced by an element for its following siblings.
+#[derive(Default)]
+pub struct SiblingJumps {
+    /// Jumps for the `+` combinator.
+    pub next: Vec<AddressRange>,
+    /// Jumps for the `~` combinator.
+    pub later: Vec<AddressRange>,
+}
+
+/// Jumps produced by the preceding siblings of the next element on the same level.
+pub struct LevelSiblingJumps {
+    /// Jumps for the `+` combinator, produced by the immediately preceding sibling.
+    next: LimitedVec<AddressRange>,
+    /// Jumps for the `~` combinator, produced by all preceding siblings.
+    later: LimitedVec<AddressRange>,
+}
+
+impl LevelSiblingJumps {
+    pub fn new(memory_limiter: SharedMemoryLimiter) -> Self {
+        LevelSiblingJumps {
+            next: LimitedVec::new(Arc::clone(&memory_limiter)),
+            later: LimitedVec::new(memory_limiter),
+        }
+    }
+
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &AddressRange> {
+        self.next.iter().chain(self.later.iter())
+    }
+
+    pub fn clear(&mut self) {
+        self.next.clear();
+        self.later.clear();
+    }
+
+    /// Records sibling jumps produced by an element on the level.
+    pub fn add(
+        &mut self,
+        SiblingJumps { next, later }: SiblingJumps,
+    ) -> Result<(), MemoryLimitExceededError> {
+        // NOTE: only the immediately preceding sibling can produce jumps for
+        // the next sibling combinator, so we drop jumps of the previous one.
+        self.next.clear();
+
+        for jumps in next {
+            self.next.push(jumps)?;
+        }
+
+        for jumps in later {
+            if !self.later.contains(&jumps) {
+                self.later.push(jumps)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
 pub struct StackItem<'i, E: ElementData> {
     pub local_name: LocalName<'i>,
     pub element_data: E,
     pub jumps: Vec<AddressRange>,
     pub hereditary_jumps: Vec<AddressRange>,
     pub child_counter: ChildCounter,
+    /// Allocated once a child of the element produces sibling jumps.
+    pub child_sibling_jumps: Option<LevelSiblingJumps>,
     pub has_ancestor_with_hereditary_jumps: bool,
     pub stack_directive: StackDirective,
+    pub inherited_attributes: InheritedAttributes,
+    /// The values of the attributes that are retained for the ancestors view.
+    pub retained_attributes: Vec<(String, String)>,
+    pub ancestor: Option<Arc<Ancestor>>,
 }
 
 impl<'i, E: ElementData> StackItem<'i, E> {
@@ -165,8 +286,12 @@ impl<'i, E: ElementData> StackItem<'i, E> {
             jumps: Vec::default(),
             hereditary_jumps: Vec::default(),
             child_counter: Default::default(),
+            child_sibling_jumps: None,
             has_ancestor_with_hereditary_jumps: false,
             stack_directive: StackDirective::Push,
+            inherited_attributes: InheritedAttributes::default(),
+            retained_attributes: Vec::default(),
+            ancestor: None,
         }
     }
 
@@ -178,8 +303,12 @@ impl<'i, E: ElementData> StackItem<'i, E> {
             jumps: self.jumps,
             hereditary_jumps: self.hereditary_jumps,
             child_counter: self.child_counter,
+            child_sibling_jumps: self.child_sibling_jumps,
             has_ancestor_with_hereditary_jumps: self.has_ancestor_with_hereditary_jumps,
             stack_directive: self.stack_directive,
+            inherited_attributes: self.inherited_attributes,
+            retained_attributes: self.retained_attributes,
+            ancestor: self.ancestor,
         }
     }
 }
@@ -187,20 +316,48 @@ impl<'i, E: ElementData> StackItem<'i, E> {
 pub struct Stack<E: ElementData> {
     /// A counter for root elements
     root_child_counter: ChildCounter,
+    /// Sibling jumps for root elements
+    root_sibling_jumps: LevelSiblingJumps,
     /// A typed counter for all elements on all frames. This is optional to indicate if types are actually being counted.
     typed_child_counters: Option<TypedChildCounterMap>,
+    /// Inherited attributes of the document root.
+    root_inherited_attributes: InheritedAttributes,
     items: LimitedVec<StackItem<'static, E>>,
+    /// Accounts the ancestors of the items that are allocated outside of the items vector.
+    memory_limiter: SharedMemoryLimiter,
 }
 
 impl<E: ElementData> Stack<E> {
     pub fn new(memory_limiter: SharedMemoryLimiter, enable_nth_of_type: bool) -> Self {
         Stack {
             root_child_counter: Default::default(),
+            root_sibling_jumps: LevelSiblingJumps::new(Arc::clone(&memory_limiter)),
             typed_child_counters: if enable_nth_of_type { Some(Default::default()) } else { None },
-            items: LimitedVec::new(memory_limiter),
+            root_inherited_attributes: InheritedAttributes::default(),
+            items: LimitedVec::new(Arc::clone(&memory_limiter)),
+            memory_limiter,
         }
     }
 
+    /// Removes all the elements from the stack and resets the root counters,
+    /// keeping the allocations.
+    pub fn reset(&mut self) {
+        self.root_child_counter = Default::default();
+        self.root_sibling_jumps.clear();
+
+        if let Some(counters) = &mut self.typed_child_counters {
+            counters.0.clear();
+        }
+
+        self.root_inherited_attributes = InheritedAttributes::default();
+        self.memory_limiter.decrease_usage(Self::ancestors_memory_usage(&self.items));
+        self.items.clear();
+    }
+
+    fn ancestors_memory_usage(items: &[StackItem<E>]) -> usize {
+        items.iter().filter_map(|i| i.ancestor.as_ref()).map(|a| a.memory_usage()).sum()
+    }
+
     /// Adds a child to child counters. Called before pushing the element to the stack.
     pub fn add_child<'i>(&mut self, name: &LocalName<'i>) {
         match self.items.last_mut() {
@@ -213,23 +370,67 @@ impl<E: ElementData> Stack<E> {
         }
     }
 
-    pub fn build_state<'a, 'i>(&'a self, name: &LocalName<'i>) -> SelectorState<'i>
+    pub fn build_state<'a, 'i>(
+        &'a self,
+        name: &LocalName<'i>,
+        ns: Namespace,
+        lookahead: LookaheadState<'i>,
+    ) -> SelectorState<'i>
     where
         'a: 'i // 'a outlives 'i, required to downcast 'a lifetimes into 'i
     {
-        let cumulative = match self.items.last() {
-            Some(last) => &last.child_counter,
-            None => &self.root_child_counter,
+        let (cumulative, inherited_attributes) = match self.items.last() {
+            Some(last) => (&last.child_counter, &last.inherited_attributes),
+            None => (&self.root_child_counter, &self.root_inherited_attributes),
         };
         SelectorState {
             cumulative,
             typed:
                 self.typed_child_counters
                     .as_ref()
-                    .and_then(|f| f.get(name, self.items.len()))
+                    .and_then(|f| f.get(name, self.items.len())),
+            ns,
+            inherited_attributes,
+            lookahead,
         }
     }
 
+    /// Returns sibling jumps for the element that is about to be added to the current level.
+    #[inline]
+    pub fn sibling_jumps(&self) -> impl Iterator<Item = &AddressRange> {
+        let sibling_jumps = match self.items.last() {
+            Some(last) => last.child_sibling_jumps.as_ref(),
+            None => Some(&self.root_sibling_jumps),
+        };
+
+        sibling_jumps.into_iter().flat_map(LevelSiblingJumps::iter)
+    }
+
+    /// Records sibling jumps produced by an element on the current level. Called before
+    /// pushing the element to the stack.
+    pub fn add_sibling_jumps(
+        &mut self,
+        jumps: SiblingJumps,
+    ) -> Result<(), MemoryLimitExceededError> {
+        let sibling_jumps = match self.items.last_mut() {
+            Some(last) => {
+                if last.child_sibling_jumps.is_none() {
+                    if jumps.next.is_empty() && jumps.later.is_empty() {
+                        return Ok(());
+                    }
+
+                    last.child_sibling_jumps =
+                        Some(LevelSiblingJumps::new(Arc::clone(&self.memory_limiter)));
+                }
+
+                last.child_sibling_jumps.as_mut().unwrap()
+            }
+            None => &mut self.root_sibling_jumps,
+        };
+
+        sibling_jumps.add(jumps)
+    }
+
     #[inline]
     pub fn get_stack_directive(item: &StackItem<E>, ns: Namespace) -> StackDirective {
         if ns == Namespace::Html {
@@ -257,10 +458,31 @@ impl<E: ElementData> Stack<E> {
                 .map(|(i, _)| i);
         if let Some(index) = pop_to_index {
             self.typed_child_counters.as_mut().map(|c| c.pop_to(index));
+            self.memory_limiter.decrease_usage(Self::ancestors_memory_usage(&self.items()[index..]));
             self.items.drain(index..).map(|i| i.element_data).for_each(popped_element_data_handler)
         }
     }
 
+    /// Returns the inherited attributes of the parent of the element that is being matched.
+    #[inline]
+    pub fn parent_inherited_attributes(&self) -> &InheritedAttributes {
+        match self.items.last() {
+            Some(last) => &last.inherited_attributes,
+            None => &self.root_inherited_attributes,
+        }
+    }
+
+    /// Returns the position of the last added child in the tree of the open elements.
+    #[inline]
+    pub fn position(&self) -> TreePosition {
+        let (parent, child_counter) = match self.items.last() {
+            Some(last) => (last.ancestor.clone(), &last.child_counter),
+            None => (None, &self.root_child_counter),
+        };
+
+        TreePosition::new(parent, self.items.len(), child_counter.count())
+    }
+
     #[inline]
     pub fn items(&self) -> &[StackItem<E>] {
         &self.items
@@ -282,7 +504,15 @@ impl<E: ElementData> Stack<E> {
             }
         }
 
-        self.items.push(item)?;
+        let ancestor_memory_usage = item.ancestor.as_ref().map_or(0, |a| a.memory_usage());
+
+        self.memory_limiter.increase_usage(ancestor_memory_usage)?;
+
+        if let Err(e) = self.items.push(item) {
+            self.memory_limiter.decrease_usage(ancestor_memory_usage);
+            return Err(e);
+        }
+
         Ok(())
     }
 }
@@ -342,6 +572,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn sibling_jumps_memory_usage() {
+        let limiter = MemoryLimiter::new_shared(2048);
+        let mut stack = Stack::<TestElementData>::new(Arc::clone(&limiter), false);
+        let range_size = std::mem::size_of::<AddressRange>();
+
+        stack.push_item(item("div", 0)).unwrap();
+
+        let usage = limiter.current_usage();
+
+        stack
+            .add_sibling_jumps(SiblingJumps {
+                next: vec![0..1, 3..4],
+                later: vec![1..2, 2..3],
+            })
+            .unwrap();
+
+        assert_eq!(limiter.current_usage(), usage + 4 * range_size);
+
+        stack
+            .add_sibling_jumps(SiblingJumps {
+                next: vec![],
+                later: vec![2..3, 1..2],
+            })
+            .unwrap();
+
+        assert_eq!(limiter.current_usage(), usage + 2 * range_size);
+        assert_eq!(stack.sibling_jumps().cloned().collect::<Vec<_>>(), [1..2, 2..3]);
+
+        stack.pop_up_to(local_name("div"), |_| ());
+
+        assert_eq!(limiter.current_usage(), 0);
+    }
+
     #[test]
     fn pop_up_to() {
         macro_rules! assert_pop_result {
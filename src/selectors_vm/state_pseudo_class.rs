@@ -0,0 +1,248 @@
+use super::AttributeMatcher;
+use crate::base::Bytes;
+use crate::html::Namespace;
+use encoding_rs::UTF_8;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref CHECKED_ATTR: Bytes<'static> = Bytes::from_str("checked", UTF_8);
+    static ref CONTENTEDITABLE_ATTR: Bytes<'static> = Bytes::from_str("contenteditable", UTF_8);
+    static ref DISABLED_ATTR: Bytes<'static> = Bytes::from_str("disabled", UTF_8);
+    static ref HREF_ATTR: Bytes<'static> = Bytes::from_str("href", UTF_8);
+    static ref PLACEHOLDER_ATTR: Bytes<'static> = Bytes::from_str("placeholder", UTF_8);
+    static ref READONLY_ATTR: Bytes<'static> = Bytes::from_str("readonly", UTF_8);
+    static ref REQUIRED_ATTR: Bytes<'static> = Bytes::from_str("required", UTF_8);
+    static ref SELECTED_ATTR: Bytes<'static> = Bytes::from_str("selected", UTF_8);
+    static ref TYPE_ATTR: Bytes<'static> = Bytes::from_str("type", UTF_8);
+    static ref VALUE_ATTR: Bytes<'static> = Bytes::from_str("value", UTF_8);
+}
+
+/// Keywords of the `type` attribute of the `input` element.
+///
+/// See: https://html.spec.whatwg.org/multipage/input.html#attr-input-type
+const INPUT_TYPES: [&str; 22] = [
+    "hidden",
+    "text",
+    "search",
+    "tel",
+    "url",
+    "email",
+    "password",
+    "date",
+    "month",
+    "week",
+    "time",
+    "datetime-local",
+    "number",
+    "range",
+    "color",
+    "checkbox",
+    "radio",
+    "file",
+    "submit",
+    "image",
+    "reset",
+    "button",
+];
+
+/// Input types to which the `readonly` attribute applies.
+const READONLY_INPUT_TYPES: [&str; 12] = [
+    "text",
+    "search",
+    "url",
+    "tel",
+    "email",
+    "password",
+    "date",
+    "month",
+    "week",
+    "time",
+    "datetime-local",
+    "number",
+];
+
+/// Input types to which the `required` attribute applies.
+const REQUIRED_INPUT_TYPES: [&str; 15] = [
+    "text",
+    "search",
+    "url",
+    "tel",
+    "email",
+    "password",
+    "date",
+    "month",
+    "week",
+    "time",
+    "datetime-local",
+    "number",
+    "checkbox",
+    "radio",
+    "file",
+];
+
+/// Input types to which the `placeholder` attribute applies.
+const PLACEHOLDER_INPUT_TYPES: [&str; 7] = [
+    "text", "search", "url", "tel", "email", "password", "number",
+];
+
+/// Elements that can be disabled with the `disabled` attribute.
+const DISABLEABLE_ELEMENTS: [&str; 7] = [
+    "button", "input", "select", "textarea", "optgroup", "option", "fieldset",
+];
+
+/// A pseudo-class that reflects the state of an element which is determined by the
+/// element's attributes.
+///
+/// See: https://html.spec.whatwg.org/multipage/semantics-other.html#pseudo-classes
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum StatePseudoClass {
+    AnyLink,
+    Checked,
+    Default,
+    Disabled,
+    Optional,
+    PlaceholderShown,
+    ReadOnly,
+    ReadWrite,
+    Required,
+}
+
+impl StatePseudoClass {
+    /// Returns the pseudo-class with the given lowercased name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        use StatePseudoClass::*;
+
+        Some(match name {
+            "any-link" => AnyLink,
+            "checked" => Checked,
+            "default" => Default,
+            "disabled" => Disabled,
+            "optional" => Optional,
+            "placeholder-shown" => PlaceholderShown,
+            "read-only" => ReadOnly,
+            "read-write" => ReadWrite,
+            "required" => Required,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        use StatePseudoClass::*;
+
+        match self {
+            AnyLink => "any-link",
+            Checked => "checked",
+            Default => "default",
+            Disabled => "disabled",
+            Optional => "optional",
+            PlaceholderShown => "placeholder-shown",
+            ReadOnly => "read-only",
+            ReadWrite => "read-write",
+            Required => "required",
+        }
+    }
+
+    pub fn matches(self, m: &AttributeMatcher) -> bool {
+        use StatePseudoClass::*;
+
+        let element = HtmlElement(m);
+
+        match self {
+            AnyLink => element.is_one_of(&["a", "area"]) && m.has_attribute(&HREF_ATTR),
+            // NOTE: without scripts the checkedness and selectedness of the form
+            // controls are determined by the attributes, so `:checked` and `:default`
+            // only differ for the default buttons of forms, which can't be determined
+            // from the start tag of the button.
+            Checked | Default => element.is_checked(),
+            Disabled => element.is_one_of(&DISABLEABLE_ELEMENTS) && m.has_attribute(&DISABLED_ATTR),
+            Required => element.is_required_applicable() && m.has_attribute(&REQUIRED_ATTR),
+            Optional => element.is_required_applicable() && !m.has_attribute(&REQUIRED_ATTR),
+            PlaceholderShown => element.is_placeholder_shown(),
+            ReadWrite => element.is_read_write(),
+            ReadOnly => !element.is_read_write(),
+        }
+    }
+}
+
+/// The attributes of an element interpreted according to the HTML spec. Foreign elements
+/// are never one of the HTML form controls or links.
+struct HtmlElement<'a, 'i>(&'a AttributeMatcher<'i>);
+
+impl HtmlElement<'_, '_> {
+    #[inline]
+    fn is(&self, tag_name: &str) -> bool {
+        self.0.ns() == Namespace::Html
+            && self.0.tag_name().eq_ignore_ascii_case(tag_name.as_bytes())
+    }
+
+    #[inline]
+    fn is_one_of(&self, tag_names: &[&str]) -> bool {
+        tag_names.iter().any(|tag_name| self.is(tag_name))
+    }
+
+    /// Returns `true` if the element is an `input` with one of the given types.
+    fn is_input_of_type(&self, types: &[&str]) -> bool {
+        if !self.is("input") {
+            return false;
+        }
+
+        // NOTE: missing and invalid values of the `type` attribute default to the text state.
+        let input_type = self
+            .0
+            .get_value(&TYPE_ATTR)
+            .and_then(|value| {
+                INPUT_TYPES
+                    .iter()
+                    .find(|t| value.eq_ignore_ascii_case(t.as_bytes()))
+            })
+            .unwrap_or(&"text");
+
+        types.contains(input_type)
+    }
+
+    #[inline]
+    fn is_checked(&self) -> bool {
+        (self.is_input_of_type(&["checkbox", "radio"]) && self.0.has_attribute(&CHECKED_ATTR))
+            || (self.is("option") && self.0.has_attribute(&SELECTED_ATTR))
+    }
+
+    #[inline]
+    fn is_required_applicable(&self) -> bool {
+        self.is_input_of_type(&REQUIRED_INPUT_TYPES) || self.is_one_of(&["select", "textarea"])
+    }
+
+    /// NOTE: the content of a `textarea` follows its start tag, so a `textarea`
+    /// with a placeholder is always considered to be empty.
+    fn is_placeholder_shown(&self) -> bool {
+        let has_placeholder = self.0.has_attribute(&PLACEHOLDER_ATTR);
+
+        if self.is_input_of_type(&PLACEHOLDER_INPUT_TYPES) {
+            has_placeholder
+                && match self.0.get_value(&VALUE_ATTR) {
+                    Some(value) => value.is_empty(),
+                    None => true,
+                }
+        } else {
+            has_placeholder && self.is("textarea")
+        }
+    }
+
+    /// NOTE: only editing hosts are considered editable, since the `contenteditable`
+    /// attributes of the ancestors are not available.
+    fn is_read_write(&self) -> bool {
+        let m = self.0;
+
+        if self.is_input_of_type(&READONLY_INPUT_TYPES) || self.is("textarea") {
+            !m.has_attribute(&READONLY_ATTR) && !m.has_attribute(&DISABLED_ATTR)
+        } else if self.is("input") || m.ns() != Namespace::Html {
+            false
+        } else {
+            matches!(
+                m.get_value(&CONTENTEDITABLE_ATTR),
+                Some(value) if value.is_empty()
+                    || value.eq_ignore_ascii_case(b"true")
+                    || value.eq_ignore_ascii_case(b"plaintext-only")
+            )
+        }
+    }
+}
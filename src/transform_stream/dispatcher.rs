@@ -1,27 +1,29 @@
-use super::*;
 use crate::base::{Bytes, Range};
 use crate::html::{LocalName, Namespace};
+use crate::memory::SharedMemoryLimiter;
 use crate::parser::{
-    Lexeme, LexemeSink, NonTagContentLexeme, ParserDirective, ParserOutputSink, TagHintSink,
-    TagLexeme, TagTokenOutline,
+    AttributeOutline, Lexeme, LexemeSink, NonTagContentLexeme, ParserDirective, ParserOutputSink,
+    TagHintSink, TagLexeme, TagTokenOutline,
 };
 use crate::rewritable_units::{
     DocumentEnd, Serialize, ToToken, Token, TokenCaptureFlags, TokenCapturer, TokenCapturerEvent,
 };
 use crate::rewriter::RewritingError;
+use crate::selectors_vm::LookaheadFactsMap;
 use encoding_rs::Encoding;
-use std::rc::Rc;
 
 use TagTokenOutline::*;
 
 pub struct AuxStartTagInfo<'i> {
     pub input: &'i Bytes<'i>,
-    pub attr_buffer: SharedAttributeBuffer,
+    pub name: Bytes<'i>,
+    pub attr_buffer: &'i [AttributeOutline],
     pub self_closing: bool,
 }
 
-type AuxStartTagInfoRequest<C> =
-    Box<dyn FnOnce(&mut C, AuxStartTagInfo<'_>) -> Result<TokenCaptureFlags, RewritingError>>;
+type AuxStartTagInfoRequest<C> = Box<
+    dyn FnOnce(&mut C, AuxStartTagInfo<'_>) -> Result<TokenCaptureFlags, RewritingError> + Send,
+>;
 
 pub enum DispatcherError<C> {
     InfoRequest(AuxStartTagInfoRequest<C>),
@@ -37,6 +39,45 @@ pub trait TransformController: Sized {
     fn handle_token(&mut self, token: &mut Token) -> Result<(), RewritingError>;
     fn handle_end(&mut self, document_end: &mut DocumentEnd) -> Result<(), RewritingError>;
     fn should_emit_content(&self) -> bool;
+
+    /// Returns `true` if the emitted content should be passed to [`capture_content`]
+    /// instead of the output sink.
+    ///
+    /// [`capture_content`]: #method.capture_content
+    #[inline]
+    fn is_capturing_content(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn capture_content(&mut self, _chunk: &[u8]) -> Result<(), RewritingError> {
+        Ok(())
+    }
+
+    /// Called once a start tag has been emitted, so the captures requested while the start
+    /// tag was handled don't capture the start tag itself.
+    #[inline]
+    fn activate_content_captures(&mut self) {}
+
+    /// Returns `true` if the captured text should be passed to [`handle_token`] as whole
+    /// text nodes, rather than as chunks.
+    ///
+    /// [`handle_token`]: #tymethod.handle_token
+    #[inline]
+    fn captures_text_nodes(&self) -> bool {
+        false
+    }
+
+    /// Returns the storage for the facts collected by the lookahead scanner, if the controller
+    /// uses them.
+    #[inline]
+    fn lookahead_facts_mut(&mut self) -> Option<&mut LookaheadFactsMap> {
+        None
+    }
+
+    /// Returns the controller to the state of the beginning of a document.
+    #[inline]
+    fn reset(&mut self) {}
 }
 
 /// Defines an interface for the [`HtmlRewriter`]'s output.
@@ -80,14 +121,19 @@ where
     C: TransformController,
     O: OutputSink,
 {
-    pub fn new(transform_controller: C, output_sink: O, encoding: &'static Encoding) -> Self {
+    pub fn new(
+        transform_controller: C,
+        output_sink: O,
+        encoding: &'static Encoding,
+        memory_limiter: SharedMemoryLimiter,
+    ) -> Self {
         let initial_capture_flags = transform_controller.initial_capture_flags();
 
         Dispatcher {
             transform_controller,
             output_sink,
             remaining_content_start: 0,
-            token_capturer: TokenCapturer::new(initial_capture_flags, encoding),
+            token_capturer: TokenCapturer::new(initial_capture_flags, encoding, memory_limiter),
             got_flags_from_hint: false,
             pending_element_aux_info_req: None,
             emission_enabled: true,
@@ -95,18 +141,62 @@ where
         }
     }
 
-    pub fn flush_remaining_input(&mut self, input: &[u8], consumed_byte_count: usize) {
+    #[inline]
+    fn emit(
+        transform_controller: &mut C,
+        output_sink: &mut O,
+        chunk: &[u8],
+    ) -> Result<(), RewritingError> {
+        if transform_controller.is_capturing_content() {
+            transform_controller.capture_content(chunk)
+        } else {
+            output_sink.handle_chunk(chunk);
+
+            Ok(())
+        }
+    }
+
+    #[inline]
+    fn emit_token(
+        transform_controller: &mut C,
+        output_sink: &mut O,
+        token: &Token,
+    ) -> Result<(), RewritingError> {
+        let mut res = Ok(());
+
+        token.to_bytes(&mut |c| {
+            if res.is_ok() {
+                res = Self::emit(transform_controller, output_sink, c);
+            }
+        });
+
+        res
+    }
+
+    pub fn flush_remaining_input(
+        &mut self,
+        input: &[u8],
+        consumed_byte_count: usize,
+    ) -> Result<(), RewritingError> {
         let output = &input[self.remaining_content_start..consumed_byte_count];
 
         if self.emission_enabled && !output.is_empty() {
-            self.output_sink.handle_chunk(&output);
+            Self::emit(
+                &mut self.transform_controller,
+                &mut self.output_sink,
+                output,
+            )?;
         }
 
         self.remaining_content_start = 0;
+        self.token_capturer
+            .consume_chunk(input, consumed_byte_count);
+
+        Ok(())
     }
 
     pub fn finish(&mut self, input: &[u8]) -> Result<(), RewritingError> {
-        self.flush_remaining_input(input, input.len());
+        self.flush_remaining_input(input, input.len())?;
 
         let mut document_end = DocumentEnd::new(&mut self.output_sink, self.encoding);
 
@@ -118,6 +208,29 @@ where
         Ok(())
     }
 
+    /// Returns the dispatcher and its transform controller to the state of the beginning
+    /// of a document. The output sink is kept as is.
+    pub fn reset(&mut self) {
+        self.transform_controller.reset();
+
+        let initial_capture_flags = self.transform_controller.initial_capture_flags();
+
+        self.remaining_content_start = 0;
+        self.token_capturer.reset(initial_capture_flags);
+        self.got_flags_from_hint = false;
+        self.pending_element_aux_info_req = None;
+        self.emission_enabled = true;
+    }
+
+    #[inline]
+    pub fn transform_controller_mut(&mut self) -> &mut C {
+        &mut self.transform_controller
+    }
+
+    pub fn with_output_sink<T>(&mut self, f: impl FnOnce(&mut O) -> T) -> T {
+        f(&mut self.output_sink)
+    }
+
     fn try_produce_token_from_lexeme<'i, T>(
         &mut self,
         lexeme: &Lexeme<'i, T>,
@@ -125,6 +238,7 @@ where
     where
         Lexeme<'i, T>: ToToken,
     {
+        let text_nodes = self.transform_controller.captures_text_nodes();
         let transform_controller = &mut self.transform_controller;
         let output_sink = &mut self.output_sink;
         let emission_enabled = self.emission_enabled;
@@ -132,7 +246,7 @@ where
         let remaining_content_start = self.remaining_content_start;
         let mut lexeme_consumed = false;
 
-        self.token_capturer.feed(lexeme, |event| {
+        self.token_capturer.feed(lexeme, text_nodes, |event| {
             match event {
                 TokenCapturerEvent::LexemeConsumed => {
                     let chunk = lexeme.input().slice(Range {
@@ -143,7 +257,7 @@ where
                     lexeme_consumed = true;
 
                     if emission_enabled && chunk.len() > 0 {
-                        output_sink.handle_chunk(&chunk);
+                        Self::emit(transform_controller, output_sink, &chunk)?;
                     }
                 }
                 TokenCapturerEvent::TokenProduced(mut token) => {
@@ -152,7 +266,7 @@ where
                     transform_controller.handle_token(&mut token)?;
 
                     if emission_enabled {
-                        token.to_bytes(&mut |c| output_sink.handle_chunk(c));
+                        Self::emit_token(transform_controller, output_sink, &token)?;
                     }
                 }
             }
@@ -167,7 +281,7 @@ where
     }
 
     #[inline]
-    fn get_next_parser_directive(&self) -> ParserDirective {
+    pub fn get_next_parser_directive(&self) -> ParserDirective {
         if self.token_capturer.has_captures() {
             ParserDirective::Lex
         } else {
@@ -182,12 +296,13 @@ where
         let input = lexeme.input();
 
         macro_rules! get_flags_from_aux_info_res {
-            ($handler:expr, $attributes:expr, $self_closing:expr) => {
+            ($handler:expr, $name:expr, $attributes:expr, $self_closing:expr) => {
                 $handler(
                     &mut self.transform_controller,
                     AuxStartTagInfo {
                         input,
-                        attr_buffer: Rc::clone($attributes),
+                        name: input.slice($name),
+                        attr_buffer: $attributes,
                         self_closing: $self_closing,
                     },
                 )
@@ -199,10 +314,11 @@ where
             // attributes and self closing flag were requested.
             Some(aux_info_req) => match *lexeme.token_outline() {
                 StartTag {
+                    name,
                     ref attributes,
                     self_closing,
                     ..
-                } => get_flags_from_aux_info_res!(aux_info_req, attributes, self_closing),
+                } => get_flags_from_aux_info_res!(aux_info_req, name, attributes, self_closing),
                 _ => unreachable!("Tag should be a start tag at this point"),
             },
 
@@ -216,12 +332,17 @@ where
                     ref attributes,
                     self_closing,
                 } => {
-                    let name = LocalName::new(input, name, name_hash);
+                    let local_name = LocalName::new(input, name, name_hash);
 
-                    match self.transform_controller.handle_start_tag(name, ns) {
+                    match self.transform_controller.handle_start_tag(local_name, ns) {
                         Ok(flags) => Ok(flags),
                         Err(DispatcherError::InfoRequest(aux_info_req)) => {
-                            get_flags_from_aux_info_res!(aux_info_req, attributes, self_closing)
+                            get_flags_from_aux_info_res!(
+                                aux_info_req,
+                                name,
+                                attributes,
+                                self_closing
+                            )
                         }
                         Err(DispatcherError::RewritingError(e)) => Err(e),
                     }
@@ -266,7 +387,7 @@ where
                 transform_controller.handle_token(&mut token)?;
 
                 if emission_enabled {
-                    token.to_bytes(&mut |c| output_sink.handle_chunk(c));
+                    Self::emit_token(transform_controller, output_sink, &token)?;
                 }
             }
 
@@ -309,6 +430,7 @@ where
         }
 
         self.try_produce_token_from_lexeme(lexeme)?;
+        self.transform_controller.activate_content_captures();
         self.emission_enabled = self.transform_controller.should_emit_content();
 
         Ok(self.get_next_parser_directive())
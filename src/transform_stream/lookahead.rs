@@ -0,0 +1,182 @@
+use crate::memory::{Arena, MemoryLimitExceededError, SharedMemoryLimiter};
+use crate::rewriter::RewritingError;
+use crate::selectors_vm::LookaheadFactsMap;
+use std::cmp::min;
+
+/// Releases the input to the parser, along with the scanner that has collected the facts
+/// about this input.
+pub type ReleaseInput<'r> =
+    dyn FnMut(&[u8], &mut dyn LookaheadScanner) -> Result<(), RewritingError> + 'r;
+
+pub trait LookaheadScanner {
+    /// Scans the `input` that starts at the given `offset` of the stream and returns
+    /// the number of consumed bytes.
+    fn scan(&mut self, input: &[u8], offset: usize, last: bool) -> Result<usize, RewritingError>;
+
+    /// Returns the stream offset starting from which the input should be withheld
+    /// from the parser, if there is any input that should be withheld.
+    fn withheld_input_start(&self) -> Option<usize>;
+
+    /// Moves the facts collected so far to `facts`.
+    fn take_facts(&mut self, facts: &mut LookaheadFactsMap);
+
+    /// Gives up on the lookahead for the currently withheld input, so it can be
+    /// released to the parser.
+    fn abandon_withheld_input(&mut self);
+
+    /// Returns the scanner to the state of the beginning of a document.
+    fn reset(&mut self);
+}
+
+/// Withholds the input from the parser until the lookahead scanner has collected all the
+/// information that is required for the parser to process it.
+pub struct Lookahead {
+    scanner: Box<dyn LookaheadScanner + Send>,
+    buffer: Arena,
+    has_buffered_data: bool,
+    released_byte_count: usize,
+    /// The number of buffered bytes that were consumed by the scanner.
+    scanned_byte_count: usize,
+    pass_through_on_memory_limit: bool,
+}
+
+impl Lookahead {
+    pub fn new(
+        scanner: Box<dyn LookaheadScanner + Send>,
+        memory_limiter: SharedMemoryLimiter,
+        pass_through_on_memory_limit: bool,
+    ) -> Self {
+        Lookahead {
+            scanner,
+            buffer: Arena::new(memory_limiter, 0),
+            has_buffered_data: false,
+            released_byte_count: 0,
+            scanned_byte_count: 0,
+            pass_through_on_memory_limit,
+        }
+    }
+
+    /// Drops the withheld input and resets the scanner, keeping the allocation of the buffer.
+    pub fn reset(&mut self) {
+        self.scanner.reset();
+        self.has_buffered_data = false;
+        self.released_byte_count = 0;
+        self.scanned_byte_count = 0;
+    }
+
+    #[inline]
+    fn get_releasable_byte_count(&self) -> usize {
+        match self.scanner.withheld_input_start() {
+            Some(start) => min(start - self.released_byte_count, self.scanned_byte_count),
+            None => self.scanned_byte_count,
+        }
+    }
+
+    #[inline]
+    fn mark_as_released(&mut self, byte_count: usize) {
+        self.released_byte_count += byte_count;
+        self.scanned_byte_count -= byte_count;
+    }
+
+    #[inline]
+    fn abandon_withheld_input(
+        &mut self,
+        err: MemoryLimitExceededError,
+    ) -> Result<(), RewritingError> {
+        if self.pass_through_on_memory_limit {
+            self.scanner.abandon_withheld_input();
+            Ok(())
+        } else {
+            Err(RewritingError::MemoryLimitExceeded(err))
+        }
+    }
+
+    fn buffer_data(
+        &mut self,
+        data: &[u8],
+        release: &mut ReleaseInput,
+    ) -> Result<(), RewritingError> {
+        if let Err(err) = self.buffer.append(data) {
+            self.abandon_withheld_input(err)?;
+
+            // NOTE: give the memory occupied by the scanned input back to
+            // the limiter before releasing it, so the parser has room to
+            // process it. Then try to buffer the data once again.
+            let releasable_byte_count = self.scanned_byte_count;
+            let scanned_input = self.buffer.bytes()[..releasable_byte_count].to_vec();
+
+            self.buffer.shift(releasable_byte_count);
+            self.buffer.shrink_to_fit();
+            release(&scanned_input, &mut *self.scanner)?;
+            self.mark_as_released(releasable_byte_count);
+
+            self.buffer
+                .append(data)
+                .map_err(RewritingError::MemoryLimitExceeded)?;
+        }
+
+        Ok(())
+    }
+
+    fn buffer_remainder(
+        &mut self,
+        data: &[u8],
+        release: &mut ReleaseInput,
+    ) -> Result<(), RewritingError> {
+        if let Err(err) = self.buffer.init_with(data) {
+            self.abandon_withheld_input(err)?;
+
+            let releasable_byte_count = self.scanned_byte_count;
+
+            self.buffer.shrink_to_fit();
+            release(&data[..releasable_byte_count], &mut *self.scanner)?;
+            self.mark_as_released(releasable_byte_count);
+
+            self.buffer
+                .init_with(&data[releasable_byte_count..])
+                .map_err(RewritingError::MemoryLimitExceeded)?;
+        }
+
+        Ok(())
+    }
+
+    /// Scans the `data` and releases the part of the input that is no longer
+    /// withheld with the `release` callback.
+    pub fn write(
+        &mut self,
+        data: &[u8],
+        last: bool,
+        release: &mut ReleaseInput,
+    ) -> Result<(), RewritingError> {
+        if self.has_buffered_data {
+            self.buffer_data(data, release)?;
+        }
+
+        let chunk = if self.has_buffered_data {
+            self.buffer.bytes()
+        } else {
+            data
+        };
+
+        self.scanned_byte_count += self.scanner.scan(
+            &chunk[self.scanned_byte_count..],
+            self.released_byte_count + self.scanned_byte_count,
+            last,
+        )?;
+
+        let releasable_byte_count = self.get_releasable_byte_count();
+
+        release(&chunk[..releasable_byte_count], &mut *self.scanner)?;
+        self.mark_as_released(releasable_byte_count);
+
+        if self.has_buffered_data {
+            self.buffer.shift(releasable_byte_count);
+            self.has_buffered_data = !self.buffer.bytes().is_empty();
+        } else if releasable_byte_count < data.len() {
+            self.buffer_remainder(&data[releasable_byte_count..], release)?;
+            self.has_buffered_data = true;
+        }
+
+        Ok(())
+    }
+}
@@ -1,16 +1,17 @@
 mod dispatcher;
+mod lookahead;
 
 use self::dispatcher::Dispatcher;
 use crate::memory::{Arena, SharedMemoryLimiter};
-use crate::parser::{Parser, ParserDirective, SharedAttributeBuffer};
+use crate::parser::{Parser, ParserDirective};
 use crate::rewriter::RewritingError;
 use encoding_rs::Encoding;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
 
 pub use self::dispatcher::{
     AuxStartTagInfo, DispatcherError, OutputSink, StartTagHandlingResult, TransformController,
 };
+pub use self::lookahead::{Lookahead, LookaheadScanner};
 
 pub struct TransformStreamSettings<C, O>
 where
@@ -23,6 +24,7 @@ where
     pub memory_limiter: SharedMemoryLimiter,
     pub encoding: &'static Encoding,
     pub strict: bool,
+    pub lookahead: Option<Lookahead>,
 }
 
 pub struct TransformStream<C, O>
@@ -30,10 +32,10 @@ where
     C: TransformController,
     O: OutputSink,
 {
-    dispatcher: Rc<RefCell<Dispatcher<C, O>>>,
     parser: Parser<Dispatcher<C, O>>,
     buffer: Arena,
     has_buffered_data: bool,
+    lookahead: Option<Lookahead>,
 }
 
 impl<C, O> TransformStream<C, O>
@@ -52,24 +54,25 @@ where
             ParserDirective::Lex
         };
 
-        let dispatcher = Rc::new(RefCell::new(Dispatcher::new(
+        let dispatcher = Dispatcher::new(
             settings.transform_controller,
             settings.output_sink,
             settings.encoding,
-        )));
+            Arc::clone(&settings.memory_limiter),
+        );
 
         let buffer = Arena::new(
             settings.memory_limiter,
             settings.preallocated_parsing_buffer_size,
         );
 
-        let parser = Parser::new(&dispatcher, initial_parser_directive, settings.strict);
+        let parser = Parser::new(dispatcher, initial_parser_directive, settings.strict);
 
         TransformStream {
-            dispatcher,
             parser,
             buffer,
             has_buffered_data: false,
+            lookahead: settings.lookahead,
         }
     }
 
@@ -93,9 +96,7 @@ where
         Ok(())
     }
 
-    pub fn write(&mut self, data: &[u8]) -> Result<(), RewritingError> {
-        trace!(@write data);
-
+    fn parse(&mut self, data: &[u8]) -> Result<(), RewritingError> {
         let chunk = if self.has_buffered_data {
             self.buffer
                 .append(data)
@@ -110,9 +111,9 @@ where
 
         let consumed_byte_count = self.parser.parse(chunk, false)?;
 
-        self.dispatcher
-            .borrow_mut()
-            .flush_remaining_input(&chunk, consumed_byte_count);
+        self.parser
+            .output_sink_mut()
+            .flush_remaining_input(chunk, consumed_byte_count)?;
 
         if consumed_byte_count < chunk.len() {
             self.buffer_blocked_bytes(data, consumed_byte_count)?;
@@ -123,9 +124,52 @@ where
         Ok(())
     }
 
+    fn release(
+        &mut self,
+        data: &[u8],
+        scanner: &mut dyn LookaheadScanner,
+    ) -> Result<(), RewritingError> {
+        let controller = self.parser.output_sink_mut().transform_controller_mut();
+
+        if let Some(facts) = controller.lookahead_facts_mut() {
+            scanner.take_facts(facts);
+        }
+
+        self.parse(data)
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), RewritingError> {
+        trace!(@write data);
+
+        match self.lookahead.take() {
+            Some(mut lookahead) => {
+                let res = lookahead.write(data, false, &mut |chunk, scanner| {
+                    self.release(chunk, scanner)
+                });
+
+                self.lookahead = Some(lookahead);
+
+                res
+            }
+            None => self.parse(data),
+        }
+    }
+
     pub fn end(&mut self) -> Result<(), RewritingError> {
         trace!(@end);
 
+        // NOTE: the end of the input completes the lookahead, so
+        // all the withheld input can be released to the parser.
+        if let Some(mut lookahead) = self.lookahead.take() {
+            let res = lookahead.write(&[], true, &mut |chunk, scanner| {
+                self.release(chunk, scanner)
+            });
+
+            self.lookahead = Some(lookahead);
+
+            res?;
+        }
+
         let chunk = if self.has_buffered_data {
             self.buffer.bytes()
         } else {
@@ -135,7 +179,33 @@ where
         trace!(@chunk chunk);
 
         self.parser.parse(chunk, true)?;
-        self.dispatcher.borrow_mut().finish(chunk)
+        self.parser.output_sink_mut().finish(chunk)
+    }
+
+    /// Returns the stream to the state of the beginning of a document, keeping the allocations
+    /// of the parsing buffers.
+    pub fn reset(&mut self) {
+        let dispatcher = self.parser.output_sink_mut();
+
+        dispatcher.reset();
+
+        let initial_parser_directive = dispatcher.get_next_parser_directive();
+
+        self.parser.reset(initial_parser_directive);
+        self.has_buffered_data = false;
+
+        if let Some(lookahead) = &mut self.lookahead {
+            lookahead.reset();
+        }
+    }
+
+    #[inline]
+    pub fn transform_controller_mut(&mut self) -> &mut C {
+        self.parser.output_sink_mut().transform_controller_mut()
+    }
+
+    pub fn with_output_sink<T>(&mut self, f: impl FnOnce(&mut O) -> T) -> T {
+        self.parser.output_sink_mut().with_output_sink(f)
     }
 
     #[cfg(feature = "integration_test")]
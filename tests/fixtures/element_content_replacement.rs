@@ -1,7 +1,7 @@
 use crate::harness::suites::selectors_tests::{get_test_cases, TestCase};
 use crate::harness::TestFixture;
 use lol_html::test_utils::Output;
-use lol_html::{HtmlRewriter, Settings, element};
+use lol_html::{HtmlRewriter, LookaheadMode, Settings, element};
 use lol_html::html_content::ContentType;
 
 // NOTE: Inner element content replacement functionality used as a basis for
@@ -31,6 +31,7 @@ impl TestFixture<TestCase> for ElementContentReplacementTests {
                         })
                     ],
                     encoding: encoding.name(),
+                    lookahead_mode: LookaheadMode::FailOnMemoryLimit,
                     ..Settings::default()
                 },
                 |c: &[u8]| output.push(c)
@@ -1,7 +1,7 @@
 use crate::harness::suites::selectors_tests::{get_test_cases, TestCase};
 use crate::harness::TestFixture;
 use lol_html::test_utils::Output;
-use lol_html::{HtmlRewriter, Settings, element, comments, text};
+use lol_html::{HtmlRewriter, LookaheadMode, Settings, element, comments, text};
 use lol_html::html_content::ContentType;
 
 pub struct SelectorMatchingTests;
@@ -68,6 +68,7 @@ impl TestFixture<TestCase> for SelectorMatchingTests {
                         })
                     ],
                     encoding: encoding.name(),
+                    lookahead_mode: LookaheadMode::FailOnMemoryLimit,
                     ..Settings::default()
                 },
                 |c: &[u8]| output.push(c)
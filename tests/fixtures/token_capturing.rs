@@ -47,13 +47,19 @@ type TokenHandler<'h> = Box<dyn FnMut(&mut Token) + 'h>;
 pub struct TestTransformController<'h> {
     token_handler: TokenHandler<'h>,
     capture_flags: TokenCaptureFlags,
+    text_nodes: bool,
 }
 
 impl<'h> TestTransformController<'h> {
-    pub fn new(token_handler: TokenHandler<'h>, capture_flags: TokenCaptureFlags) -> Self {
+    pub fn new(
+        token_handler: TokenHandler<'h>,
+        capture_flags: TokenCaptureFlags,
+        text_nodes: bool,
+    ) -> Self {
         TestTransformController {
             token_handler,
             capture_flags,
+            text_nodes,
         }
     }
 }
@@ -83,11 +89,16 @@ impl TransformController for TestTransformController<'_> {
     fn should_emit_content(&self) -> bool {
         true
     }
+
+    fn captures_text_nodes(&self) -> bool {
+        self.text_nodes
+    }
 }
 
 pub fn parse(
     input: &Input,
     capture_flags: TokenCaptureFlags,
+    text_nodes: bool,
     initial_text_type: TextType,
     last_start_tag_name_hash: LocalNameHash,
     token_handler: TokenHandler,
@@ -97,7 +108,8 @@ pub fn parse(
         .expect("Input should be initialized before parsing");
 
     let mut output = Output::new(encoding);
-    let transform_controller = TestTransformController::new(token_handler, capture_flags);
+    let transform_controller =
+        TestTransformController::new(token_handler, capture_flags, text_nodes);
     let memory_limiter = MemoryLimiter::new_shared(2048);
 
     let mut transform_stream = TransformStream::new(
@@ -107,7 +119,8 @@ pub fn parse(
             preallocated_parsing_buffer_size: 0,
             memory_limiter,
             encoding,
-            strict: true
+            strict: true,
+            lookahead: None
         }
     );
 
@@ -184,65 +197,118 @@ impl TokenCapturingTests {
         .iter()
         .cloned()
         .for_each(|capture_flags| {
-            let mut expected_tokens = filter_tokens(&test.expected_tokens, capture_flags);
-            let mut token_list = TestTokenList::default();
-
-            let parsing_result = parse(
-                &test.input,
-                capture_flags,
+            Self::run_test_case_with_capture(
+                test,
                 initial_text_type,
                 last_start_tag_name_hash,
-                Box::new(|t| token_list.push(t)),
+                capture_flags,
+                false,
             );
+        });
 
-            let mut actual_tokens = token_list.into();
-
-            // NOTE: text is a special case: it's impossible to achieve the same
-            // text chunks layout as in the test data without surrounding tokens
-            // (in test data all character tokens that are not separated by other
-            // tokens get concatenated, ignoring any non-token lexems like `<![CDATA[`
-            // in-between). On the contrary we break character token chain on non-token
-            // lexems and, therefore, if non-token lexems are present we won't get the
-            // same character token layout as in test data if we just concatenate all
-            // tokens in the chain. So, for text tokens we fold both expected and actual
-            // results to the single strings. It's not an ideal solution, but it's better
-            // than nothing.
-            if capture_flags == TokenCaptureFlags::TEXT {
-                actual_tokens = fold_text_tokens(actual_tokens);
-                expected_tokens = fold_text_tokens(expected_tokens);
-            }
+        [TokenCaptureFlags::all(), TokenCaptureFlags::TEXT]
+            .iter()
+            .cloned()
+            .for_each(|capture_flags| {
+                Self::run_test_case_with_capture(
+                    test,
+                    initial_text_type,
+                    last_start_tag_name_hash,
+                    capture_flags,
+                    true,
+                );
+            });
+    }
 
-            match parsing_result {
-                Ok(output) => {
-                    expect_eql!(
-                        actual_tokens,
-                        expected_tokens,
-                        initial_text_type,
-                        test.input,
-                        format!("Token mismatch (capture: {:#?})", capture_flags)
-                    );
+    fn run_test_case_with_capture(
+        test: &TestCase,
+        initial_text_type: TextType,
+        last_start_tag_name_hash: LocalNameHash,
+        capture_flags: TokenCaptureFlags,
+        text_nodes: bool,
+    ) {
+        let mut expected_tokens = filter_tokens(&test.expected_tokens, capture_flags);
+        let mut token_list = TestTokenList::default();
 
-                    expect_eql!(
-                        output,
-                        test.input.as_str(),
+        let parsing_result = parse(
+            &test.input,
+            capture_flags,
+            text_nodes,
+            initial_text_type,
+            last_start_tag_name_hash,
+            Box::new(|t| {
+                if let Token::TextChunk(chunk) = t {
+                    expect!(
+                        !text_nodes || chunk.last_in_text_node(),
                         initial_text_type,
                         test.input,
                         format!(
-                            "Serialized output doesn't match original input (capture: {:#?})",
+                            "Text node is split into chunks (capture: {:#?})",
                             capture_flags
                         )
                     );
                 }
-                Err(_) => {
-                    expect!(
-                        test.expected_bailout.is_some(),
-                        initial_text_type,
-                        test.input,
-                        format!("Unexpected bailout (capture: {:#?})", capture_flags)
-                    );
-                }
+
+                token_list.push(t)
+            }),
+        );
+
+        let mut actual_tokens = token_list.into();
+
+        // NOTE: text is a special case: it's impossible to achieve the same
+        // text chunks layout as in the test data without surrounding tokens
+        // (in test data all character tokens that are not separated by other
+        // tokens get concatenated, ignoring any non-token lexems like `<![CDATA[`
+        // in-between). On the contrary we break character token chain on non-token
+        // lexems and, therefore, if non-token lexems are present we won't get the
+        // same character token layout as in test data if we just concatenate all
+        // tokens in the chain. So, for text tokens we fold both expected and actual
+        // results to the single strings. It's not an ideal solution, but it's better
+        // than nothing.
+        if capture_flags == TokenCaptureFlags::TEXT {
+            actual_tokens = fold_text_tokens(actual_tokens);
+            expected_tokens = fold_text_tokens(expected_tokens);
+        }
+
+        match parsing_result {
+            Ok(output) => {
+                expect_eql!(
+                    actual_tokens,
+                    expected_tokens,
+                    initial_text_type,
+                    test.input,
+                    format!(
+                        "Token mismatch (capture: {:#?}, text nodes: {})",
+                        capture_flags, text_nodes
+                    )
+                );
+
+                expect_eql!(
+                    output,
+                    test.input.as_str(),
+                    initial_text_type,
+                    test.input,
+                    format!(
+                        "Serialized output doesn't match original input (capture: {:#?}, text nodes: {})",
+                        capture_flags, text_nodes
+                    )
+                );
             }
-        });
+            // NOTE: whole text nodes are buffered, so text nodes that are longer than
+            // the memory limit cause a bailout.
+            Err(RewritingError::MemoryLimitExceeded(_)) if text_nodes => (),
+            Err(_) => {
+                expect!(
+                    test.expected_bailout.is_some(),
+                    initial_text_type,
+                    test.input,
+                    format!(
+                        "Unexpected bailout (capture: {:#?}, text nodes: {})",
+                        capture_flags, text_nodes
+                    )
+                );
+            }
+        }
     }
 }
 